@@ -12,12 +12,14 @@ use super::image_visualization::ImageVisualization;
 use super::sensor_visualization::SensorVisualization;
 use super::event_visualization::EventVisualization;
 use super::trigger_visualization::TriggerVisualization;
+use super::live_stream::{use_live_stream, LiveEvent, StreamFilter};
+use std::rc::Rc;
 
 #[derive(Clone, PartialEq, Deserialize)]
 pub struct DeviceInfo {
     pub device_id: String,
     pub last_seen: String, // Keep as String since API returns mixed formats
-    pub status: String, // Server-calculated status: "Online", "Warning", "Offline"
+    pub status: String, // Server-calculated status: "Online", "Warning", "Offline", "Maintenance"
 }
 
 #[derive(Clone, PartialEq, Deserialize)]
@@ -34,15 +36,98 @@ pub struct UserData {
     pub is_root: bool,
 }
 
+/// Devices and topics shown on the dashboard; a reducer so that live events
+/// arriving between renders are applied on top of each other
+#[derive(Clone, PartialEq, Default)]
+struct DashboardData {
+    devices: Vec<DeviceInfo>,
+    /// Device the topic list belongs to
+    topics_device: Option<String>,
+    topics: Vec<TopicInfo>,
+}
+
+enum DashboardAction {
+    SetDevices(Vec<DeviceInfo>),
+    SetTopics(String, Vec<TopicInfo>),
+    ClearTopics,
+    Live(LiveEvent),
+}
+
+impl Reducible for DashboardData {
+    type Action = DashboardAction;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        let mut data = (*self).clone();
+        match action {
+            DashboardAction::SetDevices(devices) => data.devices = devices,
+            DashboardAction::SetTopics(device_id, topics) => {
+                data.topics_device = Some(device_id);
+                data.topics = topics;
+            }
+            DashboardAction::ClearTopics => {
+                data.topics_device = None;
+                data.topics.clear();
+            }
+            DashboardAction::Live(event) => data.apply(event),
+        }
+        Rc::new(data)
+    }
+}
+
+impl DashboardData {
+    fn apply(&mut self, event: LiveEvent) {
+        match event {
+            LiveEvent::Data { device_id, topic, data_type, timestamp, .. } => {
+                if let Some(device) = self.devices.iter_mut().find(|d| d.device_id == device_id) {
+                    device.last_seen = device.last_seen.clone().max(timestamp.clone());
+                }
+                if self.topics_device.as_ref() == Some(&device_id) {
+                    match self.topics.iter_mut().find(|t| t.topic == topic) {
+                        Some(existing) => {
+                            existing.last_seen = existing.last_seen.clone().max(timestamp);
+                            existing.data_type = data_type;
+                        }
+                        // A new topic; its status follows in a topic_status event
+                        None => self.topics.push(TopicInfo {
+                            topic,
+                            last_seen: timestamp,
+                            status: "Active".to_string(),
+                            data_type,
+                        }),
+                    }
+                }
+            }
+            LiveEvent::DeviceStatus { device_id, status, last_seen, .. } => {
+                match self.devices.iter_mut().find(|d| d.device_id == device_id) {
+                    Some(device) => {
+                        device.status = status;
+                        device.last_seen = device.last_seen.clone().max(last_seen);
+                    }
+                    None => self.devices.push(DeviceInfo { device_id, last_seen, status }),
+                }
+            }
+            LiveEvent::TopicStatus { device_id, topic, status, last_seen, .. } => {
+                if self.topics_device.as_ref() == Some(&device_id) {
+                    if let Some(existing) = self.topics.iter_mut().find(|t| t.topic == topic) {
+                        existing.status = status;
+                        existing.last_seen = existing.last_seen.clone().max(last_seen);
+                    }
+                }
+            }
+            LiveEvent::Resync => {}
+        }
+    }
+}
+
 #[function_component(Dashboard)]
 pub fn dashboard() -> Html {
-    let devices = use_state(Vec::<DeviceInfo>::new);
+    let dashboard_data = use_reducer(DashboardData::default);
     let selected_device = use_state(|| None::<String>);
-    let topics = use_state(Vec::<TopicInfo>::new);
     let selected_topic = use_state(|| None::<String>);
     let loading = use_state(|| true);
     let error = use_state(|| None::<String>);
     let auto_refresh = use_state(|| true);
+    let clock = use_state(Date::now);
     let navigator = use_navigator().unwrap();
     let user_menu_visible = use_state(|| false);
     let user_data = use_state(|| None::<UserData>);
@@ -57,70 +142,81 @@ pub fn dashboard() -> Html {
         return html! {};
     }
 
-    // Fetch devices on component mount and when auto_refresh changes
+    // Fetch devices on component mount
     {
-        let devices = devices.clone();
+        let dashboard_data = dashboard_data.clone();
         let loading = loading.clone();
         let error = error.clone();
-        let auto_refresh_val = *auto_refresh;
 
         use_effect_with_deps(
             move |_| {
-                let devices_initial = devices.clone();
-                let loading_initial = loading.clone();
-                let error_initial = error.clone();
-
-                // Initial fetch
                 spawn_local(async move {
-                    loading_initial.set(true);
+                    loading.set(true);
                     
                     match fetch_devices().await {
                         Ok(device_list) => {
-                            devices_initial.set(device_list);
-                            error_initial.set(None);
+                            dashboard_data.dispatch(DashboardAction::SetDevices(device_list));
+                            error.set(None);
                         }
                         Err(e) => {
-                            error_initial.set(Some(e));
+                            error.set(Some(e));
                         }
                     }
                     
-                    loading_initial.set(false);
+                    loading.set(false);
                 });
+                || ()
+            },
+            (),
+        );
+    }
 
-                // Set up auto-refresh interval if enabled
-                let interval_handle = if auto_refresh_val {
-                    let interval_devices = devices.clone();
-                    let interval_error = error.clone();
-                    
-                    let interval = Interval::new(5000, move || {
-                        let devices_inner = interval_devices.clone();
-                        let error_inner = interval_error.clone();
-                        spawn_local(async move {
-                            match fetch_devices().await {
-                                Ok(device_list) => {
-                                    devices_inner.set(device_list);
-                                    error_inner.set(None);
-                                }
-                                Err(e) => {
-                                    error_inner.set(Some(e));
-                                }
-                            }
-                        });
-                    });
-                    Some(interval)
-                } else {
-                    None
-                };
-
-                // Cleanup function
-                move || {
-                    drop(interval_handle);
-                }
+    // Re-render periodically so relative "last seen" labels keep ticking; this makes no requests
+    {
+        let clock = clock.clone();
+        use_effect_with_deps(
+            move |_| {
+                let interval = Interval::new(5000, move || clock.set(Date::now()));
+                move || drop(interval)
             },
-            auto_refresh_val,
+            (),
         );
     }
 
+    // Keep devices and topics current from the live stream while auto-refresh is on
+    {
+        let dashboard_data = dashboard_data.clone();
+        let error = error.clone();
+        let selected_device_id = (*selected_device).clone();
+
+        let on_event = Callback::from(move |event: LiveEvent| {
+            if event == LiveEvent::Resync {
+                // Catch up on anything missed while disconnected
+                let dashboard_data = dashboard_data.clone();
+                let error = error.clone();
+                let selected_device_id = selected_device_id.clone();
+                spawn_local(async move {
+                    match fetch_devices().await {
+                        Ok(device_list) => {
+                            dashboard_data.dispatch(DashboardAction::SetDevices(device_list));
+                            error.set(None);
+                        }
+                        Err(e) => error.set(Some(e)),
+                    }
+                    if let Some(device_id) = selected_device_id {
+                        if let Ok(topic_list) = fetch_topics(&device_id).await {
+                            dashboard_data.dispatch(DashboardAction::SetTopics(device_id, topic_list));
+                        }
+                    }
+                });
+            } else {
+                dashboard_data.dispatch(DashboardAction::Live(event));
+            }
+        });
+
+        use_live_stream(StreamFilter::default(), *auto_refresh, on_event);
+    }
+
     // Fetch user data on component mount
     {
         let user_data = user_data.clone();
@@ -151,59 +247,25 @@ pub fn dashboard() -> Html {
 
 
 
-    // Fetch topics when a device is selected or auto-refresh is enabled
+    // Fetch topics when a device is selected
     {
-        let selected_device_id = selected_device.clone();
-        let topics = topics.clone();
-        let auto_refresh_val = *auto_refresh;
+        let dashboard_data = dashboard_data.clone();
 
         use_effect_with_deps(
-            move |deps: &(Option<String>, bool)| {
-                let (device_id, auto_refresh_enabled) = deps.clone();
-                
-                let interval_handle = if let Some(device_id) = &device_id {
-                    let device_id = device_id.clone();
-                    let topics_initial = topics.clone();
-                    
-                    // Initial fetch
-                    {
-                        let device_id_fetch = device_id.clone();
+            move |device_id: &Option<String>| {
+                match device_id.clone() {
+                    Some(device_id) => {
                         spawn_local(async move {
-                            if let Ok(topic_list) = fetch_topics(&device_id_fetch).await {
-                                topics_initial.set(topic_list);
+                            if let Ok(topic_list) = fetch_topics(&device_id).await {
+                                dashboard_data.dispatch(DashboardAction::SetTopics(device_id, topic_list));
                             }
                         });
                     }
-
-                    // Set up auto-refresh for topics if enabled
-                    if auto_refresh_enabled {
-                        let device_id_interval = device_id.clone();
-                        let topics_interval = topics.clone();
-                        
-                        let interval = Interval::new(5000, move || {
-                            let device_id_inner = device_id_interval.clone();
-                            let topics_inner = topics_interval.clone();
-                            spawn_local(async move {
-                                if let Ok(topic_list) = fetch_topics(&device_id_inner).await {
-                                    topics_inner.set(topic_list);
-                                }
-                            });
-                        });
-                        Some(interval)
-                    } else {
-                        None
-                    }
-                } else {
-                    topics.set(Vec::new());
-                    None
-                };
-
-                // Cleanup function
-                move || {
-                    drop(interval_handle);
+                    None => dashboard_data.dispatch(DashboardAction::ClearTopics),
                 }
+                || ()
             },
-            ((*selected_device_id).clone(), auto_refresh_val),
+            (*selected_device).clone(),
         );
     }
 
@@ -291,7 +353,7 @@ pub fn dashboard() -> Html {
                         <div class="loading">{"Loading devices..."}</div>
                     } else if let Some(err) = &*error {
                         <div class="error">{format!("Error: {}", err)}</div>
-                    } else if devices.is_empty() {
+                    } else if dashboard_data.devices.is_empty() {
                         <div class="device-list-empty">
                             <p>{"No devices found"}</p>
                             <small>{"Devices will appear here once they start sending pings"}</small>
@@ -299,7 +361,7 @@ pub fn dashboard() -> Html {
                     } else {
                         <div class="device-list">
                             {for {
-                                let mut sorted_devices = devices.iter().collect::<Vec<_>>();
+                                let mut sorted_devices = dashboard_data.devices.iter().collect::<Vec<_>>();
                                 sorted_devices.sort_by(|a, b| {
                                     let a_time = parse_timestamp(&a.last_seen).unwrap_or(0.0);
                                     let b_time = parse_timestamp(&b.last_seen).unwrap_or(0.0);
@@ -324,7 +386,7 @@ pub fn dashboard() -> Html {
                                     >
                                         <div class="device-header">
                                             <span class="device-id">{&device.device_id}</span>
-                                            <span class={classes!("device-status", status_class)}>
+                                            <span class={classes!("device-status", status_class)} title={device.status.clone()}>
                                                 // {get_device_status(&device.last_seen)} // Removed text
                                             </span>
                                         </div>
@@ -418,14 +480,14 @@ pub fn dashboard() -> Html {
                         }
                     </h2>
                     if selected_device.is_some() {
-                        if topics.is_empty() {
+                        if dashboard_data.topics.is_empty() {
                             <div class="topic-list-empty">
                                 <p>{"No topics found"}</p>
                                 <small>{"Topics will appear here once the device sends pings"}</small>
                             </div>
                         } else {
                             <div class="topic-list">
-                                {for topics.iter().map(|topic| {
+                                {for dashboard_data.topics.iter().map(|topic| {
                                     let status_class = get_topic_status_class(&topic.status);
                                     let topic_name = topic.topic.clone();
                                     let is_topic_selected = selected_topic.as_ref() == Some(&topic_name);
@@ -583,6 +645,7 @@ fn get_device_status_class(status: &str) -> &'static str {
         "Online" => "online",
         "Warning" => "warning", 
         "Offline" => "offline",
+        "Maintenance" => "maintenance",
         _ => "unknown",
     }
 }
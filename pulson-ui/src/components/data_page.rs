@@ -0,0 +1,59 @@
+use gloo_net::http::Request;
+use gloo_storage::{LocalStorage, Storage};
+use serde_json::Value;
+
+/// Records fetched at first and by each "Load more"
+pub const PAGE_SIZE: usize = 10;
+
+/// Most records the server returns in one page
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Records of one topic from `/api/devices/{id}/data`, newest first
+#[derive(Clone, PartialEq)]
+pub struct DataPage {
+    pub data: Vec<Value>,
+    /// Pass as `before` to get the next older page; `None` once everything is loaded
+    pub next_cursor: Option<String>,
+}
+
+/// How many records a refresh should fetch so already loaded history stays visible
+pub fn refresh_size(loaded: usize) -> usize {
+    loaded.clamp(PAGE_SIZE, MAX_PAGE_SIZE)
+}
+
+/// Fetch the newest `limit` records of a topic, or the `limit` records older than `before`
+pub async fn fetch_data_page(
+    device_id: &str,
+    topic: &str,
+    data_type: &str,
+    limit: usize,
+    before: Option<&str>,
+) -> Result<DataPage, String> {
+    let token = LocalStorage::get::<String>("pulson_token")
+        .map_err(|_| "No authentication token found".to_string())?;
+
+    let mut url = format!("/api/devices/{}/data?topic={}&type={}&limit={}", device_id, topic, data_type, limit);
+    if let Some(cursor) = before {
+        url.push_str(&format!("&before={}", cursor));
+    }
+
+    let request = Request::get(&url)
+        .header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+
+    if request.status() == 200 {
+        let response: Value = request
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+        Ok(DataPage {
+            data: response["data"].as_array().cloned().unwrap_or_default(),
+            next_cursor: response["next_cursor"].as_str().map(str::to_string),
+        })
+    } else {
+        Err(format!("Server error: {}", request.status()))
+    }
+}
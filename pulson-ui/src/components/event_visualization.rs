@@ -1,10 +1,9 @@
-use gloo_net::http::Request;
-use gloo_storage::{LocalStorage, Storage};
-use gloo_timers::callback::Interval;
 use serde::{Deserialize};
 use serde_json::Value;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
+use super::data_page::{fetch_data_page, refresh_size, PAGE_SIZE};
+use super::live_stream::use_live_refresh;
 use chrono::{DateTime, Utc};
 
 #[derive(Clone, PartialEq, Deserialize)]
@@ -13,6 +12,7 @@ pub struct EventHistoryData {
     pub start_time: String,
     pub end_time: String,
     pub data: Vec<Value>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Clone, PartialEq)]
@@ -42,7 +42,6 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
     let event_history = use_state(|| None::<EventHistoryData>);
     let loading = use_state(|| false);
     let error = use_state(|| None::<String>);
-    let refresh_interval = use_state(|| None::<Interval>);
     let selected_category = use_state(|| None::<EventCategory>);
     let show_timestamps = use_state(|| true);
 
@@ -52,6 +51,8 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
         let topic = props.topic.clone();
         let event_history = event_history.clone();
         let error = error.clone();
+        // Refetch as many events as are shown, so loaded history isn't dropped
+        let limit = refresh_size(event_history.as_ref().map_or(0, |history| history.data.len()));
         
         Callback::from(move |_| {
             let device_id = device_id.clone();
@@ -62,7 +63,7 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
             spawn_local(async move {
                 error.set(None);
 
-                match fetch_event_history(&device_id, &topic).await {
+                match fetch_event_history(&device_id, &topic, limit).await {
                     Ok(history) => {
                         event_history.set(Some(history));
                     }
@@ -74,6 +75,40 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
         })
     };
 
+    // Append the next page of older events
+    let load_more = {
+        let device_id = props.device_id.clone();
+        let topic = props.topic.clone();
+        let event_history = event_history.clone();
+        let error = error.clone();
+
+        Callback::from(move |_: web_sys::MouseEvent| {
+            let Some(mut history) = (*event_history).clone() else {
+                return;
+            };
+            let Some(cursor) = history.next_cursor.clone() else {
+                return;
+            };
+            let device_id = device_id.clone();
+            let topic = topic.clone();
+            let event_history = event_history.clone();
+            let error = error.clone();
+
+            spawn_local(async move {
+                match fetch_data_page(&device_id, &topic, "event", PAGE_SIZE, Some(&cursor)).await {
+                    Ok(page) => {
+                        history.data.extend(page.data);
+                        history.next_cursor = page.next_cursor;
+                        event_history.set(Some(history));
+                    }
+                    Err(e) => {
+                        error.set(Some(format!("Failed to fetch event data: {}", e)));
+                    }
+                }
+            });
+        })
+    };
+
     // Manual refresh callback for button clicks
     let manual_refresh = {
         let refresh_data = refresh_data.clone();
@@ -82,26 +117,8 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
         })
     };
 
-    // Set up auto-refresh interval
-    {
-        let refresh_data = refresh_data.clone();
-        let refresh_interval = refresh_interval.clone();
-        
-        use_effect_with_deps(
-            move |_| {
-                refresh_interval.set(None);
-                
-                let interval = Interval::new(5000, move || {
-                    refresh_data.emit(());
-                });
-                
-                refresh_interval.set(Some(interval));
-                
-                || {}
-            },
-            (),
-        );
-    }
+    // Refetch whenever the live stream reports new data
+    use_live_refresh(props.device_id.clone(), Some(props.topic.clone()), refresh_data.clone());
 
     // Initial fetch when device or topic changes
     {
@@ -119,6 +136,8 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
         );
     }
 
+    let has_more = event_history.as_ref().is_some_and(|history| history.next_cursor.is_some());
+
     // Parse event entries
     let events = event_history.as_ref()
         .map(|history| parse_event_entries(&history.data))
@@ -252,6 +271,9 @@ pub fn event_visualization(props: &EventVisualizationProps) -> Html {
                             }
                         })}
                     </div>
+                    if has_more {
+                        <button onclick={load_more} class="btn btn-small load-more">{"Load more"}</button>
+                    }
                 </div>
             }
         </div>
@@ -341,37 +363,14 @@ fn format_time_ago(timestamp: &DateTime<Utc>) -> String {
 }
 
 // API call to fetch event history
-async fn fetch_event_history(device_id: &str, topic: &str) -> Result<EventHistoryData, String> {
-    let token = LocalStorage::get::<String>("pulson_token")
-        .map_err(|_| "No authentication token found".to_string())?;
-
-    let url = format!("/api/devices/{}/data?topic={}&type=event", device_id, topic);
-
-    let request = Request::get(&url)
-        .header("Authorization", &format!("Bearer {}", token))
-        .send()
-        .await
-        .map_err(|e| format!("Network error: {}", e))?;
-
-    if request.status() == 200 {
-        let response: Value = request
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        // Transform the data response to match our expected format
-        let data = response.get("data")
-            .and_then(|d| d.as_array())
-            .cloned()
-            .unwrap_or_default();
-
-        Ok(EventHistoryData {
-            time_range: "1d".to_string(),
-            start_time: "".to_string(),
-            end_time: "".to_string(),
-            data,
-        })
-    } else {
-        Err(format!("Server error: {}", request.status()))
-    }
+async fn fetch_event_history(device_id: &str, topic: &str, limit: usize) -> Result<EventHistoryData, String> {
+    let page = fetch_data_page(device_id, topic, "event", limit, None).await?;
+
+    Ok(EventHistoryData {
+        time_range: "1d".to_string(),
+        start_time: "".to_string(),
+        end_time: "".to_string(),
+        data: page.data,
+        next_cursor: page.next_cursor,
+    })
 }
@@ -1,9 +1,8 @@
-use gloo_net::http::Request;
-use gloo_storage::{LocalStorage, Storage};
 use serde::{Deserialize};
 use serde_json::Value;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
+use super::data_page::{fetch_data_page, refresh_size, PAGE_SIZE};
 use wasm_bindgen::JsCast;
 use web_sys::{HtmlCanvasElement, CanvasRenderingContext2d, ImageData};
 
@@ -13,6 +12,7 @@ pub struct ImageHistoryData {
     pub start_time: String,
     pub end_time: String,
     pub data: Vec<Value>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Properties, Clone, PartialEq)]
@@ -36,6 +36,8 @@ pub fn image_visualization(props: &ImageVisualizationProps) -> Html {
         let image_data = image_data.clone();
         let loading = loading.clone();
         let error = error.clone();
+        // Refetch as many images as are loaded, so older ones aren't dropped
+        let limit = refresh_size(image_data.as_ref().map_or(0, |history| history.data.len()));
         
         Callback::from(move |_: web_sys::MouseEvent| {
             let device_id = device_id.clone();
@@ -48,7 +50,7 @@ pub fn image_visualization(props: &ImageVisualizationProps) -> Html {
                 loading.set(true);
                 error.set(None);
 
-                match fetch_image_history(&device_id, &topic).await {
+                match fetch_image_history(&device_id, &topic, limit).await {
                     Ok(data) => {
                         image_data.set(Some(data));
                     }
@@ -61,6 +63,41 @@ pub fn image_visualization(props: &ImageVisualizationProps) -> Html {
         })
     };
 
+    // Append the next page of older images
+    let load_more = {
+        let device_id = props.device_id.clone();
+        let topic = props.topic.clone();
+        let image_data = image_data.clone();
+        let error = error.clone();
+
+        Callback::from(move |_: web_sys::MouseEvent| {
+            let Some(mut history) = (*image_data).clone() else {
+                return;
+            };
+            let Some(cursor) = history.next_cursor.clone() else {
+                return;
+            };
+            let device_id = device_id.clone();
+            let topic = topic.clone();
+            let image_data = image_data.clone();
+            let error = error.clone();
+
+            spawn_local(async move {
+                match fetch_data_page(&device_id, &topic, "image", PAGE_SIZE, Some(&cursor)).await {
+                    Ok(page) => {
+                        history.data.extend(page.data);
+                        history.next_cursor = page.next_cursor;
+                        image_data.set(Some(history));
+                    }
+                    Err(e) => {
+                        error.set(Some(format!("Failed to fetch image data: {}", e)));
+                    }
+                }
+            });
+        })
+    };
+    let has_more = image_data.as_ref().is_some_and(|history| history.next_cursor.is_some());
+
     // Initial fetch
     {
         let fetch_images = fetch_images.clone();
@@ -206,6 +243,9 @@ pub fn image_visualization(props: &ImageVisualizationProps) -> Html {
                             })}
                         </div>
                     }
+                    if has_more {
+                        <button onclick={load_more} class="btn btn-small load-more">{"Load older images"}</button>
+                    }
                 </div>
             }
         </div>
@@ -307,37 +347,14 @@ fn format_data_size(image_obj: &Value) -> String {
     }
 }
 
-async fn fetch_image_history(device_id: &str, topic: &str) -> Result<ImageHistoryData, String> {
-    let token = LocalStorage::get::<String>("pulson_token")
-        .map_err(|_| "No authentication token found".to_string())?;
+async fn fetch_image_history(device_id: &str, topic: &str, limit: usize) -> Result<ImageHistoryData, String> {
+    let page = fetch_data_page(device_id, topic, "image", limit, None).await?;
 
-    let url = format!("/api/devices/{}/data?topic={}&type=image", device_id, topic);
-
-    let request = Request::get(&url)
-        .header("Authorization", &format!("Bearer {}", token))
-        .send()
-        .await
-        .map_err(|e| format!("Network error: {}", e))?;
-
-    if request.status() == 200 {
-        let response: Value = request
-            .json()
-            .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        // Transform the data response to match our expected format
-        let data = response.get("data")
-            .and_then(|d| d.as_array())
-            .cloned()
-            .unwrap_or_default();
-
-        Ok(ImageHistoryData {
-            time_range: "1d".to_string(),
-            start_time: "".to_string(),
-            end_time: "".to_string(),
-            data,
-        })
-    } else {
-        Err(format!("Server error: {}", request.status()))
-    }
+    Ok(ImageHistoryData {
+        time_range: "1d".to_string(),
+        start_time: "".to_string(),
+        end_time: "".to_string(),
+        data: page.data,
+        next_cursor: page.next_cursor,
+    })
 }
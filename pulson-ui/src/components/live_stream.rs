@@ -0,0 +1,155 @@
+use gloo_storage::{LocalStorage, Storage};
+use gloo_timers::callback::Timeout;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{EventSource, MessageEvent};
+use yew::prelude::*;
+
+/// How long data events are collected before a component refetches
+const REFRESH_COALESCE_MS: u32 = 500;
+
+/// An event pushed by `/api/stream`
+#[derive(Clone, PartialEq, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LiveEvent {
+    Data {
+        device_id: String,
+        topic: String,
+        data_type: String,
+        data: serde_json::Value,
+        timestamp: String,
+    },
+    DeviceStatus {
+        device_id: String,
+        status: String,
+        previous_status: Option<String>,
+        last_seen: String,
+    },
+    TopicStatus {
+        device_id: String,
+        topic: String,
+        status: String,
+        previous_status: Option<String>,
+        last_seen: String,
+    },
+    /// The stream (re)connected or dropped events; local state should be refetched
+    #[serde(skip)]
+    Resync,
+}
+
+/// Which events to subscribe to; `None` means no filtering on that field
+#[derive(Clone, PartialEq, Default)]
+pub struct StreamFilter {
+    pub device: Option<String>,
+    pub topic: Option<String>,
+}
+
+fn stream_url(token: &str, filter: &StreamFilter) -> String {
+    let mut url = format!("/api/stream?access_token={}", js_sys::encode_uri_component(token));
+    if let Some(device) = &filter.device {
+        url.push_str(&format!("&device={}", js_sys::encode_uri_component(device)));
+    }
+    if let Some(topic) = &filter.topic {
+        url.push_str(&format!("&topic={}", js_sys::encode_uri_component(topic)));
+    }
+    url
+}
+
+/// Subscribe to the live event stream while `enabled` is true.
+///
+/// The latest `on_event` callback is always used, so callers may pass a fresh
+/// closure on every render without reconnecting.
+#[hook]
+pub fn use_live_stream(filter: StreamFilter, enabled: bool, on_event: Callback<LiveEvent>) {
+    let latest = use_mut_ref(|| on_event.clone());
+    *latest.borrow_mut() = on_event;
+
+    use_effect_with_deps(
+        move |(filter, enabled): &(StreamFilter, bool)| {
+            let mut source = None;
+
+            let token = LocalStorage::get::<String>("pulson_token").ok();
+            if let (true, Some(token)) = (*enabled, token) {
+                match EventSource::new(&stream_url(&token, filter)) {
+                    Ok(event_source) => {
+                        let on_message = {
+                            let latest = latest.clone();
+                            Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                                let Some(text) = event.data().as_string() else {
+                                    return;
+                                };
+                                match serde_json::from_str::<LiveEvent>(&text) {
+                                    Ok(live_event) => latest.borrow().emit(live_event),
+                                    Err(e) => gloo_console::warn!("Ignoring live event:", e.to_string()),
+                                }
+                            })
+                        };
+                        let on_resync = {
+                            let latest = latest.clone();
+                            Closure::<dyn FnMut(web_sys::Event)>::new(move |_| {
+                                latest.borrow().emit(LiveEvent::Resync);
+                            })
+                        };
+
+                        for name in ["data", "device_status", "topic_status"] {
+                            let _ = event_source
+                                .add_event_listener_with_callback(name, on_message.as_ref().unchecked_ref());
+                        }
+                        for name in ["open", "lagged"] {
+                            let _ = event_source
+                                .add_event_listener_with_callback(name, on_resync.as_ref().unchecked_ref());
+                        }
+
+                        // Hand the closures to the JS garbage collector; they live as long as the source
+                        on_message.into_js_value();
+                        on_resync.into_js_value();
+                        source = Some(event_source);
+                    }
+                    Err(_) => gloo_console::error!("Failed to open live event stream"),
+                }
+            }
+
+            move || {
+                if let Some(event_source) = source {
+                    event_source.close();
+                }
+            }
+        },
+        (filter, enabled),
+    );
+}
+
+/// Call `refresh` when data arrives for a device (and optionally one topic).
+///
+/// Bursts of events within `REFRESH_COALESCE_MS` trigger a single refresh, so a
+/// fast publisher does not turn into one HTTP request per record.
+#[hook]
+pub fn use_live_refresh(device_id: String, topic: Option<String>, refresh: Callback<()>) {
+    let timeout: Rc<RefCell<Option<Timeout>>> = use_mut_ref(|| None);
+    let pending: Rc<RefCell<bool>> = use_mut_ref(|| false);
+
+    let on_event = Callback::from(move |event: LiveEvent| {
+        if matches!(event, LiveEvent::Data { .. } | LiveEvent::Resync) && !*pending.borrow() {
+            *pending.borrow_mut() = true;
+            let pending = pending.clone();
+            let refresh = refresh.clone();
+            // Replacing the previous (already fired) timeout drops it outside its own callback
+            *timeout.borrow_mut() = Some(Timeout::new(REFRESH_COALESCE_MS, move || {
+                *pending.borrow_mut() = false;
+                refresh.emit(());
+            }));
+        }
+    });
+
+    use_live_stream(
+        StreamFilter {
+            device: Some(device_id),
+            topic,
+        },
+        true,
+        on_event,
+    );
+}
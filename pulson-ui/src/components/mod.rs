@@ -6,6 +6,8 @@ pub mod image_visualization;
 pub mod sensor_visualization;
 pub mod event_visualization;
 pub mod trigger_visualization;
+pub mod live_stream;
+pub mod data_page;
 
 pub use dashboard::Dashboard;
 pub use settings::Settings;
@@ -1,15 +1,14 @@
 use gloo_net::http::Request;
 use gloo_storage::{LocalStorage, Storage};
-use gloo_timers::callback::Interval;
 use serde::{Deserialize};
 use serde_json::Value;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
+use super::live_stream::use_live_refresh;
 use chrono::{DateTime, Utc, Duration, Timelike};
 
 #[derive(Clone, PartialEq, Deserialize)]
 pub struct PulseHistoryData {
-    pub time_range: String,
     pub start_time: String,
     pub end_time: String,
     pub data: Vec<Value>,
@@ -51,7 +50,6 @@ pub fn pulse_visualization(props: &PulseVisualizationProps) -> Html {
     let selected_time_range = use_state(|| "1h".to_string());
     let loading = use_state(|| false);
     let error = use_state(|| None::<String>);
-    let refresh_interval = use_state(|| None::<Interval>);
 
     // Auto-refresh function
     let refresh_data = {
@@ -86,30 +84,8 @@ pub fn pulse_visualization(props: &PulseVisualizationProps) -> Html {
         })
     };
 
-    // Set up auto-refresh interval
-    {
-        let refresh_data = refresh_data.clone();
-        let refresh_interval = refresh_interval.clone();
-        
-        use_effect_with_deps(
-            move |_| {
-                // Clear existing interval
-                refresh_interval.set(None);
-                
-                // Set up new interval (refresh every 5 seconds)
-                let interval = Interval::new(5000, move || {
-                    refresh_data.emit(());
-                });
-                
-                refresh_interval.set(Some(interval));
-                
-                || {
-                    // Cleanup on unmount
-                }
-            },
-            (),
-        );
-    }
+    // Refetch whenever the live stream reports new data
+    use_live_refresh(props.device_id.clone(), props.topic.clone(), refresh_data.clone());
 
     // Initial fetch when device, topic, or time range changes
     {
@@ -479,12 +455,22 @@ fn get_time_range_description(time_range: &str) -> String {
     }
 }
 
+// Start of a selected time range as the relative `from` the API takes
+fn time_range_start(time_range: &str) -> &'static str {
+    match time_range {
+        "1h" => "-1h",
+        "1w" => "-1w",
+        "1m" => "-30d",
+        _ => "-1d",
+    }
+}
+
 // Helper functions for API calls
 async fn fetch_pulse_history(device_id: &str, time_range: &str, topic: Option<&str>) -> Result<PulseHistoryData, String> {
     let token = LocalStorage::get::<String>("pulson_token")
         .map_err(|_| "No authentication token found".to_string())?;
 
-    let mut url = format!("/api/devices/{}/history?time_range={}", device_id, time_range);
+    let mut url = format!("/api/devices/{}/history?from={}", device_id, time_range_start(time_range));
     if let Some(topic_name) = topic {
         url.push_str(&format!("&topic={}", topic_name));
     }
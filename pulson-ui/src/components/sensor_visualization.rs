@@ -1,10 +1,11 @@
 use gloo_net::http::Request;
 use gloo_storage::{LocalStorage, Storage};
-use gloo_timers::callback::Interval;
 use serde::{Deserialize};
 use serde_json::Value;
 use wasm_bindgen_futures::spawn_local;
 use yew::prelude::*;
+use super::data_page::{fetch_data_page, refresh_size, PAGE_SIZE};
+use super::live_stream::use_live_refresh;
 use chrono::{DateTime, Utc};
 
 #[derive(Clone, PartialEq, Deserialize)]
@@ -13,6 +14,7 @@ pub struct SensorHistoryData {
     pub start_time: String,
     pub end_time: String,
     pub data: Vec<Value>,
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Clone, PartialEq)]
@@ -32,27 +34,31 @@ pub struct SensorVisualizationProps {
 #[function_component(SensorVisualization)]
 pub fn sensor_visualization(props: &SensorVisualizationProps) -> Html {
     let sensor_history = use_state(|| None::<SensorHistoryData>);
+    let daily_summary = use_state(|| None::<Value>);
     let loading = use_state(|| false);
     let error = use_state(|| None::<String>);
-    let refresh_interval = use_state(|| None::<Interval>);
 
     // Auto-refresh function
     let refresh_data = {
         let device_id = props.device_id.clone();
         let topic = props.topic.clone();
         let sensor_history = sensor_history.clone();
+        let daily_summary = daily_summary.clone();
         let error = error.clone();
+        // Refetch as many readings as are shown, so loaded history isn't dropped
+        let limit = refresh_size(sensor_history.as_ref().map_or(0, |history| history.data.len()));
         
         Callback::from(move |_| {
             let device_id = device_id.clone();
             let topic = topic.clone();
             let sensor_history = sensor_history.clone();
+            let daily_summary = daily_summary.clone();
             let error = error.clone();
 
             spawn_local(async move {
                 error.set(None);
 
-                match fetch_sensor_history(&device_id, &topic).await {
+                match fetch_sensor_history(&device_id, &topic, limit).await {
                     Ok(history) => {
                         sensor_history.set(Some(history));
                     }
@@ -60,10 +66,48 @@ pub fn sensor_visualization(props: &SensorVisualizationProps) -> Html {
                         error.set(Some(format!("Failed to fetch sensor data: {}", e)));
                     }
                 }
+
+                // Averages come from the server so they cover every reading, not just the latest few
+                daily_summary.set(fetch_daily_summary(&device_id, &topic).await.ok().flatten());
             });
         })
     };
 
+    // Append the next page of older readings
+    let load_more = {
+        let device_id = props.device_id.clone();
+        let topic = props.topic.clone();
+        let sensor_history = sensor_history.clone();
+        let error = error.clone();
+
+        Callback::from(move |_: web_sys::MouseEvent| {
+            let Some(mut history) = (*sensor_history).clone() else {
+                return;
+            };
+            let Some(cursor) = history.next_cursor.clone() else {
+                return;
+            };
+            let device_id = device_id.clone();
+            let topic = topic.clone();
+            let sensor_history = sensor_history.clone();
+            let error = error.clone();
+
+            spawn_local(async move {
+                match fetch_data_page(&device_id, &topic, "sensor", PAGE_SIZE, Some(&cursor)).await {
+                    Ok(page) => {
+                        history.data.extend(page.data);
+                        history.next_cursor = page.next_cursor;
+                        sensor_history.set(Some(history));
+                    }
+                    Err(e) => {
+                        error.set(Some(format!("Failed to fetch sensor data: {}", e)));
+                    }
+                }
+            });
+        })
+    };
+    let has_more = sensor_history.as_ref().is_some_and(|history| history.next_cursor.is_some());
+
     // Manual refresh callback for button clicks
     let manual_refresh = {
         let refresh_data = refresh_data.clone();
@@ -72,26 +116,8 @@ pub fn sensor_visualization(props: &SensorVisualizationProps) -> Html {
         })
     };
 
-    // Set up auto-refresh interval
-    {
-        let refresh_data = refresh_data.clone();
-        let refresh_interval = refresh_interval.clone();
-        
-        use_effect_with_deps(
-            move |_| {
-                refresh_interval.set(None);
-                
-                let interval = Interval::new(5000, move || {
-                    refresh_data.emit(());
-                });
-                
-                refresh_interval.set(Some(interval));
-                
-                || {}
-            },
-            (),
-        );
-    }
+    // Refetch whenever the live stream reports new data
+    use_live_refresh(props.device_id.clone(), Some(props.topic.clone()), refresh_data.clone());
 
     // Initial fetch when device or topic changes
     {
@@ -212,17 +238,28 @@ pub fn sensor_visualization(props: &SensorVisualizationProps) -> Html {
                                     <span class="stat-label">{"Latest:"}</span>
                                     <span class="stat-value">{format!("{:.1}", reading.value)}</span>
                                 </div>
-                                <div class="stat-item">
-                                    <span class="stat-label">{"Average:"}</span>
-                                    <span class="stat-value">
-                                        {format!("{:.1}", calculate_average(&historical_readings))}
-                                    </span>
-                                </div>
-                                <div class="stat-item">
-                                    <span class="stat-label">{"Readings:"}</span>
-                                    <span class="stat-value">{historical_readings.len()}</span>
-                                </div>
+                                if let Some(summary) = (*daily_summary).as_ref() {
+                                    <div class="stat-item">
+                                        <span class="stat-label">{"24h average:"}</span>
+                                        <span class="stat-value">
+                                            {format!("{:.1} ± {:.1}", summary["mean"].as_f64().unwrap_or_default(), summary["stddev"].as_f64().unwrap_or_default())}
+                                        </span>
+                                    </div>
+                                    <div class="stat-item">
+                                        <span class="stat-label">{"24h range:"}</span>
+                                        <span class="stat-value">
+                                            {format!("{:.1} - {:.1}", summary["min"].as_f64().unwrap_or_default(), summary["max"].as_f64().unwrap_or_default())}
+                                        </span>
+                                    </div>
+                                    <div class="stat-item">
+                                        <span class="stat-label">{"24h readings:"}</span>
+                                        <span class="stat-value">{summary["count"].as_i64().unwrap_or_default()}</span>
+                                    </div>
+                                }
                             </div>
+                            if has_more {
+                                <button onclick={load_more} class="btn btn-small load-more">{"Load more"}</button>
+                            }
                         </div>
                     }
                 </div>
@@ -330,21 +367,25 @@ fn generate_trend_points(readings: &[SensorReading]) -> String {
     points.join(" ")
 }
 
-fn calculate_average(readings: &[SensorReading]) -> f64 {
-    if readings.is_empty() {
-        return 0.0;
-    }
-    
-    let sum: f64 = readings.iter().map(|r| r.value).sum();
-    sum / readings.len() as f64
+// API call to fetch sensor history
+async fn fetch_sensor_history(device_id: &str, topic: &str, limit: usize) -> Result<SensorHistoryData, String> {
+    let page = fetch_data_page(device_id, topic, "sensor", limit, None).await?;
+
+    Ok(SensorHistoryData {
+        time_range: "1d".to_string(),
+        start_time: "".to_string(),
+        end_time: "".to_string(),
+        data: page.data,
+        next_cursor: page.next_cursor,
+    })
 }
 
-// API call to fetch sensor history
-async fn fetch_sensor_history(device_id: &str, topic: &str) -> Result<SensorHistoryData, String> {
+// Summary of the last 24 hours of readings, computed by the server
+async fn fetch_daily_summary(device_id: &str, topic: &str) -> Result<Option<Value>, String> {
     let token = LocalStorage::get::<String>("pulson_token")
         .map_err(|_| "No authentication token found".to_string())?;
 
-    let url = format!("/api/devices/{}/data?topic={}&type=sensor", device_id, topic);
+    let url = format!("/api/devices/{}/aggregate?topic={}&from=-1d", device_id, topic);
 
     let request = Request::get(&url)
         .header("Authorization", &format!("Bearer {}", token))
@@ -357,19 +398,7 @@ async fn fetch_sensor_history(device_id: &str, topic: &str) -> Result<SensorHist
             .json()
             .await
             .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-        // Transform the data response to match our expected format
-        let data = response.get("data")
-            .and_then(|d| d.as_array())
-            .cloned()
-            .unwrap_or_default();
-
-        Ok(SensorHistoryData {
-            time_range: "1d".to_string(),
-            start_time: "".to_string(),
-            end_time: "".to_string(),
-            data,
-        })
+        Ok(response["data"].get(0).cloned())
     } else {
         Err(format!("Server error: {}", request.status()))
     }
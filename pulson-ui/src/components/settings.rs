@@ -19,6 +19,16 @@ pub struct UserData {
     pub is_root: bool,
 }
 
+#[derive(Clone, PartialEq, Deserialize, Debug)]
+pub struct SessionData {
+    pub id: i64,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub expires_at: String,
+    pub user_agent: Option<String>,
+    pub current: bool,
+}
+
 #[derive(Serialize)]
 struct ConfigUpdateRequest {
     online_threshold_seconds: u64,
@@ -30,6 +40,8 @@ struct ConfigUpdateRequest {
 pub fn settings() -> Html {
     let config_data = use_state(|| None::<ConfigData>);
     let user_data = use_state(|| None::<UserData>);
+    let sessions = use_state(Vec::<SessionData>::new);
+    let session_error = use_state(|| None::<String>);
     let loading = use_state(|| true);
     let saving = use_state(|| false);
     let error = use_state(|| None::<String>);
@@ -52,6 +64,8 @@ pub fn settings() -> Html {
     {
         let config_data = config_data.clone();
         let user_data = user_data.clone();
+        let sessions = sessions.clone();
+        let session_error = session_error.clone();
         let loading = loading.clone();
         let error = error.clone();
         let online_threshold = online_threshold.clone();
@@ -93,6 +107,11 @@ pub fn settings() -> Html {
                             gloo_console::error!("Failed to fetch user data:", e);
                         }
                     }
+
+                    match fetch_sessions().await {
+                        Ok(list) => sessions.set(list),
+                        Err(e) => session_error.set(Some(format!("Failed to load sessions: {}", e))),
+                    }
                     
                     loading.set(false);
                 });
@@ -118,6 +137,26 @@ pub fn settings() -> Html {
         })
     };
 
+    // Session callbacks: end one session, or every session but this one
+    let on_revoke_session = {
+        let sessions = sessions.clone();
+        let session_error = session_error.clone();
+        Callback::from(move |id: Option<i64>| {
+            let sessions = sessions.clone();
+            let session_error = session_error.clone();
+            spawn_local(async move {
+                session_error.set(None);
+                if let Err(e) = revoke_sessions(id).await {
+                    session_error.set(Some(format!("Failed to revoke: {}", e)));
+                }
+                match fetch_sessions().await {
+                    Ok(list) => sessions.set(list),
+                    Err(e) => session_error.set(Some(format!("Failed to load sessions: {}", e))),
+                }
+            });
+        })
+    };
+
     // Form input callbacks
     let on_online_threshold_change = {
         let online_threshold = online_threshold.clone();
@@ -388,6 +427,67 @@ pub fn settings() -> Html {
                             </div>
                         </section>
 
+                        // Login sessions
+                        <section class="settings-section">
+                            <h2>{"Sessions"}</h2>
+                            <p class="section-description">
+                                {"Devices and browsers logged in to your account. Revoke any you do not recognise."}
+                            </p>
+
+                            if let Some(err) = &*session_error {
+                                <div class="error-message">
+                                    <span class="error-icon">{"⚠"}</span>
+                                    <span>{err}</span>
+                                </div>
+                            }
+
+                            <div class="session-list">
+                                { for sessions.iter().map(|session| {
+                                    let id = session.id;
+                                    let on_revoke = {
+                                        let on_revoke_session = on_revoke_session.clone();
+                                        Callback::from(move |_| on_revoke_session.emit(Some(id)))
+                                    };
+                                    html! {
+                                        <div class="session-item">
+                                            <div class="session-details">
+                                                <span class="session-client">
+                                                    { session.user_agent.clone().unwrap_or_else(|| "Unknown client".to_string()) }
+                                                    if session.current {
+                                                        <span class="session-current">{"This session"}</span>
+                                                    }
+                                                </span>
+                                                <span class="session-meta">
+                                                    {format!(
+                                                        "Signed in {} · last used {} · expires {}",
+                                                        session.created_at,
+                                                        session.last_used_at.clone().unwrap_or_else(|| "never".to_string()),
+                                                        session.expires_at
+                                                    )}
+                                                </span>
+                                            </div>
+                                            if !session.current {
+                                                <button class="form-button secondary" onclick={on_revoke}>{"Revoke"}</button>
+                                            }
+                                        </div>
+                                    }
+                                }) }
+                            </div>
+
+                            <div class="form-actions">
+                                <button
+                                    class="form-button"
+                                    onclick={
+                                        let on_revoke_session = on_revoke_session.clone();
+                                        Callback::from(move |_| on_revoke_session.emit(None))
+                                    }
+                                    disabled={sessions.iter().all(|session| session.current)}
+                                >
+                                    {"Sign out all other sessions"}
+                                </button>
+                            </div>
+                        </section>
+
                         // Additional Settings Sections (placeholder for future features)
                         <section class="settings-section">
                             <h2>{"Additional Settings"}</h2>
@@ -482,3 +582,45 @@ async fn update_config_data(online: u64, warning: u64, stale: u64) -> Result<(),
         Err(error_text)
     }
 }
+
+async fn fetch_sessions() -> Result<Vec<SessionData>, String> {
+    let token = LocalStorage::get::<String>("pulson_token")
+        .map_err(|_| "No authentication token found".to_string())?;
+
+    let request = Request::get("/api/account/sessions")
+        .header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+
+    if request.status() == 200 {
+        request
+            .json::<Vec<SessionData>>()
+            .await
+            .map_err(|e| format!("Failed to parse response: {}", e))
+    } else {
+        Err(format!("Server error: {}", request.status()))
+    }
+}
+
+/// Revoke one session, or every session but the current one when `id` is `None`
+async fn revoke_sessions(id: Option<i64>) -> Result<(), String> {
+    let token = LocalStorage::get::<String>("pulson_token")
+        .map_err(|_| "No authentication token found".to_string())?;
+
+    let url = match id {
+        Some(id) => format!("/api/account/sessions/{}", id),
+        None => "/api/account/sessions".to_string(),
+    };
+    let request = Request::delete(&url)
+        .header("Authorization", &format!("Bearer {}", token))
+        .send()
+        .await
+        .map_err(|e| format!("Network error: {}", e))?;
+
+    if request.status() == 200 {
+        Ok(())
+    } else {
+        Err(format!("Server error: {}", request.status()))
+    }
+}
@@ -6,6 +6,7 @@ pub enum StatusFilter {
     Online,
     Warning,
     Offline,
+    Maintenance,
     Active,
     Recent,
     Stale,
@@ -28,6 +29,20 @@ pub enum SortBy {
     PingCount,
 }
 
+#[derive(Clone, ValueEnum)]
+pub enum AlertWhen {
+    /// The device goes offline
+    Offline,
+    /// The topic goes stale or inactive
+    Stale,
+    /// A sensor reading leaves --min/--max
+    Range,
+    /// A trigger flips (to --state, if given)
+    Trigger,
+    /// An event message matches --pattern
+    Event,
+}
+
 #[derive(Clone, ValueEnum)]
 pub enum DataType {
     Pulse,
@@ -46,6 +61,10 @@ pub struct Cli {
     #[arg(short = 'H', long, default_value = "127.0.0.1:3030", env = "PULSON_HOST")]
     pub host: String,
 
+    /// Act for an organization: its devices, alerts and settings instead of your own
+    #[arg(long, global = true, env = "PULSON_ORG")]
+    pub org: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -81,6 +100,15 @@ pub enum Commands {
         /// Enable saving images to database (disabled by default to save storage space)
         #[arg(long)]
         save_images: bool,
+        /// Maximum seconds a client-supplied pulse timestamp may lie in the future
+        #[arg(long, env = "PULSON_MAX_CLOCK_SKEW", default_value_t = 60)]
+        max_clock_skew: u64,
+        /// Also accept pulses over MQTT on this address (e.g. 0.0.0.0:1883)
+        #[arg(long, value_name = "ADDR", env = "PULSON_MQTT_BIND")]
+        mqtt_bind: Option<std::net::SocketAddr>,
+        /// Also accept fire-and-forget pulse datagrams on this UDP address (e.g. 0.0.0.0:7070)
+        #[arg(long, value_name = "ADDR", env = "PULSON_UDP_BIND")]
+        udp_bind: Option<std::net::SocketAddr>,
     },
 
     /// Device management (list, delete)
@@ -92,11 +120,11 @@ pub enum Commands {
     /// Send a pulse with specific data type
     Pulse {
         /// Device identifier
-        #[arg(short = 'd', long)]
-        device_id: String,
+        #[arg(short = 'd', long, required_unless_present = "batch")]
+        device_id: Option<String>,
         /// Topic for the pulse
-        #[arg(short = 't', long)]
-        topic: String,
+        #[arg(short = 't', long, required_unless_present = "batch")]
+        topic: Option<String>,
         /// Data type to send (default: pulse)
         #[arg(long, default_value = "pulse")]
         data_type: DataType,
@@ -142,6 +170,12 @@ pub enum Commands {
         /// Number of channels for image data (default: 3 for RGB)
         #[arg(long)]
         channels: Option<u32>,
+        /// When the reading was taken: RFC3339 or Unix epoch seconds (default: now)
+        #[arg(long)]
+        timestamp: Option<String>,
+        /// Send a batch of records from a JSON file (an array, or one object per line)
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["device_id", "topic", "data", "timestamp"])]
+        batch: Option<String>,
     },
 
     /// User account management (register, login, logout, delete, list)
@@ -155,6 +189,36 @@ pub enum Commands {
         #[command(subcommand)]
         action: ConfigAction,
     },
+
+    /// Alert rules and alert history
+    Alert {
+        #[command(subcommand)]
+        action: AlertAction,
+    },
+
+    /// Maintenance windows and silences
+    Maintenance {
+        #[command(subcommand)]
+        action: MaintenanceAction,
+    },
+
+    /// Scoped API keys for devices and scripts
+    Key {
+        #[command(subcommand)]
+        action: KeyAction,
+    },
+
+    /// Server administration: user roles (admins only)
+    Admin {
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+
+    /// Organizations that share devices between their members
+    Org {
+        #[command(subcommand)]
+        action: OrgAction,
+    },
 }
 
 #[derive(Subcommand)]
@@ -199,6 +263,51 @@ pub enum DeviceAction {
         #[arg(value_name = "DEVICE_ID")]
         device_id: String,
     },
+    /// Show a device's pulse counts per bucket, or per-topic statistics, over a time window
+    History {
+        #[arg(value_name = "DEVICE_ID")]
+        device_id: String,
+        /// Only this topic
+        #[arg(short, long)]
+        topic: Option<String>,
+        /// Window start: RFC3339 or relative like -6h (default: -1d)
+        #[arg(long, allow_hyphen_values = true)]
+        from: Option<String>,
+        /// Window end: RFC3339, relative like -1h, or now (default: now)
+        #[arg(long, allow_hyphen_values = true)]
+        to: Option<String>,
+        /// Bucket size, e.g. 10s, 5m or 1h (default: picked from the window)
+        #[arg(short, long)]
+        bucket: Option<String>,
+        /// Show per-topic statistics instead of counts per bucket
+        #[arg(long, conflicts_with = "topic")]
+        stats: bool,
+    },
+    /// Show a device's (or topic's) availability, outages and time between failures over a time window
+    Uptime {
+        #[arg(value_name = "DEVICE_ID")]
+        device_id: String,
+        /// A topic instead of the device as a whole
+        #[arg(short, long)]
+        topic: Option<String>,
+        /// Window start: RFC3339 or relative like -7d (default: -1d)
+        #[arg(long, allow_hyphen_values = true)]
+        from: Option<String>,
+        /// Window end: RFC3339, relative like -1h, or now (default: now)
+        #[arg(long, allow_hyphen_values = true)]
+        to: Option<String>,
+        /// Also list every status change in the window
+        #[arg(long)]
+        transitions: bool,
+    },
+    /// Create (or rotate) the MQTT/UDP secret of a single device
+    Credentials {
+        #[arg(value_name = "DEVICE_ID")]
+        device_id: String,
+        /// Revoke the device's credentials instead
+        #[arg(long)]
+        revoke: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -224,6 +333,17 @@ pub enum AccountAction {
         username: String,
     },
     List,
+    /// List your login sessions, or end them
+    Sessions {
+        /// End the session with this ID
+        #[arg(long, value_name = "ID", conflicts_with = "revoke_all")]
+        revoke: Option<i64>,
+        /// End every session except this one
+        #[arg(long)]
+        revoke_all: bool,
+    },
+    /// Trade the saved refresh token for a new token
+    Refresh,
 }
 
 #[derive(Subcommand)]
@@ -242,6 +362,283 @@ pub enum ConfigAction {
         #[arg(long)]
         stale_threshold: Option<u64>,
     },
+    /// Manage how long data is kept before it is pruned
+    Retention {
+        #[command(subcommand)]
+        action: RetentionAction,
+    },
+    /// Manage thresholds of single devices and topics
+    Thresholds {
+        #[command(subcommand)]
+        action: ThresholdsAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ThresholdsAction {
+    /// Give a device, a topic or a topic of one device its own thresholds; those
+    /// left out are taken from your configuration
+    Set {
+        /// Only this device
+        #[arg(long)]
+        device: Option<String>,
+        /// Only this topic
+        #[arg(long)]
+        topic: Option<String>,
+        /// Online threshold in seconds
+        #[arg(long)]
+        online_threshold: Option<u64>,
+        /// Warning threshold in seconds
+        #[arg(long)]
+        warning_threshold: Option<u64>,
+        /// Stale threshold in seconds
+        #[arg(long)]
+        stale_threshold: Option<u64>,
+        /// Derive the thresholds from how often the device or topic publishes (needs --device)
+        #[arg(long, conflicts_with_all = ["online_threshold", "warning_threshold", "stale_threshold"])]
+        learn: bool,
+    },
+    /// Remove thresholds by their ID (see `pulson config show`)
+    Remove {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RetentionAction {
+    /// Keep matching data for KEEP (e.g. 24h, 30d, 1y); without filters the rule covers all your data
+    Set {
+        #[arg(value_name = "KEEP")]
+        keep: String,
+        /// Only data of this device
+        #[arg(long)]
+        device: Option<String>,
+        /// Only data of this topic
+        #[arg(long)]
+        topic: Option<String>,
+        /// Only data of this type
+        #[arg(long = "type", value_enum)]
+        data_type: Option<DataType>,
+        /// Keep the minute, hour and day rollups this long instead of the raw data
+        #[arg(long, conflicts_with = "data_type")]
+        rollups: bool,
+    },
+    /// Remove a retention rule by its ID (see `pulson config show`)
+    Remove {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AlertAction {
+    /// List your alert rules
+    List,
+    /// Add an alert rule; without --device or --topic it covers all of them
+    Add {
+        #[arg(value_name = "NAME")]
+        name: String,
+        /// What makes the rule fire
+        #[arg(long, value_enum)]
+        when: AlertWhen,
+        /// Only this device
+        #[arg(short, long)]
+        device: Option<String>,
+        /// Only this topic
+        #[arg(short, long)]
+        topic: Option<String>,
+        /// Lowest accepted sensor value (range)
+        #[arg(long, allow_hyphen_values = true)]
+        min: Option<f64>,
+        /// Highest accepted sensor value (range)
+        #[arg(long, allow_hyphen_values = true)]
+        max: Option<f64>,
+        /// Fire while the trigger is in this state instead of on every flip (trigger)
+        #[arg(long)]
+        state: Option<bool>,
+        /// Message pattern, `*` matches any text, case-insensitive (event)
+        #[arg(long)]
+        pattern: Option<String>,
+    },
+    /// Remove an alert rule by its ID
+    Remove {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+    /// Turn an alert rule back on
+    Enable {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+    /// Turn an alert rule off without removing it
+    Disable {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+    /// Show recent alerts, newest first
+    History {
+        /// Only alerts that are still firing
+        #[arg(long)]
+        firing: bool,
+        /// How many alerts to show
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MaintenanceAction {
+    /// List current and upcoming windows
+    List {
+        /// Also list windows that are over
+        #[arg(long)]
+        all: bool,
+    },
+    /// Schedule a window; devices in it show as Maintenance and send no notifications
+    Add {
+        /// Device id or glob such as 'robot-*'
+        #[arg(short, long)]
+        device: Option<String>,
+        /// Only this topic
+        #[arg(short, long)]
+        topic: Option<String>,
+        /// Why the devices are down
+        #[arg(short, long)]
+        reason: String,
+        /// Start: RFC3339 or relative like -10m (default: now)
+        #[arg(long, allow_hyphen_values = true)]
+        from: Option<String>,
+        /// End: RFC3339
+        #[arg(long, conflicts_with = "duration", required_unless_present = "duration")]
+        until: Option<String>,
+        /// Length of the window, e.g. 2h
+        #[arg(long = "for", value_name = "DURATION")]
+        duration: Option<String>,
+        /// Only hold back notifications, leaving statuses as they are
+        #[arg(long)]
+        silence: bool,
+    },
+    /// Remove a window by its ID, ending it right away
+    Remove {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeyAction {
+    /// List your API keys
+    List,
+    /// Create an API key; it is printed once and cannot be shown again
+    Create {
+        #[arg(value_name = "NAME")]
+        name: String,
+        /// What the key may do: read, pulse:write, devices:write or config:write (repeatable)
+        #[arg(short, long = "scope", value_name = "SCOPE", required = true)]
+        scopes: Vec<String>,
+        /// Limit the key to one device id, or a prefix such as 'robot-*'
+        #[arg(short, long)]
+        device: Option<String>,
+        /// Let the key expire after this long, e.g. 90d
+        #[arg(long, value_name = "DURATION")]
+        expires: Option<String>,
+    },
+    /// Revoke an API key by its ID
+    Revoke {
+        #[arg(value_name = "ID")]
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AdminAction {
+    /// List the roles and what each may do
+    Roles,
+    /// Give a user a role: viewer, device-writer, operator or admin
+    Role {
+        #[arg(value_name = "USERNAME")]
+        username: String,
+        #[arg(value_name = "ROLE")]
+        role: String,
+    },
+    /// Show the audit log of changes made through the API, newest first
+    Audit {
+        /// Only calls made by this user
+        #[arg(long)]
+        actor: Option<String>,
+        /// Only this action, or a group such as `device` or `user`
+        #[arg(long)]
+        action: Option<String>,
+        /// Only calls about this device, user or other target
+        #[arg(long)]
+        target: Option<String>,
+        /// Only calls acting for this organization
+        #[arg(long = "for-org", value_name = "ORG")]
+        for_org: Option<String>,
+        /// Only calls that ended this way: success, denied or failure
+        #[arg(long)]
+        result: Option<String>,
+        /// Only calls from this address
+        #[arg(long)]
+        ip: Option<String>,
+        /// Start of the period: RFC3339 or relative like -24h
+        #[arg(long, allow_hyphen_values = true)]
+        from: Option<String>,
+        /// End of the period: RFC3339, relative like -1h, or now (default: now)
+        #[arg(long, allow_hyphen_values = true)]
+        to: Option<String>,
+        /// Most entries to show
+        #[arg(short, long, default_value_t = 50)]
+        limit: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum OrgAction {
+    /// List your organizations and your role in each
+    List,
+    /// Create an organization; you become its admin
+    Create {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+    /// Delete an organization that has no devices left
+    Delete {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+    /// List the members of an organization
+    Members {
+        #[arg(value_name = "ORG")]
+        org: String,
+    },
+    /// Add a member or change their role: viewer, device-writer, operator or admin
+    Add {
+        #[arg(value_name = "ORG")]
+        org: String,
+        #[arg(value_name = "USERNAME")]
+        username: String,
+        #[arg(value_name = "ROLE", default_value = "operator")]
+        role: String,
+    },
+    /// Remove a member; remove yourself to leave
+    Remove {
+        #[arg(value_name = "ORG")]
+        org: String,
+        #[arg(value_name = "USERNAME")]
+        username: String,
+    },
+    /// Move your own devices, with their history, into an organization
+    Move {
+        #[arg(value_name = "ORG")]
+        org: String,
+        #[arg(value_name = "DEVICE_ID", required_unless_present = "all")]
+        devices: Vec<String>,
+        /// Move all of your devices
+        #[arg(long, conflicts_with = "devices")]
+        all: bool,
+    },
 }
 
 impl Cli {
@@ -21,11 +21,65 @@ fn token_file() -> io::Result<std::path::PathBuf> {
     Ok(dir.join("token"))
 }
 
+/// The saved login token, or an API key given in `PULSON_TOKEN`
 pub fn read_token() -> io::Result<String> {
+    if let Ok(token) = std::env::var("PULSON_TOKEN") {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
     let p = token_file()?;
     fs::read_to_string(p).map(|s| s.trim().to_string())
 }
 
+fn session_file() -> io::Result<std::path::PathBuf> {
+    Ok(token_file()?.with_file_name("session"))
+}
+
+/// Save the token of a login or refresh, and the refresh token and expiry next to it
+fn save_session(session: &Value) -> anyhow::Result<()> {
+    let token = session["token"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("no token in response"))?;
+    fs::write(token_file()?, token)?;
+    let saved = serde_json::json!({
+        "refresh_token": session["refresh_token"],
+        "expires_at": session["expires_at"],
+    });
+    fs::write(session_file()?, saved.to_string())?;
+    Ok(())
+}
+
+/// The saved token, refreshed first if it has expired since it was saved. Use
+/// pushes the expiry back on the server, so a token past its saved expiry may
+/// still be good; it is kept if the refresh fails.
+pub async fn fresh_token(base_url: Option<String>, host: &str, port: u16) -> io::Result<String> {
+    let token = read_token()?;
+    if std::env::var("PULSON_TOKEN").is_ok_and(|t| !t.trim().is_empty()) {
+        return Ok(token);
+    }
+    let Some(saved) = fs::read_to_string(session_file()?).ok().and_then(|s| serde_json::from_str::<Value>(&s).ok()) else {
+        return Ok(token);
+    };
+    let expired = saved["expires_at"]
+        .as_str()
+        .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+        .is_some_and(|at| at <= chrono::Utc::now());
+    let Some(refresh_token) = saved["refresh_token"].as_str().filter(|_| expired) else {
+        return Ok(token);
+    };
+
+    let url = build_api_url(base_url.as_deref(), host, port, "/api/account/refresh");
+    let body = serde_json::json!({ "refresh_token": refresh_token });
+    match Client::new().post(&url).json(&body).send().await {
+        Ok(resp) if resp.status().is_success() => match resp.json::<Value>().await {
+            Ok(session) if save_session(&session).is_ok() => read_token(),
+            _ => Ok(token),
+        },
+        _ => Ok(token),
+    }
+}
+
 pub async fn register(
     base_url: Option<String>,
     host: String,
@@ -62,15 +116,16 @@ pub async fn login(
         password: &password,
         rootpass: None,
     };
-    let resp = Client::new().post(&url).json(&payload).send().await?;
+    let resp = Client::new()
+        .post(&url)
+        .header(reqwest::header::USER_AGENT, concat!("pulson-cli/", env!("CARGO_PKG_VERSION")))
+        .json(&payload)
+        .send()
+        .await?;
 
     if resp.status().is_success() {
         let json: Value = resp.json().await?;
-        let tok = json["token"]
-            .as_str()
-            .ok_or_else(|| anyhow::anyhow!("no token in response"))?
-            .to_string();
-        fs::write(token_file()?, &tok)?;
+        save_session(&json)?;
         println!("✓ Logged in");
     } else {
         eprintln!("✗ Login failed: {}", resp.text().await?);
@@ -100,6 +155,7 @@ pub async fn logout(
         .await?;
 
     if resp.status().is_success() {
+        let _ = fs::remove_file(session_file()?);
         match fs::remove_file(token_file()?) {
             Ok(_) => println!("✓ Logged out successfully and local token cleared."),
             Err(e) => {
@@ -187,4 +243,88 @@ pub async fn list_users(
         println!("{:<20} {}", name, role);
     }
     Ok(())
-}
\ No newline at end of file
+}
+/// Trade the saved refresh token for a new token
+pub async fn refresh(base_url: Option<String>, host: String, port: u16) -> anyhow::Result<()> {
+    let saved: Value = match fs::read_to_string(session_file()?).map(|s| serde_json::from_str(&s)) {
+        Ok(Ok(saved)) => saved,
+        _ => {
+            eprintln!("✗ No refresh token saved: please run `pulson account login`");
+            return Ok(());
+        }
+    };
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/account/refresh");
+    let body = serde_json::json!({ "refresh_token": saved["refresh_token"] });
+    let resp = Client::new().post(&url).json(&body).send().await?;
+    if resp.status().is_success() {
+        let session: Value = resp.json().await?;
+        save_session(&session)?;
+        println!("✓ Token refreshed, valid until {}", session["expires_at"].as_str().unwrap_or("-"));
+    } else {
+        eprintln!("✗ Refresh failed: {}", resp.text().await?);
+    }
+    Ok(())
+}
+
+/// List the caller's sessions, or end one or all others
+pub async fn sessions(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    revoke: Option<i64>,
+    revoke_all: bool,
+) -> anyhow::Result<()> {
+    let token = match read_token() {
+        Ok(t) => t,
+        Err(_) => {
+            eprintln!("✗ Not logged in");
+            return Ok(());
+        }
+    };
+    let client = Client::new();
+
+    if let Some(id) = revoke {
+        let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/account/sessions/{}", id));
+        let resp = client.delete(&url).bearer_auth(&token).send().await?;
+        if resp.status().is_success() {
+            println!("✓ Session {} revoked", id);
+        } else {
+            eprintln!("✗ Revoke failed: {}", resp.text().await?);
+        }
+        return Ok(());
+    }
+
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/account/sessions");
+    if revoke_all {
+        let resp = client.delete(&url).bearer_auth(&token).send().await?;
+        if resp.status().is_success() {
+            let body: Value = resp.json().await?;
+            println!("✓ Revoked {} other sessions", body["revoked"]);
+        } else {
+            eprintln!("✗ Revoke failed: {}", resp.text().await?);
+        }
+        return Ok(());
+    }
+
+    let resp = client.get(&url).bearer_auth(&token).send().await?;
+    if !resp.status().is_success() {
+        eprintln!("✗ Failed: HTTP {}", resp.status());
+        return Ok(());
+    }
+    let sessions: Vec<Value> = resp.json().await?;
+    println!("{:<6} {:<22} {:<22} {:<22} CLIENT", "ID", "CREATED", "LAST USED", "EXPIRES");
+    for session in sessions {
+        let text = |field: &str| session[field].as_str().unwrap_or("-").to_string();
+        let marker = if session["current"].as_bool().unwrap_or(false) { " (this session)" } else { "" };
+        println!(
+            "{:<6} {:<22} {:<22} {:<22} {}{}",
+            session["id"].to_string(),
+            text("created_at"),
+            text("last_used_at"),
+            text("expires_at"),
+            text("user_agent"),
+            marker
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use crate::logic::client::url_utils::build_api_url;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+pub async fn roles(base_url: Option<String>, host: String, port: u16, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/admin/roles");
+    let response = Client::new().get(&url).bearer_auth(&token).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to list roles: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let roles: Vec<Value> = response.json().await?;
+    println!("{:<15} SCOPES", "ROLE");
+    for role in &roles {
+        let mut scopes: Vec<&str> = role["scopes"]
+            .as_array()
+            .map(|scopes| scopes.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        if role["admin"].as_bool().unwrap_or(false) {
+            scopes.push("+ users and roles");
+        }
+        println!("{:<15} {}", role["role"].as_str().unwrap_or("-"), scopes.join(" "));
+    }
+    Ok(())
+}
+
+pub async fn set_role(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    username: String,
+    role: String,
+    token: String,
+) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/admin/users/{}/role", username));
+    let response = Client::new().put(&url).bearer_auth(&token).json(&json!({ "role": role })).send().await?;
+    if response.status().is_success() {
+        println!("✓ `{}` is now {}", username, role);
+    } else {
+        eprintln!("✗ Failed to set the role of `{}`: {}", username, error_message(response).await);
+    }
+    Ok(())
+}
+
+/// Filters of `pulson admin audit`
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub org: Option<String>,
+    pub result: Option<String>,
+    pub ip: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: usize,
+}
+
+pub async fn audit(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    query: AuditQuery,
+    token: String,
+) -> anyhow::Result<()> {
+    let mut params: Vec<(&str, String)> = vec![("limit", query.limit.to_string())];
+    let filters = [
+        ("actor", query.actor),
+        ("action", query.action),
+        ("target", query.target),
+        ("org", query.org),
+        ("result", query.result),
+        ("ip", query.ip),
+        ("from", query.from),
+        ("to", query.to),
+    ];
+    params.extend(filters.into_iter().filter_map(|(name, value)| value.map(|value| (name, value))));
+
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/admin/audit");
+    let response = Client::new().get(&url).bearer_auth(&token).query(&params).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to read the audit log: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let entries: Vec<Value> = response.json().await?;
+    if entries.is_empty() {
+        println!("No matching audit entries.");
+        return Ok(());
+    }
+    println!("{:<21} {:<15} {:<26} {:<20} {:<16} RESULT", "AT", "ACTOR", "ACTION", "TARGET", "SOURCE IP");
+    for entry in &entries {
+        let text = |field: &str| entry[field].as_str().unwrap_or("-").to_string();
+        let mut actor = text("actor");
+        if let Some(key) = entry["api_key_id"].as_i64() {
+            actor = format!("{} (key {})", actor, key);
+        }
+        let mut target = text("target");
+        if let Some(org) = entry["org"].as_str() {
+            target = format!("{} @{}", target, org);
+        }
+        let mut result = format!("{} ({})", text("result"), entry["status"]);
+        if let Some(repeats) = entry["repeats"].as_u64().filter(|repeats| *repeats > 0) {
+            result = format!("{} x{} more", result, repeats);
+        }
+        println!(
+            "{:<21} {:<15} {:<26} {:<20} {:<16} {}",
+            text("at"),
+            actor,
+            text("action"),
+            target,
+            text("source_ip"),
+            result
+        );
+    }
+    Ok(())
+}
+
+async fn error_message(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.json::<Value>().await {
+        Ok(body) if body["error"].is_string() => body["error"].as_str().unwrap_or_default().to_string(),
+        _ => status.to_string(),
+    }
+}
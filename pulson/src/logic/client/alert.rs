@@ -0,0 +1,185 @@
+use crate::cli::AlertWhen;
+use crate::logic::client::url_utils::{api_client, build_api_url};
+use serde_json::{json, Value};
+
+/// An alert rule as given on the command line
+pub struct NewRule {
+    pub name: String,
+    pub when: AlertWhen,
+    pub device: Option<String>,
+    pub topic: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub state: Option<bool>,
+    pub pattern: Option<String>,
+}
+
+pub async fn list(base_url: Option<String>, host: String, port: u16, token: String) -> anyhow::Result<()> {
+    let Some(rules) = fetch_rules(base_url.as_deref(), &host, port, &token).await? else {
+        return Ok(());
+    };
+
+    if rules.is_empty() {
+        println!("No alert rules. Add one with `pulson alert add`.");
+        return Ok(());
+    }
+    for rule in &rules {
+        let scope = |field: &str| rule[field].as_str().unwrap_or("*").to_string();
+        println!(
+            "[{}] {}{}: {} (device {}, topic {})",
+            rule["id"],
+            rule["name"].as_str().unwrap_or_default(),
+            if rule["enabled"].as_bool().unwrap_or(true) { "" } else { " (disabled)" },
+            describe_condition(&rule["condition"]),
+            scope("device_id"),
+            scope("topic"),
+        );
+    }
+    Ok(())
+}
+
+pub async fn add(base_url: Option<String>, host: String, port: u16, rule: NewRule, token: String) -> anyhow::Result<()> {
+    let condition = match rule.when {
+        AlertWhen::Offline => json!({ "type": "device_offline" }),
+        AlertWhen::Stale => json!({ "type": "topic_stale" }),
+        AlertWhen::Range => json!({ "type": "sensor_range", "min": rule.min, "max": rule.max }),
+        AlertWhen::Trigger => json!({ "type": "trigger_flip", "to": rule.state }),
+        AlertWhen::Event => json!({ "type": "event_match", "pattern": rule.pattern.unwrap_or_default() }),
+    };
+    let body = json!({
+        "name": rule.name,
+        "device_id": rule.device,
+        "topic": rule.topic,
+        "condition": condition,
+    });
+
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/alerts/rules");
+    let response = api_client().post(&url).bearer_auth(&token).json(&body).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to add alert rule: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let created: Value = response.json().await?;
+    println!("✓ Alert rule {} added: {}", created["id"], describe_condition(&created["condition"]));
+    Ok(())
+}
+
+pub async fn remove(base_url: Option<String>, host: String, port: u16, id: i64, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/alerts/rules/{}", id));
+    let response = api_client().delete(&url).bearer_auth(&token).send().await?;
+    if response.status().is_success() {
+        println!("✓ Alert rule {} removed.", id);
+    } else {
+        eprintln!("✗ Failed to remove alert rule {}: {}", id, error_message(response).await);
+    }
+    Ok(())
+}
+
+/// Turn a rule on or off, keeping the rest of it
+pub async fn set_enabled(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    id: i64,
+    enabled: bool,
+    token: String,
+) -> anyhow::Result<()> {
+    let Some(rules) = fetch_rules(base_url.as_deref(), &host, port, &token).await? else {
+        return Ok(());
+    };
+    let Some(mut rule) = rules.into_iter().find(|rule| rule["id"] == id) else {
+        eprintln!("✗ Alert rule {} not found.", id);
+        return Ok(());
+    };
+    rule["enabled"] = json!(enabled);
+
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/alerts/rules/{}", id));
+    let response = api_client().put(&url).bearer_auth(&token).json(&rule).send().await?;
+    if response.status().is_success() {
+        println!("✓ Alert rule {} {}.", id, if enabled { "enabled" } else { "disabled" });
+    } else {
+        eprintln!("✗ Failed to update alert rule {}: {}", id, error_message(response).await);
+    }
+    Ok(())
+}
+
+pub async fn history(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    firing: bool,
+    limit: usize,
+    token: String,
+) -> anyhow::Result<()> {
+    let mut path = format!("/api/alerts?limit={}", limit);
+    if firing {
+        path.push_str("&state=firing");
+    }
+    let url = build_api_url(base_url.as_deref(), &host, port, &path);
+    let response = api_client().get(&url).bearer_auth(&token).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to fetch alerts: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let alerts: Vec<Value> = response.json().await?;
+    if alerts.is_empty() {
+        println!("No alerts.");
+        return Ok(());
+    }
+    for alert in &alerts {
+        let state = match alert["resolved_at"].as_str() {
+            Some(resolved_at) => format!("resolved {}", resolved_at),
+            None => "FIRING".to_string(),
+        };
+        println!(
+            "{}  {}  {}: {} [{}]",
+            alert["fired_at"].as_str().unwrap_or_default(),
+            alert["rule_name"].as_str().unwrap_or_default(),
+            alert["device_id"].as_str().unwrap_or_default(),
+            alert["message"].as_str().unwrap_or_default(),
+            state
+        );
+    }
+    Ok(())
+}
+
+/// The caller's rules, or `None` after reporting why they could not be fetched
+async fn fetch_rules(base_url: Option<&str>, host: &str, port: u16, token: &str) -> anyhow::Result<Option<Vec<Value>>> {
+    let url = build_api_url(base_url, host, port, "/api/alerts/rules");
+    let response = api_client().get(&url).bearer_auth(token).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to fetch alert rules: {}", error_message(response).await);
+        return Ok(None);
+    }
+    Ok(Some(response.json().await?))
+}
+
+/// The server's error message, or the status when there is none
+async fn error_message(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.json::<Value>().await {
+        Ok(body) if body["error"].is_string() => body["error"].as_str().unwrap_or_default().to_string(),
+        _ => status.to_string(),
+    }
+}
+
+fn describe_condition(condition: &Value) -> String {
+    let bound = |field: &str| condition[field].as_f64().map(|v| v.to_string());
+    match condition["type"].as_str().unwrap_or_default() {
+        "device_offline" => "device goes offline".to_string(),
+        "topic_stale" => "topic goes stale".to_string(),
+        "sensor_range" => format!(
+            "sensor outside {}..{}",
+            bound("min").unwrap_or_default(),
+            bound("max").unwrap_or_default()
+        ),
+        "trigger_flip" => match condition["to"].as_bool() {
+            Some(state) => format!("trigger is {}", state),
+            None => "trigger flips".to_string(),
+        },
+        "event_match" => format!("event matches '{}'", condition["pattern"].as_str().unwrap_or_default()),
+        other => other.to_string(),
+    }
+}
@@ -1,9 +1,9 @@
-use crate::logic::config::StatusConfig;
+use crate::logic::config::{format_duration, StatusConfig, ThresholdOverride};
 use crate::logic::client::account::read_token;
-use crate::logic::client::url_utils::build_api_url;
-use crate::cli::HostConfig;
+use crate::logic::client::url_utils::{api_client, build_api_url};
+use crate::cli::{DataType, HostConfig};
+use clap::ValueEnum;
 use colored::*;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -23,6 +23,33 @@ struct ConfigResponse {
     stale_threshold_seconds: u64,
 }
 
+#[derive(Deserialize)]
+struct RetentionRule {
+    id: i64,
+    device_id: Option<String>,
+    topic: Option<String>,
+    data_type: Option<String>,
+    keep_seconds: u64,
+}
+
+#[derive(Serialize)]
+struct RetentionRequest {
+    device_id: Option<String>,
+    topic: Option<String>,
+    data_type: Option<String>,
+    keep: String,
+}
+
+#[derive(Serialize)]
+struct ThresholdRequest {
+    device_id: Option<String>,
+    topic: Option<String>,
+    learn: bool,
+    online_threshold_seconds: Option<u64>,
+    warning_threshold_seconds: Option<u64>,
+    stale_threshold_seconds: Option<u64>,
+}
+
 #[derive(Serialize)]
 struct ConfigUpdateRequest {
     online_threshold_seconds: u64,
@@ -49,9 +76,201 @@ pub async fn show() -> anyhow::Result<()> {
         }
     }
 
+    if let Ok(overrides) = fetch_thresholds().await {
+        display_thresholds(&overrides);
+    }
+
+    if let Ok(rules) = fetch_retention_rules().await {
+        display_retention(&rules);
+    }
+
     Ok(())
 }
 
+fn display_retention(rules: &[RetentionRule]) {
+    println!();
+    println!("{}", "Data Retention:".bright_green().bold());
+    if rules.is_empty() {
+        println!("  All data is kept. Add a rule with 'pulson config retention set'.");
+        return;
+    }
+    for rule in rules {
+        let scope = |value: &Option<String>| value.clone().unwrap_or_else(|| "*".to_string());
+        println!(
+            "  {} device {} topic {} type {} {} {}",
+            format!("[{}]", rule.id).bright_white(),
+            scope(&rule.device_id).cyan(),
+            scope(&rule.topic).cyan(),
+            scope(&rule.data_type).cyan(),
+            "keep".yellow(),
+            format_duration(rule.keep_seconds).bright_white()
+        );
+    }
+}
+
+fn display_thresholds(overrides: &[ThresholdOverride]) {
+    println!();
+    println!("{}", "Device and Topic Thresholds:".bright_green().bold());
+    if overrides.is_empty() {
+        println!("  Every device and topic uses the thresholds above. Add some with 'pulson config thresholds set'.");
+        return;
+    }
+    for threshold in overrides {
+        let scope = |value: &Option<String>| value.clone().unwrap_or_else(|| "*".to_string());
+        let values = match (
+            threshold.online_threshold_seconds,
+            threshold.warning_threshold_seconds,
+            threshold.stale_threshold_seconds,
+        ) {
+            (Some(online), Some(warning), Some(stale)) => format!("{}s / {}s / {}s", online, warning, stale),
+            _ => "not enough data yet".to_string(),
+        };
+        let learned = match threshold.observed_interval_seconds {
+            Some(interval) if threshold.learned => format!(" (learned, every {:.2}s)", interval),
+            _ if threshold.learned => " (learned)".to_string(),
+            _ => String::new(),
+        };
+        println!(
+            "  {} device {} topic {} {}{}",
+            format!("[{}]", threshold.id).bright_white(),
+            scope(&threshold.device_id).cyan(),
+            scope(&threshold.topic).cyan(),
+            values.bright_white(),
+            learned.yellow()
+        );
+    }
+}
+
+/// Set the thresholds of a device or topic on the server
+pub async fn set_thresholds(
+    device: Option<String>,
+    topic: Option<String>,
+    thresholds: [Option<u64>; 3],
+    learn: bool,
+) -> anyhow::Result<()> {
+    let request = ThresholdRequest {
+        device_id: device,
+        topic,
+        learn,
+        online_threshold_seconds: thresholds[0],
+        warning_threshold_seconds: thresholds[1],
+        stale_threshold_seconds: thresholds[2],
+    };
+
+    let response = authorized_request(reqwest::Method::POST, "/api/user/thresholds")?
+        .json(&request)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        eprintln!("{} {} {}", "Failed to set thresholds:".red().bold(), status, error_text);
+        return Ok(());
+    }
+
+    let threshold: ThresholdOverride = response.json().await?;
+    display_thresholds(std::slice::from_ref(&threshold));
+    Ok(())
+}
+
+/// Remove the thresholds of a device or topic on the server
+pub async fn remove_thresholds(id: i64) -> anyhow::Result<()> {
+    let response = authorized_request(reqwest::Method::DELETE, &format!("/api/user/thresholds/{}", id))?
+        .send()
+        .await?;
+    if response.status().is_success() {
+        println!("{} {}", "Removed thresholds".green(), id);
+    } else {
+        eprintln!("{} {}", "Failed to remove thresholds:".red().bold(), response.status());
+    }
+    Ok(())
+}
+
+/// Fetch the user's device and topic thresholds from server
+async fn fetch_thresholds() -> anyhow::Result<Vec<ThresholdOverride>> {
+    let response = authorized_request(reqwest::Method::GET, "/api/user/thresholds")?
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Server returned status: {}", response.status()));
+    }
+    Ok(response.json().await?)
+}
+
+/// Add or update a retention rule on the server; with `rollups` the rule is
+/// for the minute, hour and day rollups instead of the raw data
+pub async fn set_retention(
+    keep: String,
+    device: Option<String>,
+    topic: Option<String>,
+    data_type: Option<DataType>,
+    rollups: bool,
+) -> anyhow::Result<()> {
+    let data_type = data_type
+        .and_then(|t| t.to_possible_value())
+        .map(|v| v.get_name().to_string());
+    let request = RetentionRequest {
+        device_id: device,
+        topic,
+        data_type: if rollups { Some("rollup".to_string()) } else { data_type },
+        keep,
+    };
+
+    let response = authorized_request(reqwest::Method::POST, "/api/user/retention")?
+        .json(&request)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        eprintln!("{} {} {}", "Failed to set retention rule:".red().bold(), status, error_text);
+        return Ok(());
+    }
+
+    let rule: RetentionRule = response.json().await?;
+    println!(
+        "{} {} (rule {})",
+        "Retention set to".green(),
+        format_duration(rule.keep_seconds).bright_white(),
+        rule.id
+    );
+    Ok(())
+}
+
+/// Remove a retention rule on the server
+pub async fn remove_retention(id: i64) -> anyhow::Result<()> {
+    let response = authorized_request(reqwest::Method::DELETE, &format!("/api/user/retention/{}", id))?
+        .send()
+        .await?;
+    if response.status().is_success() {
+        println!("{} {}", "Removed retention rule".green(), id);
+    } else {
+        eprintln!("{} {}", "Failed to remove retention rule:".red().bold(), response.status());
+    }
+    Ok(())
+}
+
+/// Fetch the user's retention rules from server
+async fn fetch_retention_rules() -> anyhow::Result<Vec<RetentionRule>> {
+    let response = authorized_request(reqwest::Method::GET, "/api/user/retention")?
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!("Server returned status: {}", response.status()));
+    }
+    Ok(response.json().await?)
+}
+
+/// Start a request to the server with the stored login token
+fn authorized_request(method: reqwest::Method, path: &str) -> anyhow::Result<reqwest::RequestBuilder> {
+    let token = read_token()
+        .map_err(|_| anyhow::anyhow!("Not logged in. Please run 'pulson account login' first."))?;
+
+    let host_config = get_host_config();
+    let url = build_api_url(host_config.base_url().as_deref(), &host_config.host, host_config.port, path);
+    Ok(api_client().request(method, &url).bearer_auth(&token))
+}
+
 fn display_config(config: &StatusConfig) {
     println!();
     println!("{}", "Current Thresholds:".bright_green().bold());
@@ -141,7 +360,7 @@ async fn fetch_user_config() -> anyhow::Result<StatusConfig> {
     let host_config = get_host_config();
     let url = build_api_url(host_config.base_url().as_deref(), &host_config.host, host_config.port, "/api/user/config");
     
-    let client = Client::new();
+    let client = api_client();
     let response = client
         .get(&url)
         .bearer_auth(&token)
@@ -158,6 +377,8 @@ async fn fetch_user_config() -> anyhow::Result<StatusConfig> {
         online_threshold_seconds: config_response.online_threshold_seconds,
         warning_threshold_seconds: config_response.warning_threshold_seconds,
         stale_threshold_seconds: config_response.stale_threshold_seconds,
+        overrides: Vec::new(),
+        maintenance: Vec::new(),
     })
 }
 
@@ -175,7 +396,7 @@ async fn update_user_config(config: &StatusConfig) -> anyhow::Result<()> {
         stale_threshold_seconds: config.stale_threshold_seconds,
     };
     
-    let client = Client::new();
+    let client = api_client();
     let response = client
         .post(&url)
         .bearer_auth(&token)
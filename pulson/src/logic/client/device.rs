@@ -1,12 +1,16 @@
-use reqwest::Client;
 use serde::Serialize;
-use crate::logic::client::url_utils::build_api_url;
+use crate::logic::client::url_utils::{api_client, build_api_url};
 
 #[derive(Serialize)]
 struct DeleteDeviceRequest {
     device_id: String,
 }
 
+/// URL of `POST /api/device/delete`
+fn delete_url(base_url: Option<&str>, host: &str, port: u16) -> String {
+    build_api_url(base_url, host, port, "/api/device/delete")
+}
+
 pub async fn delete(
     base_url: Option<String>,
     host: String,
@@ -14,8 +18,8 @@ pub async fn delete(
     device_id: String,
     token: String,
 ) -> anyhow::Result<()> {
-    let client = Client::new();
-    let url = build_api_url(base_url.as_deref(), &host, port, "/device/delete");
+    let client = api_client();
+    let url = delete_url(base_url.as_deref(), &host, port);
 
     let response = client
         .post(&url)
@@ -32,3 +36,249 @@ pub async fn delete(
 
     Ok(())
 }
+
+pub async fn credentials(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    device_id: String,
+    revoke: bool,
+    token: String,
+) -> anyhow::Result<()> {
+    let client = api_client();
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/devices/{}/credentials", device_id));
+
+    let request = if revoke { client.delete(&url) } else { client.post(&url) };
+    let response = request
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?;
+
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to update credentials of device '{}': {}", device_id, response.status());
+        return Ok(());
+    }
+
+    if revoke {
+        println!("✓ Credentials of device '{}' revoked.", device_id);
+    } else {
+        let body: serde_json::Value = response.json().await?;
+        println!("✓ Credentials for device '{}' (shown only once):", device_id);
+        println!("  username: {}", body["username"].as_str().unwrap_or_default());
+        println!("  password: {}", body["secret"].as_str().unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+/// Window and bucket of a `pulson device history` query
+pub struct HistoryQuery {
+    pub topic: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub bucket: Option<String>,
+    pub stats: bool,
+}
+
+pub async fn history(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    device_id: String,
+    query: HistoryQuery,
+    token: String,
+) -> anyhow::Result<()> {
+    let client = api_client();
+    let endpoint = if query.stats { "stats" } else { "history" };
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/devices/{}/{}", device_id, endpoint));
+
+    let params: Vec<(&str, &String)> = [
+        ("topic", query.topic.as_ref()),
+        ("from", query.from.as_ref()),
+        ("to", query.to.as_ref()),
+        ("bucket", query.bucket.as_ref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|value| (name, value)))
+    .collect();
+
+    let response = client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", token))
+        .query(&params)
+        .send()
+        .await?;
+
+    let status = response.status();
+    let body: serde_json::Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        match body["error"].as_str() {
+            Some(error) => eprintln!("✗ Failed to query device '{}': {}", device_id, error),
+            None => eprintln!("✗ Failed to query device '{}': {}", device_id, status),
+        }
+        return Ok(());
+    }
+
+    println!(
+        "{} → {} (source: {})",
+        body["start_time"].as_str().unwrap_or_default(),
+        body["end_time"].as_str().unwrap_or_default(),
+        body["source"].as_str().unwrap_or_default()
+    );
+
+    if query.stats {
+        let stats = body["stats"].as_array().cloned().unwrap_or_default();
+        if stats.is_empty() {
+            println!("No data in this window.");
+            return Ok(());
+        }
+        println!("{:<24} {:>8} {:>10} {:>10} {:>10} {:>10}", "TOPIC", "PULSES", "MIN", "MAX", "AVG", "LAST");
+        for stat in stats {
+            let value = |field: &str| stat[field].as_f64().map(|v| format!("{:.2}", v)).unwrap_or_else(|| "-".to_string());
+            println!(
+                "{:<24} {:>8} {:>10} {:>10} {:>10} {:>10}",
+                stat["topic"].as_str().unwrap_or_default(),
+                stat["total_pulses"].as_i64().unwrap_or_default(),
+                value("min"),
+                value("max"),
+                value("avg"),
+                value("last_value")
+            );
+        }
+        println!("Total: {} pulses", body["total_pulses"].as_i64().unwrap_or_default());
+    } else {
+        let buckets = body["data"].as_array().cloned().unwrap_or_default();
+        if buckets.is_empty() {
+            println!("No data in this window.");
+            return Ok(());
+        }
+        println!("Bucket: {}", body["bucket"].as_str().unwrap_or_default());
+        for bucket in buckets {
+            println!(
+                "{:<27} {:<24} {:>8}",
+                bucket["timestamp"].as_str().unwrap_or_default(),
+                bucket["topic"].as_str().or(query.topic.as_deref()).unwrap_or_default(),
+                bucket["pulse_count"].as_i64().unwrap_or_default()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub struct UptimeQuery {
+    pub topic: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub transitions: bool,
+}
+
+pub async fn uptime(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    device_id: String,
+    query: UptimeQuery,
+    token: String,
+) -> anyhow::Result<()> {
+    let client = api_client();
+    let params: Vec<(&str, &String)> = [
+        ("topic", query.topic.as_ref()),
+        ("from", query.from.as_ref()),
+        ("to", query.to.as_ref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| value.map(|value| (name, value)))
+    .collect();
+
+    let fetch = |endpoint: &str| {
+        let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/devices/{}/{}", device_id, endpoint));
+        client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", token))
+            .query(&params)
+            .send()
+    };
+
+    let response = fetch("uptime").await?;
+    let status = response.status();
+    let body: serde_json::Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        match body["error"].as_str() {
+            Some(error) => eprintln!("✗ Failed to query device '{}': {}", device_id, error),
+            None => eprintln!("✗ Failed to query device '{}': {}", device_id, status),
+        }
+        return Ok(());
+    }
+
+    let subject = match &query.topic {
+        Some(topic) => format!("{}/{}", device_id, topic),
+        None => device_id.clone(),
+    };
+    println!(
+        "{}  {} → {}",
+        subject,
+        body["from"].as_str().unwrap_or_default(),
+        body["to"].as_str().unwrap_or_default()
+    );
+    let Some(availability) = body["availability_percent"].as_f64() else {
+        println!("No status recorded in this window.");
+        return Ok(());
+    };
+    let span = |field: &str| body[field].as_f64().map(format_span).unwrap_or_else(|| "-".to_string());
+    println!("  Status:        {}", body["status"].as_str().unwrap_or("-"));
+    println!("  Availability:  {:.2}% of {} observed", availability, span("observed_seconds"));
+    println!("  Downtime:      {}", span("downtime_seconds"));
+    println!("  Outages:       {} (longest {})", body["outage_count"].as_u64().unwrap_or_default(), span("longest_outage_seconds"));
+    println!("  Longest up:    {}", span("longest_time_between_failures_seconds"));
+    println!("  MTBF:          {}", span("mean_time_between_failures_seconds"));
+
+    if query.transitions {
+        let body: serde_json::Value = fetch("transitions").await?.json().await.unwrap_or_default();
+        let transitions = body["transitions"].as_array().cloned().unwrap_or_default();
+        println!();
+        if transitions.is_empty() {
+            println!("No status changes in this window.");
+        }
+        for transition in transitions {
+            println!(
+                "{:<32} {:>8} → {}",
+                transition["at"].as_str().unwrap_or_default(),
+                transition["previous_status"].as_str().unwrap_or("new"),
+                transition["status"].as_str().unwrap_or_default()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Format seconds as the two largest units, e.g. `3h 12m`
+fn format_span(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let units = [(86_400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let parts: Vec<String> = units
+        .iter()
+        .scan(seconds, |rest, &(unit, suffix)| {
+            let count = *rest / unit;
+            *rest %= unit;
+            Some((count, suffix))
+        })
+        .skip_while(|(count, _)| *count == 0)
+        .take(2)
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, suffix)| format!("{}{}", count, suffix))
+        .collect();
+    if parts.is_empty() { "0s".to_string() } else { parts.join(" ") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_url() {
+        assert_eq!(delete_url(None, "127.0.0.1", 3030), "http://127.0.0.1:3030/api/device/delete");
+        assert_eq!(delete_url(Some("https://pulson.example.com/"), "127.0.0.1", 3030), "https://pulson.example.com/api/device/delete");
+    }
+}
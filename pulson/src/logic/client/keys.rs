@@ -0,0 +1,85 @@
+use crate::logic::client::url_utils::{api_client, build_api_url};
+use serde_json::{json, Value};
+
+/// An API key as given on the command line
+pub struct NewKey {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub device: Option<String>,
+    pub expires: Option<String>,
+}
+
+pub async fn list(base_url: Option<String>, host: String, port: u16, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/keys");
+    let response = api_client().get(&url).bearer_auth(&token).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to list API keys: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let keys: Vec<Value> = response.json().await?;
+    if keys.is_empty() {
+        println!("No API keys. Create one with `pulson key create`.");
+        return Ok(());
+    }
+    for key in &keys {
+        let text = |field: &str| key[field].as_str().unwrap_or("-").to_string();
+        println!("[{}] {} ({}…), {}", key["id"], text("name"), text("prefix"), describe(key));
+        println!("    created {}, last used {}, expires {}", text("created_at"), text("last_used_at"), text("expires_at"));
+    }
+    Ok(())
+}
+
+pub async fn create(base_url: Option<String>, host: String, port: u16, key: NewKey, token: String) -> anyhow::Result<()> {
+    let body = json!({
+        "name": key.name,
+        "scopes": key.scopes,
+        "device_id": key.device,
+        "expires_in": key.expires,
+    });
+
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/keys");
+    let response = api_client().post(&url).bearer_auth(&token).json(&body).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to create API key: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let created: Value = response.json().await?;
+    println!("✓ API key {} created, {}", created["id"], describe(&created));
+    println!("{}", created["key"].as_str().unwrap_or_default());
+    println!("Store it now: it cannot be shown again. Devices send it as a bearer token, or set PULSON_TOKEN for the CLI.");
+    Ok(())
+}
+
+pub async fn revoke(base_url: Option<String>, host: String, port: u16, id: i64, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/keys/{}", id));
+    let response = api_client().delete(&url).bearer_auth(&token).send().await?;
+    if response.status().is_success() {
+        println!("✓ API key {} revoked.", id);
+    } else {
+        eprintln!("✗ Failed to revoke API key {}: {}", id, error_message(response).await);
+    }
+    Ok(())
+}
+
+/// Scopes, device limit and organization of a key, e.g. `scopes pulse:write, device robot-*`
+fn describe(key: &Value) -> String {
+    let scopes: Vec<&str> = key["scopes"]
+        .as_array()
+        .map(|scopes| scopes.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    let described = format!("scopes {}, device {}", scopes.join(" "), key["device_id"].as_str().unwrap_or("*"));
+    match key["org"].as_str() {
+        Some(org) => format!("{}, org {}", described, org),
+        None => described,
+    }
+}
+
+async fn error_message(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.json::<Value>().await {
+        Ok(body) if body["error"].is_string() => body["error"].as_str().unwrap_or_default().to_string(),
+        _ => status.to_string(),
+    }
+}
@@ -1,9 +1,9 @@
 use crate::cli::{OutputFormat, SortBy, StatusFilter};
 use crate::logic::config::StatusConfig;
 use crate::logic::types::{DeviceInfo, TopicInfo, DeviceStatus, TopicStatus};
-use crate::logic::client::url_utils::build_api_url;
-use chrono::Utc;
+use crate::logic::client::url_utils::{api_client, build_api_url};
 use reqwest::Client;
+use chrono::Utc;
 use serde_json;
 use std::time::Duration;
 use tokio::time::sleep;
@@ -15,6 +15,7 @@ fn get_device_status_indicator(status: &DeviceStatus) -> String {
         DeviceStatus::Online => "●".green().to_string(),
         DeviceStatus::Warning => "●".yellow().to_string(),
         DeviceStatus::Offline => "●".red().to_string(),
+        DeviceStatus::Maintenance => "●".blue().to_string(),
     }
 }
 
@@ -51,11 +52,13 @@ fn sort_devices(devices: &mut Vec<DeviceInfo>, sort_by: &SortBy, _config: &Statu
                 DeviceStatus::Online => 0,
                 DeviceStatus::Warning => 1,
                 DeviceStatus::Offline => 2,
+                DeviceStatus::Maintenance => 3,
             };
             let b_priority = match b.status {
                 DeviceStatus::Online => 0,
                 DeviceStatus::Warning => 1,
                 DeviceStatus::Offline => 2,
+                DeviceStatus::Maintenance => 3,
             };
             a_priority.cmp(&b_priority)
         }),
@@ -95,6 +98,7 @@ fn filter_devices(devices: Vec<DeviceInfo>, status_filter: &Option<StatusFilter>
                 StatusFilter::Online => device.status == DeviceStatus::Online,
                 StatusFilter::Warning => device.status == DeviceStatus::Warning,
                 StatusFilter::Offline => device.status == DeviceStatus::Offline,
+                StatusFilter::Maintenance => device.status == DeviceStatus::Maintenance,
                 _ => true, // Topic-specific filters don't apply to devices
             }
         }).collect()
@@ -241,7 +245,7 @@ pub async fn run(
     extended: bool,
     config: StatusConfig,
 ) -> anyhow::Result<()> {
-    let client = Client::new();
+    let client = api_client();
 
     if watch {
         // Watch mode - continuously update
@@ -372,14 +376,19 @@ async fn run_single_fetch(
                             .iter()
                             .filter(|d| d.status == DeviceStatus::Offline)
                             .count();
+                        let maintenance = devices
+                            .iter()
+                            .filter(|d| d.status == DeviceStatus::Maintenance)
+                            .count();
                         
                         println!();
                         println!(
-                            "{} {} online, {} warning, {} offline | {} total devices",
+                            "{} {} online, {} warning, {} offline, {} in maintenance | {} total devices",
                             "Summary:".bright_white().bold(),
                             online.to_string().green(),
                             warning.to_string().yellow(),
                             offline.to_string().red(),
+                            maintenance.to_string().blue(),
                             devices.len().to_string().bright_blue()
                         );
                     }
@@ -0,0 +1,102 @@
+use crate::logic::client::url_utils::{api_client, build_api_url};
+use serde_json::{json, Value};
+
+/// A maintenance window as given on the command line
+pub struct NewWindow {
+    pub device: Option<String>,
+    pub topic: Option<String>,
+    pub reason: String,
+    pub from: Option<String>,
+    pub until: Option<String>,
+    pub duration: Option<String>,
+    pub silence: bool,
+}
+
+pub async fn list(base_url: Option<String>, host: String, port: u16, all: bool, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/maintenance");
+    let response = api_client().get(&url).bearer_auth(&token).query(&[("all", all)]).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to list maintenance windows: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let windows: Vec<Value> = response.json().await?;
+    if windows.is_empty() {
+        println!("No maintenance windows. Schedule one with `pulson maintenance add`.");
+        return Ok(());
+    }
+    let now = chrono::Utc::now();
+    for window in &windows {
+        let time = |field: &str| window[field].as_str().unwrap_or_default().to_string();
+        let parsed = |field: &str| chrono::DateTime::parse_from_rfc3339(&time(field)).map(|t| t.with_timezone(&chrono::Utc));
+        let state = if parsed("ends_at").is_ok_and(|end| end <= now) {
+            "over"
+        } else if parsed("starts_at").is_ok_and(|start| start <= now) {
+            "active"
+        } else {
+            "scheduled"
+        };
+        println!("[{}] {} {}, {}", window["id"], window["kind"].as_str().unwrap_or_default(), state, describe(window));
+        println!("    {} → {}", time("starts_at"), time("ends_at"));
+    }
+    Ok(())
+}
+
+pub async fn add(base_url: Option<String>, host: String, port: u16, window: NewWindow, token: String) -> anyhow::Result<()> {
+    let body = json!({
+        "device_id": window.device,
+        "topic": window.topic,
+        "kind": if window.silence { "silence" } else { "maintenance" },
+        "starts_at": window.from,
+        "ends_at": window.until,
+        "duration": window.duration,
+        "reason": window.reason,
+    });
+
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/maintenance");
+    let response = api_client().post(&url).bearer_auth(&token).json(&body).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to schedule maintenance: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let created: Value = response.json().await?;
+    println!(
+        "✓ {} window {} scheduled until {}, {}",
+        if window.silence { "Silence" } else { "Maintenance" },
+        created["id"],
+        created["ends_at"].as_str().unwrap_or_default(),
+        describe(&created)
+    );
+    Ok(())
+}
+
+pub async fn remove(base_url: Option<String>, host: String, port: u16, id: i64, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/maintenance/{}", id));
+    let response = api_client().delete(&url).bearer_auth(&token).send().await?;
+    if response.status().is_success() {
+        println!("✓ Maintenance window {} removed.", id);
+    } else {
+        eprintln!("✗ Failed to remove maintenance window {}: {}", id, error_message(response).await);
+    }
+    Ok(())
+}
+
+/// Scope and reason of a window, e.g. `device robot-*, topic *: battery swap`
+fn describe(window: &Value) -> String {
+    let scope = |field: &str| window[field].as_str().unwrap_or("*").to_string();
+    format!(
+        "device {}, topic {}: {}",
+        scope("device_id"),
+        scope("topic"),
+        window["reason"].as_str().unwrap_or_default()
+    )
+}
+
+async fn error_message(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.json::<Value>().await {
+        Ok(body) if body["error"].is_string() => body["error"].as_str().unwrap_or_default().to_string(),
+        _ => status.to_string(),
+    }
+}
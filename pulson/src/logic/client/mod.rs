@@ -1,6 +1,11 @@
 pub mod account;
+pub mod admin;
+pub mod alert;
 pub mod list;
+pub mod maintenance;
+pub mod orgs;
 pub mod pulse;
 pub mod device;
+pub mod keys;
 pub mod config;
 pub mod url_utils;
@@ -0,0 +1,135 @@
+use crate::logic::client::url_utils::build_api_url;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+pub async fn list(base_url: Option<String>, host: String, port: u16, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/orgs");
+    let response = Client::new().get(&url).bearer_auth(&token).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to list organizations: {}", error_message(response).await);
+        return Ok(());
+    }
+
+    let orgs: Vec<Value> = response.json().await?;
+    if orgs.is_empty() {
+        println!("Not a member of any organization. Create one with `pulson org create`.");
+        return Ok(());
+    }
+    println!("{:<25} {:<15} CREATED", "ORGANIZATION", "ROLE");
+    for org in &orgs {
+        let text = |field: &str| org[field].as_str().unwrap_or("-").to_string();
+        println!("{:<25} {:<15} {}", text("name"), text("role"), text("created_at"));
+    }
+    Ok(())
+}
+
+pub async fn create(base_url: Option<String>, host: String, port: u16, name: String, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/orgs");
+    let response = Client::new().post(&url).bearer_auth(&token).json(&json!({ "name": name })).send().await?;
+    if response.status().is_success() {
+        println!("✓ Organization `{}` created; you are its admin", name);
+        println!("Use `--org {}` (or PULSON_ORG) to work with its devices.", name);
+    } else {
+        eprintln!("✗ Failed to create organization `{}`: {}", name, error_message(response).await);
+    }
+    Ok(())
+}
+
+pub async fn delete(base_url: Option<String>, host: String, port: u16, name: String, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/orgs/{}", name));
+    let response = Client::new().delete(&url).bearer_auth(&token).send().await?;
+    if response.status().is_success() {
+        println!("✓ Organization `{}` deleted", name);
+    } else {
+        eprintln!("✗ Failed to delete organization `{}`: {}", name, error_message(response).await);
+    }
+    Ok(())
+}
+
+pub async fn members(base_url: Option<String>, host: String, port: u16, org: String, token: String) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/orgs/{}/members", org));
+    let response = Client::new().get(&url).bearer_auth(&token).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to list members of `{}`: {}", org, error_message(response).await);
+        return Ok(());
+    }
+
+    let members: Vec<Value> = response.json().await?;
+    println!("{:<25} {:<15} JOINED", "USERNAME", "ROLE");
+    for member in &members {
+        let text = |field: &str| member[field].as_str().unwrap_or("-").to_string();
+        println!("{:<25} {:<15} {}", text("username"), text("role"), text("joined_at"));
+    }
+    Ok(())
+}
+
+pub async fn set_member(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    org: String,
+    username: String,
+    role: String,
+    token: String,
+) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/orgs/{}/members/{}", org, username));
+    let response = Client::new().put(&url).bearer_auth(&token).json(&json!({ "role": role })).send().await?;
+    if response.status().is_success() {
+        println!("✓ `{}` is now {} in `{}`", username, role, org);
+    } else {
+        eprintln!("✗ Failed to set the role of `{}` in `{}`: {}", username, org, error_message(response).await);
+    }
+    Ok(())
+}
+
+pub async fn remove_member(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    org: String,
+    username: String,
+    token: String,
+) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/orgs/{}/members/{}", org, username));
+    let response = Client::new().delete(&url).bearer_auth(&token).send().await?;
+    if response.status().is_success() {
+        println!("✓ `{}` removed from `{}`", username, org);
+    } else {
+        eprintln!("✗ Failed to remove `{}` from `{}`: {}", username, org, error_message(response).await);
+    }
+    Ok(())
+}
+
+pub async fn move_devices(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    org: String,
+    devices: Vec<String>,
+    all: bool,
+    token: String,
+) -> anyhow::Result<()> {
+    let url = build_api_url(base_url.as_deref(), &host, port, &format!("/api/orgs/{}/devices", org));
+    let body = json!({ "devices": devices, "all": all });
+    let response = Client::new().post(&url).bearer_auth(&token).json(&body).send().await?;
+    if !response.status().is_success() {
+        eprintln!("✗ Failed to move devices into `{}`: {}", org, error_message(response).await);
+        return Ok(());
+    }
+
+    let moved: Value = response.json().await?;
+    let moved: Vec<&str> = moved["moved"]
+        .as_array()
+        .map(|devices| devices.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    println!("✓ Moved {} device(s) into `{}`: {}", moved.len(), org, moved.join(", "));
+    Ok(())
+}
+
+async fn error_message(response: reqwest::Response) -> String {
+    let status = response.status();
+    match response.json::<Value>().await {
+        Ok(body) if body["error"].is_string() => body["error"].as_str().unwrap_or_default().to_string(),
+        _ => status.to_string(),
+    }
+}
@@ -1,7 +1,6 @@
-use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use crate::cli::DataType;
-use crate::logic::client::url_utils::build_api_url;
+use crate::logic::client::url_utils::{api_client, build_api_url};
 use serde_json::json;
 use image::io::Reader as ImageReader;
 
@@ -10,6 +9,19 @@ struct PulsePayload {
     device_id: String,
     topic: String,
     data: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<serde_json::Value>,
+}
+
+/// One record of a batch file, sent as-is to `/api/pulse/batch`
+#[derive(Serialize, Deserialize, Debug)]
+struct BatchRecord {
+    device_id: String,
+    topic: String,
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp: Option<serde_json::Value>,
 }
 
 pub async fn run(
@@ -33,11 +45,18 @@ pub async fn run(
     image_file: Option<String>,
     image_data: Option<String>,
     channels: Option<u32>,
+    timestamp: Option<String>,
     token: String,
 ) -> anyhow::Result<()> {
-    let client = Client::new();
+    let client = api_client();
     let url = build_api_url(base_url.as_deref(), &host, port, "/api/pulse");
 
+    // Epoch numbers are sent as JSON numbers, anything else as an RFC3339 string
+    let timestamp = timestamp.map(|ts| match ts.parse::<f64>() {
+        Ok(epoch) => json!(epoch),
+        Err(_) => json!(ts),
+    });
+
     // Generate appropriate JSON data based on data type and parameters
     let json_data = if let Some(ref custom_data) = data {
         // If custom JSON data is provided, use it directly
@@ -190,7 +209,8 @@ pub async fn run(
         .json(&PulsePayload { 
             device_id: device_id.clone(), 
             topic: topic.clone(), 
-            data: json_data 
+            data: json_data,
+            timestamp,
         })
         .send()
         .await?;
@@ -228,3 +248,102 @@ pub async fn run(
 
     Ok(())
 }
+
+/// Parse a batch file: either a JSON array of records or one record per line
+fn parse_batch_file(contents: &str) -> anyhow::Result<Vec<BatchRecord>> {
+    let trimmed = contents.trim_start();
+    if trimmed.starts_with('[') {
+        return serde_json::from_str(trimmed).map_err(|e| anyhow::anyhow!("Invalid batch file: {}", e));
+    }
+
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).map_err(|e| anyhow::anyhow!("Invalid record on line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
+pub async fn run_batch(
+    base_url: Option<String>,
+    host: String,
+    port: u16,
+    file: String,
+    token: String,
+) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(&file)
+        .map_err(|e| anyhow::anyhow!("Failed to read batch file '{}': {}", file, e))?;
+    let records = parse_batch_file(&contents)?;
+    if records.is_empty() {
+        return Err(anyhow::anyhow!("Batch file '{}' contains no records", file));
+    }
+
+    let url = build_api_url(base_url.as_deref(), &host, port, "/api/pulse/batch");
+    let resp = api_client()
+        .post(&url)
+        .bearer_auth(&token)
+        .json(&records)
+        .send()
+        .await?;
+
+    let status = resp.status();
+    if !status.is_success() {
+        let error_text = resp.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        eprintln!("✗ Batch failed: HTTP {} - {}", status, error_text);
+        return Ok(());
+    }
+
+    let body: serde_json::Value = resp.json().await?;
+    println!(
+        "✓ Batch sent to {} ({} stored, {} failed)",
+        url,
+        body["stored"].as_u64().unwrap_or(0),
+        body["failed"].as_u64().unwrap_or(0)
+    );
+    for result in body["results"].as_array().into_iter().flatten() {
+        if result["status"] == "failed" {
+            let index = result["index"].as_u64().unwrap_or(0) as usize;
+            let error = result["error"].as_str().unwrap_or("unknown error");
+            match records.get(index) {
+                Some(r) => eprintln!("  ✗ record {} ({}/{}): {}", index, r.device_id, r.topic, error),
+                None => eprintln!("  ✗ record {}: {}", index, error),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch_file_array() {
+        let records = parse_batch_file(r#"[
+            {"device_id": "robot1", "topic": "temp", "data": 21.5},
+            {"device_id": "robot1", "topic": "heartbeat", "timestamp": "2025-01-01T00:00:00Z"}
+        ]"#).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].data, Some(serde_json::json!(21.5)));
+        assert!(records[1].data.is_none());
+        assert!(records[1].timestamp.is_some());
+    }
+
+    #[test]
+    fn test_parse_batch_file_lines() {
+        let records = parse_batch_file(
+            "{\"device_id\": \"a\", \"topic\": \"x\"}\n\n{\"device_id\": \"b\", \"topic\": \"y\", \"data\": true}\n",
+        ).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].device_id, "b");
+    }
+
+    #[test]
+    fn test_parse_batch_file_reports_line() {
+        let err = parse_batch_file("{\"device_id\": \"a\", \"topic\": \"x\"}\nnot json\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+}
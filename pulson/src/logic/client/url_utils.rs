@@ -1,6 +1,31 @@
 /// Utility functions for building URLs for API requests
 /// Supports both traditional host:port format and modern base URL format
 
+use std::sync::OnceLock;
+
+/// Header naming the organization a request acts for
+const ORG_HEADER: &str = "X-Pulson-Org";
+
+/// The organization given with `--org`, if any
+static ORG: OnceLock<String> = OnceLock::new();
+
+/// Act for an organization in every request made with [`api_client`]
+pub fn set_org(org: String) {
+    let _ = ORG.set(org);
+}
+
+/// HTTP client for device and settings requests; sends the `--org` organization
+pub fn api_client() -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(value) = ORG.get().and_then(|org| reqwest::header::HeaderValue::from_str(org).ok()) {
+        headers.insert(ORG_HEADER, value);
+    }
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap_or_default()
+}
+
 /// Build API URL from either base_url or host/port combination
 /// 
 /// If base_url is provided, it takes precedence and should include the protocol.
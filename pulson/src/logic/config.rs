@@ -1,5 +1,13 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use crate::logic::serve::aggregate::DEFAULT_MAX_RANGE_SECONDS;
+use crate::logic::serve::audit::AuditConfig;
+use crate::logic::serve::command_hooks::CommandHook;
+use crate::logic::serve::maintenance::MaintenanceWindow;
+use crate::logic::serve::rate_limit::RateLimitConfig;
+use crate::logic::serve::api::token_service::SessionConfig;
+use crate::logic::serve::smtp::SmtpConfig;
+use crate::logic::serve::webhooks::WebhookConfig;
 use crate::logic::types::{DeviceStatus, TopicStatus};
 
 /// Configuration for device and topic status timing thresholds
@@ -11,6 +19,57 @@ pub struct StatusConfig {
     pub warning_threshold_seconds: u64,
     /// Threshold for stale status in seconds (only for topics, default: 3600)
     pub stale_threshold_seconds: u64,
+    /// Per-device and per-topic thresholds that take precedence over the ones above
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<ThresholdOverride>,
+    /// Maintenance windows and silences that are not over yet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintenance: Vec<MaintenanceWindow>,
+}
+
+/// Thresholds of one device or topic that replace the user's, either set
+/// explicitly or learned from how often it publishes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ThresholdOverride {
+    pub id: i64,
+    /// Device the override applies to; unset covers the topic on every device
+    pub device_id: Option<String>,
+    /// Topic the override applies to; unset covers the device itself and those
+    /// of its topics without an override of their own
+    pub topic: Option<String>,
+    /// Whether the thresholds follow `observed_interval_seconds`
+    pub learned: bool,
+    /// Unset for a learned override until enough data has been seen
+    pub online_threshold_seconds: Option<u64>,
+    pub warning_threshold_seconds: Option<u64>,
+    pub stale_threshold_seconds: Option<u64>,
+    /// Median time between recent records, for learned overrides
+    pub observed_interval_seconds: Option<f64>,
+}
+
+impl ThresholdOverride {
+    fn thresholds(&self) -> Option<[u64; 3]> {
+        Some([
+            self.online_threshold_seconds?,
+            self.warning_threshold_seconds?,
+            self.stale_threshold_seconds?,
+        ])
+    }
+}
+
+/// Multiples of the publish interval used as online, warning and stale thresholds
+const LEARNED_MULTIPLIERS: [f64; 3] = [3.0, 10.0, 60.0];
+
+/// Online, warning and stale thresholds for something that publishes every
+/// `interval_seconds`. Each is a whole second longer than the one before, the
+/// online threshold at least 2 seconds.
+pub fn learned_thresholds(interval_seconds: f64) -> [u64; 3] {
+    let mut previous = 1;
+    LEARNED_MULTIPLIERS.map(|multiplier| {
+        let threshold = ((interval_seconds * multiplier).ceil() as u64).max(previous + 1);
+        previous = threshold;
+        threshold
+    })
 }
 
 impl Default for StatusConfig {
@@ -19,6 +78,8 @@ impl Default for StatusConfig {
             online_threshold_seconds: 30,
             warning_threshold_seconds: 300,
             stale_threshold_seconds: 3600,
+            overrides: Vec::new(),
+            maintenance: Vec::new(),
         }
     }
 }
@@ -63,15 +124,35 @@ impl StatusConfig {
         config
     }
 
-    /// Calculate device status based on last seen timestamp
-    pub fn calculate_device_status(&self, last_seen: &DateTime<Utc>) -> DeviceStatus {
+    /// Online, warning and stale thresholds of a device (`topic` unset) or topic.
+    /// A topic uses its own override, then one for the topic on any device, then
+    /// its device's; anything without an override uses the user's thresholds.
+    fn thresholds(&self, device_id: &str, topic: Option<&str>) -> [u64; 3] {
+        let find = |device: Option<&str>, topic: Option<&str>| {
+            self.overrides
+                .iter()
+                .find(|o| o.device_id.as_deref() == device && o.topic.as_deref() == topic)
+                .and_then(ThresholdOverride::thresholds)
+        };
+        find(Some(device_id), topic)
+            .or_else(|| topic.and_then(|topic| find(None, Some(topic)).or_else(|| find(Some(device_id), None))))
+            .unwrap_or([self.online_threshold_seconds, self.warning_threshold_seconds, self.stale_threshold_seconds])
+    }
+
+    /// Calculate device status based on last seen timestamp; a device covered by
+    /// an active maintenance window is in Maintenance regardless
+    pub fn calculate_device_status(&self, device_id: &str, last_seen: &DateTime<Utc>) -> DeviceStatus {
         let now = Utc::now();
+        if self.maintenance.iter().any(|window| window.holds_device(device_id, &now)) {
+            return DeviceStatus::Maintenance;
+        }
         let diff = now.signed_duration_since(*last_seen);
         let seconds = diff.num_seconds() as u64;
+        let [online, warning, _] = self.thresholds(device_id, None);
         
-        if seconds < self.online_threshold_seconds {
+        if seconds < online {
             DeviceStatus::Online
-        } else if seconds < self.warning_threshold_seconds {
+        } else if seconds < warning {
             DeviceStatus::Warning
         } else {
             DeviceStatus::Offline
@@ -79,19 +160,246 @@ impl StatusConfig {
     }
 
     /// Calculate topic status based on last seen timestamp
-    pub fn calculate_topic_status(&self, last_seen: &DateTime<Utc>) -> TopicStatus {
+    pub fn calculate_topic_status(&self, device_id: &str, topic: &str, last_seen: &DateTime<Utc>) -> TopicStatus {
         let now = Utc::now();
         let diff = now.signed_duration_since(*last_seen);
         let seconds = diff.num_seconds() as u64;
+        let [online, warning, stale] = self.thresholds(device_id, Some(topic));
         
-        if seconds < self.online_threshold_seconds {
+        if seconds < online {
             TopicStatus::Active
-        } else if seconds < self.warning_threshold_seconds {
+        } else if seconds < warning {
             TopicStatus::Recent
-        } else if seconds < self.stale_threshold_seconds {
+        } else if seconds < stale {
             TopicStatus::Stale
         } else {
             TopicStatus::Inactive
         }
     }
 }
+
+/// Server settings read from the TOML file given to `pulson serve --config`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Most command hooks running at the same time; further runs are skipped (default: 4)
+    pub max_concurrent_commands: usize,
+    /// Local commands run on status changes, one `[[command_hook]]` table each
+    #[serde(rename = "command_hook")]
+    pub command_hooks: Vec<CommandHook>,
+    /// Mail server for email notifications; disabled when unset
+    pub smtp: Option<SmtpConfig>,
+    /// How long login tokens and refresh tokens last
+    pub sessions: SessionConfig,
+    /// Request rate limits and the login lockout
+    pub rate_limits: RateLimitConfig,
+    /// Longest window of sensor aggregates, in seconds (default: 31 days)
+    pub max_aggregate_range_seconds: u64,
+    /// Private hosts webhooks may reach
+    pub webhooks: WebhookConfig,
+    /// Reverse proxies in front of the server, the last one on the same host,
+    /// whose `X-Forwarded-For` entries are trusted; 0 ignores the header (default: 1)
+    pub trusted_proxy_hops: usize,
+    /// How long audit entries are kept and how repeated refused calls are folded
+    pub audit: AuditConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_commands: 4,
+            command_hooks: Vec::new(),
+            smtp: None,
+            sessions: SessionConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            max_aggregate_range_seconds: DEFAULT_MAX_RANGE_SECONDS,
+            webhooks: WebhookConfig::default(),
+            trusted_proxy_hops: 1,
+            audit: AuditConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read the server config file (expanding ~)
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let path = shellexpand::tilde(path).into_owned();
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("cannot read config file {}: {}", path, e))?;
+        let config: Self = toml::from_str(&contents)
+            .map_err(|e| anyhow::anyhow!("invalid config file {}: {}", path, e))?;
+        if let Some(smtp) = &config.smtp {
+            smtp.validate().map_err(|e| anyhow::anyhow!("invalid config file {}: {}", path, e))?;
+        }
+        config.sessions.validate().map_err(|e| anyhow::anyhow!("invalid config file {}: {}", path, e))?;
+        config.rate_limits.validate().map_err(|e| anyhow::anyhow!("invalid config file {}: {}", path, e))?;
+        if config.max_aggregate_range_seconds == 0 {
+            anyhow::bail!("invalid config file {}: max_aggregate_range_seconds must be at least 1", path);
+        }
+        Ok(config)
+    }
+}
+
+/// Parse a duration such as `45s`, `90m`, `24h`, `30d`, `2w` or `1y` into seconds.
+/// A bare number is taken as seconds.
+pub fn parse_duration(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let amount: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}', expected e.g. 30s, 15m, 24h, 30d, 2w or 1y", input))?;
+    let unit_seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        "y" => 365 * 86_400,
+        _ => return Err(format!("unknown duration unit '{}' in '{}'", unit, input)),
+    };
+    amount
+        .checked_mul(unit_seconds)
+        .ok_or_else(|| format!("duration '{}' is too long", input))
+}
+
+/// Format seconds with the largest unit that divides them evenly, e.g. `86400` -> `1d`
+pub fn format_duration(seconds: u64) -> String {
+    const UNITS: [(u64, &str); 5] = [(365 * 86_400, "y"), (7 * 86_400, "w"), (86_400, "d"), (3600, "h"), (60, "m")];
+    for (unit_seconds, suffix) in UNITS {
+        if seconds > 0 && seconds.is_multiple_of(unit_seconds) {
+            return format!("{}{}", seconds / unit_seconds, suffix);
+        }
+    }
+    format!("{}s", seconds)
+}
+
+/// Parse a point in time: RFC3339, `now`, or a duration before now such as `-6h`
+pub fn parse_time(input: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    let input = input.trim();
+    if input == "now" {
+        return Ok(now);
+    }
+    if let Some(ago) = input.strip_prefix('-') {
+        let seconds = parse_duration(ago)?;
+        return i64::try_from(seconds)
+            .ok()
+            .and_then(chrono::Duration::try_seconds)
+            .and_then(|ago| now.checked_sub_signed(ago))
+            .ok_or_else(|| format!("time '{}' is out of range", input));
+    }
+    DateTime::parse_from_rfc3339(input)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("invalid time '{}', expected RFC3339, 'now' or e.g. -6h", input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45"), Ok(45));
+        assert_eq!(parse_duration("90m"), Ok(5400));
+        assert_eq!(parse_duration("24h"), Ok(86_400));
+        assert_eq!(parse_duration("30d"), Ok(2_592_000));
+        assert_eq!(parse_duration("1y"), Ok(31_536_000));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("3 days").is_err());
+        assert!(parse_duration("-5m").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(86_400), "1d");
+        assert_eq!(format_duration(14 * 86_400), "2w");
+        assert_eq!(format_duration(5400), "90m");
+        assert_eq!(format_duration(61), "61s");
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = DateTime::parse_from_rfc3339("2025-06-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_time("now", now), Ok(now));
+        assert_eq!(parse_time("-6h", now).unwrap().to_rfc3339(), "2025-06-01T06:00:00+00:00");
+        assert_eq!(parse_time("-30s", now).unwrap().to_rfc3339(), "2025-06-01T11:59:30+00:00");
+        assert_eq!(parse_time("2025-06-01T13:00:00+02:00", now).unwrap().to_rfc3339(), "2025-06-01T11:00:00+00:00");
+        assert!(parse_time("6h", now).is_err());
+        assert!(parse_time("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn test_threshold_overrides() {
+        let explicit = |id, device: Option<&str>, topic: Option<&str>, online| ThresholdOverride {
+            id,
+            device_id: device.map(str::to_string),
+            topic: topic.map(str::to_string),
+            learned: false,
+            online_threshold_seconds: Some(online),
+            warning_threshold_seconds: Some(online * 2),
+            stale_threshold_seconds: Some(online * 3),
+            observed_interval_seconds: None,
+        };
+        let config = StatusConfig {
+            overrides: vec![
+                explicit(1, None, Some("battery"), 120),
+                explicit(2, Some("robot1"), None, 10),
+                explicit(3, Some("robot1"), Some("odom"), 2),
+                ThresholdOverride { learned: true, online_threshold_seconds: None, ..explicit(4, Some("robot2"), None, 1) },
+            ],
+            ..StatusConfig::default()
+        };
+
+        assert_eq!(config.thresholds("robot1", Some("odom")), [2, 4, 6]);
+        assert_eq!(config.thresholds("robot1", Some("battery")), [120, 240, 360]);
+        assert_eq!(config.thresholds("robot1", Some("camera")), [10, 20, 30]);
+        assert_eq!(config.thresholds("robot1", None), [10, 20, 30]);
+        assert_eq!(config.thresholds("robot3", Some("battery")), [120, 240, 360]);
+        // Nothing learned yet, so the user's thresholds apply
+        assert_eq!(config.thresholds("robot2", None), [30, 300, 3600]);
+
+        let ten_seconds_ago = Utc::now() - chrono::Duration::seconds(10);
+        assert_eq!(config.calculate_topic_status("robot1", "odom", &ten_seconds_ago), TopicStatus::Inactive);
+        assert_eq!(config.calculate_topic_status("robot1", "battery", &ten_seconds_ago), TopicStatus::Active);
+        assert_eq!(config.calculate_device_status("robot1", &ten_seconds_ago), DeviceStatus::Warning);
+    }
+
+    #[test]
+    fn test_learned_thresholds() {
+        assert_eq!(learned_thresholds(60.0), [180, 600, 3600]);
+        assert_eq!(learned_thresholds(0.05), [2, 3, 4]);
+        assert_eq!(learned_thresholds(1.5), [5, 15, 90]);
+    }
+
+    #[test]
+    fn test_server_config() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            max_concurrent_commands = 2
+
+            [[command_hook]]
+            name = "restart"
+            user = "admin"
+            command = "/usr/local/bin/restart-robot"
+            args = ["--force"]
+            device = "robot1"
+            status = ["Offline"]
+
+            [rate_limits]
+            login_max_failures = 3
+            pulse_per_device = { burst = 5, per_minute = 60 }
+            "#,
+        ).unwrap();
+        assert_eq!(config.max_concurrent_commands, 2);
+        assert_eq!(config.command_hooks.len(), 1);
+        assert_eq!(config.command_hooks[0].args, vec!["--force"]);
+        assert_eq!(config.command_hooks[0].timeout_seconds, 30);
+        assert_eq!(config.rate_limits.login_max_failures, 3);
+        assert_eq!(config.rate_limits.pulse_per_device.per_minute, 60);
+        assert_eq!(config.rate_limits.pulse_per_ip, RateLimitConfig::default().pulse_per_ip);
+
+        let empty: ServerConfig = toml::from_str("").unwrap();
+        assert_eq!(empty.max_concurrent_commands, 4);
+        assert!(empty.command_hooks.is_empty());
+    }
+}
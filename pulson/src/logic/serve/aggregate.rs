@@ -0,0 +1,124 @@
+use crate::logic::config::format_duration;
+use crate::logic::serve::db_types::TimeWindow;
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Percentiles reported when a query names none
+pub const DEFAULT_PERCENTILES: [f64; 4] = [50.0, 90.0, 95.0, 99.0];
+
+/// Most percentiles a single query may ask for
+const MAX_PERCENTILES: usize = 20;
+
+/// Longest window aggregated when the server config sets none (31 days)
+pub const DEFAULT_MAX_RANGE_SECONDS: u64 = 31 * 86_400;
+
+/// Longest window in force; percentiles need every raw value, so wider windows
+/// would hold the database for too long
+static MAX_RANGE_SECONDS: AtomicU64 = AtomicU64::new(DEFAULT_MAX_RANGE_SECONDS);
+
+/// Put the longest aggregated window in force
+pub fn set_max_range(seconds: u64) {
+    MAX_RANGE_SECONDS.store(seconds, Ordering::Relaxed);
+}
+
+/// Refuse windows longer than the one in force
+pub fn check_range(window: &TimeWindow) -> Result<(), String> {
+    let max = MAX_RANGE_SECONDS.load(Ordering::Relaxed);
+    let seconds = (window.to - window.from).num_seconds();
+    if seconds > 0 && seconds as u64 > max {
+        return Err(format!("windows longer than {} cannot be aggregated", format_duration(max)));
+    }
+    Ok(())
+}
+
+/// Parse a comma-separated list of percentiles between 0 and 100, e.g. `50,90,99.9`
+pub fn parse_percentiles(input: &str) -> Result<Vec<f64>, String> {
+    let percentiles = input
+        .split(',')
+        .map(|p| {
+            p.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|p| (0.0..=100.0).contains(p))
+                .ok_or_else(|| format!("invalid percentile '{}', expected a number from 0 to 100", p.trim()))
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+    if percentiles.len() > MAX_PERCENTILES {
+        return Err(format!("at most {} percentiles can be requested", MAX_PERCENTILES));
+    }
+    Ok(percentiles)
+}
+
+/// Count, min, max, mean, population standard deviation and the requested
+/// percentiles of the values in one bucket. `values` must not be empty and is
+/// sorted in place.
+pub fn summarize(values: &mut [f64], percentiles: &[f64]) -> Value {
+    values.sort_by(f64::total_cmp);
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / count;
+
+    let mut by_name = Map::new();
+    for &p in percentiles {
+        by_name.insert(format!("p{}", p), json!(percentile(values, p)));
+    }
+
+    json!({
+        "count": values.len(),
+        "min": values[0],
+        "max": values[values.len() - 1],
+        "mean": mean,
+        "stddev": variance.sqrt(),
+        "percentiles": by_name
+    })
+}
+
+/// Percentile `p` of sorted values, interpolating linearly between the two
+/// closest ranks
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let above = rank.ceil() as usize;
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summarize() {
+        let mut values = vec![4.0, 2.0, 9.0, 5.0, 4.0, 7.0, 5.0, 4.0];
+        let summary = summarize(&mut values, &[0.0, 50.0, 90.0, 100.0]);
+
+        assert_eq!(summary["count"], 8);
+        assert_eq!(summary["min"], 2.0);
+        assert_eq!(summary["max"], 9.0);
+        assert_eq!(summary["mean"], 5.0);
+        assert_eq!(summary["stddev"], 2.0);
+        assert_eq!(summary["percentiles"]["p0"], 2.0);
+        assert_eq!(summary["percentiles"]["p50"], 4.5);
+        assert!((summary["percentiles"]["p90"].as_f64().unwrap() - 7.6).abs() < 1e-9);
+        assert_eq!(summary["percentiles"]["p100"], 9.0);
+
+        let single = summarize(&mut [21.5], &[99.0]);
+        assert_eq!(single["stddev"], 0.0);
+        assert_eq!(single["percentiles"]["p99"], 21.5);
+    }
+
+    #[test]
+    fn test_parse_percentiles() {
+        assert_eq!(parse_percentiles("50, 90,99.9"), Ok(vec![50.0, 90.0, 99.9]));
+        assert!(parse_percentiles("101").is_err());
+        assert!(parse_percentiles("p50").is_err());
+        assert!(parse_percentiles("").is_err());
+    }
+
+    #[test]
+    fn test_check_range() {
+        let to = chrono::Utc::now();
+        let window = |days| TimeWindow { from: to - chrono::Duration::days(days), to, bucket_seconds: 3600 };
+        assert!(check_range(&window(31)).is_ok());
+        assert_eq!(check_range(&window(32)), Err("windows longer than 31d cannot be aggregated".to_string()));
+    }
+}
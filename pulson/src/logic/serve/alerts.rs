@@ -0,0 +1,499 @@
+use crate::logic::serve::database::{
+    list_enabled_alert_rules, list_firing_alerts, open_alert, resolve_alert, Alert, AlertRule, Database,
+};
+use crate::logic::serve::events::{publish, split_device_id, EventBus, LiveEvent};
+use crate::logic::serve::maintenance::is_silenced;
+use crate::logic::serve::status_monitor::current_statuses;
+use crate::logic::types::{DeviceStatus, TopicStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How often the evaluator checks whether rules were changed through the API
+const RULE_RELOAD_INTERVAL_SECS: u64 = 2;
+
+/// Longest accepted rule name
+const MAX_RULE_NAME_LEN: usize = 100;
+
+/// Bumped whenever a rule is created, changed or removed
+static RULES_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Tell the evaluator to reload the rules
+pub fn rules_changed() {
+    RULES_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// What makes an alert rule fire
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// The device is Offline
+    DeviceOffline,
+    /// The topic is Stale or Inactive
+    TopicStale,
+    /// A sensor reading is below `min` or above `max`
+    SensorRange { min: Option<f64>, max: Option<f64> },
+    /// A trigger changes state. Without `to` every flip is reported once; with
+    /// `to` the alert fires while the trigger is in that state.
+    TriggerFlip { to: Option<bool> },
+    /// An event message matches `pattern`: case-insensitive, `*` matches any
+    /// text and `?` one character
+    EventMatch { pattern: String },
+}
+
+/// Something the evaluator learned about a device or topic
+#[derive(Debug, Clone, PartialEq)]
+pub enum Observation<'a> {
+    Device(&'a DeviceStatus),
+    Topic(&'a TopicStatus),
+    Sensor(f64),
+    Trigger { state: bool, previous: Option<bool> },
+    Event(&'a str),
+}
+
+/// What an observation means for one rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The condition holds; open an alert unless one is already firing
+    Fire(String),
+    /// The condition no longer holds; resolve the open alert, if any
+    Resolve,
+    /// A one-off occurrence, recorded as an alert that is resolved right away
+    Once(String),
+    /// The observation says nothing about this rule
+    Ignore,
+}
+
+impl AlertCondition {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            AlertCondition::SensorRange { min: None, max: None } => {
+                Err("sensor_range needs min, max or both".to_string())
+            }
+            AlertCondition::SensorRange { min: Some(min), max: Some(max) } if min > max => {
+                Err("sensor_range min must not be greater than max".to_string())
+            }
+            AlertCondition::EventMatch { pattern } if pattern.trim().is_empty() => {
+                Err("event_match needs a pattern".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn evaluate(&self, topic: Option<&str>, observation: &Observation) -> Outcome {
+        let topic = topic.unwrap_or_default();
+        match (self, observation) {
+            (AlertCondition::DeviceOffline, Observation::Device(status)) => match status {
+                DeviceStatus::Offline => Outcome::Fire("device is offline".to_string()),
+                _ => Outcome::Resolve,
+            },
+            (AlertCondition::TopicStale, Observation::Topic(status)) => match status {
+                TopicStatus::Stale | TopicStatus::Inactive => {
+                    Outcome::Fire(format!("topic {} is {:?}", topic, status).to_lowercase())
+                }
+                _ => Outcome::Resolve,
+            },
+            (AlertCondition::SensorRange { min, max }, Observation::Sensor(value)) => {
+                match (min, max) {
+                    (Some(min), _) if value < min => Outcome::Fire(format!("{} = {} is below {}", topic, value, min)),
+                    (_, Some(max)) if value > max => Outcome::Fire(format!("{} = {} is above {}", topic, value, max)),
+                    _ => Outcome::Resolve,
+                }
+            }
+            (AlertCondition::TriggerFlip { to: None }, Observation::Trigger { state, previous: Some(previous) })
+                if state != previous =>
+            {
+                Outcome::Once(format!("{} switched from {} to {}", topic, previous, state))
+            }
+            (AlertCondition::TriggerFlip { to: Some(to) }, Observation::Trigger { state, .. }) => {
+                if state == to {
+                    Outcome::Fire(format!("{} is {}", topic, state))
+                } else {
+                    Outcome::Resolve
+                }
+            }
+            (AlertCondition::EventMatch { pattern }, Observation::Event(message)) if glob_match(pattern, message) => {
+                Outcome::Once(format!("{}: {}", topic, message))
+            }
+            _ => Outcome::Ignore,
+        }
+    }
+}
+
+/// An alert rule as written by its owner; unset device and topic match any
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRuleSpec {
+    pub name: String,
+    pub device_id: Option<String>,
+    pub topic: Option<String>,
+    pub condition: AlertCondition,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl AlertRuleSpec {
+    /// Check the rule and drop empty device and topic filters
+    pub fn normalize(mut self) -> Result<Self, String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.len() > MAX_RULE_NAME_LEN {
+            return Err(format!("name must be 1 to {} characters", MAX_RULE_NAME_LEN));
+        }
+        self.device_id = self.device_id.filter(|d| !d.is_empty());
+        self.topic = self.topic.filter(|t| !t.is_empty());
+        if self.condition == AlertCondition::DeviceOffline && self.topic.is_some() {
+            return Err("device_offline rules apply to whole devices and cannot name a topic".to_string());
+        }
+        self.condition.validate()?;
+        Ok(self)
+    }
+}
+
+impl AlertRule {
+    fn applies_to(&self, device_id: &str, topic: Option<&str>) -> bool {
+        self.device_id.as_deref().is_none_or(|d| d == device_id)
+            && self.topic.as_deref().is_none_or(|t| Some(t) == topic)
+    }
+}
+
+/// Case-insensitive wildcard match of the whole text: `*` matches any run of
+/// characters and `?` exactly one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    glob_match_case_sensitive(&pattern.to_lowercase(), &text.to_lowercase())
+}
+
+/// Wildcard match like [`glob_match`] that tells upper and lower case apart,
+/// for patterns that grant access
+pub fn glob_match_case_sensitive(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it currently stands for
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last `*` swallow one more character and retry
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, t));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Start the background task that evaluates alert rules against live events
+/// and records when alerts fire and resolve.
+pub fn spawn_alert_evaluator(db: Database, events: EventBus) {
+    tokio::spawn(async move {
+        AlertEvaluator::new(db, events).run().await;
+    });
+}
+
+/// Open alert per (rule id, device id, topic or "")
+type AlertKey = (i64, String, String);
+
+struct AlertEvaluator {
+    db: Database,
+    events: EventBus,
+    generation: Option<u64>,
+    /// Enabled rules per owner
+    rules: HashMap<String, Vec<AlertRule>>,
+    firing: HashMap<AlertKey, i64>,
+    /// Last known status per (owner, device id)
+    devices: HashMap<(String, String), DeviceStatus>,
+    /// Last known status per (owner, device id, topic)
+    topics: HashMap<(String, String, String), TopicStatus>,
+    /// Last trigger state per (owner, device id, topic)
+    triggers: HashMap<(String, String, String), bool>,
+    /// (owner, device id, topic or "") whose alerts a window held back, checked
+    /// again until it ends so status alerts that still hold fire then
+    silenced: HashSet<(String, String, String)>,
+}
+
+impl AlertEvaluator {
+    fn new(db: Database, events: EventBus) -> Self {
+        Self {
+            db,
+            events,
+            generation: None,
+            rules: HashMap::new(),
+            firing: HashMap::new(),
+            devices: HashMap::new(),
+            topics: HashMap::new(),
+            triggers: HashMap::new(),
+            silenced: HashSet::new(),
+        }
+    }
+
+    async fn run(mut self) {
+        let mut rx = self.events.subscribe();
+        self.load_statuses();
+        let mut ticker = tokio::time::interval(Duration::from_secs(RULE_RELOAD_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    self.reload_if_changed();
+                    self.recheck_silenced();
+                }
+                event = rx.recv() => match event {
+                    Ok(event) => {
+                        self.reload_if_changed();
+                        self.on_event(event);
+                    }
+                    // Status conditions are caught up with on the next transition
+                    Err(RecvError::Lagged(missed)) => {
+                        eprintln!("Alert evaluator: missed {} events", missed);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    /// Learn the current device and topic statuses, which the status monitor only
+    /// reports once they change
+    fn load_statuses(&mut self) {
+        let Ok((devices, topics)) = current_statuses(&self.db) else {
+            eprintln!("Alert evaluator: failed to load device statuses");
+            return;
+        };
+        for (full_device_id, status) in devices {
+            if let Some((owner, device_id)) = split_device_id(&full_device_id) {
+                self.devices.insert((owner.to_string(), device_id.to_string()), status);
+            }
+        }
+        for ((full_device_id, topic), status) in topics {
+            if let Some((owner, device_id)) = split_device_id(&full_device_id) {
+                self.topics.insert((owner.to_string(), device_id.to_string(), topic), status);
+            }
+        }
+    }
+
+    /// Reload rules and open alerts after a change, then check the status rules
+    /// against what is known so a new rule fires without waiting for a transition
+    fn reload_if_changed(&mut self) {
+        let generation = RULES_GENERATION.load(Ordering::SeqCst);
+        if self.generation == Some(generation) {
+            return;
+        }
+        let (rules, firing) = match (list_enabled_alert_rules(&self.db), list_firing_alerts(&self.db)) {
+            (Ok(rules), Ok(firing)) => (rules, firing),
+            _ => {
+                eprintln!("Alert evaluator: failed to load alert rules");
+                return;
+            }
+        };
+        self.generation = Some(generation);
+
+        self.rules.clear();
+        for (owner, rule) in rules {
+            self.rules.entry(owner).or_default().push(rule);
+        }
+        self.firing = firing
+            .into_iter()
+            .map(|alert| ((alert.rule_id, alert.device_id, alert.topic.unwrap_or_default()), alert.id))
+            .collect();
+
+        let devices: Vec<_> = self.devices.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        for ((owner, device_id), status) in devices {
+            self.observe(&owner, &device_id, None, Observation::Device(&status));
+        }
+        let topics: Vec<_> = self.topics.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        for ((owner, device_id, topic), status) in topics {
+            self.observe(&owner, &device_id, Some(&topic), Observation::Topic(&status));
+        }
+    }
+
+    /// Evaluate the last known status of everything that was silenced again
+    fn recheck_silenced(&mut self) {
+        for (owner, device_id, topic) in std::mem::take(&mut self.silenced) {
+            if topic.is_empty() {
+                if let Some(status) = self.devices.get(&(owner.clone(), device_id.clone())).cloned() {
+                    self.observe(&owner, &device_id, None, Observation::Device(&status));
+                }
+            } else if let Some(status) = self.topics.get(&(owner.clone(), device_id.clone(), topic.clone())).cloned() {
+                self.observe(&owner, &device_id, Some(&topic), Observation::Topic(&status));
+            }
+        }
+    }
+
+    fn on_event(&mut self, event: LiveEvent) {
+        match event {
+            LiveEvent::DeviceStatus { owner, device_id, status, .. } => {
+                self.observe(&owner, &device_id, None, Observation::Device(&status));
+                self.devices.insert((owner, device_id), status);
+            }
+            LiveEvent::TopicStatus { owner, device_id, topic, status, .. } => {
+                self.observe(&owner, &device_id, Some(&topic), Observation::Topic(&status));
+                self.topics.insert((owner, device_id, topic), status);
+            }
+            LiveEvent::Data { owner, device_id, topic, data_type, data, .. } => match data_type.as_str() {
+                "sensor" => {
+                    if let Some(value) = data["Sensor"]["value"].as_f64() {
+                        self.observe(&owner, &device_id, Some(&topic), Observation::Sensor(value));
+                    }
+                }
+                "trigger" => {
+                    if let Some(state) = data["Trigger"]["state"].as_bool() {
+                        let previous = self.triggers.insert((owner.clone(), device_id.clone(), topic.clone()), state);
+                        self.observe(&owner, &device_id, Some(&topic), Observation::Trigger { state, previous });
+                    }
+                }
+                "event" => {
+                    if let Some(message) = data["Event"]["message"].as_str() {
+                        self.observe(&owner, &device_id, Some(&topic), Observation::Event(message));
+                    }
+                }
+                _ => {}
+            },
+            LiveEvent::Alert { .. } => {}
+        }
+    }
+
+    /// Evaluate every rule of the owner that covers the device and topic
+    fn observe(&mut self, owner: &str, device_id: &str, topic: Option<&str>, observation: Observation) {
+        let Some(rules) = self.rules.get(owner) else {
+            return;
+        };
+        let outcomes: Vec<(AlertRule, Outcome)> = rules
+            .iter()
+            .filter(|rule| rule.applies_to(device_id, topic))
+            .map(|rule| (rule.clone(), rule.condition.evaluate(topic, &observation)))
+            .collect();
+
+        let mut silenced = None;
+        for (rule, outcome) in outcomes {
+            let key = (rule.id, device_id.to_string(), topic.unwrap_or_default().to_string());
+            // Resolving always goes through; firing waits for maintenance to end
+            if matches!(outcome, Outcome::Fire(_) | Outcome::Once(_))
+                && !self.firing.contains_key(&key)
+                && *silenced.get_or_insert_with(|| is_silenced(&self.db, owner, device_id, topic))
+            {
+                self.silenced.insert((owner.to_string(), device_id.to_string(), key.2));
+                continue;
+            }
+            let once = matches!(outcome, Outcome::Once(_));
+            let result = match outcome {
+                Outcome::Fire(_) if self.firing.contains_key(&key) => continue,
+                Outcome::Fire(message) | Outcome::Once(message) => {
+                    open_alert(&self.db, owner, &rule, device_id, topic, &message, once).map(|alert| {
+                        if !once {
+                            self.firing.insert(key, alert.id);
+                        }
+                        Some(alert)
+                    })
+                }
+                Outcome::Resolve => match self.firing.remove(&key) {
+                    Some(alert_id) => resolve_alert(&self.db, alert_id),
+                    None => continue,
+                },
+                Outcome::Ignore => continue,
+            };
+
+            match result {
+                Ok(Some(alert)) => self.announce(owner, alert, once),
+                Ok(None) => {}
+                Err(_) => eprintln!("Alert evaluator: failed to record alert of rule {}", rule.id),
+            }
+        }
+    }
+
+    fn announce(&self, owner: &str, alert: Alert, once: bool) {
+        let transition = if once || alert.state == "firing" { "fired" } else { "resolved" };
+        println!(
+            "Alert '{}' {} for {} (user: {}): {}",
+            alert.rule_name, transition, alert.device_id, owner, alert.message
+        );
+        publish(&self.events, LiveEvent::Alert { owner: owner.to_string(), transition, alert });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*error*", "Motor ERROR: overheating"));
+        assert!(glob_match("battery ?%", "Battery 5%"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("error", "error in motor"));
+        assert!(!glob_match("a*b*c", "axxbyy"));
+        assert!(glob_match_case_sensitive("robot-*", "robot-7"));
+        assert!(!glob_match_case_sensitive("robot-*", "Robot-7"));
+    }
+
+    #[test]
+    fn test_evaluate_conditions() {
+        let range = AlertCondition::SensorRange { min: Some(0.0), max: Some(80.0) };
+        assert_eq!(
+            range.evaluate(Some("temp"), &Observation::Sensor(81.5)),
+            Outcome::Fire("temp = 81.5 is above 80".to_string())
+        );
+        assert_eq!(range.evaluate(Some("temp"), &Observation::Sensor(40.0)), Outcome::Resolve);
+        assert_eq!(range.evaluate(Some("temp"), &Observation::Event("hot")), Outcome::Ignore);
+
+        let offline = AlertCondition::DeviceOffline;
+        assert!(matches!(offline.evaluate(None, &Observation::Device(&DeviceStatus::Offline)), Outcome::Fire(_)));
+        assert_eq!(offline.evaluate(None, &Observation::Device(&DeviceStatus::Warning)), Outcome::Resolve);
+
+        let stale = AlertCondition::TopicStale;
+        assert_eq!(
+            stale.evaluate(Some("gps"), &Observation::Topic(&TopicStatus::Inactive)),
+            Outcome::Fire("topic gps is inactive".to_string())
+        );
+
+        let any_flip = AlertCondition::TriggerFlip { to: None };
+        let flip = Observation::Trigger { state: true, previous: Some(false) };
+        assert!(matches!(any_flip.evaluate(Some("door"), &flip), Outcome::Once(_)));
+        let first = Observation::Trigger { state: true, previous: None };
+        assert_eq!(any_flip.evaluate(Some("door"), &first), Outcome::Ignore);
+
+        let opened = AlertCondition::TriggerFlip { to: Some(true) };
+        assert!(matches!(opened.evaluate(Some("door"), &first), Outcome::Fire(_)));
+        let closed = Observation::Trigger { state: false, previous: Some(true) };
+        assert_eq!(opened.evaluate(Some("door"), &closed), Outcome::Resolve);
+
+        let event = AlertCondition::EventMatch { pattern: "*fail*".to_string() };
+        assert_eq!(
+            event.evaluate(Some("log"), &Observation::Event("Homing failed")),
+            Outcome::Once("log: Homing failed".to_string())
+        );
+        assert_eq!(event.evaluate(Some("log"), &Observation::Event("Homing done")), Outcome::Ignore);
+    }
+
+    #[test]
+    fn test_rule_spec_validation() {
+        let spec = |condition: AlertCondition, topic: Option<&str>| AlertRuleSpec {
+            name: " hot ".to_string(),
+            device_id: Some(String::new()),
+            topic: topic.map(str::to_string),
+            condition,
+            enabled: true,
+        };
+
+        let rule = spec(AlertCondition::SensorRange { min: None, max: Some(80.0) }, Some("temp")).normalize().unwrap();
+        assert_eq!(rule.name, "hot");
+        assert_eq!(rule.device_id, None);
+        assert!(spec(AlertCondition::SensorRange { min: None, max: None }, None).normalize().is_err());
+        assert!(spec(AlertCondition::SensorRange { min: Some(5.0), max: Some(1.0) }, None).normalize().is_err());
+        assert!(spec(AlertCondition::DeviceOffline, Some("temp")).normalize().is_err());
+        assert!(spec(AlertCondition::EventMatch { pattern: " ".to_string() }, None).normalize().is_err());
+    }
+}
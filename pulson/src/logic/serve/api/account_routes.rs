@@ -1,8 +1,10 @@
 use crate::logic::serve::api::password_utils::{hash_password, verify_password};
 use crate::logic::serve::api::user_management::{create_user, delete_user_by_admin, list_all_users_by_admin, NewUser};
-use crate::logic::serve::api::token_service::{generate_and_store_token, revoke_token};
-use crate::logic::serve::database::{Database, get_user_password_hash, get_user_role};
+use crate::logic::serve::api::token_service::{refresh_session, revoke_token, session_for_token, start_session, SessionConfig};
+use crate::logic::serve::database::{Database, delete_other_sessions, delete_session, get_user_password_hash, get_user_role, list_sessions, Session};
 use crate::logic::serve::auth::authenticated_user;
+use crate::logic::serve::rate_limit::{limit_ip, LimitKind, RateLimiter};
+use crate::logic::serve::roles::Role;
 use serde::Deserialize;
 use serde_json::json;
 use warp::{
@@ -16,6 +18,8 @@ use warp::{
 #[derive(serde::Serialize)]
 struct UserInfoResponse {
     username: String,
+    role: Role,
+    /// Whether the user is an admin; kept for older clients
     is_root: bool,
 }
 
@@ -32,13 +36,30 @@ struct LoginPayload {
     password: String,
 }
 
+#[derive(Deserialize)]
+struct RefreshPayload {
+    refresh_token: String,
+}
+
+/// A session as listed to its user
+#[derive(serde::Serialize)]
+struct SessionView {
+    #[serde(flatten)]
+    session: Session,
+    expires_at: String,
+    /// Whether this is the session making the request
+    current: bool,
+}
+
 /// POST /api/account/register
 pub fn register(
     db: Database,
     root_pass: Option<String>,
+    limiter: RateLimiter,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
     warp::post()
         .and(warp::path!("api" / "account" / "register"))
+        .and(limit_ip(limiter, LimitKind::AccountPerIp))
         .and(warp_body_json())
         .map(move |payload: AccountPayload| {
             let hashed_password = match hash_password(&payload.password) {
@@ -57,9 +78,9 @@ pub fn register(
                 .and_then(|rp| root_pass.as_ref().map(|rp2| rp == rp2))
                 .unwrap_or(false)
             {
-                "root"
+                Role::Admin
             } else {
-                "user"
+                Role::DEFAULT
             };
 
             let new_user_data = NewUser {
@@ -81,13 +102,25 @@ pub fn register(
         })
 }
 
-/// POST /api/account/login
-pub fn login(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+/// POST /api/account/login - Locked for a while after repeated failures
+pub fn login(db: Database, limiter: RateLimiter) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let lockout = limiter.clone();
     warp::post()
         .and(warp::path!("api" / "account" / "login"))
+        .and(limit_ip(limiter.clone(), LimitKind::AccountPerIp))
         .and(warp::body::json()) // Expect LoginPayload
-        .map(move |payload: LoginPayload| { // Use LoginPayload here
-            let err = || {
+        .and_then(move |payload: LoginPayload| {
+            let lockout = lockout.clone();
+            async move { lockout.check_login(&payload.username).map(|()| payload) }
+        })
+        .and(optional::<String>("user-agent"))
+        .map(move |payload: LoginPayload, user_agent: Option<String>| { // Use LoginPayload here
+            // Only failures for accounts that exist count towards a lockout, so
+            // guessing usernames cannot grow the failure counts
+            let err = |known: bool| {
+                if known {
+                    limiter.login_failed(&payload.username);
+                }
                 with_status(
                     warp_json(&json!({ "error": "invalid credentials" })),
                     StatusCode::UNAUTHORIZED,
@@ -98,26 +131,25 @@ pub fn login(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Re
                 Ok(Some(stored_hashed_password)) => {
                     match verify_password(&payload.password, &stored_hashed_password) {
                         Ok(true) => {
-                            match generate_and_store_token(&db, &payload.username) {
-                                Ok(token) => {
-                                    with_status(warp_json(&json!({ "token": token })), StatusCode::OK)
-                                }
+                            limiter.login_succeeded(&payload.username);
+                            match start_session(&db, &payload.username, user_agent.as_deref()) {
+                                Ok(session) => with_status(warp_json(&session), StatusCode::OK),
                                 Err(status_code) => {
                                     with_status(warp_json(&json!({ "error": "login failed" })), status_code)
                                 }
                             }
                         }
-                        Ok(false) => err(),
-                        Err(_status_code) => err(), // Prefixed status_code with _
+                        Ok(false) => err(true),
+                        Err(_status_code) => err(true), // Prefixed status_code with _
                     }
                 }
-                Ok(None) => err(),
-                Err(_) => err(),
+                Ok(None) => err(false),
+                Err(_) => err(false),
             }
         })
 }
 
-/// DELETE /api/account/{username}
+/// DELETE /api/account/{username} - Delete a user (admins only)
 pub fn delete_user(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
@@ -125,12 +157,16 @@ pub fn delete_user(
     warp::delete()
         .and(warp::path!("api" / "account" / String))
         .and(auth)
-        .map(move |target_username: String, caller_username: String| {
-            match delete_user_by_admin(&db, &target_username, &caller_username) {
+        .map(move |target_username: String, _caller_username: String| {
+            match delete_user_by_admin(&db, &target_username) {
                 Ok(_) => with_status(
                     warp_json(&json!({ "message": "user deleted successfully" })),
                     StatusCode::OK,
                 ),
+                Err(StatusCode::CONFLICT) => with_status(
+                    warp_json(&json!({ "error": "the last admin cannot be deleted" })),
+                    StatusCode::CONFLICT,
+                ),
                 Err(status_code) => with_status(
                     warp_json(&json!({ "error": "user deletion failed" })),
                     status_code,
@@ -139,7 +175,7 @@ pub fn delete_user(
         })
 }
 
-/// GET /api/account/users
+/// GET /api/account/users - List users and their roles (admins only)
 pub fn list_users(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
@@ -147,8 +183,8 @@ pub fn list_users(
     warp::get()
         .and(warp::path!("api" / "account" / "users"))
         .and(auth)
-        .map(move |caller_username: String| {
-            match list_all_users_by_admin(&db, &caller_username) {
+        .map(move |_caller_username: String| {
+            match list_all_users_by_admin(&db) {
                 Ok(users_json) => with_status(warp_json(&users_json), StatusCode::OK),
                 Err(status_code) => with_status(
                     warp_json(&json!({ "error": "forbidden or error" })),
@@ -197,6 +233,110 @@ pub fn logout(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = R
         })
 }
 
+/// POST /api/account/refresh - Trade a refresh token for a new token and refresh token
+pub fn refresh(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("api" / "account" / "refresh"))
+        .and(warp_body_json())
+        .map(move |payload: RefreshPayload| match refresh_session(&db, &payload.refresh_token) {
+            Ok(Some(session)) => with_status(warp_json(&session), StatusCode::OK),
+            Ok(None) => with_status(
+                warp_json(&json!({ "error": "invalid or expired refresh token" })),
+                StatusCode::UNAUTHORIZED,
+            ),
+            Err(status_code) => with_status(warp_json(&json!({ "error": "refresh failed" })), status_code),
+        })
+}
+
+/// Session routes; they sit before `DELETE /api/account/{username}` so
+/// `sessions` is not taken for a username
+pub fn session_routes(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list_user_sessions(db.clone())
+        .or(revoke_session(db.clone()))
+        .or(revoke_other_sessions(db))
+}
+
+/// The id of the session a request was made with
+fn current_session_id(db: &Database, auth_header: Option<String>) -> Option<i64> {
+    let token = auth_header?.strip_prefix("Bearer ")?.to_string();
+    session_for_token(db, &token).ok().flatten().map(|(_, session)| session.id)
+}
+
+/// GET /api/account/sessions - List the caller's sessions
+pub fn list_user_sessions(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "account" / "sessions"))
+        .and(auth)
+        .and(optional::<String>("authorization"))
+        .map(move |username: String, auth_header: Option<String>| {
+            let current = current_session_id(&db, auth_header);
+            let config = SessionConfig::current();
+            let now = chrono::Utc::now();
+            match list_sessions(&db, &username) {
+                Ok(sessions) => {
+                    let sessions: Vec<SessionView> = sessions
+                        .into_iter()
+                        .filter(|session| config.refresh_expires_at(session) > now)
+                        .map(|session| SessionView {
+                            expires_at: config
+                                .token_expires_at(&session)
+                                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                            current: Some(session.id) == current,
+                            session,
+                        })
+                        .collect();
+                    with_status(warp_json(&sessions), StatusCode::OK)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to list sessions" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/account/sessions/{id} - End one of the caller's sessions
+pub fn revoke_session(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "account" / "sessions" / i64))
+        .and(auth)
+        .map(move |id: i64, username: String| match delete_session(&db, &username, id) {
+            Ok(true) => with_status(warp_json(&json!({ "message": "session revoked" })), StatusCode::OK),
+            Ok(false) => with_status(
+                warp_json(&json!({ "error": "session not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to revoke session" })),
+                status_code,
+            ),
+        })
+}
+
+/// DELETE /api/account/sessions - End every session of the caller but the current one
+pub fn revoke_other_sessions(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "account" / "sessions"))
+        .and(auth)
+        .and(optional::<String>("authorization"))
+        .map(move |username: String, auth_header: Option<String>| {
+            let current = current_session_id(&db, auth_header).unwrap_or_default();
+            match delete_other_sessions(&db, &username, current) {
+                Ok(revoked) => {
+                    println!("Revoked {} other sessions (user: {})", revoked, username);
+                    with_status(warp_json(&json!({ "revoked": revoked })), StatusCode::OK)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to revoke sessions" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
 /// GET /api/userinfo
 pub fn user_info(db: Database) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
     let auth = authenticated_user(db.clone());
@@ -206,8 +346,7 @@ pub fn user_info(db: Database) -> impl Filter<Extract = impl warp::Reply, Error
         .map(move |username: String| {
             match get_user_role(&db, &username) {
                 Ok(Some(role)) => {
-                    let is_root = role == "root";
-                    let user_info_response = UserInfoResponse { username, is_root };
+                    let user_info_response = UserInfoResponse { username, role, is_root: role.is_admin() };
                     with_status(warp_json(&user_info_response), StatusCode::OK)
                 }
                 Ok(None) => with_status(
@@ -0,0 +1,105 @@
+use crate::logic::serve::api::user_management::assign_role;
+use crate::logic::serve::audit::AuditQuery;
+use crate::logic::serve::auth::authenticated_user;
+use crate::logic::serve::database::{list_audit_entries, Database};
+use crate::logic::serve::roles::Role;
+use serde::Deserialize;
+use serde_json::json;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+#[derive(Deserialize)]
+struct RolePayload {
+    role: String,
+}
+
+/// Role administration and the audit log; the auth filter lets only admins
+/// through to `/api/admin`
+pub fn admin_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list_roles(db.clone())
+        .or(set_role(db.clone()))
+        .or(list_audit(db))
+}
+
+/// GET /api/admin/roles - The roles and the scopes each grants
+pub fn list_roles(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db);
+    warp::get()
+        .and(warp::path!("api" / "admin" / "roles"))
+        .and(auth)
+        .map(|_username: String| {
+            let roles: Vec<_> = Role::ALL
+                .iter()
+                .map(|role| json!({ "role": role, "scopes": role.scopes(), "admin": role.is_admin() }))
+                .collect();
+            warp_json(&roles)
+        })
+}
+
+/// PUT /api/admin/users/{username}/role - Give a user another role
+pub fn set_role(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::put()
+        .and(warp::path!("api" / "admin" / "users" / String / "role"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |target_username: String, caller_username: String, payload: RolePayload| {
+            let Some(role) = Role::parse(payload.role.trim()) else {
+                let known: Vec<&str> = Role::ALL.iter().map(Role::as_str).collect();
+                return with_status(
+                    warp_json(&json!({ "error": format!("unknown role '{}' (expected one of {})", payload.role, known.join(", ")) })),
+                    StatusCode::BAD_REQUEST,
+                );
+            };
+            match assign_role(&db, &target_username, role) {
+                Ok(()) => {
+                    println!("Set role of {} to {} (by {})", target_username, role.as_str(), caller_username);
+                    with_status(warp_json(&json!({ "username": target_username, "role": role })), StatusCode::OK)
+                }
+                Err(StatusCode::CONFLICT) => with_status(
+                    warp_json(&json!({ "error": "the last admin cannot give up the admin role" })),
+                    StatusCode::CONFLICT,
+                ),
+                Err(StatusCode::NOT_FOUND) => with_status(
+                    warp_json(&json!({ "error": "user not found" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to set role" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// GET /api/admin/audit?actor=&action=&target=&org=&result=&ip=&from=&to=&before=&limit= -
+/// Audit log entries, newest first
+pub fn list_audit(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "admin" / "audit"))
+        .and(auth)
+        .and(warp::query::<AuditQuery>())
+        .map(move |_username: String, query: AuditQuery| {
+            let filter = match query.resolve(chrono::Utc::now()) {
+                Ok(filter) => filter,
+                Err(e) => return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            match list_audit_entries(&db, &filter) {
+                Ok(entries) => with_status(warp_json(&entries), StatusCode::OK),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to read the audit log" })),
+                    status_code,
+                ),
+            }
+        })
+}
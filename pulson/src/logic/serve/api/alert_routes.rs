@@ -0,0 +1,173 @@
+use crate::logic::serve::alerts::{rules_changed, AlertRuleSpec};
+use crate::logic::serve::auth::authenticated_owner;
+use crate::logic::serve::database::{
+    create_alert_rule, delete_alert_rule, list_alert_rules, list_alerts, update_alert_rule, Database,
+};
+use serde_json::json;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+/// Alerts returned by the history endpoint when no limit is given
+const DEFAULT_ALERT_LIMIT: usize = 50;
+
+/// Most alerts the history endpoint returns at once
+const MAX_ALERT_LIMIT: usize = 1000;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct AlertQuery {
+    /// `firing` or `resolved`
+    state: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Alert rule management and alert history
+pub fn alert_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list_rules(db.clone())
+        .or(create_rule(db.clone()))
+        .or(update_rule(db.clone()))
+        .or(delete_rule(db.clone()))
+        .or(list_history(db))
+}
+
+/// GET /api/alerts/rules - List the caller's alert rules
+pub fn list_rules(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "alerts" / "rules"))
+        .and(auth)
+        .map(move |owner: String| match list_alert_rules(&db, &owner) {
+            Ok(rules) => with_status(warp_json(&rules), StatusCode::OK),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to list alert rules" })),
+                status_code,
+            ),
+        })
+}
+
+/// POST /api/alerts/rules - Create an alert rule
+pub fn create_rule(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "alerts" / "rules"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |owner: String, spec: AlertRuleSpec| {
+            let spec = match spec.normalize() {
+                Ok(spec) => spec,
+                Err(e) => return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            match create_alert_rule(&db, &owner, &spec) {
+                Ok(rule) => {
+                    println!("Created alert rule {} '{}' (owner: {})", rule.id, rule.name, owner);
+                    rules_changed();
+                    with_status(warp_json(&rule), StatusCode::OK)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to create alert rule" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// PUT /api/alerts/rules/{id} - Replace an alert rule; its open alerts are
+/// resolved and re-evaluated under the new rule
+pub fn update_rule(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::put()
+        .and(warp::path!("api" / "alerts" / "rules" / i64))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |id: i64, owner: String, spec: AlertRuleSpec| {
+            let spec = match spec.normalize() {
+                Ok(spec) => spec,
+                Err(e) => return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            match update_alert_rule(&db, &owner, id, &spec) {
+                Ok(Some(rule)) => {
+                    rules_changed();
+                    with_status(warp_json(&rule), StatusCode::OK)
+                }
+                Ok(None) => with_status(
+                    warp_json(&json!({ "error": "alert rule not found" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to update alert rule" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/alerts/rules/{id} - Remove an alert rule, keeping its history
+pub fn delete_rule(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "alerts" / "rules" / i64))
+        .and(auth)
+        .map(move |id: i64, owner: String| match delete_alert_rule(&db, &owner, id) {
+            Ok(true) => {
+                rules_changed();
+                with_status(warp_json(&json!({ "message": "alert rule removed" })), StatusCode::OK)
+            }
+            Ok(false) => with_status(
+                warp_json(&json!({ "error": "alert rule not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to remove alert rule" })),
+                status_code,
+            ),
+        })
+}
+
+/// GET /api/alerts?state={firing|resolved}&limit={n} - The caller's alert history, newest first
+pub fn list_history(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "alerts"))
+        .and(auth)
+        .and(warp::query::<AlertQuery>())
+        .map(move |owner: String, query: AlertQuery| {
+            let state = query.state.as_deref().filter(|s| !s.is_empty());
+            if let Some(state) = state {
+                if state != "firing" && state != "resolved" {
+                    return with_status(
+                        warp_json(&json!({ "error": format!("unknown state '{}', expected firing or resolved", state) })),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+            }
+            let limit = match query.limit.unwrap_or(DEFAULT_ALERT_LIMIT) {
+                limit @ 1..=MAX_ALERT_LIMIT => limit,
+                _ => {
+                    return with_status(
+                        warp_json(&json!({ "error": format!("limit must be between 1 and {}", MAX_ALERT_LIMIT) })),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+            };
+
+            match list_alerts(&db, &owner, state, limit) {
+                Ok(alerts) => with_status(warp_json(&alerts), StatusCode::OK),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to list alerts" })),
+                    status_code,
+                ),
+            }
+        })
+}
@@ -1,23 +1,48 @@
-use crate::logic::serve::auth::authenticated_user;
-use crate::logic::serve::database::{Database, get_device_data, list_user_devices, delete_device as db_delete_device, get_user_config_or_default, set_user_config as db_set_user_config, get_pulse_history, get_pulse_stats, store_device_data_payload, get_device_latest_data};
+use crate::logic::serve::auth::{authenticated_caller, authenticated_owner, authenticated_user, Caller};
+use crate::logic::serve::database::{Database, get_device_data, list_user_devices, delete_device as db_delete_device, get_user_config_or_default, set_user_config as db_set_user_config, get_pulse_history, get_pulse_stats, get_sensor_aggregates, get_device_latest_data, set_device_credential, delete_device_credential, list_retention_policies, set_retention_policy, delete_retention_policy, list_status_overrides, set_status_override, delete_status_override, list_status_transitions};
+use crate::logic::config::parse_duration;
+use crate::logic::serve::audit::with_audit_target;
+use crate::logic::serve::aggregate::{check_range, parse_percentiles, DEFAULT_PERCENTILES};
+use crate::logic::serve::db_types::{DataPage, DataType, TimeWindow};
+use crate::logic::serve::retention::{MAX_KEEP_SECONDS, ROLLUP_TYPE};
+use crate::logic::serve::status_monitor::refresh_learned_override;
+use crate::logic::serve::uptime::uptime_report;
 use crate::logic::config::StatusConfig;
-use chrono::Utc;
+use crate::logic::serve::ingest::{IncomingPulse, IngestError, Ingestor, MAX_PULSE_BYTES};
+use crate::logic::serve::rate_limit::{limit_ip, too_many_requests, LimitKind, RateLimiter};
 use serde_json;
 use std::sync::{Arc, Mutex};
 use warp::{
-    body::{json as warp_body_json, content_length_limit}, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+    body::{json as warp_body_json, content_length_limit}, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection, Reply,
 };
 
+/// Upper bound on the number of records accepted by one batch request
+const MAX_BATCH_RECORDS: usize = 1000;
+
 #[derive(serde::Deserialize)]
-pub struct PulsePayload {
+pub struct DeleteDevicePayload {
     pub device_id: String,
-    pub topic: String,
-    pub data: Option<serde_json::Value>,
 }
 
 #[derive(serde::Deserialize)]
-pub struct DeleteDevicePayload {
-    pub device_id: String,
+struct RetentionRequest {
+    device_id: Option<String>,
+    topic: Option<String>,
+    data_type: Option<String>,
+    /// How long matching data is kept, e.g. `24h`, `30d` or `1y`
+    keep: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ThresholdRequest {
+    device_id: Option<String>,
+    topic: Option<String>,
+    /// Derive the thresholds from the observed publish interval instead
+    #[serde(default)]
+    learn: bool,
+    online_threshold_seconds: Option<u64>,
+    warning_threshold_seconds: Option<u64>,
+    stale_threshold_seconds: Option<u64>,
 }
 
 #[derive(serde::Deserialize)]
@@ -27,82 +52,174 @@ struct ConfigUpdateRequest {
     stale_threshold_seconds: u64,
 }
 
+/// Largest accepted batch request body
+const MAX_BATCH_BYTES: u64 = 50 * 1024 * 1024;
+
+/// Error for an API key sending pulses for a device outside its limit
+pub fn device_not_allowed(device_id: &str) -> String {
+    format!("this API key cannot send pulses for device '{}'", device_id)
+}
+
+/// Take a token from the bucket of the client's IP address and authenticate a
+/// pulse request, before the body is read. The limits of the owner and its
+/// devices are taken by the ingestor, like for every other transport.
+fn pulse_caller(db: Database, limiter: RateLimiter) -> impl Filter<Extract = (Caller,), Error = Rejection> + Clone {
+    limit_ip(limiter, LimitKind::PulsePerIp).and(authenticated_caller(db))
+}
+
 pub fn pulse(
     db: Database,
-    save_images: bool,
+    ingestor: Ingestor,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
     warp::post()
         .and(warp::path!("api" / "pulse"))
-        .and(auth)
-        .and(content_length_limit(50 * 1024 * 1024)) // 10MB limit for large images
+        .and(pulse_caller(db, ingestor.limiter.clone()))
+        .and(content_length_limit(MAX_PULSE_BYTES as u64))
         .and(warp_body_json())
-        .map(move |username: String, payload: PulsePayload| {
-            let ts = Utc::now().to_rfc3339();
-            // Include username in device_id to isolate user data
-            let device_id = format!("{}:{}", username, payload.device_id);
-            
-            match &payload.data {
-                Some(data_value) => {
-                    // Store data using new type system - it will automatically detect the type
-                    match store_device_data_payload(&db, &device_id, Some(&payload.device_id), &payload.topic, "", data_value, &ts, save_images) {
-                        Ok(_) => {
-                            println!("Data pulse from device {} (user: {}) - topic: {}", 
-                                payload.device_id, username, payload.topic);
-                            with_status(
-                                warp_json(&serde_json::json!({ "message": "pulse with data received" })),
-                                StatusCode::OK,
-                            )
-                        }
-                        Err(status_code) => {
-                            eprintln!("Failed to store pulse data for device {} (user: {})", payload.device_id, username);
-                            with_status(
-                                warp_json(&serde_json::json!({ "error": "pulse data storage failed" })),
-                                status_code,
-                            )
-                        }
-                    }
+        .map(move |caller: Caller, payload: IncomingPulse| {
+            if !caller.allows_device(&payload.device_id) {
+                return with_status(
+                    warp_json(&serde_json::json!({ "error": device_not_allowed(&payload.device_id) })),
+                    StatusCode::FORBIDDEN,
+                )
+                .into_response();
+            }
+            let owner = caller.owner;
+            let device_id = payload.device_id.clone();
+            let topic = payload.topic.clone();
+            let is_ping = payload.data.is_none();
+
+            match ingestor.ingest(&owner, payload) {
+                Ok(()) if is_ping => {
+                    println!("Ping pulse from device {} (owner: {})", device_id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({ "message": "ping pulse received" })),
+                        StatusCode::OK,
+                    )
+                    .into_response()
                 }
-                None => {
-                    // Handle simple ping - store as null data
-                    let ping_data = serde_json::json!(null);
-                    
-                    match store_device_data_payload(&db, &device_id, Some(&payload.device_id), &payload.topic, "", &ping_data, &ts, save_images) {
-                        Ok(_) => {
-                            println!("Ping pulse from device {} (user: {})", payload.device_id, username);
-                            with_status(
-                                warp_json(&serde_json::json!({ "message": "ping pulse received" })),
-                                StatusCode::OK,
-                            )
-                        }
-                        Err(status_code) => {
-                            eprintln!("Failed to store ping pulse for device {} (user: {})", payload.device_id, username);
-                            with_status(
-                                warp_json(&serde_json::json!({ "error": "ping pulse failed" })),
-                                status_code,
-                            )
-                        }
-                    }
+                Ok(()) => {
+                    println!("Data pulse from device {} (owner: {}) - topic: {}", 
+                        device_id, owner, topic);
+                    with_status(
+                        warp_json(&serde_json::json!({ "message": "pulse with data received" })),
+                        StatusCode::OK,
+                    )
+                    .into_response()
                 }
+                Err(IngestError::Invalid(e)) => with_status(
+                    warp_json(&serde_json::json!({ "error": e })),
+                    StatusCode::BAD_REQUEST,
+                )
+                .into_response(),
+                Err(IngestError::Limited(retry_after)) => too_many_requests(retry_after),
+                Err(IngestError::Storage(status_code)) => {
+                    eprintln!("Failed to store pulse for device {} (owner: {})", device_id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({
+                            "error": if is_ping { "ping pulse failed" } else { "pulse data storage failed" }
+                        })),
+                        status_code,
+                    )
+                    .into_response()
+                }
+            }
+        })
+}
+
+/// POST /api/pulse/batch - Store an array of pulses in a single transaction
+pub fn pulse_batch(
+    db: Database,
+    ingestor: Ingestor,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("api" / "pulse" / "batch"))
+        .and(pulse_caller(db, ingestor.limiter.clone()))
+        .and(content_length_limit(MAX_BATCH_BYTES))
+        .and(warp_body_json())
+        .map(move |caller: Caller, payload: Vec<IncomingPulse>| {
+            if let Some(pulse) = payload.iter().find(|pulse| !caller.allows_device(&pulse.device_id)) {
+                return with_status(
+                    warp_json(&serde_json::json!({ "error": device_not_allowed(&pulse.device_id) })),
+                    StatusCode::FORBIDDEN,
+                )
+                .into_response();
+            }
+            let owner = caller.owner;
+            if payload.is_empty() {
+                return with_status(
+                    warp_json(&serde_json::json!({ "error": "batch contains no records" })),
+                    StatusCode::BAD_REQUEST,
+                )
+                .into_response();
+            }
+            if payload.len() > MAX_BATCH_RECORDS {
+                return with_status(
+                    warp_json(&serde_json::json!({
+                        "error": format!("batch exceeds {} records", MAX_BATCH_RECORDS)
+                    })),
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                )
+                .into_response();
             }
+
+            let outcomes = match ingestor.ingest_batch(&owner, payload) {
+                Ok(outcomes) => outcomes,
+                Err(IngestError::Limited(retry_after)) => return too_many_requests(retry_after),
+                Err(IngestError::Invalid(e)) => {
+                    return with_status(warp_json(&serde_json::json!({ "error": e })), StatusCode::BAD_REQUEST)
+                        .into_response();
+                }
+                Err(IngestError::Storage(status_code)) => {
+                    eprintln!("Failed to store pulse batch (owner: {})", owner);
+                    return with_status(
+                        warp_json(&serde_json::json!({ "error": "pulse batch storage failed" })),
+                        status_code,
+                    )
+                    .into_response();
+                }
+            };
+
+            let results: Vec<serde_json::Value> = outcomes
+                .iter()
+                .enumerate()
+                .map(|(index, outcome)| match outcome {
+                    Ok(()) => serde_json::json!({ "index": index, "status": "stored" }),
+                    Err(e) => serde_json::json!({ "index": index, "status": "failed", "error": e.message() }),
+                })
+                .collect();
+
+            let failed = outcomes.iter().filter(|o| o.is_err()).count();
+            let stored_count = outcomes.len() - failed;
+            println!("Batch pulse from {} - {} stored, {} failed", owner, stored_count, failed);
+
+            with_status(
+                warp_json(&serde_json::json!({
+                    "stored": stored_count,
+                    "failed": failed,
+                    "results": results
+                })),
+                if failed == 0 { StatusCode::OK } else { StatusCode::MULTI_STATUS },
+            )
+            .into_response()
         })
 }
 
 pub fn list_all(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::get()
         .and(warp::path!("api" / "devices"))
         .and(warp::path::end())
         .and(auth)
-        .map(move |username: String| {
+        .map(move |owner: String| {
             // Get user's personal configuration
-            let config = get_user_config_or_default(&db, &username);
-            match list_user_devices(&db, &username, &config) {
+            let config = get_user_config_or_default(&db, &owner);
+            match list_user_devices(&db, &owner, &config) {
                 Ok(devices_json) => warp_json(&devices_json),
                 Err(_) => {
-                    eprintln!("Failed to list devices for user: {}", username);
+                    eprintln!("Failed to list devices of: {}", owner);
                     warp_json(&serde_json::json!({"error": "failed to list devices"}))
                 }
             }
@@ -112,14 +229,14 @@ pub fn list_all(
 pub fn list_one(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::get()
         .and(warp::path!("api" / "devices" / String))
         .and(auth)
-        .map(move |device_id: String, username: String| {
-            // Include username in device_id to get user-specific device
-            let full_device_id = format!("{}:{}", username, device_id);
-            let config = get_user_config_or_default(&db, &username);
+        .map(move |device_id: String, owner: String| {
+            // Include the owner in device_id to get their device
+            let full_device_id = format!("{}:{}", owner, device_id);
+            let config = get_user_config_or_default(&db, &owner);
             
             match get_device_data(&db, &full_device_id, &config) {
                 Ok(Some(topics_json)) => {
@@ -134,7 +251,7 @@ pub fn list_one(
                     warp_json(&serde_json::json!([]))
                 }
                 Err(_) => {
-                    eprintln!("Failed to get device {} for user: {}", device_id, username);
+                    eprintln!("Failed to get device {} of: {}", device_id, owner);
                     warp_json(&serde_json::json!({
                         "error": "failed to get device data"
                     }))
@@ -146,38 +263,105 @@ pub fn list_one(
 pub fn delete_device(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::post()
         .and(warp::path!("api" / "device" / "delete"))
         .and(warp::path::end())
         .and(auth)
         .and(warp_body_json())
-        .map(move |username: String, payload: DeleteDevicePayload| {
-            // Include username in device_id for user-specific deletion
-            let full_device_id = format!("{}:{}", username, payload.device_id);
+        .map(move |owner: String, payload: DeleteDevicePayload| {
+            // Include the owner in device_id to delete only their device
+            let full_device_id = format!("{}:{}", owner, payload.device_id);
             
-            match db_delete_device(&db, &full_device_id) {
+            let reply = match db_delete_device(&db, &full_device_id) {
                 Ok(true) => {
-                    println!("Deleted device {} (user: {})", payload.device_id, username);
+                    println!("Deleted device {} (owner: {})", payload.device_id, owner);
                     with_status(
                         warp_json(&serde_json::json!({ "message": "device deleted" })),
                         StatusCode::OK,
                     )
                 }
                 Ok(false) => {
-                    println!("Device {} not found for deletion (user: {})", payload.device_id, username);
+                    println!("Device {} not found for deletion (owner: {})", payload.device_id, owner);
                     with_status(
                         warp_json(&serde_json::json!({ "error": "device not found" })),
                         StatusCode::NOT_FOUND,
                     )
                 }
                 Err(status_code) => {
-                    eprintln!("Failed to delete device {} (user: {})", payload.device_id, username);
+                    eprintln!("Failed to delete device {} (owner: {})", payload.device_id, owner);
                     with_status(
                         warp_json(&serde_json::json!({ "error": "failed to delete device" })),
                         status_code,
                     )
                 }
+            };
+            with_audit_target(reply, &payload.device_id)
+        })
+}
+
+/// POST /api/devices/{device_id}/credentials - Create or rotate the secret a device uses
+/// to log in over MQTT or to sign UDP datagrams
+pub fn create_device_credentials(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "devices" / String / "credentials"))
+        .and(auth)
+        .map(move |device_id: String, owner: String| {
+            let full_device_id = format!("{}:{}", owner, device_id);
+            let secret = uuid::Uuid::new_v4().simple().to_string();
+
+            match set_device_credential(&db, &full_device_id, &secret) {
+                Ok(()) => {
+                    println!("Issued credentials for device {} (owner: {})", device_id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({
+                            "device_id": device_id,
+                            "username": full_device_id,
+                            "secret": secret,
+                        })),
+                        StatusCode::CREATED,
+                    )
+                }
+                Err(status_code) => {
+                    eprintln!("Failed to issue credentials for device {} (owner: {})", device_id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({ "error": "failed to create credentials" })),
+                        status_code,
+                    )
+                }
+            }
+        })
+}
+
+/// DELETE /api/devices/{device_id}/credentials - Revoke the secret of one device
+pub fn revoke_device_credentials(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "devices" / String / "credentials"))
+        .and(auth)
+        .map(move |device_id: String, owner: String| {
+            let full_device_id = format!("{}:{}", owner, device_id);
+            match delete_device_credential(&db, &full_device_id) {
+                Ok(true) => {
+                    println!("Revoked credentials for device {} (owner: {})", device_id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({ "message": "credentials revoked" })),
+                        StatusCode::OK,
+                    )
+                }
+                Ok(false) => with_status(
+                    warp_json(&serde_json::json!({ "error": "device has no credentials" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&serde_json::json!({ "error": "failed to revoke credentials" })),
+                    status_code,
+                ),
             }
         })
 }
@@ -254,13 +438,13 @@ pub fn update_config(
 pub fn get_user_config(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::get()
         .and(warp::path!("api" / "user" / "config"))
         .and(warp::path::end())
         .and(auth)
-        .map(move |username: String| {
-            let config = get_user_config_or_default(&db, &username);
+        .map(move |owner: String| {
+            let config = get_user_config_or_default(&db, &owner);
             with_status(
                 warp_json(&serde_json::json!({
                     "online_threshold_seconds": config.online_threshold_seconds,
@@ -276,13 +460,13 @@ pub fn get_user_config(
 pub fn set_user_config(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::post()
         .and(warp::path!("api" / "user" / "config"))
         .and(warp::path::end())
         .and(auth)
         .and(warp_body_json())
-        .map(move |username: String, payload: ConfigUpdateRequest| {
+        .map(move |owner: String, payload: ConfigUpdateRequest| {
             // Validate thresholds
             if payload.online_threshold_seconds >= payload.warning_threshold_seconds {
                 return with_status(
@@ -306,9 +490,11 @@ pub fn set_user_config(
                 online_threshold_seconds: payload.online_threshold_seconds,
                 warning_threshold_seconds: payload.warning_threshold_seconds,
                 stale_threshold_seconds: payload.stale_threshold_seconds,
+                overrides: Vec::new(),
+                maintenance: Vec::new(),
             };
 
-            match db_set_user_config(&db, &username, &config) {
+            match db_set_user_config(&db, &owner, &config) {
                 Ok(_) => {
                     with_status(
                         warp_json(&serde_json::json!({ 
@@ -329,28 +515,247 @@ pub fn set_user_config(
         })
 }
 
-/// GET /api/devices/{device_id}/history?time_range={1h|1d|1w|1m}&topic={topic_name} - Get pulse history for visualization
+/// GET /api/user/retention - List the caller's retention rules
+pub fn get_retention(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "user" / "retention"))
+        .and(auth)
+        .map(move |owner: String| match list_retention_policies(&db, &owner) {
+            Ok(policies) => with_status(warp_json(&policies), StatusCode::OK),
+            Err(status_code) => with_status(
+                warp_json(&serde_json::json!({ "error": "Failed to list retention rules" })),
+                status_code,
+            ),
+        })
+}
+
+/// POST /api/user/retention - Create a retention rule, or change the keep time of
+/// the rule with the same device, topic and data type
+pub fn set_retention(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "user" / "retention"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |owner: String, payload: RetentionRequest| {
+            let keep_seconds = match parse_duration(&payload.keep) {
+                Ok(seconds) if seconds > 0 && seconds <= MAX_KEEP_SECONDS => seconds,
+                Ok(_) => {
+                    return with_status(
+                        warp_json(&serde_json::json!({ "error": "keep must be between 1 second and 100 years" })),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+                Err(e) => return with_status(warp_json(&serde_json::json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            let data_type = payload.data_type.as_deref().filter(|t| !t.is_empty());
+            if let Some(data_type) = data_type {
+                if !DataType::TYPE_NAMES.contains(&data_type) && data_type != ROLLUP_TYPE {
+                    return with_status(
+                        warp_json(&serde_json::json!({
+                            "error": format!(
+                                "unknown data type '{}', expected one of {} or {}",
+                                data_type, DataType::TYPE_NAMES.join(", "), ROLLUP_TYPE
+                            )
+                        })),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+            }
+
+            let device_id = payload.device_id.as_deref().filter(|d| !d.is_empty());
+            let topic = payload.topic.as_deref().filter(|t| !t.is_empty());
+            match set_retention_policy(&db, &owner, device_id, topic, data_type, keep_seconds) {
+                Ok(policy) => {
+                    println!("Set retention rule {} to {}s (owner: {})", policy.id, keep_seconds, owner);
+                    with_status(warp_json(&policy), StatusCode::OK)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&serde_json::json!({ "error": "Failed to set retention rule" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/user/retention/{id} - Remove a retention rule
+pub fn delete_retention(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "user" / "retention" / i64))
+        .and(auth)
+        .map(move |id: i64, owner: String| match delete_retention_policy(&db, &owner, id) {
+            Ok(true) => with_status(
+                warp_json(&serde_json::json!({ "message": "retention rule removed" })),
+                StatusCode::OK,
+            ),
+            Ok(false) => with_status(
+                warp_json(&serde_json::json!({ "error": "retention rule not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&serde_json::json!({ "error": "Failed to remove retention rule" })),
+                status_code,
+            ),
+        })
+}
+
+/// GET /api/user/thresholds - List the caller's per-device and per-topic thresholds
+pub fn get_thresholds(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "user" / "thresholds"))
+        .and(auth)
+        .map(move |owner: String| match list_status_overrides(&db, &owner) {
+            Ok(overrides) => with_status(warp_json(&overrides), StatusCode::OK),
+            Err(status_code) => with_status(
+                warp_json(&serde_json::json!({ "error": "Failed to list thresholds" })),
+                status_code,
+            ),
+        })
+}
+
+/// POST /api/user/thresholds - Set the thresholds of a device or topic, replacing
+/// any it had. Thresholds left out are taken from the user's configuration; with
+/// `learn` they follow the observed publish interval instead.
+pub fn set_thresholds(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "user" / "thresholds"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |owner: String, payload: ThresholdRequest| {
+            let bad_request = |error: &str| {
+                with_status(warp_json(&serde_json::json!({ "error": error })), StatusCode::BAD_REQUEST)
+            };
+            let device_id = payload.device_id.as_deref().map(str::trim).filter(|d| !d.is_empty());
+            let topic = payload.topic.as_deref().map(str::trim).filter(|t| !t.is_empty());
+            if device_id.is_none() && topic.is_none() {
+                return bad_request("a device, a topic or both are required");
+            }
+            let given = [
+                payload.online_threshold_seconds,
+                payload.warning_threshold_seconds,
+                payload.stale_threshold_seconds,
+            ];
+
+            let thresholds = if payload.learn {
+                if given.iter().any(Option::is_some) {
+                    return bad_request("learned thresholds cannot be combined with explicit ones");
+                }
+                if device_id.is_none() {
+                    return bad_request("learned thresholds need a device");
+                }
+                None
+            } else {
+                if given.iter().all(Option::is_none) {
+                    return bad_request("give at least one threshold, or learn them");
+                }
+                let base = get_user_config_or_default(&db, &owner);
+                let thresholds = [
+                    given[0].unwrap_or(base.online_threshold_seconds),
+                    given[1].unwrap_or(base.warning_threshold_seconds),
+                    given[2].unwrap_or(base.stale_threshold_seconds),
+                ];
+                if thresholds[0] == 0 || thresholds[0] >= thresholds[1] || thresholds[1] >= thresholds[2] {
+                    return bad_request("thresholds must satisfy 0 < online < warning < stale");
+                }
+                Some(thresholds)
+            };
+
+            let created = match set_status_override(&db, &owner, device_id, topic, thresholds) {
+                Ok(created) => created,
+                Err(status_code) => {
+                    return with_status(
+                        warp_json(&serde_json::json!({ "error": "Failed to set thresholds" })),
+                        status_code,
+                    );
+                }
+            };
+            println!("Set thresholds {} (owner: {})", created.id, owner);
+            if !created.learned {
+                return with_status(warp_json(&created), StatusCode::OK);
+            }
+
+            // Learn right away from the data already stored
+            let learned = refresh_learned_override(&db, &owner, &created)
+                .and_then(|_| list_status_overrides(&db, &owner));
+            match learned {
+                Ok(overrides) => {
+                    let learned = overrides.into_iter().find(|o| o.id == created.id).unwrap_or(created);
+                    with_status(warp_json(&learned), StatusCode::OK)
+                }
+                Err(status_code) => {
+                    eprintln!("Failed to learn thresholds {} (owner: {})", created.id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({ "error": "Thresholds were set but could not be learned yet" })),
+                        status_code,
+                    )
+                }
+            }
+        })
+}
+
+/// DELETE /api/user/thresholds/{id} - Remove the thresholds of a device or topic
+pub fn delete_thresholds(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "user" / "thresholds" / i64))
+        .and(auth)
+        .map(move |id: i64, owner: String| match delete_status_override(&db, &owner, id) {
+            Ok(true) => with_status(
+                warp_json(&serde_json::json!({ "message": "thresholds removed" })),
+                StatusCode::OK,
+            ),
+            Ok(false) => with_status(
+                warp_json(&serde_json::json!({ "error": "thresholds not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&serde_json::json!({ "error": "Failed to remove thresholds" })),
+                status_code,
+            ),
+        })
+}
+
+/// GET /api/devices/{device_id}/history?from={time}&to={time}&bucket={duration}&topic={topic_name} - Get pulse history for visualization
 pub fn get_device_history(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::get()
         .and(warp::path!("api" / "devices" / String / "history"))
         .and(warp::query::<std::collections::HashMap<String, String>>())
         .and(auth)
-        .map(move |device_id: String, params: std::collections::HashMap<String, String>, username: String| {
-            // Include username in device_id to get user-specific device
-            let full_device_id = format!("{}:{}", username, device_id);
+        .map(move |device_id: String, params: std::collections::HashMap<String, String>, owner: String| {
+            // Include the owner in device_id to get their device
+            let full_device_id = format!("{}:{}", owner, device_id);
             
-            let time_range = params.get("time_range").map(|s| s.as_str()).unwrap_or("1d");
             let topic = params.get("topic").map(|s| s.as_str());
+            let window = match parse_time_window(&params) {
+                Ok(window) => window,
+                Err(reply) => return reply,
+            };
             
-            match get_pulse_history(&db, &full_device_id, topic, time_range) {
+            match get_pulse_history(&db, &full_device_id, topic, &window) {
                 Ok(history_data) => {
                     with_status(warp_json(&history_data), StatusCode::OK)
                 }
                 Err(status_code) => {
-                    eprintln!("Failed to get pulse history for device {} (user: {})", device_id, username);
+                    eprintln!("Failed to get pulse history for device {} (owner: {})", device_id, owner);
                     with_status(
                         warp_json(&serde_json::json!({ 
                             "error": "Failed to get pulse history" 
@@ -362,27 +767,42 @@ pub fn get_device_history(
         })
 }
 
-/// GET /api/devices/{device_id}/stats?time_range={1h|1d|1w|1m} - Get pulse statistics
+/// Read the `from`, `to`, `bucket` and legacy `time_range` query parameters
+fn parse_time_window(params: &std::collections::HashMap<String, String>) -> Result<TimeWindow, warp::reply::WithStatus<warp::reply::Json>> {
+    TimeWindow::parse(
+        params.get("from").map(|s| s.as_str()),
+        params.get("to").map(|s| s.as_str()),
+        params.get("bucket").map(|s| s.as_str()),
+        params.get("time_range").map(|s| s.as_str()),
+        chrono::Utc::now(),
+    )
+    .map_err(|message| with_status(warp_json(&serde_json::json!({ "error": message })), StatusCode::BAD_REQUEST))
+}
+
+/// GET /api/devices/{device_id}/stats?from={time}&to={time}&bucket={duration} - Get pulse statistics
 pub fn get_device_stats(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::get()
         .and(warp::path!("api" / "devices" / String / "stats"))
         .and(warp::query::<std::collections::HashMap<String, String>>())
         .and(auth)
-        .map(move |device_id: String, params: std::collections::HashMap<String, String>, username: String| {
-            // Include username in device_id to get user-specific device
-            let full_device_id = format!("{}:{}", username, device_id);
+        .map(move |device_id: String, params: std::collections::HashMap<String, String>, owner: String| {
+            // Include the owner in device_id to get their device
+            let full_device_id = format!("{}:{}", owner, device_id);
             
-            let time_range = params.get("time_range").map(|s| s.as_str()).unwrap_or("1d");
+            let window = match parse_time_window(&params) {
+                Ok(window) => window,
+                Err(reply) => return reply,
+            };
             
-            match get_pulse_stats(&db, &full_device_id, time_range) {
+            match get_pulse_stats(&db, &full_device_id, &window) {
                 Ok(stats_data) => {
                     with_status(warp_json(&stats_data), StatusCode::OK)
                 }
                 Err(status_code) => {
-                    eprintln!("Failed to get pulse stats for device {} (user: {})", device_id, username);
+                    eprintln!("Failed to get pulse stats for device {} (owner: {})", device_id, owner);
                     with_status(
                         warp_json(&serde_json::json!({ 
                             "error": "Failed to get pulse statistics" 
@@ -394,28 +814,162 @@ pub fn get_device_stats(
         })
 }
 
-/// GET /api/devices/{device_id}/data?topic={topic_name}&type={data_type} - Get latest data for a device
+/// GET /api/devices/{device_id}/uptime?from={time}&to={time}&topic={topic_name} - Availability, outages and time between failures
+pub fn get_device_uptime(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "devices" / String / "uptime"))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth)
+        .map(move |device_id: String, params: std::collections::HashMap<String, String>, owner: String| {
+            let full_device_id = format!("{}:{}", owner, device_id);
+            let topic = params.get("topic").map(|s| s.as_str());
+            let mut window = match parse_time_window(&params) {
+                Ok(window) => window,
+                Err(reply) => return reply,
+            };
+            // Nothing is known about the future
+            window.to = window.to.min(chrono::Utc::now()).max(window.from);
+
+            match list_status_transitions(&db, &full_device_id, topic, &window) {
+                Ok((initial, transitions)) => {
+                    let report = uptime_report(initial.as_deref(), &transitions, window.from, window.to);
+                    let mut body = serde_json::json!(report);
+                    body["device_id"] = serde_json::json!(device_id);
+                    body["topic"] = serde_json::json!(topic);
+                    with_status(warp_json(&body), StatusCode::OK)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&serde_json::json!({ "error": "Failed to get uptime" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// GET /api/devices/{device_id}/transitions?from={time}&to={time}&topic={topic_name} - Recorded status changes, oldest first
+pub fn get_device_transitions(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "devices" / String / "transitions"))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth)
+        .map(move |device_id: String, params: std::collections::HashMap<String, String>, owner: String| {
+            let full_device_id = format!("{}:{}", owner, device_id);
+            let topic = params.get("topic").map(|s| s.as_str());
+            let window = match parse_time_window(&params) {
+                Ok(window) => window,
+                Err(reply) => return reply,
+            };
+
+            match list_status_transitions(&db, &full_device_id, topic, &window) {
+                Ok((initial, transitions)) => with_status(
+                    warp_json(&serde_json::json!({
+                        "device_id": device_id,
+                        "topic": topic,
+                        "from": window.from.to_rfc3339(),
+                        "to": window.to.to_rfc3339(),
+                        "initial_status": initial,
+                        "transitions": transitions,
+                    })),
+                    StatusCode::OK,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&serde_json::json!({ "error": "Failed to get status transitions" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// GET /api/devices/{device_id}/aggregate?topic={topic_name}&from={time}&to={time}&bucket={duration}&percentiles={list} - Sensor statistics per bucket
+pub fn get_device_aggregate(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "devices" / String / "aggregate"))
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(auth)
+        .map(move |device_id: String, params: std::collections::HashMap<String, String>, owner: String| {
+            // Include the owner in device_id to get their device
+            let full_device_id = format!("{}:{}", owner, device_id);
+
+            let Some(topic) = params.get("topic") else {
+                return with_status(
+                    warp_json(&serde_json::json!({ "error": "topic is required" })),
+                    StatusCode::BAD_REQUEST,
+                );
+            };
+            let window = match parse_time_window(&params) {
+                Ok(window) => window,
+                Err(reply) => return reply,
+            };
+            if let Err(message) = check_range(&window) {
+                return with_status(warp_json(&serde_json::json!({ "error": message })), StatusCode::BAD_REQUEST);
+            }
+            let percentiles = match params.get("percentiles") {
+                Some(list) => match parse_percentiles(list) {
+                    Ok(percentiles) => percentiles,
+                    Err(message) => {
+                        return with_status(warp_json(&serde_json::json!({ "error": message })), StatusCode::BAD_REQUEST)
+                    }
+                },
+                None => DEFAULT_PERCENTILES.to_vec(),
+            };
+            let bucketed = params.contains_key("bucket");
+
+            match get_sensor_aggregates(&db, &full_device_id, topic, &window, bucketed, &percentiles) {
+                Ok(aggregates) => with_status(warp_json(&aggregates), StatusCode::OK),
+                Err(status_code) => {
+                    eprintln!("Failed to aggregate sensor data for device {} (owner: {})", device_id, owner);
+                    with_status(
+                        warp_json(&serde_json::json!({
+                            "error": "Failed to aggregate sensor data"
+                        })),
+                        status_code,
+                    )
+                }
+            }
+        })
+}
+
+/// GET /api/devices/{device_id}/data?topic={topic_name}&type={data_type}&limit={n}&before={cursor}&after={cursor} - Get a page of a device's data
 pub fn get_device_data_latest(
     db: Database,
 ) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
-    let auth = authenticated_user(db.clone());
+    let auth = authenticated_owner(db.clone());
     warp::get()
         .and(warp::path!("api" / "devices" / String / "data"))
         .and(warp::query::<std::collections::HashMap<String, String>>())
         .and(auth)
-        .map(move |device_id: String, params: std::collections::HashMap<String, String>, username: String| {
-            // Include username in device_id to get user-specific device
-            let full_device_id = format!("{}:{}", username, device_id);
+        .map(move |device_id: String, params: std::collections::HashMap<String, String>, owner: String| {
+            // Include the owner in device_id to get their device
+            let full_device_id = format!("{}:{}", owner, device_id);
             
             let topic = params.get("topic").map(|s| s.as_str());
             let data_type = params.get("type").map(|s| s.as_str());
+            let page = match DataPage::parse(
+                params.get("limit").map(|s| s.as_str()),
+                params.get("before").map(|s| s.as_str()),
+                params.get("after").map(|s| s.as_str()),
+            ) {
+                Ok(page) => page,
+                Err(message) => {
+                    return with_status(warp_json(&serde_json::json!({ "error": message })), StatusCode::BAD_REQUEST)
+                }
+            };
             
-            match get_device_latest_data(&db, &full_device_id, topic, data_type) {
+            match get_device_latest_data(&db, &full_device_id, topic, data_type, &page) {
                 Ok(data_response) => {
                     with_status(warp_json(&data_response), StatusCode::OK)
                 }
                 Err(status_code) => {
-                    eprintln!("Failed to get latest data for device {} (user: {})", device_id, username);
+                    eprintln!("Failed to get latest data for device {} (owner: {})", device_id, owner);
                     with_status(
                         warp_json(&serde_json::json!({ 
                             "error": "Failed to get latest data" 
@@ -0,0 +1,120 @@
+use crate::logic::serve::auth::authenticated_owner;
+use crate::logic::serve::database::{get_email_settings, set_email_settings, Database};
+use crate::logic::serve::email::EmailSettings;
+use crate::logic::serve::smtp::{Message, SmtpConfig};
+use serde_json::json;
+use std::sync::Arc;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+/// Email notification settings and a test message
+pub fn email_routes(
+    db: Database,
+    smtp: Option<Arc<SmtpConfig>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    get_settings(db.clone(), smtp.is_some())
+        .or(set_settings(db.clone()))
+        .or(send_test(db, smtp))
+}
+
+/// GET /api/notifications/email - The caller's email settings and whether the
+/// server has SMTP configured
+pub fn get_settings(
+    db: Database,
+    smtp_enabled: bool,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "notifications" / "email"))
+        .and(auth)
+        .map(move |owner: String| match get_email_settings(&db, &owner) {
+            Ok(settings) => {
+                let mut body = json!(settings.unwrap_or_default());
+                body["smtp_enabled"] = json!(smtp_enabled);
+                with_status(warp_json(&body), StatusCode::OK)
+            }
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to load email settings" })),
+                status_code,
+            ),
+        })
+}
+
+/// PUT /api/notifications/email - Replace the caller's email settings
+pub fn set_settings(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::put()
+        .and(warp::path!("api" / "notifications" / "email"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |owner: String, settings: EmailSettings| {
+            let settings = match settings.normalize() {
+                Ok(settings) => settings,
+                Err(e) => return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            match set_email_settings(&db, &owner, &settings) {
+                Ok(()) => with_status(warp_json(&settings), StatusCode::OK),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to save email settings" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// POST /api/notifications/email/test - Send a test message to the caller's recipients right away
+pub fn send_test(
+    db: Database,
+    smtp: Option<Arc<SmtpConfig>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "notifications" / "email" / "test"))
+        .and(auth)
+        .and_then(move |owner: String| {
+            let db = db.clone();
+            let smtp = smtp.clone();
+            async move {
+                let Some(smtp) = smtp else {
+                    return Ok::<_, Rejection>(with_status(
+                        warp_json(&json!({ "error": "email is not configured on this server" })),
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    ));
+                };
+                let recipients = match get_email_settings(&db, &owner) {
+                    Ok(settings) => settings.map(|s| s.recipients).unwrap_or_default(),
+                    Err(status_code) => {
+                        return Ok(with_status(
+                            warp_json(&json!({ "error": "Failed to load email settings" })),
+                            status_code,
+                        ));
+                    }
+                };
+                if recipients.is_empty() {
+                    return Ok(with_status(
+                        warp_json(&json!({ "error": "no recipients configured" })),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+
+                let message = Message {
+                    to: recipients,
+                    subject: "[pulson] Test message".to_string(),
+                    body: format!("Status emails for {} will be sent to this address.\n", owner),
+                };
+                Ok(match smtp.send(&message).await {
+                    Ok(()) => with_status(
+                        warp_json(&json!({ "message": format!("test message sent to {} recipient(s)", message.to.len()) })),
+                        StatusCode::OK,
+                    ),
+                    Err(e) => with_status(
+                        warp_json(&json!({ "error": format!("sending failed: {}", e) })),
+                        StatusCode::BAD_GATEWAY,
+                    ),
+                })
+            }
+        })
+}
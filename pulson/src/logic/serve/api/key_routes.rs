@@ -0,0 +1,90 @@
+use crate::logic::serve::api_keys::{generate_key, hash_key, shown_prefix, ApiKeySpec};
+use crate::logic::serve::auth::{authenticated_caller, authenticated_user, Caller};
+use crate::logic::serve::database::{create_api_key, delete_api_key, list_api_keys, Database};
+use serde_json::json;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+/// API keys; API keys cannot reach these routes themselves
+pub fn key_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list_keys(db.clone())
+        .or(create_key(db.clone()))
+        .or(delete_key(db))
+}
+
+/// GET /api/keys - List the caller's API keys (never the keys themselves)
+pub fn list_keys(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "keys"))
+        .and(auth)
+        .map(move |username: String| match list_api_keys(&db, &username) {
+            Ok(keys) => with_status(warp_json(&keys), StatusCode::OK),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to list API keys" })),
+                status_code,
+            ),
+        })
+}
+
+/// POST /api/keys - Create an API key; the response is the only place the key appears.
+/// A key created for an organization acts for it, while its creator stays a member.
+pub fn create_key(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_caller(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "keys"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |caller: Caller, spec: ApiKeySpec| {
+            let username = caller.username;
+            let org = (caller.owner != username).then_some(caller.owner);
+            let new_key = match spec.resolve(chrono::Utc::now()) {
+                Ok(new_key) => new_key,
+                Err(e) => return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            let key = generate_key();
+            match create_api_key(&db, &username, org.as_deref(), &hash_key(&key), &shown_prefix(&key), &new_key) {
+                Ok(created) => {
+                    println!("Created API key {} '{}' (user: {})", created.id, created.name, username);
+                    let mut body = json!(created);
+                    body["key"] = json!(key);
+                    with_status(warp_json(&body), StatusCode::CREATED)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to create API key" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/keys/{id} - Revoke an API key
+pub fn delete_key(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "keys" / i64))
+        .and(auth)
+        .map(move |id: i64, username: String| match delete_api_key(&db, &username, id) {
+            Ok(true) => {
+                println!("Revoked API key {} (user: {})", id, username);
+                with_status(warp_json(&json!({ "message": "API key revoked" })), StatusCode::OK)
+            }
+            Ok(false) => with_status(
+                warp_json(&json!({ "error": "API key not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to revoke API key" })),
+                status_code,
+            ),
+        })
+}
@@ -0,0 +1,99 @@
+use crate::logic::serve::auth::authenticated_owner;
+use crate::logic::serve::database::{
+    create_maintenance_window, delete_maintenance_window, list_maintenance_windows, Database,
+};
+use crate::logic::serve::maintenance::MaintenanceSpec;
+use serde_json::json;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct MaintenanceQuery {
+    /// Also list windows that are over
+    #[serde(default)]
+    all: bool,
+}
+
+/// Maintenance windows and silences
+pub fn maintenance_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list_windows(db.clone())
+        .or(create_window(db.clone()))
+        .or(delete_window(db))
+}
+
+/// GET /api/maintenance?all={bool} - List the caller's current and upcoming windows, or all of them
+pub fn list_windows(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "maintenance"))
+        .and(warp::query::<MaintenanceQuery>())
+        .and(auth)
+        .map(move |query: MaintenanceQuery, owner: String| {
+            match list_maintenance_windows(&db, &owner, query.all) {
+                Ok(windows) => with_status(warp_json(&windows), StatusCode::OK),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to list maintenance windows" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// POST /api/maintenance - Schedule a maintenance window or silence
+pub fn create_window(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "maintenance"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |owner: String, spec: MaintenanceSpec| {
+            let window = match spec.resolve(chrono::Utc::now()) {
+                Ok(window) => window,
+                Err(e) => return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST),
+            };
+            match create_maintenance_window(&db, &owner, &window) {
+                Ok(window) => {
+                    println!(
+                        "Scheduled {:?} window {} until {} (owner: {}): {}",
+                        window.kind, window.id, window.ends_at, owner, window.reason
+                    );
+                    with_status(warp_json(&window), StatusCode::OK)
+                }
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to create maintenance window" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/maintenance/{id} - Remove a window, ending it right away
+pub fn delete_window(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "maintenance" / i64))
+        .and(auth)
+        .map(move |id: i64, owner: String| match delete_maintenance_window(&db, &owner, id) {
+            Ok(true) => with_status(
+                warp_json(&json!({ "message": "maintenance window removed" })),
+                StatusCode::OK,
+            ),
+            Ok(false) => with_status(
+                warp_json(&json!({ "error": "maintenance window not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to remove maintenance window" })),
+                status_code,
+            ),
+        })
+}
@@ -1,13 +1,26 @@
 pub mod account_routes;
+pub mod admin_routes;
+pub mod alert_routes;
 pub mod device_routes;
+pub mod key_routes;
+pub mod email_routes;
+pub mod maintenance_routes;
+pub mod org_routes;
 pub mod password_utils;
+pub mod stream_routes;
+pub mod udp_routes;
+pub mod ws_routes;
 pub mod user_management;
+pub mod webhook_routes;
 pub mod token_service; // Add this line
 
 use crate::logic::serve::api::account_routes::{delete_user, list_users, login, register, user_info}; // Added user_info
 // use crate::logic::serve::api::device_routes::{list_all, list_one, ping, delete_device};
 use crate::logic::serve::database::Database;
-use crate::logic::config::StatusConfig;
+use crate::logic::serve::ingest::Ingestor;
+use crate::logic::serve::rate_limit::RateLimiter;
+use crate::logic::serve::udp::UdpStats;
+use crate::logic::config::{ServerConfig, StatusConfig};
 use std::sync::{Arc, Mutex};
 use warp::Filter;
 
@@ -16,28 +29,67 @@ pub fn api_routes(
     db: Database,
     root_pass: Option<String>,
     status_config: Arc<Mutex<StatusConfig>>,
-    save_images: bool,
+    ingestor: Ingestor,
+    udp_stats: Arc<UdpStats>,
+    server_config: &ServerConfig,
+    limiter: RateLimiter,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let reg = register(db.clone(), root_pass.clone());
-    let log = login(db.clone());
+    let reg = register(db.clone(), root_pass.clone(), limiter.clone());
+    let log = login(db.clone(), limiter.clone());
     let logout_route = crate::logic::serve::api::account_routes::logout(db.clone()); // Add logout
+    let sessions = account_routes::refresh(db.clone()) // Token refresh and session management
+        .or(account_routes::session_routes(db.clone()));
     let del = delete_user(db.clone());
     let list = list_users(db.clone());
     let userinfo_route = user_info(db.clone()); // Add userinfo route
+    let keys = key_routes::key_routes(db.clone()); // Scoped API keys
+    let admin = admin_routes::admin_routes(db.clone()) // Role assignment
+        .or(org_routes::org_routes(db.clone())); // Organizations and their members
 
-    let p = device_routes::pulse(db.clone(), save_images);
+    let events = ingestor.events.clone();
+    let p = device_routes::pulse(db.clone(), ingestor.clone());
+    let pb = device_routes::pulse_batch(db.clone(), ingestor.clone());
+    let pws = ws_routes::pulse_socket(db.clone(), ingestor); // WebSocket ingestion
+    let live = stream_routes::stream(db.clone(), events); // Server-Sent Events
+    let udp = udp_routes::udp_stats(db.clone(), udp_stats); // UDP listener counters
     let la = device_routes::list_all(db.clone());
     let lo = device_routes::list_one(db.clone());
     let dd = device_routes::delete_device(db.clone()); // Add delete_device route
+    let creds_new = device_routes::create_device_credentials(db.clone()); // MQTT device login
+    let creds_del = device_routes::revoke_device_credentials(db.clone());
     // config_reload route removed - no longer needed with purely server-based configuration
     let config_get = device_routes::get_config(status_config.clone()); // Add config get route
     let config_update = device_routes::update_config(status_config.clone(), db.clone()); // Add config update route
     let user_config_get = device_routes::get_user_config(db.clone()); // Add user config get route
     let user_config_set = device_routes::set_user_config(db.clone()); // Add user config set route
+    let retention_get = device_routes::get_retention(db.clone()); // Data retention rules
+    let retention_set = device_routes::set_retention(db.clone());
+    let retention_del = device_routes::delete_retention(db.clone());
+    let thresholds = device_routes::get_thresholds(db.clone()) // Per-device and per-topic thresholds
+        .or(device_routes::set_thresholds(db.clone()))
+        .or(device_routes::delete_thresholds(db.clone()));
     let device_history = device_routes::get_device_history(db.clone()); // Add pulse history route
     let device_stats = device_routes::get_device_stats(db.clone()); // Add pulse stats route
+    let uptime = device_routes::get_device_uptime(db.clone()) // Status transitions and availability
+        .or(device_routes::get_device_transitions(db.clone()));
+    let device_aggregate = device_routes::get_device_aggregate(db.clone()); // Sensor statistics per bucket
     let device_data_latest = device_routes::get_device_data_latest(db.clone()); // Add device data route
+    let alerts = alert_routes::alert_routes(db.clone()); // Alert rules and history
+    let webhooks = webhook_routes::webhook_routes(db.clone(), Arc::new(server_config.webhooks.clone())); // Webhooks and their delivery logs
+    let email = email_routes::email_routes(db.clone(), server_config.smtp.clone().map(Arc::new)); // Email notification settings
+    let maintenance = maintenance_routes::maintenance_routes(db.clone()); // Maintenance windows and silences
+    let notifications = webhooks.or(email).or(maintenance);
 
-    // Routes already include /api prefix in their individual definitions
-    reg.or(log).or(logout_route).or(del).or(list).or(userinfo_route).or(p).or(lo).or(la).or(dd).or(config_get).or(config_update).or(user_config_get).or(user_config_set).or(device_history).or(device_stats).or(device_data_latest)
+    // Routes already include /api prefix in their individual definitions. They
+    // are combined in boxed groups: one long `.or()` chain nests too deep a type
+    // for the compiler.
+    let account = reg.or(log).or(logout_route).or(sessions).or(del).or(list).or(userinfo_route).or(keys).or(admin).boxed();
+    let ingest = p.or(pb).or(pws).boxed();
+    let devices = lo.or(la).or(dd).or(creds_new).or(creds_del).boxed();
+    let config = config_get.or(config_update).or(user_config_get).or(user_config_set).or(retention_get).or(retention_set).or(retention_del).or(thresholds).boxed();
+    let data = device_history.or(device_stats).or(uptime).or(device_aggregate).or(device_data_latest).boxed();
+    let notify = alerts.or(notifications).boxed();
+    let streams = live.or(udp).boxed();
+
+    account.or(ingest).or(devices).or(config).or(data).or(notify).or(streams)
 }
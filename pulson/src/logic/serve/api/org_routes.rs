@@ -0,0 +1,314 @@
+use crate::logic::serve::api_keys::Scope;
+use crate::logic::serve::auth::authenticated_user;
+use crate::logic::serve::database::{
+    count_org_admins, create_organization, delete_organization, get_org_role, get_user_role, list_org_members,
+    list_organizations, list_owner_device_ids, move_devices, remove_org_member, set_org_member, Database,
+};
+use crate::logic::serve::orgs::validate_org_name;
+use crate::logic::serve::roles::{user_grants, Role};
+use serde::Deserialize;
+use serde_json::json;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+#[derive(Deserialize)]
+struct OrgPayload {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct MemberPayload {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct MovePayload {
+    #[serde(default)]
+    devices: Vec<String>,
+    /// Move every personal device of the caller
+    #[serde(default)]
+    all: bool,
+}
+
+/// Organizations, their members and moving personal devices into them. Each
+/// route checks the caller's role in the organization itself.
+pub fn org_routes(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list_orgs(db.clone())
+        .or(create_org(db.clone()))
+        .or(delete_org(db.clone()))
+        .or(list_members(db.clone()))
+        .or(set_member(db.clone()))
+        .or(remove_member(db.clone()))
+        .or(move_into_org(db))
+}
+
+/// GET /api/orgs - The organizations the caller belongs to, with their role in each
+pub fn list_orgs(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "orgs"))
+        .and(auth)
+        .map(move |username: String| match list_organizations(&db, &username) {
+            Ok(orgs) => with_status(warp_json(&orgs), StatusCode::OK),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to list organizations" })),
+                status_code,
+            ),
+        })
+}
+
+/// POST /api/orgs - Create an organization with the caller as its admin
+pub fn create_org(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "orgs"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |username: String, payload: OrgPayload| {
+            let name = payload.name.trim();
+            if let Err(e) = validate_org_name(name) {
+                return with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST);
+            }
+            match create_organization(&db, name, &username) {
+                Ok(org) => {
+                    println!("Created organization {} (admin: {})", org.name, username);
+                    with_status(warp_json(&org), StatusCode::CREATED)
+                }
+                Err(StatusCode::CONFLICT) => with_status(
+                    warp_json(&json!({ "error": format!("the name '{}' is already taken", name) })),
+                    StatusCode::CONFLICT,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to create organization" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/orgs/{org} - Delete an empty organization (org or server admins)
+pub fn delete_org(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "orgs" / String))
+        .and(auth)
+        .map(move |org: String, username: String| {
+            let server_admin = matches!(get_user_role(&db, &username), Ok(Some(role)) if role.is_admin());
+            if !server_admin {
+                if let Err(reply) = require_org_role(&db, &org, &username, Role::is_admin) {
+                    return reply;
+                }
+            }
+            match delete_organization(&db, &org) {
+                Ok(true) => {
+                    println!("Deleted organization {} (by {})", org, username);
+                    with_status(warp_json(&json!({ "message": "Organization deleted" })), StatusCode::OK)
+                }
+                Ok(false) => with_status(
+                    warp_json(&json!({ "error": "organization not found" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(StatusCode::CONFLICT) => with_status(
+                    warp_json(&json!({ "error": "the organization still has devices; move or delete them first" })),
+                    StatusCode::CONFLICT,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to delete organization" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// GET /api/orgs/{org}/members - The members of an organization and their roles
+pub fn list_members(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "orgs" / String / "members"))
+        .and(auth)
+        .map(move |org: String, username: String| {
+            if let Err(reply) = require_org_role(&db, &org, &username, |_| true) {
+                return reply;
+            }
+            match list_org_members(&db, &org) {
+                Ok(members) => with_status(warp_json(&members), StatusCode::OK),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to list members" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// PUT /api/orgs/{org}/members/{username} - Add a member or change their role (org admins)
+pub fn set_member(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::put()
+        .and(warp::path!("api" / "orgs" / String / "members" / String))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |org: String, member: String, username: String, payload: MemberPayload| {
+            if let Err(reply) = require_org_role(&db, &org, &username, Role::is_admin) {
+                return reply;
+            }
+            let Some(role) = Role::parse(payload.role.trim()) else {
+                let known: Vec<&str> = Role::ALL.iter().map(Role::as_str).collect();
+                return with_status(
+                    warp_json(&json!({ "error": format!("unknown role '{}' (expected one of {})", payload.role, known.join(", ")) })),
+                    StatusCode::BAD_REQUEST,
+                );
+            };
+            if role != Role::Admin && is_last_org_admin(&db, &org, &member) {
+                return with_status(
+                    warp_json(&json!({ "error": "the last admin of an organization cannot give up the admin role" })),
+                    StatusCode::CONFLICT,
+                );
+            }
+            match set_org_member(&db, &org, &member, role) {
+                Ok(true) => {
+                    println!("Set role of {} in {} to {} (by {})", member, org, role.as_str(), username);
+                    with_status(warp_json(&json!({ "org": org, "username": member, "role": role })), StatusCode::OK)
+                }
+                Ok(false) => with_status(
+                    warp_json(&json!({ "error": "user not found" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to set member role" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// DELETE /api/orgs/{org}/members/{username} - Remove a member (org admins), or leave
+pub fn remove_member(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "orgs" / String / "members" / String))
+        .and(auth)
+        .map(move |org: String, member: String, username: String| {
+            let required: fn(&Role) -> bool = if member == username { |_| true } else { Role::is_admin };
+            if let Err(reply) = require_org_role(&db, &org, &username, required) {
+                return reply;
+            }
+            if is_last_org_admin(&db, &org, &member) {
+                return with_status(
+                    warp_json(&json!({ "error": "the last admin of an organization cannot leave it" })),
+                    StatusCode::CONFLICT,
+                );
+            }
+            match remove_org_member(&db, &org, &member) {
+                Ok(true) => {
+                    println!("Removed {} from {} (by {})", member, org, username);
+                    with_status(warp_json(&json!({ "message": "Member removed" })), StatusCode::OK)
+                }
+                Ok(false) => with_status(
+                    warp_json(&json!({ "error": "not a member" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to remove member" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// POST /api/orgs/{org}/devices - Move personal devices, with their history,
+/// into an organization. Their ids change from `user:device` to `org:device`.
+pub fn move_into_org(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "orgs" / String / "devices"))
+        .and(auth)
+        .and(warp_body_json())
+        .map(move |org: String, username: String, payload: MovePayload| {
+            if let Err(reply) = require_org_role(&db, &org, &username, |role| role.grants(Scope::DevicesWrite)) {
+                return reply;
+            }
+            if !user_grants(&db, &username, Scope::DevicesWrite) {
+                return with_status(
+                    warp_json(&json!({ "error": "your role does not allow moving your devices" })),
+                    StatusCode::FORBIDDEN,
+                );
+            }
+            let devices = if payload.all {
+                match list_owner_device_ids(&db, &username) {
+                    Ok(devices) => devices,
+                    Err(status_code) => return with_status(
+                        warp_json(&json!({ "error": "Failed to list devices" })),
+                        status_code,
+                    ),
+                }
+            } else {
+                payload.devices
+            };
+            match move_devices(&db, &username, &org, &devices) {
+                Ok(moved) => {
+                    println!("Moved {} devices of {} into {}", moved, username, org);
+                    with_status(warp_json(&json!({ "moved": devices })), StatusCode::OK)
+                }
+                Err(StatusCode::NOT_FOUND) => with_status(
+                    warp_json(&json!({ "error": "one of the devices does not exist" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(StatusCode::CONFLICT) => with_status(
+                    warp_json(&json!({ "error": "the organization already has a device with one of these names" })),
+                    StatusCode::CONFLICT,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to move devices" })),
+                    status_code,
+                ),
+            }
+        })
+}
+
+/// Check the caller's role in an organization; non-members get the same
+/// answer as for an organization that does not exist
+fn require_org_role(
+    db: &Database,
+    org: &str,
+    username: &str,
+    allowed: impl Fn(&Role) -> bool,
+) -> Result<Role, warp::reply::WithStatus<warp::reply::Json>> {
+    match get_org_role(db, org, username) {
+        Ok(Some(role)) if allowed(&role) => Ok(role),
+        Ok(Some(_)) => Err(with_status(
+            warp_json(&json!({ "error": "your role in this organization does not allow this" })),
+            StatusCode::FORBIDDEN,
+        )),
+        Ok(None) => Err(with_status(
+            warp_json(&json!({ "error": "organization not found" })),
+            StatusCode::NOT_FOUND,
+        )),
+        Err(status_code) => Err(with_status(
+            warp_json(&json!({ "error": "Failed to check organization role" })),
+            status_code,
+        )),
+    }
+}
+
+/// Whether `username` is the only admin left in the organization
+fn is_last_org_admin(db: &Database, org: &str, username: &str) -> bool {
+    matches!(get_org_role(db, org, username), Ok(Some(Role::Admin)))
+        && count_org_admins(db, org).is_ok_and(|admins| admins <= 1)
+}
@@ -19,3 +19,8 @@ pub fn verify_password(password: &str, hashed_password_str: &str) -> Result<bool
         StatusCode::UNAUTHORIZED 
     })
 }
+
+/// Compares two secrets in time that depends only on their lengths.
+pub fn secrets_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,74 @@
+use crate::logic::serve::auth::authenticated_owner_or_query_token;
+use crate::logic::serve::database::Database;
+use crate::logic::serve::events::{EventBus, LiveEvent};
+use futures_util::stream;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+use warp::{sse::Event, Filter, Rejection};
+
+/// Filters for a live event subscription. Each one only applies to events that
+/// carry the field: `type` narrows data records, status events have no type.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct StreamQuery {
+    pub device: Option<String>,
+    pub topic: Option<String>,
+    #[serde(rename = "type")]
+    pub data_type: Option<String>,
+}
+
+impl StreamQuery {
+    fn matches(&self, event: &LiveEvent) -> bool {
+        fn field_matches(filter: &Option<String>, value: Option<&str>) -> bool {
+            match (filter, value) {
+                (Some(wanted), Some(value)) => wanted == value,
+                _ => true,
+            }
+        }
+
+        field_matches(&self.device, Some(event.device_id()))
+            && field_matches(&self.topic, event.topic())
+            && field_matches(&self.data_type, event.data_type())
+    }
+}
+
+/// GET /api/stream - Server-Sent Events of stored data and status transitions.
+///
+/// Events are named `data`, `device_status`, `topic_status` and `alert`; `lagged`
+/// tells a slow client how many events it missed.
+pub fn stream(
+    db: Database,
+    events: EventBus,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner_or_query_token(db);
+    warp::get()
+        .and(warp::path!("api" / "stream"))
+        .and(auth)
+        .and(warp::query::<StreamQuery>())
+        .map(move |owner: String, query: StreamQuery| {
+            let rx = events.subscribe();
+            let events = stream::unfold(rx, move |mut rx| {
+                let owner = owner.clone();
+                let query = query.clone();
+                async move {
+                    loop {
+                        match rx.recv().await {
+                            Ok(event) if event.owner() == owner && query.matches(&event) => {
+                                let sse = Event::default()
+                                    .event(event.kind())
+                                    .json_data(&event)
+                                    .unwrap_or_else(|_| Event::default().comment("unserializable event"));
+                                return Some((Ok::<_, Infallible>(sse), rx));
+                            }
+                            Ok(_) => continue,
+                            Err(RecvError::Lagged(missed)) => {
+                                let sse = Event::default().event("lagged").data(missed.to_string());
+                                return Some((Ok(sse), rx));
+                            }
+                            Err(RecvError::Closed) => return None,
+                        }
+                    }
+                }
+            });
+            warp::sse::reply(warp::sse::keep_alive().stream(events))
+        })
+}
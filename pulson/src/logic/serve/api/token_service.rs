@@ -1,25 +1,253 @@
-use crate::logic::serve::database::{Database, store_token, get_username_by_token, revoke_token as db_revoke_token};
+use crate::logic::serve::database::{
+    create_session, delete_session, delete_sessions_issued_before, get_session_by_previous_refresh_token,
+    get_session_by_refresh_token, get_session_by_token, revoke_token as db_revoke_token, rotate_session, touch_session, Database, Session,
+};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit as _, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 use uuid::Uuid;
 use warp::http::StatusCode;
 use warp::Rejection;
 
 use crate::logic::serve::auth::Unauthorized; // To use the same rejection type
 
-/// Generates a new UUID token, stores it with the associated username, and returns the token.
-pub fn generate_and_store_token(db: &Database, username: &str) -> Result<String, StatusCode> {
+const DAY_SECONDS: u64 = 24 * 60 * 60;
+
+// Lifetimes in force, set once from the server config at startup
+static IDLE_TIMEOUT_SECONDS: AtomicU64 = AtomicU64::new(7 * DAY_SECONDS);
+static MAX_LIFETIME_SECONDS: AtomicU64 = AtomicU64::new(30 * DAY_SECONDS);
+static REFRESH_LIFETIME_SECONDS: AtomicU64 = AtomicU64::new(90 * DAY_SECONDS);
+
+// Key tokens are hashed with, set once at startup
+static TOKEN_KEY: OnceLock<Vec<u8>> = OnceLock::new();
+
+/// Read the key tokens are hashed with from `PULSON_TOKEN_KEY`, or from a key
+/// file, creating the file with a fresh key on first start. A changed key
+/// ends every session.
+pub fn load_token_key(path: &Path) -> anyhow::Result<Vec<u8>> {
+    if let Ok(key) = std::env::var("PULSON_TOKEN_KEY") {
+        if !key.trim().is_empty() {
+            return Ok(key.trim().as_bytes().to_vec());
+        }
+    }
+    if path.exists() {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("cannot read token key {}: {}", path.display(), e))?;
+        return hex::decode(contents.trim())
+            .map_err(|e| anyhow::anyhow!("invalid token key {}: {}", path.display(), e));
+    }
+
+    let key = random_key();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("cannot create token key {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, hex::encode(&key).as_bytes())?;
+    println!("Created token key {}", path.display());
+    Ok(key)
+}
+
+fn random_key() -> Vec<u8> {
+    [Uuid::new_v4(), Uuid::new_v4()].iter().flat_map(|uuid| *uuid.as_bytes()).collect()
+}
+
+/// Hash tokens with this key from now on; only the first call counts. Without
+/// one, a random key is used and sessions do not outlive the process.
+pub fn set_token_key(key: Vec<u8>) {
+    let _ = TOKEN_KEY.set(key);
+}
+
+/// What is stored in place of a token or refresh token: an HMAC-SHA256 under
+/// the server's token key, so a copy of the database lets nobody log in
+pub fn hash_token(token: &str) -> String {
+    let key = TOKEN_KEY.get_or_init(random_key);
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(token.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Cipher for device secrets, keyed by an HMAC of a fixed label under the token key
+fn secret_cipher() -> Aes256Gcm {
+    let key = TOKEN_KEY.get_or_init(random_key);
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"pulson device secrets");
+    Aes256Gcm::new(&mac.finalize().into_bytes())
+}
+
+/// What is stored in place of a device secret: the secret encrypted with
+/// AES-256-GCM under a key derived from the token key, hex-encoded after its
+/// nonce. Unlike tokens these must be recoverable, as UDP datagrams are signed
+/// with them.
+pub fn seal_secret(secret: &str) -> String {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = secret_cipher()
+        .encrypt(&nonce, secret.as_bytes())
+        .expect("encrypting a short secret cannot fail");
+    hex::encode([nonce.as_slice(), &ciphertext].concat())
+}
+
+/// The device secret sealed by [`seal_secret`]; `None` if it was sealed under
+/// another key or altered
+pub fn open_secret(sealed: &str) -> Option<String> {
+    let bytes = hex::decode(sealed).ok()?;
+    if bytes.len() < 12 {
+        return None;
+    }
+    let (nonce, ciphertext) = bytes.split_at(12);
+    let plain = secret_cipher().decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+    String::from_utf8(plain).ok()
+}
+
+/// Lifetimes of login sessions, the `[sessions]` table of the server config
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// A token unused for this long expires; every use pushes this back (default: 7 days)
+    pub idle_timeout_seconds: u64,
+    /// A token expires this long after it was issued, however often it is used (default: 30 days)
+    pub max_lifetime_seconds: u64,
+    /// How long the refresh token of a session can be traded for a new token (default: 90 days)
+    pub refresh_lifetime_seconds: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_seconds: 7 * DAY_SECONDS,
+            max_lifetime_seconds: 30 * DAY_SECONDS,
+            refresh_lifetime_seconds: 90 * DAY_SECONDS,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.idle_timeout_seconds == 0 || self.max_lifetime_seconds == 0 {
+            return Err("sessions idle_timeout_seconds and max_lifetime_seconds must be at least 1".to_string());
+        }
+        if self.refresh_lifetime_seconds < self.max_lifetime_seconds {
+            return Err("sessions refresh_lifetime_seconds must not be shorter than max_lifetime_seconds".to_string());
+        }
+        Ok(())
+    }
+
+    /// The lifetimes in force
+    pub fn current() -> Self {
+        Self {
+            idle_timeout_seconds: IDLE_TIMEOUT_SECONDS.load(Ordering::Relaxed),
+            max_lifetime_seconds: MAX_LIFETIME_SECONDS.load(Ordering::Relaxed),
+            refresh_lifetime_seconds: REFRESH_LIFETIME_SECONDS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Put these lifetimes in force for every session, old ones included
+    pub fn apply(&self) {
+        IDLE_TIMEOUT_SECONDS.store(self.idle_timeout_seconds, Ordering::Relaxed);
+        MAX_LIFETIME_SECONDS.store(self.max_lifetime_seconds, Ordering::Relaxed);
+        REFRESH_LIFETIME_SECONDS.store(self.refresh_lifetime_seconds, Ordering::Relaxed);
+    }
+
+    /// When the session's token stops being accepted
+    pub fn token_expires_at(&self, session: &Session) -> DateTime<Utc> {
+        let issued_at = parse_time(&session.issued_at);
+        let last_used_at = session.last_used_at.as_deref().map(parse_time).unwrap_or(issued_at);
+        (last_used_at + seconds(self.idle_timeout_seconds)).min(issued_at + seconds(self.max_lifetime_seconds))
+    }
+
+    /// When the session's refresh token stops being accepted
+    pub fn refresh_expires_at(&self, session: &Session) -> DateTime<Utc> {
+        parse_time(&session.issued_at) + seconds(self.refresh_lifetime_seconds)
+    }
+}
+
+fn seconds(seconds: u64) -> chrono::Duration {
+    chrono::Duration::try_seconds(i64::try_from(seconds).unwrap_or(i64::MAX)).unwrap_or(chrono::Duration::MAX)
+}
+
+/// Unreadable times count as long past, so the session is treated as expired
+fn parse_time(text: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(text).map(|t| t.with_timezone(&Utc)).unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/// What a client gets when it logs in or refreshes its session
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedSession {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_at: String,
+}
+
+fn issued(session: &Session, token: String, refresh_token: String) -> IssuedSession {
+    let expires_at = SessionConfig::current().token_expires_at(session);
+    IssuedSession { token, refresh_token, expires_at: expires_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true) }
+}
+
+/// Start a session for a user who just logged in. Sessions past refreshing are
+/// cleared out on the way.
+pub fn start_session(db: &Database, username: &str, user_agent: Option<&str>) -> Result<IssuedSession, StatusCode> {
+    let refresh_lifetime = seconds(SessionConfig::current().refresh_lifetime_seconds);
+    let _ = delete_sessions_issued_before(db, &(Utc::now() - refresh_lifetime));
     let token = Uuid::new_v4().to_string();
-    store_token(db, &token, username)?;
-    Ok(token)
+    let refresh_token = Uuid::new_v4().to_string();
+    let session = create_session(db, &hash_token(&token), &hash_token(&refresh_token), username, user_agent)?;
+    Ok(issued(&session, token, refresh_token))
+}
+
+/// Trade a refresh token for a new token and refresh token. Both old ones stop
+/// working; `None` if the refresh token is unknown or expired. A refresh token
+/// used again after it was traded, or twice at once, ends its session, as one
+/// of the uses may be a thief's.
+pub fn refresh_session(db: &Database, refresh_token: &str) -> Result<Option<IssuedSession>, StatusCode> {
+    let refresh_token_hash = hash_token(refresh_token);
+    let Some((username, session)) = get_session_by_refresh_token(db, &refresh_token_hash)? else {
+        if let Some((username, session)) = get_session_by_previous_refresh_token(db, &refresh_token_hash)? {
+            eprintln!("Refresh token of session {} reused; ending the session (user: {})", session.id, username);
+            delete_session(db, &username, session.id)?;
+        }
+        return Ok(None);
+    };
+    if SessionConfig::current().refresh_expires_at(&session) <= Utc::now() {
+        db_revoke_token(db, &session.token_hash)?;
+        return Ok(None);
+    }
+    let token = Uuid::new_v4().to_string();
+    let new_refresh_token = Uuid::new_v4().to_string();
+    match rotate_session(db, session.id, &refresh_token_hash, &hash_token(&token), &hash_token(&new_refresh_token))? {
+        Some(session) => Ok(Some(issued(&session, token, new_refresh_token))),
+        None => {
+            eprintln!("Refresh token of session {} reused; ending the session (user: {})", session.id, username);
+            delete_session(db, &username, session.id)?;
+            Ok(None)
+        }
+    }
 }
 
 /// Validates a token string and returns the associated username if valid.
-/// Otherwise, returns a Rejection.
+/// Otherwise, returns a Rejection. Each use pushes the token's idle expiry back.
 pub fn validate_token(db: &Database, token_str: &str) -> Result<String, Rejection> {
-    match get_username_by_token(db, token_str) {
-        Ok(Some(username)) => Ok(username),
+    match session_for_token(db, token_str) {
+        Ok(Some((username, session))) => {
+            let config = SessionConfig::current();
+            if config.token_expires_at(&session) <= Utc::now() {
+                return Err(warp::reject::custom(Unauthorized)); // Token expired
+            }
+            // Record use often enough for short idle timeouts, at most once a minute
+            let interval = (config.idle_timeout_seconds / 10).clamp(1, 60) as i64;
+            let _ = touch_session(db, session.id, interval);
+            Ok(username)
+        }
         Ok(None) => Err(warp::reject::custom(Unauthorized)), // Token not found
         Err(_) => {
-            eprintln!("Database error validating token {}", token_str);
+            eprintln!("Database error validating a token");
             Err(warp::reject::custom(Unauthorized)) // DB error, treat as unauthorized
         }
     }
@@ -28,5 +256,93 @@ pub fn validate_token(db: &Database, token_str: &str) -> Result<String, Rejectio
 /// Function to remove a token, e.g., for logout.
 /// Returns true if the token was found and removed, false otherwise.
 pub fn revoke_token(db: &Database, token_str: &str) -> Result<bool, StatusCode> {
-    db_revoke_token(db, token_str)
+    db_revoke_token(db, &hash_token(token_str))
+}
+
+/// The session a token belongs to, with its user
+pub fn session_for_token(db: &Database, token_str: &str) -> Result<Option<(String, Session)>, StatusCode> {
+    get_session_by_token(db, &hash_token(token_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::database::{create_user, init_database};
+    use crate::logic::serve::roles::Role;
+
+    #[test]
+    fn test_session_expiry() {
+        let config = SessionConfig { idle_timeout_seconds: 600, max_lifetime_seconds: 3600, refresh_lifetime_seconds: 7200 };
+        let at = |minutes: i64| {
+            (parse_time("2025-06-01T12:00:00Z") + chrono::Duration::minutes(minutes))
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        };
+        let mut session = Session {
+            id: 1,
+            token_hash: hash_token("t"),
+            created_at: at(0),
+            issued_at: at(0),
+            last_used_at: None,
+            user_agent: None,
+        };
+        assert_eq!(config.token_expires_at(&session).to_rfc3339_opts(chrono::SecondsFormat::Secs, true), at(10));
+
+        // Use slides the idle expiry, up to the lifetime
+        session.last_used_at = Some(at(30));
+        assert_eq!(config.token_expires_at(&session).to_rfc3339_opts(chrono::SecondsFormat::Secs, true), at(40));
+        session.last_used_at = Some(at(55));
+        assert_eq!(config.token_expires_at(&session).to_rfc3339_opts(chrono::SecondsFormat::Secs, true), at(60));
+        assert_eq!(config.refresh_expires_at(&session).to_rfc3339_opts(chrono::SecondsFormat::Secs, true), at(120));
+
+        session.issued_at = "garbage".to_string();
+        assert!(config.token_expires_at(&session) < Utc::now());
+
+        assert!(config.validate().is_ok());
+        assert!(SessionConfig { refresh_lifetime_seconds: 60, ..config }.validate().is_err());
+        assert!(SessionConfig { idle_timeout_seconds: 0, ..config }.validate().is_err());
+    }
+
+    #[test]
+    fn test_refresh_token_reuse() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let first = start_session(&db, "alice", None).unwrap();
+        let (_, session) = get_session_by_refresh_token(&db, &hash_token(&first.refresh_token)).unwrap().unwrap();
+
+        // A refresh that raced another finds the refresh token replaced
+        let second = refresh_session(&db, &first.refresh_token).unwrap().unwrap();
+        assert!(rotate_session(&db, session.id, &hash_token(&first.refresh_token), "t", "r").unwrap().is_none());
+        assert!(session_for_token(&db, &second.token).unwrap().is_some());
+
+        // Refreshing again with the traded refresh token ends the session
+        assert!(refresh_session(&db, &first.refresh_token).unwrap().is_none());
+        assert!(session_for_token(&db, &second.token).unwrap().is_none());
+        assert!(refresh_session(&db, &second.refresh_token).unwrap().is_none());
+
+        // Unknown refresh tokens leave other sessions alone
+        let third = start_session(&db, "alice", None).unwrap();
+        assert!(refresh_session(&db, "unknown").unwrap().is_none());
+        assert!(session_for_token(&db, &third.token).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_sealed_secret() {
+        let sealed = seal_secret("s3cret");
+        assert!(!sealed.contains(&hex::encode("s3cret")));
+        assert_ne!(sealed, seal_secret("s3cret"));
+        assert_eq!(open_secret(&sealed).as_deref(), Some("s3cret"));
+
+        let mut altered = hex::decode(&sealed).unwrap();
+        *altered.last_mut().unwrap() ^= 1;
+        assert_eq!(open_secret(&hex::encode(altered)), None);
+        assert_eq!(open_secret("00ff"), None);
+    }
+
+    #[test]
+    fn test_token_hash() {
+        let hash = hash_token("t");
+        assert_eq!(hash, hash_token("t"));
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, hash_token("u"));
+    }
 }
@@ -0,0 +1,17 @@
+use crate::logic::serve::auth::authenticated_user;
+use crate::logic::serve::database::Database;
+use crate::logic::serve::udp::UdpStats;
+use std::sync::Arc;
+use warp::{reply::json as warp_json, Filter, Rejection};
+
+/// GET /api/udp/stats - Counters of the UDP listener (admins only)
+pub fn udp_stats(
+    db: Database,
+    stats: Arc<UdpStats>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_user(db);
+    warp::get()
+        .and(warp::path!("api" / "udp" / "stats"))
+        .and(auth)
+        .map(move |_username: String| warp_json(&stats.snapshot()))
+}
@@ -1,11 +1,14 @@
-use crate::logic::serve::database::{Database, create_user as db_create_user, delete_user, get_user_role, list_all_users};
+use crate::logic::serve::database::{
+    count_users_with_role, create_user as db_create_user, delete_user, get_user_role, list_all_users, set_user_role, Database,
+};
+use crate::logic::serve::roles::Role;
 use warp::http::StatusCode;
 
 /// Represents the data needed to create a new user.
 pub struct NewUser<'a> {
     pub username: &'a str,
     pub hashed_password: &'a str,
-    pub role: &'a str,
+    pub role: Role,
 }
 
 /// Creates a new user in the database.
@@ -13,28 +16,36 @@ pub fn create_user(db: &Database, user_data: NewUser) -> Result<(), StatusCode>
     db_create_user(db, user_data.username, user_data.hashed_password, user_data.role)
 }
 
-/// Deletes a user from the database, only if the caller is a root user.
-pub fn delete_user_by_admin(db: &Database, target_username: &str, caller_username: &str) -> Result<(), StatusCode> {
-    // Check if caller is root
-    match get_user_role(db, caller_username)? {
-        Some(role) if role == "root" => {
-            match delete_user(db, target_username)? {
-                true => Ok(()),
-                false => Err(StatusCode::NOT_FOUND), // User not found
-            }
-        }
-        Some(_) => Err(StatusCode::FORBIDDEN), // Caller is not root
-        None => Err(StatusCode::FORBIDDEN), // Caller not found
+/// Deletes a user from the database. Only admins get here (the auth filter
+/// checks), and the last admin is never deleted.
+pub fn delete_user_by_admin(db: &Database, target_username: &str) -> Result<(), StatusCode> {
+    if is_last_admin(db, target_username)? {
+        return Err(StatusCode::CONFLICT);
+    }
+    match delete_user(db, target_username)? {
+        true => Ok(()),
+        false => Err(StatusCode::NOT_FOUND), // User not found
     }
 }
 
-/// Lists all users, only if the caller is a root user.
+/// Lists all users with their roles.
 /// Returns a JSON Value representing the list of users or an error StatusCode.
-pub fn list_all_users_by_admin(db: &Database, caller_username: &str) -> Result<serde_json::Value, StatusCode> {
-    // Check if caller is root
-    match get_user_role(db, caller_username)? {
-        Some(role) if role == "root" => list_all_users(db),
-        Some(_) => Err(StatusCode::FORBIDDEN), // Caller is not root
-        None => Err(StatusCode::FORBIDDEN), // Caller not found
+pub fn list_all_users_by_admin(db: &Database) -> Result<serde_json::Value, StatusCode> {
+    list_all_users(db)
+}
+
+/// Gives a user another role. The last admin keeps theirs, so the server is
+/// never left without one.
+pub fn assign_role(db: &Database, target_username: &str, role: Role) -> Result<(), StatusCode> {
+    if role != Role::Admin && is_last_admin(db, target_username)? {
+        return Err(StatusCode::CONFLICT);
     }
+    match set_user_role(db, target_username, role)? {
+        true => Ok(()),
+        false => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+fn is_last_admin(db: &Database, username: &str) -> Result<bool, StatusCode> {
+    Ok(get_user_role(db, username)? == Some(Role::Admin) && count_users_with_role(db, Role::Admin)? <= 1)
 }
@@ -0,0 +1,176 @@
+use crate::logic::serve::auth::authenticated_owner;
+use crate::logic::serve::database::{
+    create_webhook, delete_webhook, list_webhook_deliveries, list_webhooks, update_webhook, Database,
+};
+use crate::logic::serve::webhooks::{WebhookConfig, WebhookSpec};
+use std::sync::Arc;
+use serde_json::json;
+use warp::{
+    body::json as warp_body_json, http::StatusCode, reply::{json as warp_json, with_status}, Filter, Rejection,
+};
+
+/// Deliveries returned when no limit is given
+const DEFAULT_DELIVERY_LIMIT: usize = 50;
+
+/// Most deliveries returned at once
+const MAX_DELIVERY_LIMIT: usize = 500;
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct DeliveryQuery {
+    limit: Option<usize>,
+}
+
+/// Webhook management and delivery logs
+pub fn webhook_routes(
+    db: Database,
+    config: Arc<WebhookConfig>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    list(db.clone())
+        .or(create(db.clone(), config.clone()))
+        .or(update(db.clone(), config))
+        .or(delete(db.clone()))
+        .or(deliveries(db))
+}
+
+/// GET /api/webhooks - List the caller's webhooks
+pub fn list(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "webhooks"))
+        .and(auth)
+        .map(move |owner: String| match list_webhooks(&db, &owner) {
+            Ok(webhooks) => with_status(warp_json(&webhooks), StatusCode::OK),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to list webhooks" })),
+                status_code,
+            ),
+        })
+}
+
+/// Validate a webhook and check that its url does not point into the server's network
+async fn checked_spec(spec: WebhookSpec, config: &WebhookConfig) -> Result<WebhookSpec, String> {
+    let spec = spec.normalize()?;
+    config.check_url(&spec.url).await?;
+    Ok(spec)
+}
+
+/// POST /api/webhooks - Add a webhook
+pub fn create(
+    db: Database,
+    config: Arc<WebhookConfig>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::post()
+        .and(warp::path!("api" / "webhooks"))
+        .and(auth)
+        .and(warp_body_json())
+        .and_then(move |owner: String, spec: WebhookSpec| {
+            let db = db.clone();
+            let config = config.clone();
+            async move {
+                let spec = match checked_spec(spec, &config).await {
+                    Ok(spec) => spec,
+                    Err(e) => return Ok::<_, Rejection>(with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST)),
+                };
+                Ok(match create_webhook(&db, &owner, &spec) {
+                    Ok(webhook) => {
+                        println!("Created webhook {} '{}' (owner: {})", webhook.id, webhook.name, owner);
+                        with_status(warp_json(&webhook), StatusCode::OK)
+                    }
+                    Err(status_code) => with_status(
+                        warp_json(&json!({ "error": "Failed to create webhook" })),
+                        status_code,
+                    ),
+                })
+            }
+        })
+}
+
+/// PUT /api/webhooks/{id} - Replace a webhook; leave out `secret` to keep it
+pub fn update(
+    db: Database,
+    config: Arc<WebhookConfig>,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::put()
+        .and(warp::path!("api" / "webhooks" / i64))
+        .and(auth)
+        .and(warp_body_json())
+        .and_then(move |id: i64, owner: String, spec: WebhookSpec| {
+            let db = db.clone();
+            let config = config.clone();
+            async move {
+                let spec = match checked_spec(spec, &config).await {
+                    Ok(spec) => spec,
+                    Err(e) => return Ok::<_, Rejection>(with_status(warp_json(&json!({ "error": e })), StatusCode::BAD_REQUEST)),
+                };
+                Ok(match update_webhook(&db, &owner, id, &spec) {
+                    Ok(Some(webhook)) => with_status(warp_json(&webhook), StatusCode::OK),
+                    Ok(None) => with_status(
+                        warp_json(&json!({ "error": "webhook not found" })),
+                        StatusCode::NOT_FOUND,
+                    ),
+                    Err(status_code) => with_status(
+                        warp_json(&json!({ "error": "Failed to update webhook" })),
+                        status_code,
+                    ),
+                })
+            }
+        })
+}
+
+/// DELETE /api/webhooks/{id} - Remove a webhook and its delivery log
+pub fn delete(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::delete()
+        .and(warp::path!("api" / "webhooks" / i64))
+        .and(auth)
+        .map(move |id: i64, owner: String| match delete_webhook(&db, &owner, id) {
+            Ok(true) => with_status(warp_json(&json!({ "message": "webhook removed" })), StatusCode::OK),
+            Ok(false) => with_status(
+                warp_json(&json!({ "error": "webhook not found" })),
+                StatusCode::NOT_FOUND,
+            ),
+            Err(status_code) => with_status(
+                warp_json(&json!({ "error": "Failed to remove webhook" })),
+                status_code,
+            ),
+        })
+}
+
+/// GET /api/webhooks/{id}/deliveries?limit={n} - A webhook's delivery log, newest first
+pub fn deliveries(
+    db: Database,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_owner(db.clone());
+    warp::get()
+        .and(warp::path!("api" / "webhooks" / i64 / "deliveries"))
+        .and(auth)
+        .and(warp::query::<DeliveryQuery>())
+        .map(move |id: i64, owner: String, query: DeliveryQuery| {
+            let limit = match query.limit.unwrap_or(DEFAULT_DELIVERY_LIMIT) {
+                limit @ 1..=MAX_DELIVERY_LIMIT => limit,
+                _ => {
+                    return with_status(
+                        warp_json(&json!({ "error": format!("limit must be between 1 and {}", MAX_DELIVERY_LIMIT) })),
+                        StatusCode::BAD_REQUEST,
+                    );
+                }
+            };
+            match list_webhook_deliveries(&db, &owner, id, limit) {
+                Ok(Some(deliveries)) => with_status(warp_json(&deliveries), StatusCode::OK),
+                Ok(None) => with_status(
+                    warp_json(&json!({ "error": "webhook not found" })),
+                    StatusCode::NOT_FOUND,
+                ),
+                Err(status_code) => with_status(
+                    warp_json(&json!({ "error": "Failed to list deliveries" })),
+                    status_code,
+                ),
+            }
+        })
+}
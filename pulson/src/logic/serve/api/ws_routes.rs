@@ -0,0 +1,199 @@
+use crate::logic::serve::api::device_routes::device_not_allowed;
+use crate::logic::serve::auth::{authenticated_caller_or_query_token, Caller};
+use crate::logic::serve::database::Database;
+use crate::logic::serve::ingest::{IncomingPulse, IngestError, Ingestor, MAX_PULSE_BYTES};
+use futures_util::{SinkExt, StreamExt};
+use std::collections::HashSet;
+use std::time::Duration;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Rejection};
+
+/// Frames already waiting on the socket are stored together, up to this many
+const MAX_FRAMES_PER_BATCH: usize = 256;
+
+/// How often the server pings the client. Each pong keeps the socket's devices
+/// online, so this must stay below the online threshold.
+const PING_INTERVAL_SECS: u64 = 10;
+
+#[derive(serde::Deserialize)]
+pub struct SocketQuery {
+    /// Device kept alive by this connection even before it sends any data
+    pub device_id: Option<String>,
+}
+
+/// A pulse frame: the `/api/pulse` payload plus an optional client sequence
+/// number that is echoed back in the acknowledgement
+#[derive(serde::Deserialize)]
+struct PulseFrame {
+    seq: Option<u64>,
+    #[serde(flatten)]
+    pulse: IncomingPulse,
+}
+
+/// GET /api/pulse/ws - Stream pulses over one long-lived WebSocket.
+///
+/// Every text frame is acknowledged with `{"ack": seq, "status": "stored"}` or
+/// `{"ack": seq, "status": "failed", "error": ...}`. Frames are read only after the
+/// previous ones were stored and acknowledged, so a client that sends faster than
+/// the database can write is slowed down by the socket itself.
+pub fn pulse_socket(
+    db: Database,
+    ingestor: Ingestor,
+) -> impl Filter<Extract = impl warp::Reply, Error = Rejection> + Clone {
+    let auth = authenticated_caller_or_query_token(db);
+    warp::path!("api" / "pulse" / "ws")
+        .and(auth)
+        .and(warp::query::<SocketQuery>())
+        .and(warp::ws())
+        .map(move |caller: Caller, query: SocketQuery, ws: Ws| {
+            if let Some(device_id) = query.device_id.as_deref().filter(|d| !caller.allows_device(d)) {
+                return Box::new(warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({ "error": device_not_allowed(device_id) })),
+                    warp::http::StatusCode::FORBIDDEN,
+                )) as Box<dyn warp::Reply>;
+            }
+            let ingestor = ingestor.clone();
+            Box::new(
+                ws.max_message_size(MAX_PULSE_BYTES)
+                    .on_upgrade(move |socket| handle_socket(socket, caller, query.device_id, ingestor)),
+            )
+        })
+}
+
+/// Outcome of one frame, in the order the frames arrived
+enum Slot {
+    Pulse(u64),
+    Rejected(u64, String),
+}
+
+async fn handle_socket(socket: WebSocket, caller: Caller, declared_device: Option<String>, ingestor: Ingestor) {
+    let owner = caller.owner.clone();
+    let (mut tx, rx) = socket.split();
+    let mut frames = rx.ready_chunks(MAX_FRAMES_PER_BATCH);
+    let mut ping = tokio::time::interval(Duration::from_secs(PING_INTERVAL_SECS));
+
+    // Devices whose liveness this connection vouches for
+    let mut devices: HashSet<String> = declared_device.into_iter().filter(|d| !d.is_empty()).collect();
+    let mut frame_count: u64 = 0;
+
+    println!("WebSocket pulse stream opened (owner: {})", owner);
+    ingestor.keep_alive(&owner, &devices);
+
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if tx.send(Message::ping(Vec::new())).await.is_err() {
+                    break;
+                }
+            }
+            chunk = frames.next() => {
+                let Some(chunk) = chunk else {
+                    break;
+                };
+
+                let mut slots = Vec::with_capacity(chunk.len());
+                let mut pulses = Vec::with_capacity(chunk.len());
+                let mut pong = false;
+                let mut closing = false;
+
+                for message in chunk {
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(_) => {
+                            closing = true;
+                            break;
+                        }
+                    };
+                    if message.is_close() {
+                        closing = true;
+                        break;
+                    }
+                    if message.is_pong() {
+                        pong = true;
+                        continue;
+                    }
+                    if message.is_ping() {
+                        // Answered automatically by the WebSocket layer
+                        continue;
+                    }
+
+                    frame_count += 1;
+                    let Ok(text) = message.to_str() else {
+                        slots.push(Slot::Rejected(frame_count, "only JSON text frames are supported".to_string()));
+                        continue;
+                    };
+                    match serde_json::from_str::<PulseFrame>(text) {
+                        Ok(frame) if !caller.allows_device(&frame.pulse.device_id) => {
+                            let seq = frame.seq.unwrap_or(frame_count);
+                            slots.push(Slot::Rejected(seq, device_not_allowed(&frame.pulse.device_id)));
+                        }
+                        Ok(frame) => {
+                            slots.push(Slot::Pulse(frame.seq.unwrap_or(frame_count)));
+                            pulses.push(frame.pulse);
+                        }
+                        Err(e) => slots.push(Slot::Rejected(frame_count, format!("invalid frame: {}", e))),
+                    }
+                }
+
+                if pong {
+                    ingestor.keep_alive(&owner, &devices);
+                }
+
+                let acks = store_frames(&ingestor, &owner, pulses, slots, &mut devices);
+                for ack in acks {
+                    if tx.feed(Message::text(ack.to_string())).await.is_err() {
+                        closing = true;
+                        break;
+                    }
+                }
+                if tx.flush().await.is_err() || closing {
+                    break;
+                }
+            }
+        }
+    }
+
+    println!("WebSocket pulse stream closed (owner: {}, {} frames)", owner, frame_count);
+}
+
+/// Store the valid pulses of one read in a single transaction and build the
+/// acknowledgements for every frame
+fn store_frames(
+    ingestor: &Ingestor,
+    owner: &str,
+    pulses: Vec<IncomingPulse>,
+    slots: Vec<Slot>,
+    devices: &mut HashSet<String>,
+) -> Vec<serde_json::Value> {
+    let device_ids: Vec<String> = pulses.iter().map(|p| p.device_id.clone()).collect();
+    let mut outcomes = if pulses.is_empty() {
+        Vec::new()
+    } else {
+        match ingestor.ingest_batch(owner, pulses) {
+            Ok(outcomes) => outcomes.into_iter().map(|o| o.map_err(|e| e.message())).collect(),
+            Err(e) => {
+                if let IngestError::Storage(_) = e {
+                    eprintln!("Failed to store WebSocket pulses (owner: {})", owner);
+                }
+                device_ids.iter().map(|_| Err(e.message())).collect()
+            }
+        }
+    }
+    .into_iter()
+    .zip(device_ids);
+
+    slots
+        .into_iter()
+        .map(|slot| match slot {
+            Slot::Pulse(seq) => match outcomes.next() {
+                Some((Ok(()), device_id)) => {
+                    devices.insert(device_id);
+                    serde_json::json!({ "ack": seq, "status": "stored" })
+                }
+                Some((Err(e), _)) => serde_json::json!({ "ack": seq, "status": "failed", "error": e }),
+                None => serde_json::json!({ "ack": seq, "status": "failed", "error": "storage failed" }),
+            },
+            Slot::Rejected(seq, e) => serde_json::json!({ "ack": seq, "status": "failed", "error": e }),
+        })
+        .collect()
+}
@@ -0,0 +1,239 @@
+use crate::logic::config::parse_duration;
+use crate::logic::serve::alerts::glob_match_case_sensitive;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Every API key starts with this, which is how they are told apart from login tokens
+pub const KEY_PREFIX: &str = "pulson_";
+
+/// Characters of a key kept in the clear so its owner can recognise it
+const SHOWN_KEY_LEN: usize = KEY_PREFIX.len() + 8;
+
+/// Longest accepted key name
+const MAX_NAME_LEN: usize = 100;
+
+/// What an API key may do. Account management, users and API keys themselves
+/// are only open to login tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    /// Every GET: devices, data, configuration, alerts and the live stream
+    #[serde(rename = "read")]
+    Read,
+    /// Send pulses over HTTP, in batches or over the WebSocket
+    #[serde(rename = "pulse:write")]
+    PulseWrite,
+    /// Delete devices and change their credentials, thresholds, retention and maintenance
+    #[serde(rename = "devices:write")]
+    DevicesWrite,
+    /// Change status thresholds, alert rules, webhooks and email settings
+    #[serde(rename = "config:write")]
+    ConfigWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [Scope::Read, Scope::PulseWrite, Scope::DevicesWrite, Scope::ConfigWrite];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::PulseWrite => "pulse:write",
+            Scope::DevicesWrite => "devices:write",
+            Scope::ConfigWrite => "config:write",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Scope> {
+        Scope::ALL.into_iter().find(|scope| scope.as_str() == text)
+    }
+
+    /// The scope a request needs, from its method and path; `None` for the
+    /// routes only a login token may use
+    pub fn required(method: &str, path: &str) -> Option<Scope> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["api", "account" | "keys" | "admin" | "orgs", ..] => None,
+            ["api", "pulse", ..] => Some(Scope::PulseWrite),
+            _ if method == "GET" => Some(Scope::Read),
+            ["api", "devices" | "device" | "maintenance", ..] => Some(Scope::DevicesWrite),
+            ["api", "user", "thresholds" | "retention", ..] => Some(Scope::DevicesWrite),
+            _ => Some(Scope::ConfigWrite),
+        }
+    }
+}
+
+/// The device a request path is about, e.g. `robot1` for `/api/devices/robot1/history`
+pub fn path_device(path: &str) -> Option<&str> {
+    let mut segments = path.trim_matches('/').split('/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some("api"), Some("devices"), Some(device_id)) if !device_id.is_empty() => Some(device_id),
+        _ => None,
+    }
+}
+
+/// An API key of one user. The key itself is only shown once, when it is
+/// created; `prefix` is its first few characters.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    /// Device id, or a prefix such as `robot-*`, the key is limited to
+    pub device_id: Option<String>,
+    /// Organization the key acts for; without one it acts for its user's own devices
+    pub org: Option<String>,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    pub created_at: String,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self, now: &DateTime<Utc>) -> bool {
+        self.expires_at
+            .as_deref()
+            .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+            .is_some_and(|at| at <= *now)
+    }
+
+    /// Whether the key may act on a device; unrestricted keys may act on all.
+    /// Device ids are case-sensitive, so the pattern is too.
+    pub fn allows_device(&self, device_id: &str) -> bool {
+        self.device_id.as_deref().is_none_or(|pattern| glob_match_case_sensitive(pattern, device_id))
+    }
+}
+
+/// A key as requested by its owner; `expires_in` is a duration such as `90d`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeySpec {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub device_id: Option<String>,
+    pub expires_in: Option<String>,
+}
+
+/// A checked `ApiKeySpec`, ready to be stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub device_id: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKeySpec {
+    pub fn resolve(self, now: DateTime<Utc>) -> Result<NewApiKey, String> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(format!("name must be 1 to {} characters", MAX_NAME_LEN));
+        }
+        let mut scopes = Vec::new();
+        for scope in &self.scopes {
+            let scope = Scope::parse(scope.trim()).ok_or_else(|| {
+                let known: Vec<&str> = Scope::ALL.iter().map(Scope::as_str).collect();
+                format!("unknown scope '{}' (expected one of {})", scope, known.join(", "))
+            })?;
+            if !scopes.contains(&scope) {
+                scopes.push(scope);
+            }
+        }
+        if scopes.is_empty() {
+            return Err("at least one scope is required".to_string());
+        }
+        let device_id = self.device_id.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+        let expires_at = match self.expires_in.as_deref() {
+            Some(expires_in) => {
+                let seconds = i64::try_from(parse_duration(expires_in)?).map_err(|_| "expires_in is too long".to_string())?;
+                Some(
+                    chrono::Duration::try_seconds(seconds)
+                        .and_then(|duration| now.checked_add_signed(duration))
+                        .ok_or_else(|| "expires_in is too long".to_string())?,
+                )
+            }
+            None => None,
+        };
+        Ok(NewApiKey { name, scopes, device_id, expires_at })
+    }
+}
+
+/// A fresh random key
+pub fn generate_key() -> String {
+    format!("{}{}", KEY_PREFIX, uuid::Uuid::new_v4().simple())
+}
+
+/// The part of a key that is stored and shown in the clear
+pub fn shown_prefix(key: &str) -> String {
+    key.chars().take(SHOWN_KEY_LEN).collect()
+}
+
+/// What is stored in place of a key. Keys are long and random, so a plain
+/// digest is enough to look them up without keeping them.
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(Scope::required("POST", "/api/pulse"), Some(Scope::PulseWrite));
+        assert_eq!(Scope::required("GET", "/api/pulse/ws"), Some(Scope::PulseWrite));
+        assert_eq!(Scope::required("GET", "/api/devices/robot1/history"), Some(Scope::Read));
+        assert_eq!(Scope::required("DELETE", "/api/devices/robot1/credentials"), Some(Scope::DevicesWrite));
+        assert_eq!(Scope::required("POST", "/api/user/thresholds"), Some(Scope::DevicesWrite));
+        assert_eq!(Scope::required("PUT", "/api/webhooks/3"), Some(Scope::ConfigWrite));
+        assert_eq!(Scope::required("GET", "/api/account/users"), None);
+        assert_eq!(Scope::required("POST", "/api/keys"), None);
+        assert_eq!(Scope::required("PUT", "/api/admin/users/alice/role"), None);
+        assert_eq!(Scope::required("POST", "/api/orgs/acme/devices"), None);
+
+        assert_eq!(path_device("/api/devices/robot1/history"), Some("robot1"));
+        assert_eq!(path_device("/api/devices"), None);
+        assert_eq!(path_device("/api/pulse"), None);
+    }
+
+    #[test]
+    fn test_spec_validation() {
+        let now = Utc::now();
+        let spec = |scopes: &[&str], expires_in: Option<&str>| ApiKeySpec {
+            name: " robot1 ".to_string(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            device_id: Some("robot-*".to_string()),
+            expires_in: expires_in.map(str::to_string),
+        };
+
+        let key = spec(&["pulse:write", "read", "pulse:write"], Some("1d")).resolve(now).unwrap();
+        assert_eq!(key.name, "robot1");
+        assert_eq!(key.scopes, vec![Scope::PulseWrite, Scope::Read]);
+        assert_eq!(key.expires_at, Some(now + chrono::Duration::days(1)));
+
+        assert!(spec(&[], None).resolve(now).is_err());
+        assert!(spec(&["admin"], None).resolve(now).is_err());
+        assert!(spec(&["read"], Some("soon")).resolve(now).is_err());
+    }
+
+    #[test]
+    fn test_key_limits() {
+        let now = Utc::now();
+        let mut key = ApiKey {
+            id: 1,
+            name: "robot".to_string(),
+            prefix: shown_prefix(&generate_key()),
+            scopes: vec![Scope::PulseWrite],
+            device_id: Some("robot-*".to_string()),
+            org: None,
+            expires_at: None,
+            last_used_at: None,
+            created_at: now.to_rfc3339(),
+        };
+        assert!(key.prefix.starts_with(KEY_PREFIX) && key.prefix.len() == SHOWN_KEY_LEN);
+        assert!(key.allows_device("robot-7") && !key.allows_device("drone-1"));
+        assert!(!key.allows_device("ROBOT-7"));
+        assert!(!key.is_expired(&now));
+        key.expires_at = Some((now - chrono::Duration::seconds(1)).to_rfc3339());
+        assert!(key.is_expired(&now));
+        assert_ne!(hash_key("pulson_a"), hash_key("pulson_b"));
+    }
+}
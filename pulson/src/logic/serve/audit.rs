@@ -0,0 +1,589 @@
+use crate::logic::config::parse_time;
+use crate::logic::serve::auth::{token_owner, ORG_HEADER};
+use crate::logic::serve::database::{record_audit_entry, Database};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::{path::FullPath, Filter, Rejection, Reply};
+
+/// Most entries one audit query returns
+pub const MAX_AUDIT_LIMIT: usize = 1000;
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+
+/// Most windows of refused calls open at once; further calls are recorded singly
+const MAX_OPEN_WINDOWS: usize = 10_000;
+
+/// Audit log settings, the `[audit]` table of the server config
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Entries older than this are removed by the retention task; 0 keeps them forever (default)
+    pub keep_seconds: u64,
+    /// Identical refused calls from one address within this long are recorded
+    /// as one entry and a count; 0 records each (default: 60)
+    pub coalesce_seconds: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { keep_seconds: 0, coalesce_seconds: 60 }
+    }
+}
+
+/// One recorded API call. Entries are only ever added: the table refuses
+/// updates, and deletes other than by the retention task.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: String,
+    /// The user whose token or API key made the call; `None` for calls
+    /// without one, such as logins and registrations
+    pub actor: Option<String>,
+    pub api_key_id: Option<i64>,
+    /// The organization the call acted for, from the `X-Pulson-Org` header
+    pub org: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub source_ip: Option<String>,
+    pub status: u16,
+    /// `success`, `denied` (401 or 403) or `failure`
+    pub result: String,
+    /// How many more identical refused calls were made since the entry before
+    /// this one; they are folded into one entry when the window closes
+    pub repeats: u32,
+}
+
+/// An entry about to be recorded
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewAuditEntry {
+    pub actor: Option<String>,
+    pub api_key_id: Option<i64>,
+    pub org: Option<String>,
+    pub action: String,
+    pub target: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub source_ip: Option<String>,
+    pub status: u16,
+    pub repeats: u32,
+}
+
+/// Query parameters of `GET /api/admin/audit`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    /// An action, or a group of them such as `device`
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub org: Option<String>,
+    pub result: Option<String>,
+    pub ip: Option<String>,
+    /// RFC3339 or relative like `-6h`
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Only entries older than this id, for paging
+    pub before: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+/// A checked audit query; empty filters are dropped and times normalized
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub org: Option<String>,
+    pub result: Option<String>,
+    pub ip: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub before: Option<i64>,
+    pub limit: usize,
+}
+
+impl AuditQuery {
+    pub fn resolve(self, now: chrono::DateTime<chrono::Utc>) -> Result<AuditFilter, String> {
+        let text = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        let time = |value: Option<String>| -> Result<Option<String>, String> {
+            text(value)
+                .map(|v| parse_time(&v, now).map(|at| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)))
+                .transpose()
+        };
+        let result = text(self.result);
+        if let Some(result) = &result {
+            if !["success", "denied", "failure"].contains(&result.as_str()) {
+                return Err(format!("unknown result '{}', expected success, denied or failure", result));
+            }
+        }
+        let limit = match self.limit.unwrap_or(DEFAULT_AUDIT_LIMIT) {
+            limit @ 1..=MAX_AUDIT_LIMIT => limit,
+            _ => return Err(format!("limit must be between 1 and {}", MAX_AUDIT_LIMIT)),
+        };
+        Ok(AuditFilter {
+            actor: text(self.actor),
+            action: text(self.action),
+            target: text(self.target),
+            org: text(self.org),
+            result,
+            ip: text(self.ip),
+            from: time(self.from)?,
+            to: time(self.to)?,
+            before: self.before,
+            limit,
+        })
+    }
+}
+
+/// The action and target of a mutating API call, from its method and path.
+/// Reads and data ingestion (pulses over HTTP and the WebSocket) are not audited.
+pub fn describe(method: &Method, path: &str) -> Option<(String, Option<String>)> {
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return None;
+    }
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let verb = match *method {
+        Method::POST => "create",
+        Method::PUT | Method::PATCH => "update",
+        Method::DELETE => "delete",
+        _ => "call",
+    };
+    let target = |value: &str| Some(value.to_string());
+    let (action, target) = match (method.as_str(), segments.as_slice()) {
+        (_, ["api", "pulse", ..]) => return None,
+        (_, [first, ..]) if *first != "api" => return None,
+        ("POST", ["api", "account", "register"]) => ("user.register".to_string(), None),
+        ("POST", ["api", "account", "login"]) => ("account.login".to_string(), None),
+        ("POST", ["api", "account", "logout"]) => ("account.logout".to_string(), None),
+        ("POST", ["api", "account", "refresh"]) => ("session.refresh".to_string(), None),
+        ("DELETE", ["api", "account", "sessions"]) => ("session.revoke_others".to_string(), None),
+        ("DELETE", ["api", "account", "sessions", id]) => ("session.revoke".to_string(), target(id)),
+        ("DELETE", ["api", "account", username]) => ("user.delete".to_string(), target(username)),
+        ("PUT", ["api", "admin", "users", username, "role"]) => ("user.role".to_string(), target(username)),
+        // The device is named in the body; the handler reports it with `AuditTarget`
+        ("POST", ["api", "device", "delete"]) => ("device.delete".to_string(), None),
+        (_, ["api", "devices", device_id, "credentials"]) => {
+            let action = if verb == "delete" { "device.credentials.revoke" } else { "device.credentials.rotate" };
+            (action.to_string(), target(device_id))
+        }
+        (_, ["api", "devices", device_id]) => (format!("device.{}", verb), target(device_id)),
+        ("POST", ["api", "config", "update"]) => ("server_config.update".to_string(), None),
+        ("POST", ["api", "user", "config"]) => ("status_config.update".to_string(), None),
+        ("POST", ["api", "notifications", "email", "test"]) => ("email.test".to_string(), None),
+        ("POST", ["api", "orgs", org, "devices"]) => ("org.devices.move".to_string(), target(org)),
+        (_, ["api", "orgs", org, "members", username]) => {
+            (format!("org.member.{}", verb), Some(format!("{}/{}", org, username)))
+        }
+        (_, ["api", "alerts", "rules", rest @ ..]) => (format!("alert_rule.{}", verb), rest.first().and_then(|id| target(id))),
+        (_, ["api", "user", resource, rest @ ..]) | (_, ["api", resource, rest @ ..]) => {
+            let resource = match *resource {
+                "keys" => "api_key",
+                "orgs" => "org",
+                "webhooks" => "webhook",
+                "notifications" => "email",
+                other => other,
+            };
+            // Retention rules and thresholds are created or replaced by the same call
+            let verb = match resource {
+                "retention" | "thresholds" if verb == "create" => "set",
+                _ => verb,
+            };
+            let target = rest.first().filter(|_| resource != "email").and_then(|id| target(id));
+            (format!("{}.{}", resource, verb), target)
+        }
+        _ => (format!("{}.{}", path, verb), None),
+    };
+    Some((action, target))
+}
+
+/// How an audited call turned out
+pub fn result_of(status: StatusCode) -> &'static str {
+    match status {
+        status if status.is_success() => "success",
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => "denied",
+        _ => "failure",
+    }
+}
+
+/// Proxies in front of the server whose `X-Forwarded-For` entries are trusted
+static TRUSTED_PROXY_HOPS: AtomicUsize = AtomicUsize::new(1);
+
+/// Set how many proxies, the last one on the same host, pass requests on
+pub fn set_trusted_proxy_hops(hops: usize) {
+    TRUSTED_PROXY_HOPS.store(hops, Ordering::Relaxed);
+}
+
+/// The address a request came from. Behind reverse proxies, the last one on the
+/// same host, that is the `X-Forwarded-For` entry added by the outermost trusted
+/// proxy: with one proxy the rightmost entry, as the ones before it come from
+/// the client and could be forged. `X-Real-IP` is used without that header.
+/// The headers are ignored for requests from anywhere but the same host.
+pub fn client_ip(remote: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+    forwarded_ip(remote, headers, TRUSTED_PROXY_HOPS.load(Ordering::Relaxed))
+}
+
+fn forwarded_ip(remote: Option<SocketAddr>, headers: &HeaderMap, hops: usize) -> Option<IpAddr> {
+    let remote = remote?.ip();
+    if !remote.is_loopback() || hops == 0 {
+        return Some(remote);
+    }
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let forwarded = match header("x-forwarded-for") {
+        Some(list) => {
+            let entries: Vec<&str> = list.split(',').collect();
+            // Fewer entries than proxies means they were all added by trusted ones
+            entries.get(entries.len().saturating_sub(hops)).copied()
+        }
+        None => header("x-real-ip"),
+    };
+    forwarded.and_then(|ip| ip.trim().parse().ok()).or(Some(remote))
+}
+
+/// The status warp answers a rejection with, for the rejections that stand for
+/// a refused call rather than a route that does not exist
+fn rejected_status(rejection: &Rejection) -> Option<StatusCode> {
+    if rejection.find::<warp::reject::UnsupportedMediaType>().is_some() {
+        Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        Some(StatusCode::PAYLOAD_TOO_LARGE)
+    } else if rejection.find::<warp::reject::LengthRequired>().is_some() {
+        Some(StatusCode::LENGTH_REQUIRED)
+    } else if rejection.find::<warp::body::BodyDeserializeError>().is_some()
+        || rejection.find::<warp::reject::InvalidQuery>().is_some()
+        || rejection.find::<warp::reject::InvalidHeader>().is_some()
+        || rejection.find::<warp::reject::MissingHeader>().is_some()
+    {
+        Some(StatusCode::BAD_REQUEST)
+    } else {
+        None
+    }
+}
+
+/// Calls refused for who made them, or anonymous calls that failed, which one
+/// client can repeat as fast as it likes
+fn coalescable(entry: &NewAuditEntry) -> bool {
+    matches!(entry.status, 401 | 403 | 429) || (entry.actor.is_none() && !(200..300).contains(&entry.status))
+}
+
+/// Calls that count as the same for coalescing
+#[derive(PartialEq, Eq, Hash)]
+struct CoalesceKey {
+    source_ip: Option<String>,
+    actor: Option<String>,
+    action: String,
+    target: Option<String>,
+    status: u16,
+}
+
+impl CoalesceKey {
+    fn of(entry: &NewAuditEntry) -> Self {
+        Self {
+            source_ip: entry.source_ip.clone(),
+            actor: entry.actor.clone(),
+            action: entry.action.clone(),
+            target: entry.target.clone(),
+            status: entry.status,
+        }
+    }
+}
+
+struct OpenWindow {
+    opened: Instant,
+    entry: NewAuditEntry,
+    repeats: u32,
+}
+
+impl OpenWindow {
+    /// The entry that records the calls folded into the window, if there were any
+    fn summary(&self) -> Option<NewAuditEntry> {
+        (self.repeats > 0).then(|| NewAuditEntry { repeats: self.repeats, ..self.entry.clone() })
+    }
+}
+
+/// Folds identical refused calls from one address into one entry per window:
+/// the first is recorded at once, the others as a count once the window closes
+struct Coalescer {
+    window: Duration,
+    open: Mutex<HashMap<CoalesceKey, OpenWindow>>,
+}
+
+impl Coalescer {
+    fn new(window: Duration) -> Self {
+        Self { window, open: Mutex::new(HashMap::new()) }
+    }
+
+    /// The entries to record for a call that just ended
+    fn admit(&self, entry: NewAuditEntry, now: Instant) -> Vec<NewAuditEntry> {
+        if self.window.is_zero() || !coalescable(&entry) {
+            return vec![entry];
+        }
+        let Ok(mut open) = self.open.lock() else {
+            return vec![entry];
+        };
+        let key = CoalesceKey::of(&entry);
+        if let Some(window) = open.get_mut(&key) {
+            if now.duration_since(window.opened) < self.window {
+                window.repeats += 1;
+                return Vec::new();
+            }
+        }
+        let mut record: Vec<NewAuditEntry> = open.remove(&key).and_then(|window| window.summary()).into_iter().collect();
+        if open.len() < MAX_OPEN_WINDOWS {
+            open.insert(key, OpenWindow { opened: now, entry: entry.clone(), repeats: 0 });
+        }
+        record.push(entry);
+        record
+    }
+
+    /// Close the windows that ran out, returning the entries to record for them
+    fn close_expired(&self, now: Instant) -> Vec<NewAuditEntry> {
+        let Ok(mut open) = self.open.lock() else {
+            return Vec::new();
+        };
+        let mut record = Vec::new();
+        open.retain(|_, window| {
+            if now.duration_since(window.opened) < self.window {
+                return true;
+            }
+            record.extend(window.summary());
+            false
+        });
+        record
+    }
+}
+
+fn record(db: &Database, entry: &NewAuditEntry) {
+    if record_audit_entry(db, entry).is_err() {
+        eprintln!("Failed to record audit entry for {} {}", entry.method, entry.path);
+    }
+}
+
+/// The target of a call that names it in its body rather than its path; the
+/// handler attaches it to its response for the audit log
+#[derive(Debug, Clone)]
+pub struct AuditTarget(pub String);
+
+/// `reply` carrying the target the audit log records for it
+pub fn with_audit_target(reply: impl Reply, target: &str) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    response.extensions_mut().insert(AuditTarget(target.to_string()));
+    response
+}
+
+/// What is known about a mutating call before it runs: the caller is looked up
+/// first, so that e.g. a logout is still attributed to the session it ended
+struct Pending(NewAuditEntry);
+
+/// Record every mutating call that `routes` answers or refuses in the audit
+/// log, folding repeated refused calls together
+pub fn audited<F, R>(
+    db: Database,
+    routes: F,
+    config: AuditConfig,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    let coalescer = Arc::new(Coalescer::new(Duration::from_secs(config.coalesce_seconds)));
+    if config.coalesce_seconds > 0 {
+        let (db, coalescer) = (db.clone(), coalescer.clone());
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(coalescer.window);
+            loop {
+                ticker.tick().await;
+                for entry in coalescer.close_expired(Instant::now()) {
+                    record(&db, &entry);
+                }
+            }
+        });
+    }
+
+    let lookup_db = db.clone();
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .and(warp::addr::remote())
+        .map(move |method: Method, path: FullPath, headers: HeaderMap, remote: Option<SocketAddr>| {
+            let (action, target) = describe(&method, path.as_str())?;
+            let token = headers
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            let (actor, api_key_id) = token.and_then(|token| token_owner(&lookup_db, token)).unzip();
+            let org = headers
+                .get(ORG_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .filter(|org| !org.is_empty())
+                .map(str::to_string);
+            Some(Pending(NewAuditEntry {
+                actor,
+                api_key_id: api_key_id.flatten(),
+                org,
+                action,
+                target,
+                method: method.to_string(),
+                path: path.as_str().to_string(),
+                source_ip: client_ip(remote, &headers).map(|ip| ip.to_string()),
+                status: 0,
+                repeats: 0,
+            }))
+        })
+        // Rejections left over by `recover`, such as unreadable bodies, are
+        // recorded too before they are passed on
+        .and(
+            routes
+                .map(|reply: R| Ok(reply.into_response()))
+                .or_else(|rejection: Rejection| async move { Ok::<_, Rejection>((Err(rejection),)) }),
+        )
+        .and_then(move |pending: Option<Pending>, outcome: Result<warp::reply::Response, Rejection>| {
+            let db = db.clone();
+            let coalescer = coalescer.clone();
+            async move {
+                let status = match &outcome {
+                    Ok(response) => Some(response.status()),
+                    Err(rejection) => rejected_status(rejection),
+                };
+                if let (Some(Pending(mut entry)), Some(status)) = (pending, status) {
+                    entry.status = status.as_u16();
+                    if let Some(AuditTarget(target)) = outcome.as_ref().ok().and_then(|response| response.extensions().get()) {
+                        entry.target = Some(target.clone());
+                    }
+                    for entry in coalescer.admit(entry, Instant::now()) {
+                        record(&db, &entry);
+                    }
+                }
+                outcome
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::api::device_routes::delete_device;
+    use crate::logic::serve::api::token_service::hash_token;
+    use crate::logic::serve::database::{
+        create_session, create_user, get_device_secret, init_database, list_audit_entries, set_device_credential,
+        store_device_data,
+    };
+    use crate::logic::serve::roles::Role;
+    use serde_json::json;
+
+    fn action(method: Method, path: &str) -> Option<(String, Option<String>)> {
+        describe(&method, path)
+    }
+
+    #[test]
+    fn test_audit_actions() {
+        let some = |action: &str, target: Option<&str>| Some((action.to_string(), target.map(str::to_string)));
+        assert_eq!(action(Method::POST, "/api/devices/robot1/credentials"), some("device.credentials.rotate", Some("robot1")));
+        assert_eq!(action(Method::DELETE, "/api/account/bob"), some("user.delete", Some("bob")));
+        assert_eq!(action(Method::DELETE, "/api/account/sessions/4"), some("session.revoke", Some("4")));
+        assert_eq!(action(Method::PUT, "/api/admin/users/bob/role"), some("user.role", Some("bob")));
+        assert_eq!(action(Method::POST, "/api/config/update"), some("server_config.update", None));
+        assert_eq!(action(Method::DELETE, "/api/user/thresholds/3"), some("thresholds.delete", Some("3")));
+        assert_eq!(action(Method::PUT, "/api/webhooks/2"), some("webhook.update", Some("2")));
+        assert_eq!(action(Method::POST, "/api/keys"), some("api_key.create", None));
+        assert_eq!(action(Method::PUT, "/api/orgs/acme/members/bob"), some("org.member.update", Some("acme/bob")));
+        assert_eq!(action(Method::POST, "/api/alerts/rules"), some("alert_rule.create", None));
+        assert_eq!(action(Method::PUT, "/api/notifications/email"), some("email.update", None));
+        assert_eq!(action(Method::POST, "/api/user/thresholds"), some("thresholds.set", None));
+        assert_eq!(action(Method::GET, "/api/devices"), None);
+        assert_eq!(action(Method::POST, "/api/pulse"), None);
+        assert_eq!(action(Method::POST, "/api/pulse/batch"), None);
+        assert_eq!(action(Method::POST, "/index.html"), None);
+    }
+
+    #[tokio::test]
+    async fn test_device_deletion_names_the_device() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        create_session(&db, &hash_token("alice-token"), &hash_token("alice-refresh"), "alice", None).unwrap();
+        store_device_data(&db, "alice:robot1", Some("robot1"), "temp", &json!(21.5), &chrono::Utc::now().to_rfc3339(), false)
+            .unwrap();
+        set_device_credential(&db, "alice:robot1", "s3cret").unwrap();
+        let routes = delete_device(db.clone()).recover(|rejection| async move { Err::<warp::reply::Response, _>(rejection) });
+        let api = audited(db.clone(), routes, AuditConfig::default());
+
+        for _ in 0..2 {
+            warp::test::request()
+                .method("POST")
+                .path("/api/device/delete")
+                .header("authorization", "Bearer alice-token")
+                .json(&json!({ "device_id": "robot1" }))
+                .reply(&api)
+                .await;
+        }
+
+        let filter = AuditQuery::default().resolve(chrono::Utc::now()).unwrap();
+        let entries = list_audit_entries(&db, &filter).unwrap();
+        let recorded: Vec<_> = entries.iter().map(|entry| (entry.action.as_str(), entry.target.as_deref(), entry.status)).collect();
+        assert_eq!(recorded, [("device.delete", Some("robot1"), 404), ("device.delete", Some("robot1"), 200)]);
+        // The credential is deleted with the device
+        assert_eq!(get_device_secret(&db, "alice:robot1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_refused_calls_are_coalesced() {
+        let coalescer = Coalescer::new(Duration::from_secs(60));
+        let entry = |source_ip: &str, status: u16| NewAuditEntry {
+            actor: None,
+            api_key_id: None,
+            org: None,
+            action: "account.login".to_string(),
+            target: None,
+            method: "POST".to_string(),
+            path: "/api/account/login".to_string(),
+            source_ip: Some(source_ip.to_string()),
+            status,
+            repeats: 0,
+        };
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+
+        assert_eq!(coalescer.admit(entry("203.0.113.7", 401), at(0)).len(), 1);
+        for second in 1..=5 {
+            assert!(coalescer.admit(entry("203.0.113.7", 401), at(second)).is_empty());
+        }
+        // Another address, and successful calls, are recorded as they come
+        assert_eq!(coalescer.admit(entry("203.0.113.8", 401), at(5)).len(), 1);
+        assert_eq!(coalescer.admit(entry("203.0.113.7", 200), at(5)).len(), 1);
+        assert_eq!(coalescer.admit(entry("203.0.113.7", 200), at(6)).len(), 1);
+
+        assert!(coalescer.close_expired(at(30)).is_empty());
+        let closed = coalescer.close_expired(at(61));
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].repeats, 5);
+
+        // A call after the window opens the next one
+        let recorded = coalescer.admit(entry("203.0.113.8", 401), at(70));
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].repeats, 0);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let mut headers = HeaderMap::new();
+        // A forged entry from the client, then the one the proxy added
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        let local: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let remote: SocketAddr = "198.51.100.2:5000".parse().unwrap();
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        assert_eq!(forwarded_ip(Some(local), &headers, 1), ip("10.0.0.1"));
+        assert_eq!(forwarded_ip(Some(local), &headers, 2), ip("203.0.113.7"));
+        assert_eq!(forwarded_ip(Some(local), &headers, 5), ip("203.0.113.7"));
+        assert_eq!(forwarded_ip(Some(local), &headers, 0), ip("127.0.0.1"));
+        assert_eq!(forwarded_ip(Some(remote), &headers, 1), ip("198.51.100.2"));
+        assert_eq!(forwarded_ip(Some(local), &HeaderMap::new(), 1), ip("127.0.0.1"));
+        assert_eq!(forwarded_ip(None, &headers, 1), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", "203.0.113.9".parse().unwrap());
+        assert_eq!(forwarded_ip(Some(local), &headers, 1), ip("203.0.113.9"));
+    }
+}
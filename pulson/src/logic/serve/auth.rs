@@ -1,26 +1,206 @@
-use crate::logic::serve::database::Database;
-use warp::{header::optional, reject::Reject, Filter, Rejection};
+use crate::logic::serve::api_keys::{hash_key, path_device, ApiKey, Scope, KEY_PREFIX};
+use crate::logic::serve::database::{get_api_key_by_hash, get_org_role, get_user_role, touch_api_key, Database};
+use crate::logic::serve::roles::admin_only;
+use std::collections::HashMap;
+use warp::{header::optional, http::Method, path::FullPath, reject::Reject, Filter, Rejection};
 
-use crate::logic::serve::api::token_service::validate_token;
+use crate::logic::serve::api::token_service::{session_for_token, validate_token};
 
 /// Marker for unauthorized rejection
 #[derive(Debug)]
 pub struct Unauthorized;
 impl Reject for Unauthorized {}
 
+/// Marker for a request the caller's role, or API key, does not allow
+#[derive(Debug)]
+pub struct Forbidden;
+impl Reject for Forbidden {}
+
+/// Header naming the organization a request acts for. Without it a request
+/// acts for the caller's own devices.
+pub const ORG_HEADER: &str = "x-pulson-org";
+
+/// Who a request acts for: a logged-in user, or one of their API keys
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub username: String,
+    /// Whose devices the request is about: the user's own (their username) or
+    /// an organization's. Device ids are stored as `owner:device`.
+    pub owner: String,
+    pub key: Option<ApiKey>,
+}
+
+impl Caller {
+    /// Whether the caller may act on one of its owner's devices
+    pub fn allows_device(&self, device_id: &str) -> bool {
+        self.key.as_ref().is_none_or(|key| key.allows_device(device_id))
+    }
+}
+
 /// A filter that extracts `Authorization: Bearer <token>` and looks up the username in the database.
+/// Every route is checked here against the caller's role, and API keys also
+/// against their scopes; keys limited to some devices are only let through to
+/// routes naming one of them.
 pub fn authenticated_user(
     db: Database,
 ) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
-    optional::<String>("authorization").and_then(move |auth_header: Option<String>| {
-        let db_clone = db.clone();
-        async move {
-            let header = auth_header.ok_or_else(|| warp::reject::custom(Unauthorized))?;
-            let token_str = header
-                .strip_prefix("Bearer ")
-                .ok_or_else(|| warp::reject::custom(Unauthorized))?;
-
-            validate_token(&db_clone, token_str) // Use the new token service function
-        }
+    authenticated_caller(db).and(warp::path::full()).and_then(|caller: Caller, path: FullPath| async move {
+        device_bound(caller, path.as_str()).map(|caller| caller.username)
+    })
+}
+
+/// Like `authenticated_user`, but extracts the owner of the devices the request
+/// acts on: the organization named in the `X-Pulson-Org` header, checked
+/// against the caller's role in it, or else the user themselves.
+pub fn authenticated_owner(
+    db: Database,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    authenticated_caller(db).and(warp::path::full()).and_then(|caller: Caller, path: FullPath| async move {
+        device_bound(caller, path.as_str()).map(|caller| caller.owner)
     })
 }
+
+/// Like `authenticated_owner`, but also accepts the token as an `access_token` query
+/// parameter, and the organization as `org`, for clients that cannot set headers
+/// (e.g. the browser `EventSource`).
+pub fn authenticated_owner_or_query_token(
+    db: Database,
+) -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    authenticated_caller_or_query_token(db)
+        .and(warp::path::full())
+        .and_then(|caller: Caller, path: FullPath| async move {
+            device_bound(caller, path.as_str()).map(|caller| caller.owner)
+        })
+}
+
+/// Like `authenticated_owner`, but lets device-limited keys through to routes
+/// that name their devices in the body; those routes check `Caller::allows_device`.
+pub fn authenticated_caller(
+    db: Database,
+) -> impl Filter<Extract = (Caller,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(optional::<String>("authorization"))
+        .and(optional::<String>(ORG_HEADER))
+        .and_then(move |method: Method, path: FullPath, auth_header: Option<String>, org: Option<String>| {
+            let db_clone = db.clone();
+            async move {
+                let header = auth_header.ok_or_else(|| warp::reject::custom(Unauthorized))?;
+                let token_str = header
+                    .strip_prefix("Bearer ")
+                    .ok_or_else(|| warp::reject::custom(Unauthorized))?;
+
+                authorize(&db_clone, token_str, org.as_deref(), &method, path.as_str())
+            }
+        })
+}
+
+/// `authenticated_caller` with the `access_token` and `org` query parameter fallbacks
+pub fn authenticated_caller_or_query_token(
+    db: Database,
+) -> impl Filter<Extract = (Caller,), Error = Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(optional::<String>("authorization"))
+        .and(optional::<String>(ORG_HEADER))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |method: Method, path: FullPath, auth_header: Option<String>, org: Option<String>, query: HashMap<String, String>| {
+            let db_clone = db.clone();
+            async move {
+                let token_str = match auth_header {
+                    Some(header) => header
+                        .strip_prefix("Bearer ")
+                        .map(str::to_string)
+                        .ok_or_else(|| warp::reject::custom(Unauthorized))?,
+                    None => query
+                        .get("access_token")
+                        .cloned()
+                        .ok_or_else(|| warp::reject::custom(Unauthorized))?,
+                };
+                let org = org.or_else(|| query.get("org").cloned());
+
+                authorize(&db_clone, &token_str, org.as_deref(), &method, path.as_str())
+            }
+        })
+}
+
+/// Resolve a login token or API key and check the request against the user's
+/// role, or their role in the organization the request is for. Keys are also
+/// held to their scopes, expiry, organization and the device named in the
+/// path, and never reach admin routes.
+fn authorize(db: &Database, token_str: &str, org: Option<&str>, method: &Method, path: &str) -> Result<Caller, Rejection> {
+    let scope = Scope::required(method.as_str(), path);
+    let (username, key) = if token_str.starts_with(KEY_PREFIX) {
+        let (username, key) = match get_api_key_by_hash(db, &hash_key(token_str)) {
+            Ok(Some(found)) => found,
+            Ok(None) => return Err(warp::reject::custom(Unauthorized)),
+            Err(_) => {
+                eprintln!("Database error validating an API key");
+                return Err(warp::reject::custom(Unauthorized));
+            }
+        };
+        if key.is_expired(&chrono::Utc::now()) {
+            return Err(warp::reject::custom(Unauthorized));
+        }
+        let in_scope = scope.is_some_and(|scope| key.scopes.contains(&scope));
+        if !in_scope || path_device(path).is_some_and(|device_id| !key.allows_device(device_id)) {
+            return Err(warp::reject::custom(Forbidden));
+        }
+        (username, Some(key))
+    } else {
+        (validate_token(db, token_str)?, None)
+    };
+
+    // Keys act for the organization they were made for, whatever the request says
+    let owner = match (key.as_ref().and_then(|key| key.org.as_deref()), org.filter(|org| !org.is_empty())) {
+        (Some(key_org), Some(org)) if key_org != org => return Err(warp::reject::custom(Forbidden)),
+        (Some(key_org), _) => key_org.to_string(),
+        (None, Some(org)) if key.is_some() && org != username => return Err(warp::reject::custom(Forbidden)),
+        (None, Some(org)) => org.to_string(),
+        (None, None) => username.clone(),
+    };
+    let role = match get_user_role(db, &username) {
+        Ok(Some(role)) => role,
+        _ => return Err(warp::reject::custom(Unauthorized)),
+    };
+    let allowed = if admin_only(method.as_str(), path) {
+        role.is_admin() && key.is_none()
+    } else if owner == username {
+        scope.is_none_or(|scope| role.grants(scope))
+    } else {
+        // Not being a member of the organization is the same as it not existing
+        match get_org_role(db, &owner, &username) {
+            Ok(Some(org_role)) => scope.is_none_or(|scope| org_role.grants(scope)),
+            Ok(None) => false,
+            Err(_) => return Err(warp::reject::custom(Unauthorized)),
+        }
+    };
+    if !allowed {
+        return Err(warp::reject::custom(Forbidden));
+    }
+    if let Some(key) = &key {
+        let _ = touch_api_key(db, key.id);
+    }
+    Ok(Caller { username, owner, key })
+}
+
+/// Who a bearer token belongs to, and the API key's id if it is one, without
+/// checking expiry or recording use. For the audit log, which also names the
+/// callers of requests that were refused.
+pub fn token_owner(db: &Database, token_str: &str) -> Option<(String, Option<i64>)> {
+    if token_str.starts_with(KEY_PREFIX) {
+        let (username, key) = get_api_key_by_hash(db, &hash_key(token_str)).ok()??;
+        return Some((username, Some(key.id)));
+    }
+    let (username, _session) = session_for_token(db, token_str).ok()??;
+    Some((username, None))
+}
+
+/// Only let a device-limited key through if the path names one of its devices
+fn device_bound(caller: Caller, path: &str) -> Result<Caller, Rejection> {
+    let limited = caller.key.as_ref().is_some_and(|key| key.device_id.is_some());
+    if limited && path_device(path).is_none() {
+        return Err(warp::reject::custom(Forbidden));
+    }
+    Ok(caller)
+}
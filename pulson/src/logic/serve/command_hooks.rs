@@ -0,0 +1,311 @@
+use crate::logic::config::ServerConfig;
+use crate::logic::serve::database::{get_org_role, get_user_role, Database};
+use crate::logic::serve::events::{EventBus, LiveEvent};
+use crate::logic::serve::maintenance::event_silenced;
+use serde::Deserialize;
+use std::process::{Output, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Semaphore;
+
+/// Longest time a hook command may be given to run
+const MAX_TIMEOUT_SECONDS: u64 = 3600;
+
+/// A command the server runs when a device or topic of `user`, or of an
+/// organization `user` is a member of, changes status. Hooks are only read
+/// from the server config file and only run for admins.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandHook {
+    pub name: String,
+    /// Admin whose devices, and those of their organizations, trigger the hook
+    pub user: String,
+    /// Executable to run; it is started directly, not through a shell
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Only this device
+    pub device: Option<String>,
+    /// Only this topic; device status changes are then ignored
+    pub topic: Option<String>,
+    /// Only changes to these statuses, e.g. `["Offline", "Inactive"]`; empty means any
+    #[serde(default)]
+    pub status: Vec<String>,
+    /// Seconds the command may run before it is killed
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+/// The parts of a status change a hook looks at
+#[derive(Debug, PartialEq)]
+struct StatusChange<'a> {
+    kind: &'static str,
+    owner: &'a str,
+    device_id: &'a str,
+    topic: Option<&'a str>,
+    status: String,
+    previous_status: Option<String>,
+    last_seen: &'a str,
+}
+
+impl<'a> StatusChange<'a> {
+    fn from_event(event: &'a LiveEvent) -> Option<Self> {
+        match event {
+            LiveEvent::DeviceStatus { owner, device_id, status, previous_status, last_seen } => Some(Self {
+                kind: event.kind(),
+                owner,
+                device_id,
+                topic: None,
+                status: format!("{:?}", status),
+                previous_status: previous_status.as_ref().map(|s| format!("{:?}", s)),
+                last_seen,
+            }),
+            LiveEvent::TopicStatus { owner, device_id, topic, status, previous_status, last_seen } => Some(Self {
+                kind: event.kind(),
+                owner,
+                device_id,
+                topic: Some(topic),
+                status: format!("{:?}", status),
+                previous_status: previous_status.as_ref().map(|s| format!("{:?}", s)),
+                last_seen,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl CommandHook {
+    /// Check the hook before it is enabled at startup
+    fn validate(&self) -> Result<(), String> {
+        if self.command.trim().is_empty() {
+            return Err("command is empty".to_string());
+        }
+        if self.timeout_seconds == 0 || self.timeout_seconds > MAX_TIMEOUT_SECONDS {
+            return Err(format!("timeout_seconds must be between 1 and {}", MAX_TIMEOUT_SECONDS));
+        }
+        Ok(())
+    }
+
+    fn is_admin(&self, db: &Database) -> bool {
+        matches!(get_user_role(db, &self.user), Ok(Some(role)) if role.is_admin())
+    }
+
+    fn matches(&self, db: &Database, change: &StatusChange) -> bool {
+        self.device.as_deref().is_none_or(|d| d == change.device_id)
+            && self.topic.as_deref().is_none_or(|t| Some(t) == change.topic)
+            && (self.status.is_empty() || self.status.iter().any(|s| s.eq_ignore_ascii_case(&change.status)))
+            && (change.owner == self.user || matches!(get_org_role(db, change.owner, &self.user), Ok(Some(_))))
+    }
+
+    /// Environment describing the change, all prefixed with `PULSON_`
+    fn environment(&self, change: &StatusChange) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            ("PULSON_HOOK", self.name.clone()),
+            ("PULSON_EVENT", change.kind.to_string()),
+            ("PULSON_USER", change.owner.to_string()),
+            ("PULSON_DEVICE_ID", change.device_id.to_string()),
+            ("PULSON_STATUS", change.status.clone()),
+            ("PULSON_PREVIOUS_STATUS", change.previous_status.clone().unwrap_or_default()),
+            ("PULSON_LAST_SEEN", change.last_seen.to_string()),
+        ];
+        if let Some(topic) = change.topic {
+            env.push(("PULSON_TOPIC", topic.to_string()));
+        }
+        env
+    }
+
+    /// Run the command with `stdin` as its input, killing it once the timeout passes
+    async fn execute(&self, env: &[(&str, String)], stdin: &[u8]) -> Result<Output, String> {
+        let run = async {
+            let mut child = Command::new(&self.command)
+                .args(&self.args)
+                .envs(env.iter().map(|(k, v)| (*k, v.as_str())))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| format!("failed to start {}: {}", self.command, e))?;
+            if let Some(mut input) = child.stdin.take() {
+                // A command that ignores its input may close it early; that is fine
+                let _ = input.write_all(stdin).await;
+            }
+            child.wait_with_output().await.map_err(|e| e.to_string())
+        };
+
+        tokio::time::timeout(Duration::from_secs(self.timeout_seconds), run)
+            .await
+            .unwrap_or_else(|_| Err(format!("killed after {}s timeout", self.timeout_seconds)))
+    }
+}
+
+/// Start running the command hooks of the server config on status changes.
+/// A hook only runs while its user exists and has the admin role.
+pub fn spawn_command_hooks(db: Database, events: EventBus, config: &ServerConfig) {
+    let hooks: Vec<Arc<CommandHook>> = config
+        .command_hooks
+        .iter()
+        .filter(|hook| match hook.validate() {
+            Ok(()) => {
+                if !hook.is_admin(&db) {
+                    eprintln!("Command hook '{}': user '{}' is not an admin yet", hook.name, hook.user);
+                }
+                true
+            }
+            Err(e) => {
+                eprintln!("Command hook '{}' disabled: {}", hook.name, e);
+                false
+            }
+        })
+        .map(|hook| Arc::new(hook.clone()))
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    println!("{} command hook(s) enabled", hooks.len());
+
+    let max_concurrent = config.max_concurrent_commands.max(1);
+    let limit = Arc::new(Semaphore::new(max_concurrent));
+    let mut rx = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    eprintln!("Command hooks: missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            let Some(change) = StatusChange::from_event(&event) else {
+                continue;
+            };
+            let matching: Vec<&Arc<CommandHook>> = hooks.iter().filter(|hook| hook.matches(&db, &change)).collect();
+            if matching.is_empty() || event_silenced(&db, &event) {
+                continue;
+            }
+
+            for hook in matching {
+                // Runs that find every slot taken are dropped, not queued, so a
+                // burst of changes cannot pile up tasks
+                let Ok(permit) = limit.clone().try_acquire_owned() else {
+                    eprintln!(
+                        "Command hook '{}' skipped: {} commands are already running",
+                        hook.name, max_concurrent
+                    );
+                    continue;
+                };
+                let hook = hook.clone();
+                let db = db.clone();
+                let env = hook.environment(&change);
+                let mut stdin = serde_json::to_value(&event).unwrap_or_default();
+                stdin["username"] = serde_json::Value::String(change.owner.to_string());
+
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    // Checked on every run, so a user losing admin stops their hooks
+                    if !hook.is_admin(&db) {
+                        eprintln!("Command hook '{}' skipped: user '{}' is not an admin", hook.name, hook.user);
+                        return;
+                    }
+                    match hook.execute(&env, stdin.to_string().as_bytes()).await {
+                        Ok(output) if output.status.success() => {
+                            println!("Command hook '{}' ran successfully", hook.name);
+                        }
+                        Ok(output) => eprintln!(
+                            "Command hook '{}' exited with {}: {}",
+                            hook.name,
+                            output.status,
+                            String::from_utf8_lossy(&output.stderr).trim()
+                        ),
+                        Err(e) => eprintln!("Command hook '{}' failed: {}", hook.name, e),
+                    }
+                });
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::database::{create_organization, create_user, init_database};
+    use crate::logic::serve::roles::Role;
+    use crate::logic::types::TopicStatus;
+
+    fn hook(command: &str, args: &[&str]) -> CommandHook {
+        CommandHook {
+            name: "restart".to_string(),
+            user: "admin".to_string(),
+            command: command.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            device: Some("robot1".to_string()),
+            topic: None,
+            status: vec!["inactive".to_string()],
+            timeout_seconds: 5,
+        }
+    }
+
+    fn topic_event(owner: &str, device_id: &str, status: TopicStatus) -> LiveEvent {
+        LiveEvent::TopicStatus {
+            owner: owner.to_string(),
+            device_id: device_id.to_string(),
+            topic: "camera".to_string(),
+            status,
+            previous_status: Some(TopicStatus::Stale),
+            last_seen: "2025-06-01T12:00:00Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hook_matching() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "admin", "hash", Role::Admin).unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        create_organization(&db, "acme", "admin").unwrap();
+        create_organization(&db, "globex", "alice").unwrap();
+        let hook = hook("true", &[]);
+        let matches = |event: &LiveEvent| hook.matches(&db, &StatusChange::from_event(event).unwrap());
+
+        assert!(matches(&topic_event("admin", "robot1", TopicStatus::Inactive)));
+        assert!(!matches(&topic_event("admin", "robot1", TopicStatus::Stale)));
+        assert!(!matches(&topic_event("admin", "robot2", TopicStatus::Inactive)));
+        assert!(!matches(&topic_event("alice", "robot1", TopicStatus::Inactive)));
+        // Devices of organizations the hook's user belongs to count too
+        assert!(matches(&topic_event("acme", "robot1", TopicStatus::Inactive)));
+        assert!(!matches(&topic_event("globex", "robot1", TopicStatus::Inactive)));
+
+        let camera_only = CommandHook { topic: Some("lidar".to_string()), ..hook.clone() };
+        let change = topic_event("admin", "robot1", TopicStatus::Inactive);
+        assert!(!camera_only.matches(&db, &StatusChange::from_event(&change).unwrap()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_execute_passes_details_and_times_out() {
+        let event = topic_event("admin", "robot1", TopicStatus::Inactive);
+        let change = StatusChange::from_event(&event).unwrap();
+
+        let echo = hook("/bin/sh", &["-c", "echo \"$PULSON_DEVICE_ID/$PULSON_TOPIC $PULSON_PREVIOUS_STATUS->$PULSON_STATUS\"; cat"]);
+        let output = echo.execute(&echo.environment(&change), b"{\"kind\":\"topic_status\"}").await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "robot1/camera Stale->Inactive\n{\"kind\":\"topic_status\"}"
+        );
+
+        let slow = CommandHook { timeout_seconds: 1, ..hook("/bin/sh", &["-c", "sleep 10"]) };
+        let started = std::time::Instant::now();
+        assert!(slow.execute(&[], b"").await.unwrap_err().contains("timeout"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let missing = hook("/nonexistent/command", &[]);
+        assert!(missing.execute(&[], b"").await.is_err());
+    }
+}
@@ -1,9 +1,21 @@
 use rusqlite::{Connection, OptionalExtension};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use warp::http::StatusCode;
 use serde_json::{json, Value};
-use super::db_types::DataType;
+use super::aggregate::summarize;
+use super::alerts::{glob_match, AlertCondition, AlertRuleSpec};
+use super::api::token_service::{hash_token, open_secret, seal_secret};
+use super::api_keys::{ApiKey, NewApiKey, Scope};
+use super::audit::{result_of, AuditEntry, AuditFilter, NewAuditEntry};
+use super::roles::Role;
+use super::email::EmailSettings;
+use super::maintenance::{MaintenanceWindow, WindowKind};
+use super::orgs::{OrgMember, Organization};
+use super::webhooks::WebhookSpec;
+use super::db_types::{DataCursor, DataPage, DataType, Rollup, TimeWindow};
+use crate::logic::config::{format_duration, ThresholdOverride};
 
 pub type Database = Arc<Mutex<Connection>>;
 
@@ -15,11 +27,16 @@ pub fn init_database<P: AsRef<Path>>(db_path: P) -> anyhow::Result<Database> {
         "CREATE TABLE IF NOT EXISTS users (
             username TEXT PRIMARY KEY,
             password_hash TEXT NOT NULL,
-            role TEXT NOT NULL DEFAULT 'user',
+            role TEXT NOT NULL DEFAULT 'operator',
             created_at DATETIME DEFAULT CURRENT_TIMESTAMP
         )",
         [],
     )?;
+    // Roles from before role-based access: root users become admins, the rest operators
+    conn.execute_batch(
+        "UPDATE users SET role = 'admin' WHERE role = 'root';
+         UPDATE users SET role = 'operator' WHERE role = 'user';",
+    )?;
 
     conn.execute(
         "CREATE TABLE IF NOT EXISTS tokens (
@@ -31,6 +48,62 @@ pub fn init_database<P: AsRef<Path>>(db_path: P) -> anyhow::Result<Database> {
         [],
     )?;
 
+    // Sessions: a token can be traded for a new one with its refresh token.
+    // Tokens from before sessions existed count as issued at login.
+    let token_columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('tokens')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if !token_columns.iter().any(|column| column == "issued_at") {
+        conn.execute_batch(
+            "ALTER TABLE tokens ADD COLUMN refresh_token TEXT;
+             ALTER TABLE tokens ADD COLUMN issued_at TEXT;
+             ALTER TABLE tokens ADD COLUMN last_used_at TEXT;
+             ALTER TABLE tokens ADD COLUMN user_agent TEXT;
+             UPDATE tokens SET created_at = strftime('%Y-%m-%dT%H:%M:%SZ', created_at),
+                               issued_at = strftime('%Y-%m-%dT%H:%M:%SZ', created_at);",
+        )?;
+    }
+
+    // Only keyed hashes of tokens are kept. Rows from before are hashed in
+    // place; the renamed columns mark this as done.
+    let token_columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('tokens')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if token_columns.iter().any(|column| column == "token") {
+        let tx = conn.unchecked_transaction()?;
+        let rows: Vec<(i64, String, Option<String>)> = tx
+            .prepare("SELECT rowid, token, refresh_token FROM tokens")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        for (rowid, token, refresh_token) in rows {
+            tx.execute(
+                "UPDATE tokens SET token = ?1, refresh_token = ?2 WHERE rowid = ?3",
+                rusqlite::params![hash_token(&token), refresh_token.as_deref().map(hash_token), rowid],
+            )?;
+        }
+        tx.execute_batch(
+            "ALTER TABLE tokens RENAME COLUMN token TO token_hash;
+             ALTER TABLE tokens RENAME COLUMN refresh_token TO refresh_token_hash;",
+        )?;
+        tx.commit()?;
+    }
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_tokens_refresh_token ON tokens(refresh_token_hash)",
+        [],
+    )?;
+
+    // The refresh token a session was last refreshed with, so that using it
+    // again can be told apart from using an unknown one
+    if !token_columns.iter().any(|column| column == "previous_refresh_token_hash") {
+        conn.execute("ALTER TABLE tokens ADD COLUMN previous_refresh_token_hash TEXT", [])?;
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_tokens_previous_refresh_token ON tokens(previous_refresh_token_hash)",
+        [],
+    )?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS devices (
             id TEXT PRIMARY KEY,
@@ -59,8 +132,7 @@ pub fn init_database<P: AsRef<Path>>(db_path: P) -> anyhow::Result<Database> {
             warning_threshold_seconds INTEGER NOT NULL DEFAULT 300,
             stale_threshold_seconds INTEGER NOT NULL DEFAULT 3600,
             created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-            FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
         )",
         [],
     )?;
@@ -93,6 +165,377 @@ pub fn init_database<P: AsRef<Path>>(db_path: P) -> anyhow::Result<Database> {
         [],
     )?;
 
+    // Per-device secrets for transports that can't carry a user token (MQTT, UDP).
+    // UDP datagrams are signed with them (HMAC), so they are kept encrypted under
+    // the token key rather than hashed.
+    let create_credentials = "CREATE TABLE IF NOT EXISTS device_credentials (
+        device_id TEXT PRIMARY KEY,
+        encrypted_secret TEXT NOT NULL,
+        created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+    )";
+    conn.execute(create_credentials, [])?;
+
+    // Secrets stored in the clear are encrypted in place. Bcrypt hashes from the
+    // first version cannot be turned back into secrets, so those credentials are
+    // dropped and have to be created again.
+    let credential_columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('device_credentials')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if credential_columns.iter().any(|column| column == "secret") {
+        let tx = conn.unchecked_transaction()?;
+        let rows: Vec<(String, String)> = tx
+            .prepare("SELECT device_id, secret FROM device_credentials")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (device_id, secret) in rows {
+            tx.execute(
+                "UPDATE device_credentials SET secret = ?1 WHERE device_id = ?2",
+                [seal_secret(&secret), device_id],
+            )?;
+        }
+        tx.execute("ALTER TABLE device_credentials RENAME COLUMN secret TO encrypted_secret", [])?;
+        tx.commit()?;
+    } else if credential_columns.iter().any(|column| column == "secret_hash") {
+        let tx = conn.unchecked_transaction()?;
+        let dropped: i64 = tx.query_row("SELECT COUNT(*) FROM device_credentials", [], |row| row.get(0))?;
+        tx.execute("DROP TABLE device_credentials", [])?;
+        tx.execute(create_credentials, [])?;
+        tx.commit()?;
+        if dropped > 0 {
+            eprintln!("Dropped {} device credentials stored as hashes by an older version; create them again", dropped);
+        }
+    }
+
+    // Retention rules; an empty device, topic or data type matches any
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS retention_policies (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            device_id TEXT NOT NULL DEFAULT '',
+            topic TEXT NOT NULL DEFAULT '',
+            data_type TEXT NOT NULL DEFAULT '',
+            keep_seconds INTEGER NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(username, device_id, topic, data_type)
+        )",
+        [],
+    )?;
+
+    // Per-device and per-topic status thresholds; an empty device covers the topic
+    // on every device and an empty topic the device itself. Learned overrides
+    // have their thresholds filled in from the observed publish interval.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS status_overrides (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            device_id TEXT NOT NULL DEFAULT '',
+            topic TEXT NOT NULL DEFAULT '',
+            learned BOOLEAN NOT NULL DEFAULT 0,
+            online_threshold_seconds INTEGER,
+            warning_threshold_seconds INTEGER,
+            stale_threshold_seconds INTEGER,
+            observed_interval_seconds REAL,
+            updated_at TEXT NOT NULL,
+            UNIQUE(username, device_id, topic)
+        )",
+        [],
+    )?;
+
+    // Alert rules; an empty device or topic matches any. `condition` is the JSON
+    // of an `AlertCondition`.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS alert_rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            name TEXT NOT NULL,
+            device_id TEXT NOT NULL DEFAULT '',
+            topic TEXT NOT NULL DEFAULT '',
+            condition TEXT NOT NULL,
+            enabled BOOLEAN NOT NULL DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    // Every time an alert rule fired, with when it resolved. The rule name is
+    // copied so history stays readable after the rule is removed.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS alerts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            rule_id INTEGER NOT NULL,
+            rule_name TEXT NOT NULL,
+            username TEXT NOT NULL,
+            device_id TEXT NOT NULL,
+            topic TEXT NOT NULL DEFAULT '',
+            state TEXT NOT NULL CHECK(state IN ('firing', 'resolved')),
+            message TEXT NOT NULL,
+            fired_at TEXT NOT NULL,
+            resolved_at TEXT
+        )",
+        [],
+    )?;
+
+    // Webhook destinations. `headers` is a JSON object, `template` the JSON body
+    // template and `events` a comma-separated list of event kinds (empty: all).
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS webhooks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            name TEXT NOT NULL,
+            url TEXT NOT NULL,
+            encrypted_secret TEXT,
+            headers TEXT NOT NULL DEFAULT '{}',
+            template TEXT,
+            events TEXT NOT NULL DEFAULT '',
+            enabled BOOLEAN NOT NULL DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    // Signing secrets stored in the clear are encrypted in place under the token
+    // key, like device secrets; the renamed column marks this as done
+    let webhook_columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('webhooks')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if webhook_columns.iter().any(|column| column == "secret") {
+        let tx = conn.unchecked_transaction()?;
+        let rows: Vec<(i64, String)> = tx
+            .prepare("SELECT id, secret FROM webhooks WHERE secret IS NOT NULL")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        for (id, secret) in rows {
+            tx.execute("UPDATE webhooks SET secret = ?1 WHERE id = ?2", rusqlite::params![seal_secret(&secret), id])?;
+        }
+        tx.execute("ALTER TABLE webhooks RENAME COLUMN secret TO encrypted_secret", [])?;
+        tx.commit()?;
+    }
+
+    // Delivery log of every webhook
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            webhook_id INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            state TEXT NOT NULL CHECK(state IN ('pending', 'delivered', 'failed')),
+            attempts INTEGER NOT NULL DEFAULT 0,
+            response_status INTEGER,
+            error TEXT,
+            created_at TEXT NOT NULL,
+            last_attempt_at TEXT,
+            FOREIGN KEY(webhook_id) REFERENCES webhooks(id) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    // Email notification settings of each user; the lists are JSON arrays
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS email_settings (
+            username TEXT PRIMARY KEY,
+            recipients TEXT NOT NULL DEFAULT '[]',
+            device_statuses TEXT NOT NULL DEFAULT '[]',
+            topic_statuses TEXT NOT NULL DEFAULT '[]',
+            enabled BOOLEAN NOT NULL DEFAULT 1,
+            updated_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Maintenance windows and silences; an empty device_id or topic covers all
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS maintenance_windows (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            device_id TEXT NOT NULL DEFAULT '',
+            topic TEXT NOT NULL DEFAULT '',
+            kind TEXT NOT NULL CHECK(kind IN ('maintenance', 'silence')),
+            starts_at TEXT NOT NULL,
+            ends_at TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    // Scoped API keys; only a digest of each key is kept
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS api_keys (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            name TEXT NOT NULL,
+            key_hash TEXT NOT NULL UNIQUE,
+            prefix TEXT NOT NULL,
+            scopes TEXT NOT NULL,
+            device_id TEXT NOT NULL DEFAULT '',
+            expires_at TEXT,
+            last_used_at TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    // Organizations own devices like users do, shared by their members. Each
+    // member has a role in the organization.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS organizations (
+            name TEXT PRIMARY KEY,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS org_members (
+            org TEXT NOT NULL,
+            username TEXT NOT NULL,
+            role TEXT NOT NULL,
+            joined_at TEXT NOT NULL,
+            PRIMARY KEY (org, username),
+            FOREIGN KEY(org) REFERENCES organizations(name) ON DELETE CASCADE,
+            FOREIGN KEY(username) REFERENCES users(username) ON DELETE CASCADE
+        )",
+        [],
+    )?;
+
+    // Settings tables used to reference `users`, and organizations own settings
+    // too: rebuild them without that foreign key. Deleting a user clears them.
+    for table in OWNER_SETTINGS_TABLES {
+        let sql: String = conn.query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [table],
+            |row| row.get(0),
+        )?;
+        if let Some(sql) = without_users_reference(&sql) {
+            let sql = sql.replacen(&format!("CREATE TABLE {}", table), &format!("CREATE TABLE {}_rebuilt", table), 1);
+            conn.execute_batch(&format!(
+                "PRAGMA foreign_keys = OFF;
+                 BEGIN;
+                 {sql};
+                 INSERT INTO {table}_rebuilt SELECT * FROM {table};
+                 DROP TABLE {table};
+                 ALTER TABLE {table}_rebuilt RENAME TO {table};
+                 COMMIT;
+                 PRAGMA foreign_keys = ON;"
+            ))?;
+        }
+    }
+
+    // API keys act for their user's own devices (empty org) or for one organization
+    let api_key_columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('api_keys')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if !api_key_columns.iter().any(|column| column == "org") {
+        conn.execute("ALTER TABLE api_keys ADD COLUMN org TEXT NOT NULL DEFAULT ''", [])?;
+    }
+
+    // Every mutating API call, with who made it and how it ended; `repeats` counts
+    // identical refused calls folded into an entry. Append-only: the triggers
+    // refuse to change entries, and to remove them except for the retention
+    // task, which names its cutoff in `audit_log_prune` for the length of its
+    // transaction.
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at TEXT NOT NULL,
+            actor TEXT,
+            api_key_id INTEGER,
+            org TEXT,
+            action TEXT NOT NULL,
+            target TEXT,
+            method TEXT NOT NULL,
+            path TEXT NOT NULL,
+            source_ip TEXT,
+            status INTEGER NOT NULL,
+            result TEXT NOT NULL,
+            repeats INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS audit_log_prune (cutoff TEXT NOT NULL);
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+        BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+    )?;
+    let audit_columns: Vec<String> = conn
+        .prepare("SELECT name FROM pragma_table_info('audit_log')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if !audit_columns.iter().any(|column| column == "repeats") {
+        conn.execute("ALTER TABLE audit_log ADD COLUMN repeats INTEGER NOT NULL DEFAULT 0", [])?;
+    }
+    // The delete trigger of older databases refused every delete
+    let delete_trigger: Option<String> = conn
+        .query_row("SELECT sql FROM sqlite_master WHERE type = 'trigger' AND name = 'audit_log_no_delete'", [], |row| row.get(0))
+        .optional()?;
+    if !delete_trigger.is_some_and(|sql| sql.contains("audit_log_prune")) {
+        conn.execute_batch(
+            "DROP TRIGGER IF EXISTS audit_log_no_delete;
+             CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+             WHEN NOT EXISTS (SELECT 1 FROM audit_log_prune WHERE OLD.at < cutoff)
+             BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+        )?;
+    }
+
+    // Every status change of a device (empty topic) or topic, for uptime reports
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS status_transitions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            topic TEXT NOT NULL DEFAULT '',
+            status TEXT NOT NULL,
+            previous_status TEXT,
+            at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Per-bucket rollups of every topic, kept when raw data is pruned. Records
+    // from before the tables existed are rolled up in the background, from
+    // `next_id` up to `last_id`; the row is removed once that is done.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rollup_backfill (
+            next_id INTEGER NOT NULL,
+            last_id INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    let rollups_exist: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1)",
+        [Rollup::Minute.table()],
+        |row| row.get(0),
+    )?;
+    for rollup in Rollup::ALL {
+        conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    device_id TEXT NOT NULL,
+                    topic TEXT NOT NULL,
+                    bucket TEXT NOT NULL,
+                    count INTEGER NOT NULL,
+                    value_count INTEGER NOT NULL,
+                    min REAL,
+                    max REAL,
+                    sum REAL,
+                    last_value REAL,
+                    last_value_at TEXT,
+                    first_timestamp TEXT NOT NULL,
+                    last_timestamp TEXT NOT NULL,
+                    PRIMARY KEY (device_id, topic, bucket)
+                )",
+                rollup.table()
+            ),
+            [],
+        )?;
+    }
+    if !rollups_exist {
+        conn.execute(
+            "INSERT INTO rollup_backfill (next_id, last_id)
+             SELECT MIN(id), MAX(id) FROM device_data HAVING COUNT(*) > 0",
+            [],
+        )?;
+    }
+
     // Migration: Update existing data to new type system if needed
     let _ = conn.execute("DROP TABLE IF EXISTS device_data_old", []);
     
@@ -121,17 +564,58 @@ pub fn init_database<P: AsRef<Path>>(db_path: P) -> anyhow::Result<Database> {
         [],
     )?;
 
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_device_data_topic_timestamp 
+         ON device_data(device_id, topic, timestamp)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_alerts_user_fired
+         ON alerts(username, fired_at)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_status_transitions_device_at
+         ON status_transitions(device_id, topic, at)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_actor
+         ON audit_log(actor, id)",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook
+         ON webhook_deliveries(webhook_id, id)",
+        [],
+    )?;
+
     Ok(Arc::new(Mutex::new(conn)))
 }
 
+/// `CREATE TABLE` text without its foreign key to `users`, if it has one
+fn without_users_reference(sql: &str) -> Option<String> {
+    let start = sql.find("FOREIGN KEY(username) REFERENCES users(username)")?;
+    let end = start + sql[start..].find("CASCADE")? + "CASCADE".len();
+    let columns = sql[..start].trim_end().strip_suffix(',')?;
+    Some(format!("{}{}", columns, &sql[end..]))
+}
+
 // User management functions
-pub fn create_user(db: &Database, username: &str, password_hash: &str, role: &str) -> Result<(), StatusCode> {
+pub fn create_user(db: &Database, username: &str, password_hash: &str, role: Role) -> Result<(), StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
+    // Usernames and organization names both prefix device ids, so they must not collide
     match conn.execute(
-        "INSERT INTO users (username, password_hash, role) VALUES (?1, ?2, ?3)",
-        [username, password_hash, role],
+        "INSERT INTO users (username, password_hash, role)
+         SELECT ?1, ?2, ?3 WHERE NOT EXISTS (SELECT 1 FROM organizations WHERE name = ?1)",
+        [username, password_hash, role.as_str()],
     ) {
+        Ok(0) => Err(StatusCode::CONFLICT), // Taken by an organization
         Ok(_) => Ok(()),
         Err(rusqlite::Error::SqliteFailure(err, _)) if err.code == rusqlite::ErrorCode::ConstraintViolation => {
             Err(StatusCode::CONFLICT) // User already exists
@@ -157,7 +641,8 @@ pub fn get_user_password_hash(db: &Database, username: &str) -> Result<Option<St
     }
 }
 
-pub fn get_user_role(db: &Database, username: &str) -> Result<Option<String>, StatusCode> {
+/// A user's role; a role this version does not know grants no more than viewing
+pub fn get_user_role(db: &Database, username: &str) -> Result<Option<Role>, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
     let mut stmt = conn.prepare("SELECT role FROM users WHERE username = ?1")
@@ -168,20 +653,38 @@ pub fn get_user_role(db: &Database, username: &str) -> Result<Option<String>, St
     }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
     match rows.next() {
-        Some(Ok(role)) => Ok(Some(role)),
+        Some(Ok(role)) => Ok(Some(Role::parse(&role).unwrap_or(Role::Viewer))),
         Some(Err(_)) => Err(StatusCode::INTERNAL_SERVER_ERROR),
         None => Ok(None),
     }
 }
 
+/// Give a user another role; false if there is no such user
+pub fn set_user_role(db: &Database, username: &str, role: Role) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute("UPDATE users SET role = ?1 WHERE username = ?2", [role.as_str(), username])
+        .map(|changed| changed > 0)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn count_users_with_role(db: &Database, role: Role) -> Result<i64, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row("SELECT COUNT(*) FROM users WHERE role = ?1", [role.as_str()], |row| row.get(0))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
 pub fn delete_user(db: &Database, username: &str) -> Result<bool, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    match conn.execute("DELETE FROM users WHERE username = ?1", [username]) {
-        Ok(0) => Ok(false), // No user was deleted
-        Ok(_) => Ok(true),  // User was deleted
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let tx = conn.unchecked_transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let deleted = tx.execute("DELETE FROM users WHERE username = ?1", [username])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if deleted == 0 {
+        return Ok(false); // No user was deleted
     }
+    delete_owner_settings(&tx, username)?;
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(true)
 }
 
 pub fn list_all_users(db: &Database) -> Result<Value, StatusCode> {
@@ -207,39 +710,167 @@ pub fn list_all_users(db: &Database) -> Result<Value, StatusCode> {
 }
 
 // Token management functions
-pub fn store_token(db: &Database, token: &str, username: &str) -> Result<(), StatusCode> {
+/// A login session; `id` is the row id of its token
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Session {
+    pub id: i64,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub created_at: String,
+    /// When the current token was handed out, at login or the last refresh
+    pub issued_at: String,
+    pub last_used_at: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+const SESSION_COLUMNS: &str = "rowid, token_hash, created_at, issued_at, last_used_at, user_agent";
+
+fn session_from_row(row: &rusqlite::Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        id: row.get(0)?,
+        token_hash: row.get(1)?,
+        created_at: row.get(2)?,
+        issued_at: row.get(3)?,
+        last_used_at: row.get(4)?,
+        user_agent: row.get(5)?,
+    })
+}
+
+fn now_rfc3339() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// Store a new session under the hashes of its token and refresh token
+pub fn create_session(
+    db: &Database,
+    token_hash: &str,
+    refresh_token_hash: &str,
+    username: &str,
+    user_agent: Option<&str>,
+) -> Result<Session, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    match conn.execute(
-        "INSERT INTO tokens (token, username) VALUES (?1, ?2)",
-        [token, username],
-    ) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    conn.query_row(
+        &format!(
+            "INSERT INTO tokens (token_hash, refresh_token_hash, username, created_at, issued_at, user_agent)
+             VALUES (?1, ?2, ?3, ?4, ?4, ?5)
+             RETURNING {}",
+            SESSION_COLUMNS
+        ),
+        rusqlite::params![token_hash, refresh_token_hash, username, now_rfc3339(), user_agent],
+        session_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-pub fn get_username_by_token(db: &Database, token: &str) -> Result<Option<String>, StatusCode> {
+fn get_session_by(db: &Database, column: &str, value: &str) -> Result<Option<(String, Session)>, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut stmt = conn.prepare("SELECT username FROM tokens WHERE token = ?1")
+    conn.query_row(
+        &format!("SELECT {}, username FROM tokens WHERE {} = ?1", SESSION_COLUMNS, column),
+        [value],
+        |row| Ok((row.get(6)?, session_from_row(row)?)),
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The session stored under a token hash, with its user
+pub fn get_session_by_token(db: &Database, token_hash: &str) -> Result<Option<(String, Session)>, StatusCode> {
+    get_session_by(db, "token_hash", token_hash)
+}
+
+pub fn get_session_by_refresh_token(db: &Database, refresh_token_hash: &str) -> Result<Option<(String, Session)>, StatusCode> {
+    get_session_by(db, "refresh_token_hash", refresh_token_hash)
+}
+
+/// The session that was last refreshed with a refresh token, with its user
+pub fn get_session_by_previous_refresh_token(
+    db: &Database,
+    refresh_token_hash: &str,
+) -> Result<Option<(String, Session)>, StatusCode> {
+    get_session_by(db, "previous_refresh_token_hash", refresh_token_hash)
+}
+
+/// Replace the hashes of a session's token and refresh token, issuing it anew.
+/// `None` when the session's refresh token is no longer `old_refresh_token_hash`,
+/// because the session was refreshed or ended in the meantime.
+pub fn rotate_session(
+    db: &Database,
+    id: i64,
+    old_refresh_token_hash: &str,
+    token_hash: &str,
+    refresh_token_hash: &str,
+) -> Result<Option<Session>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!(
+            "UPDATE tokens SET token_hash = ?1, refresh_token_hash = ?2, previous_refresh_token_hash = ?5,
+                               issued_at = ?3, last_used_at = NULL
+             WHERE rowid = ?4 AND refresh_token_hash = ?5
+             RETURNING {}",
+            SESSION_COLUMNS
+        ),
+        rusqlite::params![token_hash, refresh_token_hash, now_rfc3339(), id, old_refresh_token_hash],
+        session_from_row,
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Note that a session was used, pushing back its idle expiry. Written at most
+/// once per `interval_seconds` per session.
+pub fn touch_session(db: &Database, id: i64, interval_seconds: i64) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now = chrono::Utc::now();
+    let format = |time: chrono::DateTime<chrono::Utc>| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    conn.execute(
+        "UPDATE tokens SET last_used_at = ?1
+         WHERE rowid = ?2 AND (last_used_at IS NULL OR last_used_at < ?3)",
+        rusqlite::params![format(now), id, format(now - chrono::Duration::seconds(interval_seconds))],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// A user's sessions, oldest first
+pub fn list_sessions(db: &Database, username: &str) -> Result<Vec<Session>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tokens WHERE username = ?1 ORDER BY rowid",
+        SESSION_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let sessions = stmt
+        .query_map([username], session_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let mut rows = stmt.query_map([token], |row| {
-        Ok(row.get::<_, String>(0)?)
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    match rows.next() {
-        Some(Ok(username)) => Ok(Some(username)),
-        Some(Err(_)) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-        None => Ok(None),
-    }
+    Ok(sessions)
+}
+
+pub fn delete_session(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM tokens WHERE rowid = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rows_affected > 0)
+}
+
+/// Remove sessions issued before a cutoff, whose refresh tokens have expired
+pub fn delete_sessions_issued_before(db: &Database, cutoff: &chrono::DateTime<chrono::Utc>) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "DELETE FROM tokens WHERE issued_at < ?1",
+        [cutoff.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// End every session of a user except one; returns how many ended
+pub fn delete_other_sessions(db: &Database, username: &str, keep_id: i64) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "DELETE FROM tokens WHERE username = ?1 AND rowid != ?2",
+        rusqlite::params![username, keep_id],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-pub fn revoke_token(db: &Database, token: &str) -> Result<bool, StatusCode> {
+pub fn revoke_token(db: &Database, token_hash: &str) -> Result<bool, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
-    match conn.execute("DELETE FROM tokens WHERE token = ?1", [token]) {
+    match conn.execute("DELETE FROM tokens WHERE token_hash = ?1", [token_hash]) {
         Ok(0) => Ok(false), // No token was deleted
         Ok(_) => Ok(true),  // Token was deleted
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
@@ -258,6 +889,8 @@ pub fn get_user_config(db: &Database, username: &str) -> Result<Option<crate::lo
             online_threshold_seconds: row.get::<_, u64>(0)?,
             warning_threshold_seconds: row.get::<_, u64>(1)?,
             stale_threshold_seconds: row.get::<_, u64>(2)?,
+            overrides: Vec::new(),
+            maintenance: Vec::new(),
         })
     }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
@@ -299,17 +932,55 @@ pub fn set_user_config(db: &Database, username: &str, config: &crate::logic::con
     }
 }
 
+/// The user's thresholds, or the defaults, together with their per-device and
+/// per-topic overrides and the maintenance windows that are not over yet
 pub fn get_user_config_or_default(db: &Database, username: &str) -> crate::logic::config::StatusConfig {
-    match get_user_config(db, username) {
+    let mut config = match get_user_config(db, username) {
         Ok(Some(config)) => config,
         Ok(None) | Err(_) => crate::logic::config::StatusConfig::default(),
-    }
+    };
+    config.overrides = list_status_overrides(db, username).unwrap_or_default();
+    config.maintenance = list_maintenance_windows(db, username, false).unwrap_or_default();
+    config
 }
 
 // Device management functions
 
+/// Create or replace the credential of a (namespaced) device
+pub fn set_device_credential(db: &Database, device_id: &str, secret: &str) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "INSERT INTO device_credentials (device_id, encrypted_secret) VALUES (?1, ?2)
+         ON CONFLICT(device_id) DO UPDATE SET encrypted_secret = excluded.encrypted_secret, created_at = CURRENT_TIMESTAMP",
+        [device_id, &seal_secret(secret)],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// The secret of a device; `None` without a credential, or when it was
+/// encrypted under another token key
+pub fn get_device_secret(db: &Database, device_id: &str) -> Result<Option<String>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let sealed = conn.query_row(
+        "SELECT encrypted_secret FROM device_credentials WHERE device_id = ?1",
+        [device_id],
+        |row| row.get::<_, String>(0),
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(sealed.and_then(|sealed| open_secret(&sealed)))
+}
+
+pub fn delete_device_credential(db: &Database, device_id: &str) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM device_credentials WHERE device_id = ?1",
+        [device_id],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rows_affected > 0)
+}
+
 pub fn get_device_data(db: &Database, device_id: &str, status_config: &crate::logic::config::StatusConfig) -> Result<Option<String>, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let display_device_id = device_id.split_once(':').map_or(device_id, |(_, device)| device);
     
     // Check if device exists
     let mut device_stmt = conn.prepare("SELECT id FROM devices WHERE id = ?1")
@@ -337,7 +1008,7 @@ pub fn get_device_data(db: &Database, device_id: &str, status_config: &crate::lo
             ))?
             .with_timezone(&chrono::Utc);
         
-        let status = status_config.calculate_topic_status(&last_seen);
+        let status = status_config.calculate_topic_status(display_device_id, &topic_name, &last_seen);
         
         Ok((topic_name, last_seen_str, status))
     }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
@@ -376,15 +1047,15 @@ pub fn list_user_devices(db: &Database, username: &str, status_config: &crate::l
     let user_prefix = format!("{}:", username);
     let mut stmt = conn.prepare("
         SELECT d.id, d.name, 
-               COALESCE(MAX(t.last_seen), d.last_seen) as last_activity
+               MAX(COALESCE(MAX(t.last_seen), d.last_seen), d.last_seen) as last_activity
         FROM devices d
         LEFT JOIN topics t ON d.id = t.device_id
-        WHERE d.id LIKE ?1
+        WHERE substr(d.id, 1, length(?1)) = ?1
         GROUP BY d.id, d.name, d.last_seen
         ORDER BY last_activity DESC
     ").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
-    let device_iter = stmt.query_map([format!("{}%", user_prefix)], |row| {
+    let device_iter = stmt.query_map([&user_prefix], |row| {
         let full_device_id = row.get::<_, String>(0)?;
         let last_seen_str = row.get::<_, String>(2)?;
         
@@ -399,7 +1070,7 @@ pub fn list_user_devices(db: &Database, username: &str, status_config: &crate::l
             )),
         };
         
-        let status = status_config.calculate_device_status(&last_seen);
+        let status = status_config.calculate_device_status(display_device_id, &last_seen);
         
         Ok(json!({
             "device_id": display_device_id,
@@ -416,76 +1087,1550 @@ pub fn list_user_devices(db: &Database, username: &str, status_config: &crate::l
     Ok(json!(devices))
 }
 
-/// Deletes a device from the database.
-pub fn delete_device(db: &Database, device_id: &str) -> Result<bool, StatusCode> {
-    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    // Delete device - topics will be deleted automatically due to CASCADE
-    let rows_affected = conn.execute(
-        "DELETE FROM devices WHERE id = ?1",
-        [device_id],
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    Ok(rows_affected > 0)
+/// Last activity of a device and of each of its topics
+pub struct DeviceActivity {
+    /// Namespaced device id (`username:device`)
+    pub device_id: String,
+    /// Latest of the device's own last_seen and its topics' activity
+    pub last_seen: String,
+    /// (topic, last_seen) pairs
+    pub topics: Vec<(String, String)>,
 }
 
-/// Get historical pulse data for visualization
-pub fn get_pulse_history(db: &Database, device_id: &str, topic: Option<&str>, time_range: &str) -> Result<Value, StatusCode> {
+/// Activity of every device of every user, used by the background status monitor
+pub fn list_device_activity(db: &Database) -> Result<Vec<DeviceActivity>, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    // Calculate time range
-    let now = chrono::Utc::now();
-    let start_time = match time_range {
-        "1h" => now - chrono::Duration::hours(1),
-        "1d" => now - chrono::Duration::days(1),
-        "1w" => now - chrono::Duration::weeks(1),
-        "1m" => now - chrono::Duration::days(30),
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
-    
-    // Build query based on whether we want specific topic or all topics
-    let (query, params): (String, Vec<String>) = if let Some(topic_name) = topic {
-        (
-            "SELECT 
-                datetime(timestamp, 'utc')||'Z' as timestamp,
-                COUNT(*) as pulse_count
-             FROM device_data 
-             WHERE device_id = ?1 AND topic = ?2 AND timestamp >= ?3
-             GROUP BY strftime('%Y-%m-%d %H:%M', timestamp)
-             ORDER BY timestamp".to_string(),
-            vec![device_id.to_string(), topic_name.to_string(), start_time.to_rfc3339()]
+    let mut stmt = conn.prepare("
+        SELECT d.id, d.last_seen, t.topic, t.last_seen
+        FROM devices d
+        LEFT JOIN topics t ON d.id = t.device_id
+        ORDER BY d.id
+    ").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, Option<String>>(2)?,
+            row.get::<_, Option<String>>(3)?,
+        ))
+    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut devices: Vec<DeviceActivity> = Vec::new();
+    for row in rows {
+        let (device_id, device_last_seen, topic, topic_last_seen) =
+            row.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        if devices.last().map(|d| d.device_id != device_id).unwrap_or(true) {
+            devices.push(DeviceActivity {
+                device_id,
+                last_seen: device_last_seen,
+                topics: Vec::new(),
+            });
+        }
+        let device = devices.last_mut().expect("device was just pushed");
+        if let (Some(topic), Some(topic_last_seen)) = (topic, topic_last_seen) {
+            // Same rule as list_user_devices: the most recent of topic and device activity
+            if topic_last_seen > device.last_seen {
+                device.last_seen = topic_last_seen.clone();
+            }
+            device.topics.push((topic, topic_last_seen));
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Mark a device as alive without storing data, e.g. while it holds a WebSocket open.
+/// Creates the device if needed; last_seen never moves backwards.
+pub fn touch_device(db: &Database, device_id: &str, name: &str, timestamp: &str) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "INSERT INTO devices (id, name, last_seen) VALUES (?1, ?2, ?3) 
+         ON CONFLICT(id) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
+        [device_id, name, timestamp],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// Deletes a device from the database.
+pub fn delete_device(db: &Database, device_id: &str) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tx = conn.unchecked_transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    
+    // Delete device - topics will be deleted automatically due to CASCADE
+    let rows_affected = tx.execute(
+        "DELETE FROM devices WHERE id = ?1",
+        [device_id],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    for rollup in Rollup::ALL {
+        tx.execute(&format!("DELETE FROM {} WHERE device_id = ?1", rollup.table()), [device_id])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    tx.execute("DELETE FROM status_transitions WHERE device_id = ?1", [device_id])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // The credential goes too, so that a device registered later under the
+    // same id does not inherit its secret
+    let credentials = tx.execute("DELETE FROM device_credentials WHERE device_id = ?1", [device_id])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    
+    Ok(rows_affected + credentials > 0)
+}
+
+/// A retention rule: matching data older than `keep_seconds` is pruned.
+/// Unset fields match any device, topic or data type.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RetentionPolicy {
+    pub id: i64,
+    pub device_id: Option<String>,
+    pub topic: Option<String>,
+    pub data_type: Option<String>,
+    pub keep_seconds: u64,
+}
+
+fn retention_policy_from_row(row: &rusqlite::Row) -> rusqlite::Result<RetentionPolicy> {
+    let optional = |value: String| if value.is_empty() { None } else { Some(value) };
+    Ok(RetentionPolicy {
+        id: row.get(0)?,
+        device_id: optional(row.get(1)?),
+        topic: optional(row.get(2)?),
+        data_type: optional(row.get(3)?),
+        keep_seconds: row.get::<_, i64>(4)? as u64,
+    })
+}
+
+pub fn list_retention_policies(db: &Database, username: &str) -> Result<Vec<RetentionPolicy>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, device_id, topic, data_type, keep_seconds FROM retention_policies
+         WHERE username = ?1 ORDER BY device_id, topic, data_type"
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let policies = stmt
+        .query_map([username], retention_policy_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(policies)
+}
+
+/// Every retention rule of every user, as (username, rule)
+pub fn list_all_retention_policies(db: &Database) -> Result<Vec<(String, RetentionPolicy)>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(
+        "SELECT id, device_id, topic, data_type, keep_seconds, username FROM retention_policies"
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let policies = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(5)?, retention_policy_from_row(row)?)))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(policies)
+}
+
+/// Create a retention rule, or update the keep time of the rule with the same scope
+pub fn set_retention_policy(
+    db: &Database,
+    username: &str,
+    device_id: Option<&str>,
+    topic: Option<&str>,
+    data_type: Option<&str>,
+    keep_seconds: u64,
+) -> Result<RetentionPolicy, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        "INSERT INTO retention_policies (username, device_id, topic, data_type, keep_seconds)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(username, device_id, topic, data_type) DO UPDATE SET keep_seconds = excluded.keep_seconds
+         RETURNING id, device_id, topic, data_type, keep_seconds",
+        rusqlite::params![
+            username,
+            device_id.unwrap_or_default(),
+            topic.unwrap_or_default(),
+            data_type.unwrap_or_default(),
+            keep_seconds as i64
+        ],
+        retention_policy_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn delete_retention_policy(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM retention_policies WHERE id = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rows_affected > 0)
+}
+
+const STATUS_OVERRIDE_COLUMNS: &str = "id, device_id, topic, learned, online_threshold_seconds, \
+    warning_threshold_seconds, stale_threshold_seconds, observed_interval_seconds";
+
+fn status_override_from_row(row: &rusqlite::Row) -> rusqlite::Result<ThresholdOverride> {
+    let optional = |value: String| if value.is_empty() { None } else { Some(value) };
+    Ok(ThresholdOverride {
+        id: row.get(0)?,
+        device_id: optional(row.get(1)?),
+        topic: optional(row.get(2)?),
+        learned: row.get(3)?,
+        online_threshold_seconds: row.get::<_, Option<i64>>(4)?.map(|v| v as u64),
+        warning_threshold_seconds: row.get::<_, Option<i64>>(5)?.map(|v| v as u64),
+        stale_threshold_seconds: row.get::<_, Option<i64>>(6)?.map(|v| v as u64),
+        observed_interval_seconds: row.get(7)?,
+    })
+}
+
+pub fn list_status_overrides(db: &Database, username: &str) -> Result<Vec<ThresholdOverride>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM status_overrides WHERE username = ?1 ORDER BY device_id, topic",
+        STATUS_OVERRIDE_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let overrides = stmt
+        .query_map([username], status_override_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(overrides)
+}
+
+/// Every learned override of every user, as (username, override)
+pub fn list_learned_status_overrides(db: &Database) -> Result<Vec<(String, ThresholdOverride)>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}, username FROM status_overrides WHERE learned",
+        STATUS_OVERRIDE_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let overrides = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(8)?, status_override_from_row(row)?)))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(overrides)
+}
+
+/// Create or replace the override of a device and topic. Without thresholds the
+/// override is learned and applies once an interval has been observed.
+pub fn set_status_override(
+    db: &Database,
+    username: &str,
+    device_id: Option<&str>,
+    topic: Option<&str>,
+    thresholds: Option<[u64; 3]>,
+) -> Result<ThresholdOverride, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let threshold = |index: usize| thresholds.map(|t| t[index] as i64);
+    conn.query_row(
+        &format!(
+            "INSERT INTO status_overrides (username, device_id, topic, learned, online_threshold_seconds,
+                 warning_threshold_seconds, stale_threshold_seconds, observed_interval_seconds, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, NULL, ?8)
+             ON CONFLICT(username, device_id, topic) DO UPDATE SET
+             learned = excluded.learned,
+             online_threshold_seconds = excluded.online_threshold_seconds,
+             warning_threshold_seconds = excluded.warning_threshold_seconds,
+             stale_threshold_seconds = excluded.stale_threshold_seconds,
+             observed_interval_seconds = NULL,
+             updated_at = excluded.updated_at
+             RETURNING {}",
+            STATUS_OVERRIDE_COLUMNS
+        ),
+        rusqlite::params![
+            username,
+            device_id.unwrap_or_default(),
+            topic.unwrap_or_default(),
+            thresholds.is_none(),
+            threshold(0),
+            threshold(1),
+            threshold(2),
+            chrono::Utc::now().to_rfc3339()
+        ],
+        status_override_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Store a newly observed interval of a learned override and the thresholds derived from it
+pub fn record_learned_interval(
+    db: &Database,
+    id: i64,
+    interval_seconds: f64,
+    thresholds: [u64; 3],
+) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "UPDATE status_overrides SET observed_interval_seconds = ?2, online_threshold_seconds = ?3,
+             warning_threshold_seconds = ?4, stale_threshold_seconds = ?5, updated_at = ?6
+         WHERE id = ?1 AND learned",
+        rusqlite::params![
+            id,
+            interval_seconds,
+            thresholds[0] as i64,
+            thresholds[1] as i64,
+            thresholds[2] as i64,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+pub fn delete_status_override(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM status_overrides WHERE id = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rows_affected > 0)
+}
+
+/// Median time in seconds between the last `samples + 1` records of a device,
+/// or of one of its topics. `None` until a few distinct timestamps exist.
+pub fn observed_publish_interval(
+    db: &Database,
+    device_id: &str,
+    topic: Option<&str>,
+    samples: usize,
+) -> Result<Option<f64>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(
+        "SELECT timestamp FROM device_data
+         WHERE device_id = ?1 AND (?2 IS NULL OR topic = ?2)
+         ORDER BY timestamp DESC
+         LIMIT ?3"
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let timestamps = stmt
+        .query_map(rusqlite::params![device_id, topic, samples as i64 + 1], |row| row.get::<_, String>(0))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let times: Vec<_> = timestamps
+        .iter()
+        .filter_map(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .collect();
+    // Records stored in one batch share a timestamp and say nothing about the interval
+    let mut intervals: Vec<f64> = times
+        .windows(2)
+        .filter_map(|pair| (pair[0] - pair[1]).num_microseconds())
+        .filter(|&micros| micros > 0)
+        .map(|micros| micros as f64 / 1_000_000.0)
+        .collect();
+    if intervals.len() < 3 {
+        return Ok(None);
+    }
+    intervals.sort_by(f64::total_cmp);
+    Ok(Some(intervals[intervals.len() / 2]))
+}
+
+/// An alert rule of one user. Unset device and topic match any.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AlertRule {
+    pub id: i64,
+    pub name: String,
+    pub device_id: Option<String>,
+    pub topic: Option<String>,
+    pub condition: AlertCondition,
+    pub enabled: bool,
+    pub created_at: String,
+}
+
+const ALERT_RULE_COLUMNS: &str = "id, name, device_id, topic, condition, enabled, created_at";
+
+fn alert_rule_from_row(row: &rusqlite::Row) -> rusqlite::Result<AlertRule> {
+    let optional = |value: String| if value.is_empty() { None } else { Some(value) };
+    let condition: String = row.get(4)?;
+    Ok(AlertRule {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        device_id: optional(row.get(2)?),
+        topic: optional(row.get(3)?),
+        condition: serde_json::from_str(&condition).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        enabled: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+pub fn list_alert_rules(db: &Database, username: &str) -> Result<Vec<AlertRule>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM alert_rules WHERE username = ?1 ORDER BY id",
+        ALERT_RULE_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rules = stmt
+        .query_map([username], alert_rule_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rules)
+}
+
+/// Every enabled alert rule of every user, as (username, rule)
+pub fn list_enabled_alert_rules(db: &Database) -> Result<Vec<(String, AlertRule)>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}, username FROM alert_rules WHERE enabled ORDER BY id",
+        ALERT_RULE_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rules = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(7)?, alert_rule_from_row(row)?)))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rules)
+}
+
+pub fn create_alert_rule(db: &Database, username: &str, spec: &AlertRuleSpec) -> Result<AlertRule, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let condition = serde_json::to_string(&spec.condition).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!(
+            "INSERT INTO alert_rules (username, name, device_id, topic, condition, enabled)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             RETURNING {}",
+            ALERT_RULE_COLUMNS
+        ),
+        rusqlite::params![
+            username,
+            spec.name,
+            spec.device_id.as_deref().unwrap_or_default(),
+            spec.topic.as_deref().unwrap_or_default(),
+            condition,
+            spec.enabled
+        ],
+        alert_rule_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Replace an alert rule and resolve the alerts it has open, which are
+/// re-evaluated against the new rule. `None` if the user has no such rule.
+pub fn update_alert_rule(
+    db: &Database,
+    username: &str,
+    id: i64,
+    spec: &AlertRuleSpec,
+) -> Result<Option<AlertRule>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let condition = serde_json::to_string(&spec.condition).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rule = conn.query_row(
+        &format!(
+            "UPDATE alert_rules SET name = ?3, device_id = ?4, topic = ?5, condition = ?6, enabled = ?7
+             WHERE id = ?1 AND username = ?2
+             RETURNING {}",
+            ALERT_RULE_COLUMNS
+        ),
+        rusqlite::params![
+            id,
+            username,
+            spec.name,
+            spec.device_id.as_deref().unwrap_or_default(),
+            spec.topic.as_deref().unwrap_or_default(),
+            condition,
+            spec.enabled
+        ],
+        alert_rule_from_row,
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if rule.is_some() {
+        resolve_rule_alerts(&conn, id)?;
+    }
+    Ok(rule)
+}
+
+/// Remove an alert rule and resolve its open alerts; its history is kept
+pub fn delete_alert_rule(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM alert_rules WHERE id = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if rows_affected > 0 {
+        resolve_rule_alerts(&conn, id)?;
+    }
+    Ok(rows_affected > 0)
+}
+
+fn resolve_rule_alerts(conn: &Connection, rule_id: i64) -> Result<(), StatusCode> {
+    conn.execute(
+        "UPDATE alerts SET state = 'resolved', resolved_at = ?2 WHERE rule_id = ?1 AND state = 'firing'",
+        rusqlite::params![rule_id, chrono::Utc::now().to_rfc3339()],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// One firing of an alert rule
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Alert {
+    pub id: i64,
+    pub rule_id: i64,
+    pub rule_name: String,
+    pub device_id: String,
+    pub topic: Option<String>,
+    /// `firing` or `resolved`
+    pub state: String,
+    pub message: String,
+    pub fired_at: String,
+    pub resolved_at: Option<String>,
+}
+
+const ALERT_COLUMNS: &str = "id, rule_id, rule_name, device_id, topic, state, message, fired_at, resolved_at";
+
+fn alert_from_row(row: &rusqlite::Row) -> rusqlite::Result<Alert> {
+    let topic: String = row.get(4)?;
+    Ok(Alert {
+        id: row.get(0)?,
+        rule_id: row.get(1)?,
+        rule_name: row.get(2)?,
+        device_id: row.get(3)?,
+        topic: if topic.is_empty() { None } else { Some(topic) },
+        state: row.get(5)?,
+        message: row.get(6)?,
+        fired_at: row.get(7)?,
+        resolved_at: row.get(8)?,
+    })
+}
+
+/// Record that a rule fired for a device (and topic). A one-off alert is
+/// stored as resolved at the moment it fired.
+pub fn open_alert(
+    db: &Database,
+    username: &str,
+    rule: &AlertRule,
+    device_id: &str,
+    topic: Option<&str>,
+    message: &str,
+    once: bool,
+) -> Result<Alert, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now = chrono::Utc::now().to_rfc3339();
+    conn.query_row(
+        &format!(
+            "INSERT INTO alerts (rule_id, rule_name, username, device_id, topic, state, message, fired_at, resolved_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             RETURNING {}",
+            ALERT_COLUMNS
+        ),
+        rusqlite::params![
+            rule.id,
+            rule.name,
+            username,
+            device_id,
+            topic.unwrap_or_default(),
+            if once { "resolved" } else { "firing" },
+            message,
+            now,
+            once.then_some(&now)
+        ],
+        alert_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Mark a firing alert resolved; `None` if it was already resolved
+pub fn resolve_alert(db: &Database, id: i64) -> Result<Option<Alert>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!(
+            "UPDATE alerts SET state = 'resolved', resolved_at = ?2
+             WHERE id = ?1 AND state = 'firing'
+             RETURNING {}",
+            ALERT_COLUMNS
+        ),
+        rusqlite::params![id, chrono::Utc::now().to_rfc3339()],
+        alert_from_row,
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Every alert that is still firing, of every user
+pub fn list_firing_alerts(db: &Database) -> Result<Vec<Alert>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM alerts WHERE state = 'firing'", ALERT_COLUMNS))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let alerts = stmt
+        .query_map([], alert_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(alerts)
+}
+
+/// A user's alert history, newest first, optionally only `firing` or `resolved` ones
+pub fn list_alerts(
+    db: &Database,
+    username: &str,
+    state: Option<&str>,
+    limit: usize,
+) -> Result<Vec<Alert>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM alerts
+         WHERE username = ?1 AND (?2 IS NULL OR state = ?2)
+         ORDER BY fired_at DESC, id DESC
+         LIMIT ?3",
+        ALERT_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let alerts = stmt
+        .query_map(rusqlite::params![username, state, limit as i64], alert_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(alerts)
+}
+
+/// A recorded change of a device's or topic's status
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct StatusTransition {
+    pub status: String,
+    pub previous_status: Option<String>,
+    pub at: String,
+}
+
+/// Persist a status change of a device (namespaced id), or of one of its topics
+pub fn record_status_transition(
+    db: &Database,
+    device_id: &str,
+    topic: Option<&str>,
+    status: &str,
+    previous_status: Option<&str>,
+) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "INSERT INTO status_transitions (device_id, topic, status, previous_status, at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            device_id,
+            topic.unwrap_or_default(),
+            status,
+            previous_status,
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+        ],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// The last recorded status of every device and topic, keyed by (namespaced
+/// device id, topic) with an empty topic for the device itself
+pub fn latest_status_transitions(db: &Database) -> Result<HashMap<(String, String), String>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // SQLite takes the bare columns from the row holding MAX(id)
+    let mut stmt = conn.prepare(
+        "SELECT device_id, topic, status, MAX(id) FROM status_transitions GROUP BY device_id, topic"
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let latest = stmt
+        .query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(latest)
+}
+
+/// The status a device or topic had at `from`, if any was recorded by then, and
+/// its transitions after that up to `to`, oldest first
+pub fn list_status_transitions(
+    db: &Database,
+    device_id: &str,
+    topic: Option<&str>,
+    window: &TimeWindow,
+) -> Result<(Option<String>, Vec<StatusTransition>), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let format = |time: &chrono::DateTime<chrono::Utc>| time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    let (from, to) = (format(&window.from), format(&window.to));
+    let topic = topic.unwrap_or_default();
+
+    let initial = conn.query_row(
+        "SELECT status FROM status_transitions
+         WHERE device_id = ?1 AND topic = ?2 AND at <= ?3
+         ORDER BY at DESC, id DESC LIMIT 1",
+        rusqlite::params![device_id, topic, from],
+        |row| row.get(0),
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut stmt = conn.prepare(
+        "SELECT status, previous_status, at FROM status_transitions
+         WHERE device_id = ?1 AND topic = ?2 AND at > ?3 AND at <= ?4
+         ORDER BY at, id"
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let transitions = stmt
+        .query_map(rusqlite::params![device_id, topic, from, to], |row| {
+            Ok(StatusTransition { status: row.get(0)?, previous_status: row.get(1)?, at: row.get(2)? })
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok((initial, transitions))
+}
+
+const MAINTENANCE_COLUMNS: &str = "id, device_id, topic, kind, starts_at, ends_at, reason";
+
+fn maintenance_window_from_row(row: &rusqlite::Row) -> rusqlite::Result<MaintenanceWindow> {
+    fn parse_time(index: usize, text: String) -> rusqlite::Result<chrono::DateTime<chrono::Utc>> {
+        chrono::DateTime::parse_from_rfc3339(&text)
+            .map(|time| time.with_timezone(&chrono::Utc))
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+    }
+    let optional = |value: String| if value.is_empty() { None } else { Some(value) };
+    let kind: String = row.get(3)?;
+    Ok(MaintenanceWindow {
+        id: row.get(0)?,
+        device_id: optional(row.get(1)?),
+        topic: optional(row.get(2)?),
+        kind: if kind == "silence" { WindowKind::Silence } else { WindowKind::Maintenance },
+        starts_at: parse_time(4, row.get(4)?)?,
+        ends_at: parse_time(5, row.get(5)?)?,
+        reason: row.get(6)?,
+    })
+}
+
+/// A user's maintenance windows and silences by start time; only the ones
+/// not over yet unless `include_past`
+pub fn list_maintenance_windows(
+    db: &Database,
+    username: &str,
+    include_past: bool,
+) -> Result<Vec<MaintenanceWindow>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM maintenance_windows
+         WHERE username = ?1 AND (?2 OR ends_at > ?3)
+         ORDER BY starts_at, id",
+        MAINTENANCE_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let windows = stmt
+        .query_map(rusqlite::params![username, include_past, now], maintenance_window_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(windows)
+}
+
+pub fn create_maintenance_window(
+    db: &Database,
+    username: &str,
+    window: &MaintenanceWindow,
+) -> Result<MaintenanceWindow, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let format = |time: &chrono::DateTime<chrono::Utc>| time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+    conn.query_row(
+        &format!(
+            "INSERT INTO maintenance_windows (username, device_id, topic, kind, starts_at, ends_at, reason)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING {}",
+            MAINTENANCE_COLUMNS
+        ),
+        rusqlite::params![
+            username,
+            window.device_id.as_deref().unwrap_or_default(),
+            window.topic.as_deref().unwrap_or_default(),
+            if window.kind == WindowKind::Silence { "silence" } else { "maintenance" },
+            format(&window.starts_at),
+            format(&window.ends_at),
+            window.reason
+        ],
+        maintenance_window_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn delete_maintenance_window(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM maintenance_windows WHERE id = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rows_affected > 0)
+}
+
+const API_KEY_COLUMNS: &str = "id, name, prefix, scopes, device_id, expires_at, last_used_at, created_at, org";
+
+fn api_key_from_row(row: &rusqlite::Row) -> rusqlite::Result<ApiKey> {
+    let optional = |value: String| if value.is_empty() { None } else { Some(value) };
+    let scopes: String = row.get(3)?;
+    Ok(ApiKey {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        prefix: row.get(2)?,
+        scopes: scopes.split(',').filter_map(Scope::parse).collect(),
+        device_id: optional(row.get(4)?),
+        org: optional(row.get(8)?),
+        expires_at: row.get(5)?,
+        last_used_at: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+/// A user's API keys, oldest first
+pub fn list_api_keys(db: &Database, username: &str) -> Result<Vec<ApiKey>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM api_keys WHERE username = ?1 ORDER BY id",
+        API_KEY_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let keys = stmt
+        .query_map([username], api_key_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(keys)
+}
+
+/// Store a key of a user, acting for `org` or, without one, for the user's own devices
+pub fn create_api_key(
+    db: &Database,
+    username: &str,
+    org: Option<&str>,
+    key_hash: &str,
+    prefix: &str,
+    key: &NewApiKey,
+) -> Result<ApiKey, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let scopes: Vec<&str> = key.scopes.iter().map(Scope::as_str).collect();
+    conn.query_row(
+        &format!(
+            "INSERT INTO api_keys (username, org, name, key_hash, prefix, scopes, device_id, expires_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+             RETURNING {}",
+            API_KEY_COLUMNS
+        ),
+        rusqlite::params![
+            username,
+            org.unwrap_or_default(),
+            key.name,
+            key_hash,
+            prefix,
+            scopes.join(","),
+            key.device_id.as_deref().unwrap_or_default(),
+            key.expires_at.map(|at| at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ],
+        api_key_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The key stored under a digest, with its owner
+pub fn get_api_key_by_hash(db: &Database, key_hash: &str) -> Result<Option<(String, ApiKey)>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!(
+            "SELECT {}, username FROM api_keys
+             WHERE key_hash = ?1 AND EXISTS (SELECT 1 FROM users WHERE users.username = api_keys.username)",
+            API_KEY_COLUMNS
+        ),
+        [key_hash],
+        |row| Ok((row.get(9)?, api_key_from_row(row)?)),
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Note that a key was used. Written at most once a minute per key so busy
+/// devices do not turn every request into a write.
+pub fn touch_api_key(db: &Database, id: i64) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now = chrono::Utc::now();
+    let format = |time: chrono::DateTime<chrono::Utc>| time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    conn.execute(
+        "UPDATE api_keys SET last_used_at = ?1
+         WHERE id = ?2 AND (last_used_at IS NULL OR last_used_at < ?3)",
+        rusqlite::params![format(now), id, format(now - chrono::Duration::minutes(1))],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+pub fn delete_api_key(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM api_keys WHERE id = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(rows_affected > 0)
+}
+
+/// Tables besides `devices` and the rollups holding full device ids
+/// (`owner:device`), which move with a device
+const DEVICE_ID_TABLES: [&str; 5] = ["topics", "pulse_history", "device_data", "device_credentials", "status_transitions"];
+
+/// Settings kept per owner, in tables keyed by `username`
+const OWNER_SETTINGS_TABLES: [&str; 8] = [
+    "user_config",
+    "retention_policies",
+    "status_overrides",
+    "alert_rules",
+    "alerts",
+    "webhooks",
+    "email_settings",
+    "maintenance_windows",
+];
+
+fn organization_from_row(row: &rusqlite::Row) -> rusqlite::Result<Organization> {
+    Ok(Organization {
+        name: row.get(0)?,
+        role: Role::parse(&row.get::<_, String>(1)?).unwrap_or(Role::Viewer),
+        created_at: row.get(2)?,
+    })
+}
+
+/// Create an organization with `username` as its admin; `CONFLICT` if a user
+/// or another organization has the name
+pub fn create_organization(db: &Database, name: &str, username: &str) -> Result<Organization, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tx = conn.unchecked_transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let now = now_rfc3339();
+    let created = tx.execute(
+        "INSERT INTO organizations (name, created_at)
+         SELECT ?1, ?2 WHERE NOT EXISTS (SELECT 1 FROM users WHERE username = ?1)
+         ON CONFLICT(name) DO NOTHING",
+        [name, &now],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if created == 0 {
+        return Err(StatusCode::CONFLICT);
+    }
+    tx.execute(
+        "INSERT INTO org_members (org, username, role, joined_at) VALUES (?1, ?2, ?3, ?4)",
+        [name, username, Role::Admin.as_str(), &now],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Organization { name: name.to_string(), role: Role::Admin, created_at: now })
+}
+
+/// The organizations a user is a member of, with their role in each
+pub fn list_organizations(db: &Database, username: &str) -> Result<Vec<Organization>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(
+        "SELECT o.name, m.role, o.created_at FROM organizations o
+         JOIN org_members m ON m.org = o.name
+         WHERE m.username = ?1 ORDER BY o.name",
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let orgs = stmt
+        .query_map([username], organization_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(orgs)
+}
+
+/// A user's role in an organization; `None` if they are not a member
+pub fn get_org_role(db: &Database, org: &str, username: &str) -> Result<Option<Role>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        "SELECT role FROM org_members WHERE org = ?1 AND username = ?2",
+        [org, username],
+        |row| row.get::<_, String>(0),
+    ).optional()
+        .map(|role| role.map(|role| Role::parse(&role).unwrap_or(Role::Viewer)))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn list_org_members(db: &Database, org: &str) -> Result<Vec<OrgMember>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(
+        "SELECT username, role, joined_at FROM org_members WHERE org = ?1 ORDER BY username",
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let members = stmt
+        .query_map([org], |row| {
+            Ok(OrgMember {
+                username: row.get(0)?,
+                role: Role::parse(&row.get::<_, String>(1)?).unwrap_or(Role::Viewer),
+                joined_at: row.get(2)?,
+            })
+        })
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(members)
+}
+
+/// Add a user to an organization, or change their role in it; false if there
+/// is no such user
+pub fn set_org_member(db: &Database, org: &str, username: &str, role: Role) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "INSERT INTO org_members (org, username, role, joined_at)
+         SELECT ?1, username, ?3, ?4 FROM users WHERE username = ?2
+         ON CONFLICT(org, username) DO UPDATE SET role = excluded.role",
+        [org, username, role.as_str(), &now_rfc3339()],
+    )
+        .map(|changed| changed > 0)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn remove_org_member(db: &Database, org: &str, username: &str) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute("DELETE FROM org_members WHERE org = ?1 AND username = ?2", [org, username])
+        .map(|changed| changed > 0)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn count_org_admins(db: &Database, org: &str) -> Result<i64, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        "SELECT COUNT(*) FROM org_members WHERE org = ?1 AND role = ?2",
+        [org, Role::Admin.as_str()],
+        |row| row.get(0),
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Display ids of the devices of a user or organization
+pub fn list_owner_device_ids(db: &Database, owner: &str) -> Result<Vec<String>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let prefix = format!("{}:", owner);
+    let mut stmt = conn.prepare("SELECT id FROM devices WHERE substr(id, 1, length(?1)) = ?1 ORDER BY id")
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let ids = stmt
+        .query_map([&prefix], |row| row.get::<_, String>(0))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(ids.into_iter().map(|id| id[prefix.len()..].to_string()).collect())
+}
+
+/// Delete an organization, its memberships, settings and API keys. Its devices
+/// must be moved or deleted first: `CONFLICT` while it still has any.
+pub fn delete_organization(db: &Database, name: &str) -> Result<bool, StatusCode> {
+    if !list_owner_device_ids(db, name)?.is_empty() {
+        return Err(StatusCode::CONFLICT);
+    }
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tx = conn.unchecked_transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let deleted = tx.execute("DELETE FROM organizations WHERE name = ?1", [name])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    delete_owner_settings(&tx, name)?;
+    tx.execute("DELETE FROM org_members WHERE org = ?1", [name]).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.execute("DELETE FROM api_keys WHERE org = ?1", [name]).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(deleted > 0)
+}
+
+/// Delete the settings, alert history and webhook deliveries of a user or organization
+fn delete_owner_settings(conn: &Connection, owner: &str) -> Result<(), StatusCode> {
+    conn.execute(
+        "DELETE FROM webhook_deliveries WHERE webhook_id IN (SELECT id FROM webhooks WHERE username = ?1)",
+        [owner],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    for table in OWNER_SETTINGS_TABLES {
+        conn.execute(&format!("DELETE FROM {} WHERE username = ?1", table), [owner])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    Ok(())
+}
+
+/// Settings of an owner that can name a single device in their `device_id`
+const DEVICE_SETTINGS_TABLES: [&str; 4] = ["retention_policies", "status_overrides", "alert_rules", "maintenance_windows"];
+
+/// Move devices, with their data, credentials, status history, alert history
+/// and the settings naming them, from one owner to another, e.g. from a user
+/// into an organization. Maintenance windows and silences not over yet that
+/// cover a device by a pattern are copied for it. Nothing moves if any of them
+/// is unknown (`NOT_FOUND`) or the new owner already has a device of that name
+/// (`CONFLICT`). Returns how many moved.
+pub fn move_devices(db: &Database, from: &str, to: &str, device_ids: &[String]) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let exists = |id: &str| -> Result<bool, StatusCode> {
+        conn.query_row("SELECT EXISTS(SELECT 1 FROM devices WHERE id = ?1)", [id], |row| row.get(0))
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    };
+    for device_id in device_ids {
+        if !exists(&format!("{}:{}", from, device_id))? {
+            return Err(StatusCode::NOT_FOUND);
+        }
+        if exists(&format!("{}:{}", to, device_id))? {
+            return Err(StatusCode::CONFLICT);
+        }
+    }
+
+    let tx = conn.unchecked_transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // Rows referencing a device are renamed after it; check them once, at commit
+    tx.execute_batch("PRAGMA defer_foreign_keys = ON").map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tables: Vec<&str> = DEVICE_ID_TABLES.into_iter().chain(Rollup::ALL.iter().map(|rollup| rollup.table())).collect();
+    for device_id in device_ids {
+        let (old_id, new_id) = (format!("{}:{}", from, device_id), format!("{}:{}", to, device_id));
+        tx.execute("UPDATE devices SET id = ?2 WHERE id = ?1", [&old_id, &new_id])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        for table in &tables {
+            tx.execute(&format!("UPDATE {} SET device_id = ?2 WHERE device_id = ?1", table), [&old_id, &new_id])
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+        tx.execute(
+            "UPDATE alerts SET username = ?2 WHERE username = ?1 AND device_id = ?3",
+            [from, to, device_id.as_str()],
+        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        // The device's own settings replace any the new owner kept for the name
+        for table in DEVICE_SETTINGS_TABLES {
+            tx.execute(
+                &format!("UPDATE OR REPLACE {} SET username = ?2 WHERE username = ?1 AND device_id = ?3", table),
+                [from, to, device_id.as_str()],
+            ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+    }
+
+    // A pattern may also cover devices that stay, so the window is copied
+    let patterns = {
+        let mut stmt = tx.prepare(
+            "SELECT device_id, topic, kind, starts_at, ends_at, reason FROM maintenance_windows
+             WHERE username = ?1 AND ends_at > ?2 AND (instr(device_id, '*') > 0 OR instr(device_id, '?') > 0)"
+        ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        let patterns = stmt
+            .query_map([from, &now], |row| {
+                Ok((row.get::<_, String>(0)?, [row.get::<_, String>(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?]))
+            })
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        patterns
+    };
+    for device_id in device_ids {
+        for (pattern, [topic, kind, starts_at, ends_at, reason]) in &patterns {
+            if glob_match(pattern, device_id) {
+                tx.execute(
+                    "INSERT INTO maintenance_windows (username, device_id, topic, kind, starts_at, ends_at, reason)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    [to, device_id, topic, kind, starts_at, ends_at, reason],
+                ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            }
+        }
+    }
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(device_ids.len())
+}
+
+/// A webhook destination of one user. The signing secret is never sent back;
+/// `signed` tells whether one is set.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub name: String,
+    pub url: String,
+    #[serde(skip)]
+    pub secret: Option<String>,
+    pub signed: bool,
+    pub headers: BTreeMap<String, String>,
+    pub template: Option<Value>,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub created_at: String,
+}
+
+const WEBHOOK_COLUMNS: &str = "id, name, url, encrypted_secret, headers, template, events, enabled, created_at";
+
+fn webhook_from_row(row: &rusqlite::Row) -> rusqlite::Result<Webhook> {
+    fn parse_json<T: serde::de::DeserializeOwned>(index: usize, text: &str) -> rusqlite::Result<T> {
+        serde_json::from_str(text).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+        })
+    }
+    // A secret sealed under another token key cannot be opened; the webhook
+    // still counts as signed so that deliveries fail rather than go unsigned
+    let sealed: Option<String> = row.get(3)?;
+    let template: Option<String> = row.get(5)?;
+    let events: String = row.get(6)?;
+    Ok(Webhook {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        url: row.get(2)?,
+        signed: sealed.is_some(),
+        secret: sealed.as_deref().and_then(open_secret),
+        headers: parse_json(4, &row.get::<_, String>(4)?)?,
+        template: template.map(|t| parse_json(5, &t)).transpose()?,
+        events: events.split(',').filter(|e| !e.is_empty()).map(str::to_string).collect(),
+        enabled: row.get(7)?,
+        created_at: row.get(8)?,
+    })
+}
+
+pub fn list_webhooks(db: &Database, username: &str) -> Result<Vec<Webhook>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM webhooks WHERE username = ?1 ORDER BY id",
+        WEBHOOK_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let webhooks = stmt
+        .query_map([username], webhook_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(webhooks)
+}
+
+pub fn list_enabled_webhooks(db: &Database, username: &str) -> Result<Vec<Webhook>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM webhooks WHERE username = ?1 AND enabled ORDER BY id",
+        WEBHOOK_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let webhooks = stmt
+        .query_map([username], webhook_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(webhooks)
+}
+
+pub fn get_webhook(db: &Database, id: i64) -> Result<Option<Webhook>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!("SELECT {} FROM webhooks WHERE id = ?1", WEBHOOK_COLUMNS),
+        [id],
+        webhook_from_row,
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn create_webhook(db: &Database, username: &str, spec: &WebhookSpec) -> Result<Webhook, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let headers = serde_json::to_string(&spec.headers).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!(
+            "INSERT INTO webhooks (username, name, url, encrypted_secret, headers, template, events, enabled)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+             RETURNING {}",
+            WEBHOOK_COLUMNS
+        ),
+        rusqlite::params![
+            username,
+            spec.name,
+            spec.url,
+            spec.secret.as_deref().filter(|s| !s.is_empty()).map(seal_secret),
+            headers,
+            spec.template.as_ref().map(|t| t.to_string()),
+            spec.events.join(","),
+            spec.enabled
+        ],
+        webhook_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Replace a webhook. A missing secret keeps the current one and an empty one
+/// removes it. `None` if the user has no such webhook.
+pub fn update_webhook(
+    db: &Database,
+    username: &str,
+    id: i64,
+    spec: &WebhookSpec,
+) -> Result<Option<Webhook>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let headers = serde_json::to_string(&spec.headers).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        &format!(
+            "UPDATE webhooks SET name = ?3, url = ?4,
+                 encrypted_secret = CASE WHEN ?5 IS NULL THEN encrypted_secret WHEN ?5 = '' THEN NULL ELSE ?5 END,
+                 headers = ?6, template = ?7, events = ?8, enabled = ?9
+             WHERE id = ?1 AND username = ?2
+             RETURNING {}",
+            WEBHOOK_COLUMNS
+        ),
+        rusqlite::params![
+            id,
+            username,
+            spec.name,
+            spec.url,
+            spec.secret.as_deref().map(|s| if s.is_empty() { String::new() } else { seal_secret(s) }),
+            headers,
+            spec.template.as_ref().map(|t| t.to_string()),
+            spec.events.join(","),
+            spec.enabled
+        ],
+        webhook_from_row,
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Remove a webhook together with its delivery log
+pub fn delete_webhook(db: &Database, username: &str, id: i64) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let rows_affected = conn.execute(
+        "DELETE FROM webhooks WHERE id = ?1 AND username = ?2",
+        rusqlite::params![id, username],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if rows_affected > 0 {
+        conn.execute("DELETE FROM webhook_deliveries WHERE webhook_id = ?1", [id])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    Ok(rows_affected > 0)
+}
+
+/// One event sent (or being sent) to a webhook
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    /// The request body
+    pub payload: String,
+    /// `pending`, `delivered` or `failed`
+    pub state: String,
+    pub attempts: u32,
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+    pub created_at: String,
+    pub last_attempt_at: Option<String>,
+}
+
+const WEBHOOK_DELIVERY_COLUMNS: &str =
+    "id, webhook_id, event, payload, state, attempts, response_status, error, created_at, last_attempt_at";
+
+/// Deliveries kept per webhook; older ones are dropped as new ones are logged
+const MAX_WEBHOOK_DELIVERIES: i64 = 500;
+
+fn webhook_delivery_from_row(row: &rusqlite::Row) -> rusqlite::Result<WebhookDelivery> {
+    Ok(WebhookDelivery {
+        id: row.get(0)?,
+        webhook_id: row.get(1)?,
+        event: row.get(2)?,
+        payload: row.get(3)?,
+        state: row.get(4)?,
+        attempts: row.get(5)?,
+        response_status: row.get(6)?,
+        error: row.get(7)?,
+        created_at: row.get(8)?,
+        last_attempt_at: row.get(9)?,
+    })
+}
+
+/// Log a pending delivery, dropping the oldest ones past the per-webhook limit
+pub fn create_webhook_delivery(
+    db: &Database,
+    webhook_id: i64,
+    event: &str,
+    payload: &str,
+) -> Result<WebhookDelivery, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let delivery = conn.query_row(
+        &format!(
+            "INSERT INTO webhook_deliveries (webhook_id, event, payload, state, created_at)
+             VALUES (?1, ?2, ?3, 'pending', ?4)
+             RETURNING {}",
+            WEBHOOK_DELIVERY_COLUMNS
+        ),
+        rusqlite::params![webhook_id, event, payload, chrono::Utc::now().to_rfc3339()],
+        webhook_delivery_from_row,
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    conn.execute(
+        "DELETE FROM webhook_deliveries WHERE webhook_id = ?1 AND id <= ?2 - ?3",
+        rusqlite::params![webhook_id, delivery.id, MAX_WEBHOOK_DELIVERIES],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(delivery)
+}
+
+pub fn record_webhook_attempt(
+    db: &Database,
+    id: i64,
+    attempts: u32,
+    state: &str,
+    response_status: Option<u16>,
+    error: Option<&str>,
+) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "UPDATE webhook_deliveries
+         SET attempts = ?2, state = ?3, response_status = ?4, error = ?5, last_attempt_at = ?6
+         WHERE id = ?1",
+        rusqlite::params![id, attempts, state, response_status, error, chrono::Utc::now().to_rfc3339()],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// Deliveries of enabled webhooks that have not finished, oldest first
+pub fn list_pending_webhook_deliveries(db: &Database) -> Result<Vec<WebhookDelivery>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM webhook_deliveries
+         WHERE state = 'pending' AND webhook_id IN (SELECT id FROM webhooks WHERE enabled)
+         ORDER BY id",
+        WEBHOOK_DELIVERY_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let deliveries = stmt
+        .query_map([], webhook_delivery_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(deliveries)
+}
+
+/// The newest deliveries of a webhook; `None` if the user has no such webhook
+pub fn list_webhook_deliveries(
+    db: &Database,
+    username: &str,
+    webhook_id: i64,
+    limit: usize,
+) -> Result<Option<Vec<WebhookDelivery>>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let owned: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM webhooks WHERE id = ?1 AND username = ?2)",
+        rusqlite::params![webhook_id, username],
+        |row| row.get(0),
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !owned {
+        return Ok(None);
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM webhook_deliveries WHERE webhook_id = ?1 ORDER BY id DESC LIMIT ?2",
+        WEBHOOK_DELIVERY_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let deliveries = stmt
+        .query_map(rusqlite::params![webhook_id, limit as i64], webhook_delivery_from_row)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Some(deliveries))
+}
+
+/// A user's email notification settings; `None` if they never set any
+pub fn get_email_settings(db: &Database, username: &str) -> Result<Option<EmailSettings>, StatusCode> {
+    fn parse_json<T: serde::de::DeserializeOwned>(index: usize, text: &str) -> rusqlite::Result<T> {
+        serde_json::from_str(text).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e))
+        })
+    }
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.query_row(
+        "SELECT recipients, device_statuses, topic_statuses, enabled FROM email_settings WHERE username = ?1",
+        [username],
+        |row| {
+            Ok(EmailSettings {
+                recipients: parse_json(0, &row.get::<_, String>(0)?)?,
+                device_statuses: parse_json(1, &row.get::<_, String>(1)?)?,
+                topic_statuses: parse_json(2, &row.get::<_, String>(2)?)?,
+                enabled: row.get(3)?,
+            })
+        },
+    ).optional().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub fn set_email_settings(db: &Database, username: &str, settings: &EmailSettings) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let json = |value: serde_json::Result<String>| value.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    conn.execute(
+        "INSERT INTO email_settings (username, recipients, device_statuses, topic_statuses, enabled, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(username) DO UPDATE SET
+         recipients = excluded.recipients,
+         device_statuses = excluded.device_statuses,
+         topic_statuses = excluded.topic_statuses,
+         enabled = excluded.enabled,
+         updated_at = excluded.updated_at",
+        rusqlite::params![
+            username,
+            json(serde_json::to_string(&settings.recipients))?,
+            json(serde_json::to_string(&settings.device_statuses))?,
+            json(serde_json::to_string(&settings.topic_statuses))?,
+            settings.enabled,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// Every (namespaced device id, topic) pair
+pub fn list_all_topics(db: &Database) -> Result<Vec<(String, String)>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare("SELECT device_id, topic FROM topics")
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let topics = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(topics)
+}
+
+/// Delete up to `limit` records of one topic and data type older than `cutoff`.
+/// Returns how many were deleted; fewer than `limit` means nothing older is left.
+pub fn prune_device_data(
+    db: &Database,
+    device_id: &str,
+    topic: &str,
+    data_type: &str,
+    cutoff: &str,
+    limit: usize,
+) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "DELETE FROM device_data WHERE id IN (
+            SELECT id FROM device_data
+            WHERE device_id = ?1 AND topic = ?2 AND data_type = ?3 AND timestamp < ?4
+            LIMIT ?5
+        )",
+        rusqlite::params![device_id, topic, data_type, cutoff, limit as i64],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Delete up to `limit` status transitions of one device or topic older than
+/// `cutoff`, keeping the newest of them: it holds the status at the cutoff.
+/// Returns how many were deleted.
+pub fn prune_status_transitions(
+    db: &Database,
+    device_id: &str,
+    topic: &str,
+    cutoff: &str,
+    limit: usize,
+) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        "DELETE FROM status_transitions WHERE id IN (
+            SELECT id FROM status_transitions
+            WHERE device_id = ?1 AND topic = ?2 AND at < ?3
+            ORDER BY at DESC, id DESC
+            LIMIT ?4 OFFSET 1
+        )",
+        rusqlite::params![device_id, topic, cutoff, limit as i64],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Name of the data a window is read from: a rollup, or `raw` records
+fn window_source(window: &TimeWindow) -> &'static str {
+    window.rollup().map(Rollup::name).unwrap_or("raw")
+}
+
+/// Table, time column, record count and time bounds a window is read from. Rollup
+/// keys are bucket starts, so the bucket containing each end of the window counts.
+fn window_table(window: &TimeWindow) -> (&'static str, &'static str, &'static str, String, String) {
+    match window.rollup() {
+        Some(rollup) => (rollup.table(), "bucket", "SUM(count)", rollup.bucket(&window.from), rollup.bucket(&window.to)),
+        None => ("device_data", "timestamp", "COUNT(*)", window.from.to_rfc3339(), window.to.to_rfc3339()),
+    }
+}
+
+/// Get historical pulse data for visualization: record counts per bucket of the
+/// window, read from the coarsest rollup that fits the bucket size
+pub fn get_pulse_history(db: &Database, device_id: &str, topic: Option<&str>, window: &TimeWindow) -> Result<Value, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (table, time_column, count, from, to) = window_table(window);
+    let slot = format!("CAST(strftime('%s', {}) AS INTEGER) / ?2 * ?2", time_column);
+    
+    // Build query based on whether we want specific topic or all topics
+    let (query, params): (String, Vec<Box<dyn rusqlite::ToSql>>) = if let Some(topic_name) = topic {
+        (
+            format!(
+                "SELECT {slot} AS slot, {count}
+                 FROM {table}
+                 WHERE device_id = ?1 AND {time_column} >= ?3 AND {time_column} <= ?4 AND topic = ?5
+                 GROUP BY slot
+                 ORDER BY slot"
+            ),
+            vec![Box::new(device_id.to_string()), Box::new(window.bucket_seconds), Box::new(from), Box::new(to), Box::new(topic_name.to_string())]
         )
     } else {
         (
-            "SELECT 
-                datetime(timestamp, 'utc')||'Z' as timestamp,
-                topic,
-                COUNT(*) as pulse_count
-             FROM device_data 
-             WHERE device_id = ?1 AND timestamp >= ?2
-             GROUP BY strftime('%Y-%m-%d %H:%M', timestamp), topic
-             ORDER BY timestamp, topic".to_string(),
-            vec![device_id.to_string(), start_time.to_rfc3339()]
+            format!(
+                "SELECT {slot} AS slot, {count}, topic
+                 FROM {table}
+                 WHERE device_id = ?1 AND {time_column} >= ?3 AND {time_column} <= ?4
+                 GROUP BY slot, topic
+                 ORDER BY slot, topic"
+            ),
+            vec![Box::new(device_id.to_string()), Box::new(window.bucket_seconds), Box::new(from), Box::new(to)]
         )
     };
     
     let mut stmt = conn.prepare(&query)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
-    let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|s| s as &dyn rusqlite::ToSql).collect();
+    let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
     let pulse_iter = stmt.query_map(params_refs.as_slice(), |row| {
-        if topic.is_some() {
-            Ok(json!({
-                "timestamp": row.get::<_, String>(0)?,
-                "pulse_count": row.get::<_, i64>(1)?
-            }))
-        } else {
-            Ok(json!({
-                "timestamp": row.get::<_, String>(0)?,
-                "topic": row.get::<_, String>(1)?,
-                "pulse_count": row.get::<_, i64>(2)?
-            }))
+        let mut pulse = json!({
+            "timestamp": window.bucket_start(row.get(0)?),
+            "pulse_count": row.get::<_, i64>(1)?
+        });
+        if topic.is_none() {
+            pulse["topic"] = json!(row.get::<_, String>(2)?);
         }
+        Ok(pulse)
     }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
     let mut pulses = Vec::new();
@@ -494,46 +2639,81 @@ pub fn get_pulse_history(db: &Database, device_id: &str, topic: Option<&str>, ti
     }
     
     Ok(json!({
-        "time_range": time_range,
-        "start_time": start_time.to_rfc3339(),
-        "end_time": now.to_rfc3339(),
+        "start_time": window.from.to_rfc3339(),
+        "end_time": window.to.to_rfc3339(),
+        "bucket": format_duration(window.bucket_seconds as u64),
+        "source": window_source(window),
         "data": pulses
     }))
 }
 
-/// Get pulse statistics for a device
-pub fn get_pulse_stats(db: &Database, device_id: &str, time_range: &str) -> Result<Value, StatusCode> {
+/// Get pulse statistics for a device over a window. Rollups only hold whole
+/// buckets, so with a rollup source the first and last bucket may reach past
+/// the window; sub-minute buckets read the raw records and are exact.
+/// Sensor topics also get min, max, average and last value.
+pub fn get_pulse_stats(db: &Database, device_id: &str, window: &TimeWindow) -> Result<Value, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (_, _, _, from, to) = window_table(window);
     
-    // Calculate time range
-    let now = chrono::Utc::now();
-    let start_time = match time_range {
-        "1h" => now - chrono::Duration::hours(1),
-        "1d" => now - chrono::Duration::days(1),
-        "1w" => now - chrono::Duration::weeks(1),
-        "1m" => now - chrono::Duration::days(30),
-        _ => return Err(StatusCode::BAD_REQUEST),
+    let query = match window.rollup() {
+        Some(rollup) => format!(
+            "SELECT 
+                topic,
+                SUM(count) as total_pulses,
+                MIN(first_timestamp) as first_pulse,
+                MAX(last_timestamp) as last_pulse,
+                SUM(value_count),
+                MIN(min),
+                MAX(max),
+                SUM(sum),
+                (SELECT last_value FROM {table} latest
+                 WHERE latest.device_id = r.device_id AND latest.topic = r.topic
+                   AND latest.bucket >= ?2 AND latest.bucket <= ?3 AND latest.last_value_at IS NOT NULL
+                 ORDER BY latest.last_value_at DESC LIMIT 1)
+             FROM {table} r
+             WHERE device_id = ?1 AND bucket >= ?2 AND bucket <= ?3
+             GROUP BY topic
+             ORDER BY total_pulses DESC",
+            table = rollup.table()
+        ),
+        None => "SELECT 
+                topic,
+                COUNT(*) as total_pulses,
+                MIN(timestamp) as first_pulse,
+                MAX(timestamp) as last_pulse,
+                COUNT(value),
+                MIN(value),
+                MAX(value),
+                SUM(value),
+                (SELECT json_extract(latest.data_payload, '$.Sensor.value') FROM device_data latest
+                 WHERE latest.device_id = r.device_id AND latest.topic = r.topic AND latest.data_type = 'sensor'
+                   AND latest.timestamp >= ?2 AND latest.timestamp <= ?3
+                 ORDER BY latest.timestamp DESC LIMIT 1)
+             FROM (SELECT device_id, topic, timestamp,
+                          CASE WHEN data_type = 'sensor' THEN json_extract(data_payload, '$.Sensor.value') END AS value
+                   FROM device_data
+                   WHERE device_id = ?1 AND timestamp >= ?2 AND timestamp <= ?3) r
+             GROUP BY topic
+             ORDER BY total_pulses DESC".to_string(),
     };
+    let mut stmt = conn.prepare(&query).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
-    let mut stmt = conn.prepare(
-        "SELECT 
-            topic,
-            COUNT(*) as total_pulses,
-            MIN(timestamp) as first_pulse,
-            MAX(timestamp) as last_pulse
-         FROM pulse_history 
-         WHERE device_id = ?1 AND timestamp >= ?2
-         GROUP BY topic
-         ORDER BY total_pulses DESC"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let stats_iter = stmt.query_map([device_id, &start_time.to_rfc3339()], |row| {
-        Ok(json!({
+    let stats_iter = stmt.query_map([device_id, &from, &to], |row| {
+        let mut stat = json!({
             "topic": row.get::<_, String>(0)?,
             "total_pulses": row.get::<_, i64>(1)?,
             "first_pulse": row.get::<_, String>(2)?,
             "last_pulse": row.get::<_, String>(3)?
-        }))
+        });
+        let value_count: i64 = row.get(4)?;
+        if value_count > 0 {
+            stat["value_count"] = json!(value_count);
+            stat["min"] = json!(row.get::<_, Option<f64>>(5)?);
+            stat["max"] = json!(row.get::<_, Option<f64>>(6)?);
+            stat["avg"] = json!(row.get::<_, Option<f64>>(7)?.map(|sum| sum / value_count as f64));
+            stat["last_value"] = json!(row.get::<_, Option<f64>>(8)?);
+        }
+        Ok(stat)
     }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
     let mut stats_data = Vec::new();
@@ -542,23 +2722,73 @@ pub fn get_pulse_stats(db: &Database, device_id: &str, time_range: &str) -> Resu
     }
     
     // Get total count for the device
-    let mut total_stmt = conn.prepare(
-        "SELECT COUNT(*) FROM pulse_history WHERE device_id = ?1 AND timestamp >= ?2"
-    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let total_count: i64 = total_stmt.query_row([device_id, &start_time.to_rfc3339()], |row| {
-        Ok(row.get::<_, i64>(0)?)
-    }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let total_count: i64 = stats_data.iter().filter_map(|stat| stat["total_pulses"].as_i64()).sum();
     
     Ok(json!({
-        "time_range": time_range,
-        "start_time": start_time.to_rfc3339(),
-        "end_time": now.to_rfc3339(),
+        "start_time": window.from.to_rfc3339(),
+        "end_time": window.to.to_rfc3339(),
+        "source": window_source(window),
         "total_pulses": total_count,
         "stats": stats_data // Changed from "topics" to "stats"
     }))
 }
 
+/// Summarize the sensor values of one topic per bucket of the window, read from
+/// the raw records. Without `bucketed` the whole window is a single bucket.
+pub fn get_sensor_aggregates(
+    db: &Database,
+    device_id: &str,
+    topic: &str,
+    window: &TimeWindow,
+    bucketed: bool,
+    percentiles: &[f64],
+) -> Result<Value, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(
+        "SELECT CAST(strftime('%s', timestamp) AS INTEGER), json_extract(data_payload, '$.Sensor.value')
+         FROM device_data
+         WHERE device_id = ?1 AND topic = ?2 AND data_type = 'sensor' AND timestamp >= ?3 AND timestamp <= ?4
+         ORDER BY timestamp"
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut rows = stmt.query([device_id, topic, &window.from.to_rfc3339(), &window.to.to_rfc3339()])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Rows come in time order, so each bucket is summarized as soon as the next one starts
+    let bucket_start = |slot: i64| if bucketed { window.bucket_start(slot) } else { window.from.to_rfc3339() };
+    let mut buckets = Vec::new();
+    let mut current: Option<(i64, Vec<f64>)> = None;
+    while let Some(row) = rows.next().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? {
+        let epoch: i64 = row.get(0).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let Some(value) = row.get::<_, Option<f64>>(1).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)? else {
+            continue;
+        };
+        let slot = if bucketed { epoch - epoch.rem_euclid(window.bucket_seconds) } else { 0 };
+        match &mut current {
+            Some((current_slot, values)) if *current_slot == slot => values.push(value),
+            _ => {
+                if let Some((done, mut values)) = current.replace((slot, vec![value])) {
+                    let mut summary = summarize(&mut values, percentiles);
+                    summary["timestamp"] = json!(bucket_start(done));
+                    buckets.push(summary);
+                }
+            }
+        }
+    }
+    if let Some((done, mut values)) = current {
+        let mut summary = summarize(&mut values, percentiles);
+        summary["timestamp"] = json!(bucket_start(done));
+        buckets.push(summary);
+    }
+
+    Ok(json!({
+        "topic": topic,
+        "start_time": window.from.to_rfc3339(),
+        "end_time": window.to.to_rfc3339(),
+        "bucket": bucketed.then(|| format_duration(window.bucket_seconds as u64)),
+        "data": buckets
+    }))
+}
+
 /// Store data for a device using the new type system
 pub fn store_device_data(
     db: &Database, 
@@ -568,9 +2798,65 @@ pub fn store_device_data(
     raw_data: &serde_json::Value,
     timestamp: &str,
     save_images: bool,
-) -> Result<(), StatusCode> {
+) -> Result<DataType, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
+    insert_device_data(&conn, device_id, device_name, topic, raw_data, timestamp, save_images)
+}
+
+/// A single record of a batch upload, already namespaced to its owner
+pub struct DeviceDataRecord {
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub topic: String,
+    pub data: serde_json::Value,
+    pub timestamp: String,
+}
+
+/// Store many records in one transaction, taking the database lock only once.
+/// Each record runs in its own savepoint so a failing record does not discard
+/// the others; the returned vector holds one result per input record.
+pub fn store_device_data_batch(
+    db: &Database,
+    records: &[DeviceDataRecord],
+    save_images: bool,
+) -> Result<Vec<Result<DataType, StatusCode>>, StatusCode> {
+    let mut conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut tx = conn.transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut results = Vec::with_capacity(records.len());
+    for record in records {
+        let sp = tx.savepoint().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        let result = insert_device_data(
+            &sp,
+            &record.device_id,
+            record.device_name.as_deref(),
+            &record.topic,
+            &record.data,
+            &record.timestamp,
+            save_images,
+        );
+        if result.is_ok() {
+            sp.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        }
+        // Otherwise dropping the savepoint rolls back this record only
+        results.push(result);
+    }
+
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(results)
+}
+
+/// Insert one record on an already locked connection (or transaction) and
+/// return the data as it was stored
+fn insert_device_data(
+    conn: &Connection,
+    device_id: &str, 
+    device_name: Option<&str>, 
+    topic: &str,
+    raw_data: &serde_json::Value,
+    timestamp: &str,
+    save_images: bool,
+) -> Result<DataType, StatusCode> {
     // Parse the raw data into our type system
     let data_type = match DataType::from_json(raw_data, topic) {
         Some(dt) => dt,
@@ -582,17 +2868,20 @@ pub fn store_device_data(
         }
     };
     
-    // Handle device insertion/update
+    // Handle device insertion/update. Timestamps may be client-supplied, so
+    // last_seen only ever moves forward: late (backfilled) data is stored but
+    // never makes a device or topic look older than it already is. All stored
+    // timestamps are UTC RFC3339, which compares correctly as text.
     if let Some(name) = device_name {
         conn.execute(
             "INSERT INTO devices (id, name, last_seen) VALUES (?1, ?2, ?3) 
-             ON CONFLICT(id) DO UPDATE SET name = excluded.name, last_seen = excluded.last_seen",
+             ON CONFLICT(id) DO UPDATE SET name = excluded.name, last_seen = MAX(last_seen, excluded.last_seen)",
             [device_id, name, timestamp],
         ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     } else {
         conn.execute(
             "INSERT INTO devices (id, name, last_seen) VALUES (?1, NULL, ?2) 
-             ON CONFLICT(id) DO UPDATE SET last_seen = excluded.last_seen",
+             ON CONFLICT(id) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
             [device_id, timestamp],
         ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     }
@@ -600,7 +2889,7 @@ pub fn store_device_data(
     // Insert or update the topic record
     conn.execute(
         "INSERT INTO topics (device_id, topic, last_seen) VALUES (?1, ?2, ?3) 
-         ON CONFLICT(device_id, topic) DO UPDATE SET last_seen = excluded.last_seen",
+         ON CONFLICT(device_id, topic) DO UPDATE SET last_seen = MAX(last_seen, excluded.last_seen)",
         [device_id, topic, timestamp],
     ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
@@ -631,95 +2920,321 @@ pub fn store_device_data(
             timestamp
         ],
     ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let value = match &final_data_type {
+        DataType::Sensor { value, .. } => Some(*value),
+        _ => None,
+    };
+    record_rollups(conn, device_id, topic, value, timestamp).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
     
-    Ok(())
+    Ok(final_data_type)
 }
 
-/// Legacy function for backward compatibility - delegates to new store_device_data
-pub fn store_device_data_payload(
-    db: &Database, 
-    device_id: &str, 
-    name: Option<&str>, 
+/// Add one record to every rollup. `value` is set for sensor readings only.
+fn record_rollups(
+    conn: &Connection,
+    device_id: &str,
     topic: &str,
-    _data_type: &str, // Ignored - we detect the type from the data
-    data_payload: &serde_json::Value,
+    value: Option<f64>,
     timestamp: &str,
-    save_images: bool,
-) -> Result<(), StatusCode> {
-    store_device_data(db, device_id, name, topic, data_payload, timestamp, save_images)
+) -> rusqlite::Result<()> {
+    let Ok(parsed) = chrono::DateTime::parse_from_rfc3339(timestamp) else {
+        return Ok(());
+    };
+    let parsed = parsed.with_timezone(&chrono::Utc);
+    let value_at = value.map(|_| timestamp);
+
+    for rollup in Rollup::ALL {
+        conn.execute(
+            &format!(
+                "INSERT INTO {} (device_id, topic, bucket, count, value_count, min, max, sum,
+                                 last_value, last_value_at, first_timestamp, last_timestamp)
+                 VALUES (?1, ?2, ?3, 1, ?4, ?5, ?5, ?5, ?5, ?6, ?7, ?7)
+                 ON CONFLICT(device_id, topic, bucket) DO UPDATE SET
+                    count = count + 1,
+                    value_count = value_count + excluded.value_count,
+                    min = CASE WHEN min IS NULL OR excluded.min < min THEN excluded.min ELSE min END,
+                    max = CASE WHEN max IS NULL OR excluded.max > max THEN excluded.max ELSE max END,
+                    sum = CASE WHEN excluded.sum IS NULL THEN sum ELSE COALESCE(sum, 0) + excluded.sum END,
+                    last_value = CASE WHEN excluded.last_value_at >= COALESCE(last_value_at, '')
+                                      THEN excluded.last_value ELSE last_value END,
+                    last_value_at = CASE WHEN excluded.last_value_at >= COALESCE(last_value_at, '')
+                                         THEN excluded.last_value_at ELSE last_value_at END,
+                    first_timestamp = MIN(first_timestamp, excluded.first_timestamp),
+                    last_timestamp = MAX(last_timestamp, excluded.last_timestamp)",
+                rollup.table()
+            ),
+            rusqlite::params![
+                device_id,
+                topic,
+                rollup.bucket(&parsed),
+                value.is_some() as i64,
+                value,
+                value_at,
+                timestamp
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Roll up the next `limit` records from before the rollup tables existed.
+/// Returns whether any are left.
+pub fn backfill_rollups(db: &Database, limit: usize) -> Result<bool, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let step = || -> rusqlite::Result<bool> {
+        let Some((next_id, last_id)) = conn
+            .query_row("SELECT next_id, last_id FROM rollup_backfill", [], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+            })
+            .optional()?
+        else {
+            return Ok(false);
+        };
+
+        let tx = conn.unchecked_transaction()?;
+        let mut rolled_up = 0;
+        let mut upto = next_id - 1;
+        {
+            let mut stmt = tx.prepare(
+                "SELECT id, device_id, topic,
+                        CASE WHEN data_type = 'sensor' THEN json_extract(data_payload, '$.Sensor.value') END,
+                        timestamp
+                 FROM device_data
+                 WHERE id >= ?1 AND id <= ?2
+                 ORDER BY id
+                 LIMIT ?3",
+            )?;
+            let mut rows = stmt.query(rusqlite::params![next_id, last_id, limit as i64])?;
+            while let Some(row) = rows.next()? {
+                upto = row.get(0)?;
+                let device_id: String = row.get(1)?;
+                let topic: String = row.get(2)?;
+                let value: Option<f64> = row.get(3)?;
+                let timestamp: String = row.get(4)?;
+                record_rollups(&tx, &device_id, &topic, value, &timestamp)?;
+                rolled_up += 1;
+            }
+        }
+        let more = rolled_up == limit && upto < last_id;
+        if more {
+            tx.execute("UPDATE rollup_backfill SET next_id = ?1", [upto + 1])?;
+        } else {
+            tx.execute("DELETE FROM rollup_backfill", [])?;
+        }
+        tx.commit()?;
+        Ok(more)
+    };
+    step().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Delete up to `limit` buckets of one topic's rollup that start before `cutoff`.
+/// Returns how many were deleted.
+pub fn prune_rollups(
+    db: &Database,
+    device_id: &str,
+    topic: &str,
+    rollup: Rollup,
+    cutoff: &chrono::DateTime<chrono::Utc>,
+    limit: usize,
+) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    conn.execute(
+        &format!(
+            "DELETE FROM {0} WHERE rowid IN (
+                SELECT rowid FROM {0} WHERE device_id = ?1 AND topic = ?2 AND bucket < ?3 LIMIT ?4
+            )",
+            rollup.table()
+        ),
+        rusqlite::params![device_id, topic, rollup.bucket(cutoff), limit as i64],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-/// Get latest data for a device and topic
+/// Get a page of a device's records, newest first, optionally limited to a
+/// topic and data type. `next_cursor` continues in the same direction: older
+/// records for the latest page and `before`, newer ones for `after`.
 pub fn get_device_latest_data(
-    db: &Database, 
-    device_id: &str, 
-    topic: Option<&str>, 
-    data_type: Option<&str>
+    db: &Database,
+    device_id: &str,
+    topic: Option<&str>,
+    data_type: Option<&str>,
+    page: &DataPage,
 ) -> Result<Value, StatusCode> {
     let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let (query, params): (String, Vec<String>) = match (topic, data_type) {
-        (Some(t), Some(dt)) => (
-            "SELECT topic, data_type, data_payload, timestamp 
-             FROM device_data 
-             WHERE device_id = ?1 AND topic = ?2 AND data_type = ?3 
-             ORDER BY timestamp DESC 
-             LIMIT 10".to_string(),
-            vec![device_id.to_string(), t.to_string(), dt.to_string()]
-        ),
-        (Some(t), None) => (
-            "SELECT topic, data_type, data_payload, timestamp 
-             FROM device_data 
-             WHERE device_id = ?1 AND topic = ?2 
-             ORDER BY timestamp DESC 
-             LIMIT 10".to_string(),
-            vec![device_id.to_string(), t.to_string()]
-        ),
-        (None, Some(dt)) => (
-            "SELECT topic, data_type, data_payload, timestamp 
-             FROM device_data 
-             WHERE device_id = ?1 AND data_type = ?2 
-             ORDER BY timestamp DESC 
-             LIMIT 10".to_string(),
-            vec![device_id.to_string(), dt.to_string()]
-        ),
-        (None, None) => (
-            "SELECT topic, data_type, data_payload, timestamp 
-             FROM device_data 
-             WHERE device_id = ?1 
-             ORDER BY timestamp DESC 
-             LIMIT 10".to_string(),
-            vec![device_id.to_string()]
-        ),
+
+    let mut conditions = vec!["device_id = ?".to_string()];
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(device_id.to_string())];
+    if let Some(t) = topic {
+        conditions.push("topic = ?".to_string());
+        params.push(Box::new(t.to_string()));
+    }
+    if let Some(dt) = data_type {
+        conditions.push("data_type = ?".to_string());
+        params.push(Box::new(dt.to_string()));
+    }
+    let (cursor, comparison, order) = match (&page.before, &page.after) {
+        (_, Some(after)) => (Some(after), ">", "ASC"),
+        (before, None) => (before.as_ref(), "<", "DESC"),
     };
-    
+    if let Some(cursor) = cursor {
+        conditions.push(format!("(timestamp {0} ? OR (timestamp = ? AND id {0} ?))", comparison));
+        params.push(Box::new(cursor.timestamp.clone()));
+        params.push(Box::new(cursor.timestamp.clone()));
+        params.push(Box::new(cursor.id));
+    }
+    // One extra row tells whether another page follows
+    params.push(Box::new((page.limit + 1) as i64));
+
+    let query = format!(
+        "SELECT topic, data_type, data_payload, timestamp, id
+         FROM device_data
+         WHERE {}
+         ORDER BY timestamp {order}, id {order}
+         LIMIT ?",
+        conditions.join(" AND ")
+    );
     let mut stmt = conn.prepare(&query)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
-    let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|s| s as &dyn rusqlite::ToSql).collect();
+
+    let params_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
     let data_iter = stmt.query_map(params_refs.as_slice(), |row| {
         let data_payload_str: String = row.get(2)?;
         let data_payload: serde_json::Value = serde_json::from_str(&data_payload_str)
             .map_err(|_| rusqlite::Error::FromSqlConversionFailure(
-                2, rusqlite::types::Type::Text, 
+                2, rusqlite::types::Type::Text,
                 Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid JSON"))
             ))?;
-        
+        let cursor = DataCursor { timestamp: row.get(3)?, id: row.get(4)? };
+
         Ok(json!({
             "topic": row.get::<_, String>(0)?,
             "data_type": row.get::<_, String>(1)?,
             "data": data_payload,
-            "timestamp": row.get::<_, String>(3)?
+            "timestamp": cursor.timestamp,
+            "cursor": cursor.encode()
         }))
     }).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    
+
     let mut data_records = Vec::new();
     for record in data_iter {
         data_records.push(record.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?);
     }
-    
+
+    let has_more = data_records.len() > page.limit;
+    data_records.truncate(page.limit);
+    let next_cursor = if has_more { data_records.last().map(|record| record["cursor"].clone()) } else { None };
+    if page.after.is_some() {
+        data_records.reverse();
+    }
+
     Ok(json!({
         "device_id": device_id,
-        "data": data_records
+        "data": data_records,
+        "next_cursor": next_cursor
     }))
-}
\ No newline at end of file
+}
+
+const AUDIT_COLUMNS: &str =
+    "id, at, actor, api_key_id, org, action, target, method, path, source_ip, status, result, repeats";
+
+fn audit_entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        at: row.get(1)?,
+        actor: row.get(2)?,
+        api_key_id: row.get(3)?,
+        org: row.get(4)?,
+        action: row.get(5)?,
+        target: row.get(6)?,
+        method: row.get(7)?,
+        path: row.get(8)?,
+        source_ip: row.get(9)?,
+        status: row.get(10)?,
+        result: row.get(11)?,
+        repeats: row.get(12)?,
+    })
+}
+
+/// Append an entry to the audit log
+pub fn record_audit_entry(db: &Database, entry: &NewAuditEntry) -> Result<(), StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let status = StatusCode::from_u16(entry.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    conn.execute(
+        "INSERT INTO audit_log (at, actor, api_key_id, org, action, target, method, path, source_ip, status, result, repeats)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        rusqlite::params![
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            entry.actor,
+            entry.api_key_id,
+            entry.org,
+            entry.action,
+            entry.target,
+            entry.method,
+            entry.path,
+            entry.source_ip,
+            entry.status,
+            result_of(status),
+            entry.repeats
+        ],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(())
+}
+
+/// Remove up to `limit` of the oldest audit entries made before `cutoff`,
+/// returning how many were removed. The cutoff is only named to the delete
+/// trigger inside this transaction.
+pub fn prune_audit_log(db: &Database, cutoff: &chrono::DateTime<chrono::Utc>, limit: usize) -> Result<usize, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let tx = conn.unchecked_transaction().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let cutoff = cutoff.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    tx.execute("INSERT INTO audit_log_prune (cutoff) VALUES (?1)", [&cutoff])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let deleted = tx.execute(
+        "DELETE FROM audit_log WHERE id IN (SELECT id FROM audit_log WHERE at < ?1 ORDER BY id LIMIT ?2)",
+        rusqlite::params![cutoff, limit as i64],
+    ).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.execute("DELETE FROM audit_log_prune", []).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    tx.commit().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(deleted)
+}
+
+/// Audit entries matching a filter, newest first. An action filter also
+/// matches the actions under it, e.g. `device` matches `device.delete`.
+pub fn list_audit_entries(db: &Database, filter: &AuditFilter) -> Result<Vec<AuditEntry>, StatusCode> {
+    let conn = db.lock().map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM audit_log
+         WHERE (?1 IS NULL OR actor = ?1)
+           AND (?2 IS NULL OR action = ?2 OR substr(action, 1, length(?2) + 1) = ?2 || '.')
+           AND (?3 IS NULL OR target = ?3)
+           AND (?4 IS NULL OR org = ?4)
+           AND (?5 IS NULL OR result = ?5)
+           AND (?6 IS NULL OR source_ip = ?6)
+           AND (?7 IS NULL OR at >= ?7)
+           AND (?8 IS NULL OR at < ?8)
+           AND (?9 IS NULL OR id < ?9)
+         ORDER BY id DESC
+         LIMIT ?10",
+        AUDIT_COLUMNS
+    )).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let entries = stmt
+        .query_map(
+            rusqlite::params![
+                filter.actor,
+                filter.action,
+                filter.target,
+                filter.org,
+                filter.result,
+                filter.ip,
+                filter.from,
+                filter.to,
+                filter.before,
+                filter.limit as i64
+            ],
+            audit_entry_from_row,
+        )
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(entries)
+}
@@ -1,4 +1,5 @@
 
+use crate::logic::config::{parse_duration, parse_time};
 use serde::{Deserialize, Serialize};
 
 /// Data types that can be stored in the database and transmitted via REST API
@@ -24,6 +25,9 @@ pub enum DataType {
 }
 
 impl DataType {
+    /// Every type name that can appear in the `data_type` column
+    pub const TYPE_NAMES: [&'static str; 6] = ["pulse", "gps", "sensor", "trigger", "event", "image"];
+
     /// Get the type name as a string for database storage
     pub fn type_name(&self) -> &'static str {
         match self {
@@ -257,6 +261,200 @@ impl DataType {
 
 }
 
+/// Granularity of a pre-aggregated rollup table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollup {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Rollup {
+    /// Every rollup, finest first
+    pub const ALL: [Rollup; 3] = [Rollup::Minute, Rollup::Hour, Rollup::Day];
+
+    /// Ranges are split into at least this many buckets when picking a rollup
+    const MIN_BUCKETS: i64 = 24;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rollup::Minute => "minute",
+            Rollup::Hour => "hour",
+            Rollup::Day => "day",
+        }
+    }
+
+    pub fn table(self) -> &'static str {
+        match self {
+            Rollup::Minute => "rollup_minute",
+            Rollup::Hour => "rollup_hour",
+            Rollup::Day => "rollup_day",
+        }
+    }
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            Rollup::Minute => 60,
+            Rollup::Hour => 3600,
+            Rollup::Day => 86_400,
+        }
+    }
+
+    /// The coarsest rollup that still splits a range into enough buckets to plot
+    pub fn for_range(range_seconds: i64) -> Rollup {
+        Self::ALL
+            .into_iter()
+            .rev()
+            .find(|rollup| range_seconds / rollup.seconds() >= Self::MIN_BUCKETS)
+            .unwrap_or(Rollup::Minute)
+    }
+
+    /// Key of the bucket containing `timestamp`: its start as UTC RFC3339
+    pub fn bucket(self, timestamp: &chrono::DateTime<chrono::Utc>) -> String {
+        let secs = timestamp.timestamp();
+        let start = secs - secs.rem_euclid(self.seconds());
+        chrono::DateTime::from_timestamp(start, 0)
+            .unwrap_or_default()
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+    }
+}
+
+/// A span of time split into equal buckets, as requested by history and stats queries
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    pub from: chrono::DateTime<chrono::Utc>,
+    pub to: chrono::DateTime<chrono::Utc>,
+    pub bucket_seconds: i64,
+}
+
+impl TimeWindow {
+    /// Most buckets a single query may split its window into
+    const MAX_BUCKETS: i64 = 10_000;
+
+    /// Build a window from query parameters. `from` and `to` are RFC3339 or
+    /// relative like `-6h` (default: the last day up to now); `bucket` is a
+    /// duration such as `10s` or `1h` (default: picked from the window's length).
+    /// The legacy `time_range` of `1h`, `1d`, `1w` or `1m` stands for `from`.
+    pub fn parse(
+        from: Option<&str>,
+        to: Option<&str>,
+        bucket: Option<&str>,
+        time_range: Option<&str>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<TimeWindow, String> {
+        let from = match (from, time_range) {
+            (Some(from), _) => from,
+            (None, Some("1h")) => "-1h",
+            (None, Some("1d")) | (None, None) => "-1d",
+            (None, Some("1w")) => "-1w",
+            (None, Some("1m")) => "-30d",
+            (None, Some(other)) => return Err(format!("unknown time range '{}', expected 1h, 1d, 1w or 1m", other)),
+        };
+        let from = parse_time(from, now)?;
+        let to = parse_time(to.unwrap_or("now"), now)?;
+        if from >= to {
+            return Err("'from' must be before 'to'".to_string());
+        }
+
+        let range_seconds = (to - from).num_seconds();
+        let bucket_seconds = match bucket {
+            Some(bucket) => i64::try_from(parse_duration(bucket)?).map_err(|_| format!("bucket '{}' is too long", bucket))?,
+            None => Rollup::for_range(range_seconds).seconds(),
+        };
+        if bucket_seconds == 0 {
+            return Err("bucket must be at least 1s".to_string());
+        }
+        if range_seconds / bucket_seconds > Self::MAX_BUCKETS {
+            return Err(format!("window splits into more than {} buckets, use a larger bucket", Self::MAX_BUCKETS));
+        }
+
+        Ok(TimeWindow { from, to, bucket_seconds })
+    }
+
+    /// The coarsest rollup whose buckets fit evenly into the requested bucket;
+    /// `None` means the raw records have to be read
+    pub fn rollup(&self) -> Option<Rollup> {
+        Rollup::ALL
+            .into_iter()
+            .rev()
+            .find(|rollup| self.bucket_seconds % rollup.seconds() == 0)
+    }
+
+    /// Start of the bucket containing `epoch_seconds`, as UTC RFC3339
+    pub fn bucket_start(&self, epoch_seconds: i64) -> String {
+        let start = epoch_seconds - epoch_seconds.rem_euclid(self.bucket_seconds);
+        chrono::DateTime::from_timestamp(start, 0)
+            .unwrap_or_default()
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+    }
+}
+
+/// Position of a stored record: records are ordered by timestamp, then by
+/// insertion order. Sent to clients as an opaque, URL-safe string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataCursor {
+    pub timestamp: String,
+    pub id: i64,
+}
+
+impl DataCursor {
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}|{}", self.timestamp, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Result<DataCursor, String> {
+        let invalid = || format!("invalid cursor '{}'", cursor);
+        let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+        let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (timestamp, id) = text.rsplit_once('|').ok_or_else(invalid)?;
+        Ok(DataCursor {
+            timestamp: timestamp.to_string(),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Which records of a raw data query to return, newest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataPage {
+    pub limit: usize,
+    /// Only records older than this one
+    pub before: Option<DataCursor>,
+    /// Only records newer than this one
+    pub after: Option<DataCursor>,
+}
+
+impl DataPage {
+    pub const DEFAULT_LIMIT: usize = 10;
+    pub const MAX_LIMIT: usize = 1000;
+
+    /// Build a page from the `limit`, `before` and `after` query parameters
+    pub fn parse(limit: Option<&str>, before: Option<&str>, after: Option<&str>) -> Result<DataPage, String> {
+        let limit = match limit {
+            Some(limit) => limit
+                .parse::<usize>()
+                .ok()
+                .filter(|limit| (1..=Self::MAX_LIMIT).contains(limit))
+                .ok_or_else(|| format!("limit must be a number from 1 to {}", Self::MAX_LIMIT))?,
+            None => Self::DEFAULT_LIMIT,
+        };
+        if before.is_some() && after.is_some() {
+            return Err("use either 'before' or 'after', not both".to_string());
+        }
+        Ok(DataPage {
+            limit,
+            before: before.map(DataCursor::decode).transpose()?,
+            after: after.map(DataCursor::decode).transpose()?,
+        })
+    }
+}
+
+impl Default for DataPage {
+    fn default() -> Self {
+        DataPage { limit: Self::DEFAULT_LIMIT, before: None, after: None }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -343,4 +541,56 @@ mod tests {
         assert_eq!(DataType::Event { message: "test".to_string() }.type_name(), "event");
         assert_eq!(DataType::Image { rows: 10, cols: 10, channels: 3, data: vec![] }.type_name(), "image");
     }
+
+    #[test]
+    fn test_rollup_buckets() {
+        let ts = chrono::DateTime::parse_from_rfc3339("2025-06-01T13:47:12.5+00:00").unwrap().with_timezone(&chrono::Utc);
+        assert_eq!(Rollup::Minute.bucket(&ts), "2025-06-01T13:47:00+00:00");
+        assert_eq!(Rollup::Hour.bucket(&ts), "2025-06-01T13:00:00+00:00");
+        assert_eq!(Rollup::Day.bucket(&ts), "2025-06-01T00:00:00+00:00");
+
+        assert_eq!(Rollup::for_range(3600), Rollup::Minute);
+        assert_eq!(Rollup::for_range(86_400), Rollup::Hour);
+        assert_eq!(Rollup::for_range(7 * 86_400), Rollup::Hour);
+        assert_eq!(Rollup::for_range(30 * 86_400), Rollup::Day);
+        assert_eq!(Rollup::for_range(10), Rollup::Minute);
+    }
+
+    #[test]
+    fn test_time_window() {
+        let now = chrono::DateTime::parse_from_rfc3339("2025-06-01T12:00:00Z").unwrap().with_timezone(&chrono::Utc);
+
+        let legacy = TimeWindow::parse(None, None, None, Some("1w"), now).unwrap();
+        assert_eq!(legacy.to, now);
+        assert_eq!(legacy.bucket_seconds, 3600);
+        assert_eq!(legacy.rollup(), Some(Rollup::Hour));
+
+        let incident = TimeWindow::parse(Some("2025-06-01T11:50:00Z"), Some("-5m"), Some("10s"), None, now).unwrap();
+        assert_eq!((incident.to - incident.from).num_seconds(), 300);
+        assert_eq!(incident.rollup(), None);
+        assert_eq!(incident.bucket_start(1748778619), "2025-06-01T11:50:10+00:00");
+
+        let twelve_hours = TimeWindow::parse(Some("-1w"), None, Some("12h"), None, now).unwrap();
+        assert_eq!(twelve_hours.rollup(), Some(Rollup::Hour));
+
+        assert!(TimeWindow::parse(Some("-1h"), Some("-2h"), None, None, now).is_err());
+        assert!(TimeWindow::parse(Some("-1w"), None, Some("1s"), None, now).is_err());
+        assert!(TimeWindow::parse(None, None, Some("0s"), None, now).is_err());
+        assert!(TimeWindow::parse(None, None, None, Some("2d"), now).is_err());
+    }
+
+    #[test]
+    fn test_data_page() {
+        let cursor = DataCursor { timestamp: "2025-06-01T12:00:00+00:00".to_string(), id: 42 };
+        assert_eq!(DataCursor::decode(&cursor.encode()), Ok(cursor.clone()));
+        assert!(cursor.encode().chars().all(|c| c.is_ascii_alphanumeric()));
+        assert!(DataCursor::decode("not-a-cursor").is_err());
+
+        assert_eq!(DataPage::parse(None, None, None), Ok(DataPage::default()));
+        let older = DataPage::parse(Some("50"), Some(&cursor.encode()), None).unwrap();
+        assert_eq!((older.limit, older.before), (50, Some(cursor.clone())));
+        assert!(DataPage::parse(Some("0"), None, None).is_err());
+        assert!(DataPage::parse(Some("5000"), None, None).is_err());
+        assert!(DataPage::parse(None, Some(&cursor.encode()), Some(&cursor.encode())).is_err());
+    }
 }
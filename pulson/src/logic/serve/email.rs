@@ -0,0 +1,303 @@
+use crate::logic::serve::database::{get_email_settings, Database};
+use crate::logic::serve::events::{EventBus, LiveEvent};
+use crate::logic::serve::maintenance::event_silenced;
+use crate::logic::serve::smtp::{is_valid_address, Message, SmtpConfig};
+use crate::logic::types::{DeviceStatus, TopicStatus};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+/// Most recipients one user can have
+const MAX_RECIPIENTS: usize = 20;
+
+/// Most status changes listed in one email; the rest are only counted
+const MAX_DIGEST_LINES: usize = 500;
+
+/// Pauses between attempts to send a digest
+const RETRY_DELAYS: [Duration; 2] = [Duration::from_secs(10), Duration::from_secs(60)];
+
+/// Who gets status emails for a user's devices and for which transitions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmailSettings {
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Device statuses that send mail when a device changes to them
+    #[serde(default = "default_device_statuses")]
+    pub device_statuses: Vec<DeviceStatus>,
+    /// Topic statuses that send mail when a topic changes to them
+    #[serde(default)]
+    pub topic_statuses: Vec<TopicStatus>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_device_statuses() -> Vec<DeviceStatus> {
+    vec![DeviceStatus::Offline]
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for EmailSettings {
+    fn default() -> Self {
+        Self {
+            recipients: Vec::new(),
+            device_statuses: default_device_statuses(),
+            topic_statuses: Vec::new(),
+            enabled: true,
+        }
+    }
+}
+
+impl EmailSettings {
+    /// Validate settings sent by a client, trimming and deduplicating them
+    pub fn normalize(mut self) -> Result<Self, String> {
+        let mut recipients: Vec<String> = Vec::new();
+        for recipient in self.recipients.iter().map(|r| r.trim()) {
+            if !is_valid_address(recipient) {
+                return Err(format!("'{}' is not an email address", recipient));
+            }
+            if !recipients.iter().any(|r| r.eq_ignore_ascii_case(recipient)) {
+                recipients.push(recipient.to_string());
+            }
+        }
+        if recipients.len() > MAX_RECIPIENTS {
+            return Err(format!("at most {} recipients are allowed", MAX_RECIPIENTS));
+        }
+        self.recipients = recipients;
+
+        let mut device_statuses = Vec::new();
+        for status in self.device_statuses {
+            if !device_statuses.contains(&status) {
+                device_statuses.push(status);
+            }
+        }
+        self.device_statuses = device_statuses;
+        let mut topic_statuses = Vec::new();
+        for status in self.topic_statuses {
+            if !topic_statuses.contains(&status) {
+                topic_statuses.push(status);
+            }
+        }
+        self.topic_statuses = topic_statuses;
+        Ok(self)
+    }
+
+    fn wants(&self, event: &LiveEvent) -> bool {
+        self.enabled
+            && !self.recipients.is_empty()
+            && match event {
+                LiveEvent::DeviceStatus { status, .. } => self.device_statuses.contains(status),
+                LiveEvent::TopicStatus { status, .. } => self.topic_statuses.contains(status),
+                _ => false,
+            }
+    }
+}
+
+/// A status change waiting for the next digest
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    device_id: String,
+    topic: Option<String>,
+    status: String,
+    previous_status: Option<String>,
+    at: DateTime<Utc>,
+}
+
+impl Change {
+    fn from_event(event: &LiveEvent, at: DateTime<Utc>) -> Option<Self> {
+        let (device_id, topic, status, previous_status) = match event {
+            LiveEvent::DeviceStatus { device_id, status, previous_status, .. } => (
+                device_id,
+                None,
+                format!("{:?}", status),
+                previous_status.as_ref().map(|s| format!("{:?}", s)),
+            ),
+            LiveEvent::TopicStatus { device_id, topic, status, previous_status, .. } => (
+                device_id,
+                Some(topic.clone()),
+                format!("{:?}", status),
+                previous_status.as_ref().map(|s| format!("{:?}", s)),
+            ),
+            _ => return None,
+        };
+        Some(Self { device_id: device_id.clone(), topic, status, previous_status, at })
+    }
+
+    fn subject(&self) -> String {
+        match &self.topic {
+            Some(topic) => format!("{}/{}", self.device_id, topic),
+            None => self.device_id.clone(),
+        }
+    }
+}
+
+/// Subject and body of the email for a batch of changes. A single change is
+/// named in the subject; several are counted per status.
+fn compose_digest(changes: &[Change]) -> (String, String) {
+    let subject = match changes {
+        [change] => format!("[pulson] {} is {}", change.subject(), change.status),
+        _ => {
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for change in changes {
+                *counts.entry(change.status.as_str()).or_default() += 1;
+            }
+            let mut counts: Vec<_> = counts.into_iter().collect();
+            counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+            let summary: Vec<String> = counts.iter().map(|(status, n)| format!("{} {}", n, status)).collect();
+            format!("[pulson] {} status changes: {}", changes.len(), summary.join(", "))
+        }
+    };
+
+    let mut body = String::new();
+    for change in changes.iter().take(MAX_DIGEST_LINES) {
+        body.push_str(&format!(
+            "{}  {}  {} -> {}\n",
+            change.at.format("%Y-%m-%d %H:%M:%S UTC"),
+            change.subject(),
+            change.previous_status.as_deref().unwrap_or("new"),
+            change.status
+        ));
+    }
+    if changes.len() > MAX_DIGEST_LINES {
+        body.push_str(&format!("... and {} more\n", changes.len() - MAX_DIGEST_LINES));
+    }
+    (subject, body)
+}
+
+/// Start mailing status changes to the users that asked for them. Changes are
+/// collected for `digest_seconds` after the first one and then sent as one
+/// email per user, so an outage of many devices sends a single message.
+pub fn spawn_email_notifier(db: Database, events: EventBus, smtp: Option<Arc<SmtpConfig>>) {
+    let Some(smtp) = smtp else {
+        return;
+    };
+    println!("Email notifications via {}:{}", smtp.host, smtp.port);
+
+    let window = Duration::from_secs(smtp.digest_seconds);
+    let mut rx = events.subscribe();
+    tokio::spawn(async move {
+        let mut pending: BTreeMap<String, Vec<Change>> = BTreeMap::new();
+        let mut deadline: Option<Instant> = None;
+        loop {
+            tokio::select! {
+                received = rx.recv() => {
+                    let event = match received {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            eprintln!("Email notifier: missed {} events", missed);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    let Some(change) = Change::from_event(&event, Utc::now()) else {
+                        continue;
+                    };
+                    let owner = event.owner().to_string();
+                    match get_email_settings(&db, &owner) {
+                        Ok(Some(settings)) if settings.wants(&event) => {}
+                        _ => continue,
+                    }
+                    if event_silenced(&db, &event) {
+                        continue;
+                    }
+                    pending.entry(owner).or_default().push(change);
+                    deadline.get_or_insert_with(|| Instant::now() + window);
+                }
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    deadline = None;
+                    for (username, changes) in std::mem::take(&mut pending) {
+                        tokio::spawn(send_digest(db.clone(), smtp.clone(), username, changes));
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Mail a digest to the user's current recipients, retrying a few times
+async fn send_digest(db: Database, smtp: Arc<SmtpConfig>, username: String, changes: Vec<Change>) {
+    let (subject, body) = compose_digest(&changes);
+    for attempt in 0..=RETRY_DELAYS.len() {
+        // Read on every attempt, so changed recipients are honoured
+        let recipients = match get_email_settings(&db, &username) {
+            Ok(Some(settings)) if settings.enabled && !settings.recipients.is_empty() => settings.recipients,
+            _ => return,
+        };
+        let message = Message { to: recipients, subject: subject.clone(), body: body.clone() };
+        match smtp.send(&message).await {
+            Ok(()) => {
+                println!(
+                    "Mailed {} status change(s) to {} recipient(s) (user: {})",
+                    changes.len(),
+                    message.to.len(),
+                    username
+                );
+                return;
+            }
+            Err(e) => {
+                eprintln!("Status email for {} failed (attempt {}): {}", username, attempt + 1, e);
+                if let Some(delay) = RETRY_DELAYS.get(attempt) {
+                    tokio::time::sleep(*delay).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_event(device_id: &str, status: DeviceStatus) -> LiveEvent {
+        LiveEvent::DeviceStatus {
+            owner: "alice".to_string(),
+            device_id: device_id.to_string(),
+            status,
+            previous_status: Some(DeviceStatus::Online),
+            last_seen: "2025-06-01T12:00:00Z".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_settings() {
+        let settings = EmailSettings {
+            recipients: vec![" ops@example.com".to_string(), "OPS@example.com".to_string()],
+            device_statuses: vec![DeviceStatus::Offline, DeviceStatus::Offline],
+            ..EmailSettings::default()
+        }
+        .normalize()
+        .unwrap();
+        assert_eq!(settings.recipients, vec!["ops@example.com"]);
+        assert_eq!(settings.device_statuses, vec![DeviceStatus::Offline]);
+        assert!(settings.wants(&device_event("robot1", DeviceStatus::Offline)));
+        assert!(!settings.wants(&device_event("robot1", DeviceStatus::Warning)));
+        assert!(!EmailSettings::default().wants(&device_event("robot1", DeviceStatus::Offline)));
+
+        let invalid = EmailSettings { recipients: vec!["not an address".to_string()], ..EmailSettings::default() };
+        assert!(invalid.normalize().is_err());
+    }
+
+    #[test]
+    fn test_compose_digest() {
+        let at = DateTime::parse_from_rfc3339("2025-06-01T12:00:00Z").unwrap().with_timezone(&Utc);
+        let offline = Change::from_event(&device_event("robot1", DeviceStatus::Offline), at).unwrap();
+        let (subject, body) = compose_digest(std::slice::from_ref(&offline));
+        assert_eq!(subject, "[pulson] robot1 is Offline");
+        assert_eq!(body, "2025-06-01 12:00:00 UTC  robot1  Online -> Offline\n");
+
+        let mut changes: Vec<Change> = (0..600)
+            .map(|i| Change { device_id: format!("robot{}", i), ..offline.clone() })
+            .collect();
+        changes.push(Change { status: "Warning".to_string(), ..offline });
+        let (subject, body) = compose_digest(&changes);
+        assert_eq!(subject, "[pulson] 601 status changes: 600 Offline, 1 Warning");
+        assert_eq!(body.lines().count(), MAX_DIGEST_LINES + 1);
+        assert!(body.ends_with("... and 101 more\n"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::logic::serve::database::Alert;
+use crate::logic::types::{DeviceStatus, TopicStatus};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+
+/// How many events a slow subscriber may fall behind before it starts losing them
+const EVENT_BUS_CAPACITY: usize = 4096;
+
+/// Live notification about stored data or a status change.
+///
+/// `owner` is the namespace prefix of the device (the username) and is used to
+/// scope subscriptions; `device_id` is the id as the owner knows it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LiveEvent {
+    /// A record was stored through `store_device_data`
+    Data {
+        #[serde(skip)]
+        owner: String,
+        device_id: String,
+        topic: String,
+        data_type: String,
+        data: Value,
+        timestamp: String,
+    },
+    /// A device moved from one status to another (`previous_status` is empty for new devices)
+    DeviceStatus {
+        #[serde(skip)]
+        owner: String,
+        device_id: String,
+        status: DeviceStatus,
+        previous_status: Option<DeviceStatus>,
+        last_seen: String,
+    },
+    /// A topic moved from one status to another (`previous_status` is empty for new topics)
+    TopicStatus {
+        #[serde(skip)]
+        owner: String,
+        device_id: String,
+        topic: String,
+        status: TopicStatus,
+        previous_status: Option<TopicStatus>,
+        last_seen: String,
+    },
+    /// An alert rule fired or resolved. One-off alerts (event matches, trigger
+    /// flips) are stored resolved but announced as `fired`.
+    Alert {
+        #[serde(skip)]
+        owner: String,
+        /// `fired` or `resolved`
+        transition: &'static str,
+        #[serde(flatten)]
+        alert: Alert,
+    },
+}
+
+impl LiveEvent {
+    /// Event name used on the wire, e.g. as the SSE `event:` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LiveEvent::Data { .. } => "data",
+            LiveEvent::DeviceStatus { .. } => "device_status",
+            LiveEvent::TopicStatus { .. } => "topic_status",
+            LiveEvent::Alert { .. } => "alert",
+        }
+    }
+
+    pub fn owner(&self) -> &str {
+        match self {
+            LiveEvent::Data { owner, .. }
+            | LiveEvent::DeviceStatus { owner, .. }
+            | LiveEvent::TopicStatus { owner, .. }
+            | LiveEvent::Alert { owner, .. } => owner,
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        match self {
+            LiveEvent::Data { device_id, .. }
+            | LiveEvent::DeviceStatus { device_id, .. }
+            | LiveEvent::TopicStatus { device_id, .. } => device_id,
+            LiveEvent::Alert { alert, .. } => &alert.device_id,
+        }
+    }
+
+    pub fn topic(&self) -> Option<&str> {
+        match self {
+            LiveEvent::Data { topic, .. } | LiveEvent::TopicStatus { topic, .. } => Some(topic),
+            LiveEvent::Alert { alert, .. } => alert.topic.as_deref(),
+            LiveEvent::DeviceStatus { .. } => None,
+        }
+    }
+
+    pub fn data_type(&self) -> Option<&str> {
+        match self {
+            LiveEvent::Data { data_type, .. } => Some(data_type),
+            _ => None,
+        }
+    }
+}
+
+/// Broadcast channel carrying every `LiveEvent` of the server
+pub type EventBus = broadcast::Sender<LiveEvent>;
+
+pub fn new_event_bus() -> EventBus {
+    broadcast::channel(EVENT_BUS_CAPACITY).0
+}
+
+/// Publish an event; having no subscribers is not an error
+pub fn publish(events: &EventBus, event: LiveEvent) {
+    let _ = events.send(event);
+}
+
+/// Split a stored device id (`owner:device`) into its owner and display id
+pub fn split_device_id(full_device_id: &str) -> Option<(&str, &str)> {
+    full_device_id.split_once(':')
+}
@@ -0,0 +1,311 @@
+use crate::logic::serve::database::{store_device_data, store_device_data_batch, touch_device, Database, DeviceDataRecord};
+use crate::logic::serve::db_types::DataType;
+use crate::logic::serve::events::{publish, EventBus, LiveEvent};
+use crate::logic::serve::rate_limit::RateLimiter;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use warp::http::StatusCode;
+
+/// Epoch values above this are taken to be milliseconds rather than seconds
+/// (as seconds it would be roughly the year 5138).
+const EPOCH_MILLIS_THRESHOLD: f64 = 100_000_000_000.0;
+
+/// Largest accepted pulse, whatever the transport; room for a camera image
+pub const MAX_PULSE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Server-side options shared by every ingestion path
+#[derive(Debug, Clone, Copy)]
+pub struct IngestOptions {
+    /// Keep every image instead of only the latest one per topic
+    pub save_images: bool,
+    /// How far into the future a client-supplied timestamp may lie, in seconds
+    pub max_clock_skew_seconds: u64,
+}
+
+/// A pulse as sent by a client, before it is namespaced to its owner
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct IncomingPulse {
+    pub device_id: String,
+    pub topic: String,
+    pub data: Option<Value>,
+    /// When the reading was taken: RFC3339 string or Unix epoch (defaults to now)
+    pub timestamp: Option<Value>,
+}
+
+/// Why a single pulse was not stored
+#[derive(Debug)]
+pub enum IngestError {
+    /// The pulse itself is malformed; the message is meant for the client
+    Invalid(String),
+    /// The database refused the record
+    Storage(StatusCode),
+    /// A pulse rate limit of the owner or device is used up; seconds until it is not
+    Limited(u64),
+}
+
+impl IngestError {
+    pub fn message(&self) -> String {
+        match self {
+            IngestError::Invalid(message) => message.clone(),
+            IngestError::Storage(_) => "storage failed".to_string(),
+            IngestError::Limited(retry_after) => format!("too many pulses, try again in {}s", retry_after),
+        }
+    }
+}
+
+/// Stores pulses from any transport, within the pulse rate limits of their
+/// owner and device, and announces them on the event bus
+#[derive(Clone)]
+pub struct Ingestor {
+    pub db: Database,
+    pub events: EventBus,
+    pub options: IngestOptions,
+    pub limiter: RateLimiter,
+}
+
+impl Ingestor {
+    pub fn new(db: Database, events: EventBus, options: IngestOptions, limiter: RateLimiter) -> Self {
+        Self { db, events, options, limiter }
+    }
+
+    /// Validate, store and publish a single pulse for `username`
+    pub fn ingest(&self, username: &str, pulse: IncomingPulse) -> Result<(), IngestError> {
+        self.limiter
+            .check_pulses(username, [pulse.device_id.as_str()])
+            .map_err(IngestError::Limited)?;
+        let record = self.prepare(username, pulse, Utc::now())?;
+        let stored = store_device_data(
+            &self.db,
+            &record.device_id,
+            record.device_name.as_deref(),
+            &record.topic,
+            &record.data,
+            &record.timestamp,
+            self.options.save_images,
+        )
+        .map_err(IngestError::Storage)?;
+        self.announce(username, record, &stored);
+        Ok(())
+    }
+
+    /// Validate and store many pulses in one transaction. The outer error means
+    /// nothing was stored; otherwise there is one result per input pulse, in order.
+    /// Every pulse in it counts against the limits, and a batch over them stores nothing.
+    pub fn ingest_batch(
+        &self,
+        username: &str,
+        pulses: Vec<IncomingPulse>,
+    ) -> Result<Vec<Result<(), IngestError>>, IngestError> {
+        self.limiter
+            .check_pulses(username, pulses.iter().map(|pulse| pulse.device_id.as_str()))
+            .map_err(IngestError::Limited)?;
+        let now = Utc::now();
+
+        // Validate every pulse first; only valid ones are sent to the database
+        let mut results: Vec<Result<(), IngestError>> = Vec::with_capacity(pulses.len());
+        let mut records = Vec::with_capacity(pulses.len());
+        for pulse in pulses {
+            match self.prepare(username, pulse, now) {
+                Ok(record) => {
+                    results.push(Ok(()));
+                    records.push(record);
+                }
+                Err(e) => results.push(Err(e)),
+            }
+        }
+
+        let stored = store_device_data_batch(&self.db, &records, self.options.save_images).map_err(IngestError::Storage)?;
+
+        // Merge storage outcomes back into the slots of the valid pulses
+        let mut outcomes = records.into_iter().zip(stored);
+        for result in results.iter_mut().filter(|r| r.is_ok()) {
+            if let Some((record, outcome)) = outcomes.next() {
+                match outcome {
+                    Ok(data) => self.announce(username, record, &data),
+                    Err(status) => *result = Err(IngestError::Storage(status)),
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Refresh last_seen of devices served by an open connection, without storing data
+    pub fn keep_alive<'a>(&self, username: &str, devices: impl IntoIterator<Item = &'a String>) {
+        let now = Utc::now().to_rfc3339();
+        for device in devices {
+            // Include username in device_id to isolate user data
+            let device_id = format!("{}:{}", username, device);
+            if touch_device(&self.db, &device_id, device, &now).is_err() {
+                eprintln!("Failed to refresh liveness of device {} (user: {})", device, username);
+            }
+        }
+    }
+
+    fn prepare(&self, username: &str, pulse: IncomingPulse, now: DateTime<Utc>) -> Result<DeviceDataRecord, IngestError> {
+        if pulse.device_id.is_empty() || pulse.topic.is_empty() {
+            return Err(IngestError::Invalid("device_id and topic must not be empty".to_string()));
+        }
+        let timestamp = resolve_timestamp(pulse.timestamp.as_ref(), now, self.options.max_clock_skew_seconds)
+            .map_err(IngestError::Invalid)?;
+        Ok(DeviceDataRecord {
+            // Include username in device_id to isolate user data
+            device_id: format!("{}:{}", username, pulse.device_id),
+            device_name: Some(pulse.device_id),
+            topic: pulse.topic,
+            data: pulse.data.unwrap_or(Value::Null),
+            timestamp: timestamp.to_rfc3339(),
+        })
+    }
+
+    fn announce(&self, username: &str, record: DeviceDataRecord, stored: &DataType) {
+        publish(&self.events, LiveEvent::Data {
+            owner: username.to_string(),
+            device_id: record.device_name.unwrap_or_default(),
+            topic: record.topic,
+            data_type: stored.type_name().to_string(),
+            data: stored.to_json(),
+            timestamp: record.timestamp,
+        });
+    }
+}
+
+/// Resolve the timestamp of an incoming record.
+///
+/// Records without a timestamp are stamped with `now`. Otherwise the value may be
+/// an RFC3339 string or a Unix epoch number (seconds or milliseconds, fractions
+/// allowed). Timestamps more than `max_skew_seconds` ahead of `now` are rejected.
+pub fn resolve_timestamp(
+    raw: Option<&Value>,
+    now: DateTime<Utc>,
+    max_skew_seconds: u64,
+) -> Result<DateTime<Utc>, String> {
+    let timestamp = match raw {
+        None | Some(Value::Null) => return Ok(now),
+        Some(Value::String(s)) => DateTime::parse_from_rfc3339(s)
+            .map(|dt| dt.with_timezone(&Utc))
+            .map_err(|_| format!("invalid timestamp '{}', expected RFC3339 or epoch seconds", s))?,
+        Some(Value::Number(n)) => {
+            let epoch = n.as_f64().ok_or_else(|| format!("invalid epoch timestamp {}", n))?;
+            let millis = if epoch.abs() >= EPOCH_MILLIS_THRESHOLD { epoch } else { epoch * 1000.0 };
+            Utc.timestamp_millis_opt(millis.round() as i64)
+                .single()
+                .ok_or_else(|| format!("epoch timestamp {} is out of range", n))?
+        }
+        Some(other) => return Err(format!("invalid timestamp {}, expected RFC3339 or epoch seconds", other)),
+    };
+
+    if timestamp > now + chrono::Duration::seconds(max_skew_seconds as i64) {
+        return Err(format!(
+            "timestamp {} is more than {}s in the future",
+            timestamp.to_rfc3339(),
+            max_skew_seconds
+        ));
+    }
+
+    Ok(timestamp)
+}
+
+/// Decode the raw payload of a transport without JSON framing (MQTT, UDP).
+/// An empty payload is a ping, JSON is taken as is and other UTF-8 text as a string.
+pub fn decode_payload(payload: &[u8]) -> Result<Option<Value>, String> {
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(value) = serde_json::from_slice::<Value>(payload) {
+        return Ok(Some(value));
+    }
+    std::str::from_utf8(payload)
+        .map(|text| Some(Value::String(text.to_string())))
+        .map_err(|_| "payload must be JSON or UTF-8 text".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::database::{create_user, init_database};
+    use crate::logic::serve::events::new_event_bus;
+    use crate::logic::serve::rate_limit::{Limit, RateLimitConfig};
+    use crate::logic::serve::roles::Role;
+    use serde_json::json;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-06-01T12:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_missing_timestamp_uses_now() {
+        assert_eq!(resolve_timestamp(None, now(), 60), Ok(now()));
+        assert_eq!(resolve_timestamp(Some(&json!(null)), now(), 60), Ok(now()));
+    }
+
+    #[test]
+    fn test_rfc3339_timestamp() {
+        let ts = resolve_timestamp(Some(&json!("2025-06-01T13:00:00+02:00")), now(), 60).unwrap();
+        assert_eq!(ts.to_rfc3339(), "2025-06-01T11:00:00+00:00");
+    }
+
+    #[test]
+    fn test_epoch_seconds_and_millis() {
+        let secs = resolve_timestamp(Some(&json!(1748779200)), now(), 60).unwrap();
+        assert_eq!(secs, now());
+
+        let millis = resolve_timestamp(Some(&json!(1748779200500i64)), now(), 60).unwrap();
+        assert_eq!(millis.timestamp_millis(), 1748779200500);
+
+        let fractional = resolve_timestamp(Some(&json!(1748779199.25)), now(), 60).unwrap();
+        assert_eq!(fractional.timestamp_millis(), 1748779199250);
+    }
+
+    #[test]
+    fn test_future_skew_rejected() {
+        assert!(resolve_timestamp(Some(&json!("2025-06-01T12:00:30Z")), now(), 60).is_ok());
+        assert!(resolve_timestamp(Some(&json!("2025-06-01T12:05:00Z")), now(), 60).is_err());
+    }
+
+    #[test]
+    fn test_invalid_timestamp() {
+        assert!(resolve_timestamp(Some(&json!("yesterday")), now(), 60).is_err());
+        assert!(resolve_timestamp(Some(&json!(true)), now(), 60).is_err());
+    }
+
+    #[test]
+    fn test_decode_payload() {
+        assert_eq!(decode_payload(b""), Ok(None));
+        assert_eq!(decode_payload(b"21.5"), Ok(Some(json!(21.5))));
+        assert_eq!(decode_payload(b"door opened"), Ok(Some(json!("door opened"))));
+        assert!(decode_payload(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn test_pulse_limits() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let limiter = RateLimiter::new(RateLimitConfig {
+            pulse_per_device: Limit { burst: 2, per_minute: 1 },
+            ..RateLimitConfig::default()
+        });
+        let options = IngestOptions { save_images: false, max_clock_skew_seconds: 60 };
+        let ingestor = Ingestor::new(db, new_event_bus(), options, limiter);
+        let pulse = |device_id: &str| IncomingPulse {
+            device_id: device_id.to_string(),
+            topic: "temp".to_string(),
+            data: Some(json!(21.5)),
+            timestamp: None,
+        };
+
+        // Every transport stores through the ingestor, so one limit covers them all
+        assert!(ingestor.ingest("alice", pulse("robot1")).is_ok());
+        assert!(ingestor.ingest("alice", pulse("robot1")).is_ok());
+        assert!(matches!(ingestor.ingest("alice", pulse("robot1")), Err(IngestError::Limited(_))));
+        assert!(matches!(
+            ingestor.ingest_batch("alice", vec![pulse("robot2"), pulse("robot1")]),
+            Err(IngestError::Limited(_))
+        ));
+        // Every pulse of a batch counts, so one larger than the burst is refused whole
+        assert!(matches!(
+            ingestor.ingest_batch("alice", vec![pulse("robot3"), pulse("robot3"), pulse("robot3")]),
+            Err(IngestError::Limited(_))
+        ));
+        assert_eq!(ingestor.ingest_batch("alice", vec![pulse("robot3"), pulse("robot3")]).unwrap().len(), 2);
+    }
+}
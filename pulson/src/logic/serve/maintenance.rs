@@ -0,0 +1,177 @@
+use crate::logic::config::{parse_duration, parse_time};
+use crate::logic::serve::alerts::glob_match;
+use crate::logic::serve::database::{list_maintenance_windows, Database};
+use crate::logic::serve::events::LiveEvent;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Longest accepted reason
+const MAX_REASON_LEN: usize = 500;
+
+/// What a window does to the devices and topics it covers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowKind {
+    /// Covered devices show as Maintenance and send no notifications
+    Maintenance,
+    /// Statuses are left alone; only notifications are held back
+    Silence,
+}
+
+/// A period in which some devices or topics are expected to be down
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub id: i64,
+    /// Device id, or a glob such as `robot-*`; unset covers every device
+    pub device_id: Option<String>,
+    /// Only this topic; unset covers the devices and all their topics
+    pub topic: Option<String>,
+    pub kind: WindowKind,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub reason: String,
+}
+
+impl MaintenanceWindow {
+    pub fn is_active(&self, now: &DateTime<Utc>) -> bool {
+        self.starts_at <= *now && *now < self.ends_at
+    }
+
+    /// Whether the window is about a device (`topic` unset) or one of its topics
+    pub fn covers(&self, device_id: &str, topic: Option<&str>) -> bool {
+        self.device_id.as_deref().is_none_or(|pattern| glob_match(pattern, device_id))
+            && self.topic.as_deref().is_none_or(|t| Some(t) == topic)
+    }
+
+    /// Whether a device shows as Maintenance right now
+    pub fn holds_device(&self, device_id: &str, now: &DateTime<Utc>) -> bool {
+        self.kind == WindowKind::Maintenance && self.is_active(now) && self.covers(device_id, None)
+    }
+}
+
+/// A window as requested by its owner. It starts now unless `starts_at` is
+/// given and ends at `ends_at` or after `duration`, e.g. `2h`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceSpec {
+    pub device_id: Option<String>,
+    pub topic: Option<String>,
+    #[serde(default = "default_kind")]
+    pub kind: WindowKind,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    pub duration: Option<String>,
+    pub reason: String,
+}
+
+fn default_kind() -> WindowKind {
+    WindowKind::Maintenance
+}
+
+impl MaintenanceSpec {
+    /// Check the request and resolve its times into a window (with id 0)
+    pub fn resolve(self, now: DateTime<Utc>) -> Result<MaintenanceWindow, String> {
+        let device_id = self.device_id.map(|d| d.trim().to_string()).filter(|d| !d.is_empty());
+        let topic = self.topic.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        if device_id.is_none() && topic.is_none() {
+            return Err("a device (or device glob such as '*'), a topic or both are required".to_string());
+        }
+        let reason = self.reason.trim().to_string();
+        if reason.is_empty() || reason.len() > MAX_REASON_LEN {
+            return Err(format!("reason must be 1 to {} characters", MAX_REASON_LEN));
+        }
+
+        let starts_at = match self.starts_at.as_deref() {
+            Some(start) => parse_time(start, now)?,
+            None => now,
+        };
+        let ends_at = match (self.ends_at.as_deref(), self.duration.as_deref()) {
+            (Some(end), None) => parse_time(end, now)?,
+            (None, Some(duration)) => {
+                let seconds = i64::try_from(parse_duration(duration)?).map_err(|_| "duration is too long".to_string())?;
+                chrono::Duration::try_seconds(seconds)
+                    .and_then(|duration| starts_at.checked_add_signed(duration))
+                    .ok_or_else(|| "duration is too long".to_string())?
+            }
+            _ => return Err("give either ends_at or duration".to_string()),
+        };
+        if ends_at <= starts_at {
+            return Err("the window must end after it starts".to_string());
+        }
+        if ends_at <= now {
+            return Err("the window is already over".to_string());
+        }
+
+        Ok(MaintenanceWindow { id: 0, device_id, topic, kind: self.kind, starts_at, ends_at, reason })
+    }
+}
+
+/// Whether notifications about a device (or one of its topics) are held back
+/// by an active window of its owner
+pub fn is_silenced(db: &Database, owner: &str, device_id: &str, topic: Option<&str>) -> bool {
+    let now = Utc::now();
+    list_maintenance_windows(db, owner, false)
+        .unwrap_or_default()
+        .iter()
+        .any(|window| window.is_active(&now) && window.covers(device_id, topic))
+}
+
+/// Whether a status change or alert should not be passed on to webhooks, email
+/// or command hooks. Stored data is never held back.
+pub fn event_silenced(db: &Database, event: &LiveEvent) -> bool {
+    match event {
+        LiveEvent::DeviceStatus { owner, device_id, .. } => is_silenced(db, owner, device_id, None),
+        LiveEvent::TopicStatus { owner, device_id, topic, .. } => is_silenced(db, owner, device_id, Some(topic)),
+        LiveEvent::Alert { owner, alert, .. } => is_silenced(db, owner, &alert.device_id, alert.topic.as_deref()),
+        LiveEvent::Data { .. } => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(device_id: Option<&str>, topic: Option<&str>) -> MaintenanceSpec {
+        MaintenanceSpec {
+            device_id: device_id.map(str::to_string),
+            topic: topic.map(str::to_string),
+            kind: WindowKind::Maintenance,
+            starts_at: None,
+            ends_at: None,
+            duration: Some("2h".to_string()),
+            reason: "battery swap".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_window_scope() {
+        let now = Utc::now();
+        let fleet = spec(Some("robot-*"), None).resolve(now).unwrap();
+        assert_eq!(fleet.ends_at - fleet.starts_at, chrono::Duration::hours(2));
+        assert!(fleet.holds_device("robot-7", &now));
+        assert!(fleet.covers("robot-7", Some("camera")));
+        assert!(!fleet.holds_device("drone-1", &now));
+        assert!(!fleet.holds_device("robot-7", &(now + chrono::Duration::hours(3))));
+
+        let camera = spec(None, Some("camera")).resolve(now).unwrap();
+        assert!(camera.covers("drone-1", Some("camera")));
+        assert!(!camera.covers("drone-1", Some("lidar")));
+        assert!(!camera.holds_device("drone-1", &now));
+
+        let silence = MaintenanceSpec { kind: WindowKind::Silence, ..spec(Some("robot-7"), None) }.resolve(now).unwrap();
+        assert!(silence.covers("robot-7", None) && !silence.holds_device("robot-7", &now));
+    }
+
+    #[test]
+    fn test_spec_validation() {
+        let now = Utc::now();
+        assert!(spec(None, None).resolve(now).is_err());
+        assert!(MaintenanceSpec { reason: " ".to_string(), ..spec(Some("robot1"), None) }.resolve(now).is_err());
+        assert!(MaintenanceSpec { ends_at: Some("-1h".to_string()), duration: None, ..spec(Some("robot1"), None) }
+            .resolve(now)
+            .is_err());
+        assert!(MaintenanceSpec { ends_at: Some("now".to_string()), ..spec(Some("robot1"), None) }.resolve(now).is_err());
+
+        let later = MaintenanceSpec { starts_at: Some("-1h".to_string()), ..spec(Some("robot1"), None) }.resolve(now).unwrap();
+        assert_eq!(later.ends_at, now + chrono::Duration::hours(1));
+    }
+}
@@ -1,19 +1,63 @@
+pub mod aggregate;
+pub mod alerts;
 pub mod api;
+pub mod api_keys;
+pub mod audit;
 pub mod auth;
+pub mod command_hooks;
 pub mod database;
 pub mod db_types;
+pub mod email;
+pub mod events;
+pub mod ingest;
+pub mod maintenance;
+pub mod mqtt;
+pub mod orgs;
+pub mod rate_limit;
+pub mod retention;
+pub mod roles;
+pub mod smtp;
+pub mod status_monitor;
+pub mod udp;
+pub mod uptime;
 pub mod ui;
+pub mod webhooks;
 
+use crate::logic::serve::aggregate::set_max_range as set_max_aggregate_range;
+use crate::logic::serve::alerts::spawn_alert_evaluator;
 use crate::logic::serve::api::api_routes;
-use crate::logic::serve::auth::Unauthorized;
+use crate::logic::serve::audit::{audited, set_trusted_proxy_hops};
+use crate::logic::serve::api::token_service::{load_token_key, set_token_key};
+use crate::logic::serve::auth::{Forbidden, Unauthorized};
+use crate::logic::serve::command_hooks::spawn_command_hooks;
 use crate::logic::serve::database::init_database;
+use crate::logic::serve::email::spawn_email_notifier;
+use crate::logic::serve::events::new_event_bus;
+use crate::logic::serve::ingest::{IngestOptions, Ingestor};
+use crate::logic::serve::rate_limit::{too_many_requests, RateLimiter, TooManyRequests};
+use crate::logic::serve::retention::{spawn_retention_task, spawn_rollup_backfill};
+use crate::logic::serve::status_monitor::spawn_status_monitor;
+use crate::logic::serve::udp::UdpStats;
 use crate::logic::serve::ui::ui_routes;
-use crate::logic::config::StatusConfig;
+use crate::logic::serve::webhooks::spawn_webhook_dispatcher;
+use crate::logic::config::{ServerConfig, StatusConfig};
 use daemonize::Daemonize;
 use shellexpand;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
-use warp::{Filter, Rejection};
+use warp::{Filter, Rejection, Reply};
+
+/// Server options beyond the HTTP listener itself
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub ingest: IngestOptions,
+    /// Address of the MQTT listener; disabled when unset
+    pub mqtt_bind: Option<SocketAddr>,
+    /// Address of the UDP datagram listener; disabled when unset
+    pub udp_bind: Option<SocketAddr>,
+    /// Settings from the server config file
+    pub server_config: ServerConfig,
+}
 
 pub async fn run(
     host_config: crate::cli::HostConfig,
@@ -22,7 +66,7 @@ pub async fn run(
     root_pass: Option<String>,
     _webui: bool,
     status_config: Arc<Mutex<StatusConfig>>,
-    save_images: bool,
+    options: ServeOptions,
 ) -> anyhow::Result<()> {
     // 1) Daemonize if requested
     if daemon {
@@ -40,26 +84,66 @@ pub async fn run(
     } else { 
         format!("{}/pulson.db", expanded) 
     };
+    set_token_key(load_token_key(&std::path::Path::new(&db_file).with_extension("key"))?);
     let db = init_database(&db_file)?;
+    options.server_config.sessions.apply();
+    set_max_aggregate_range(options.server_config.max_aggregate_range_seconds);
+    set_trusted_proxy_hops(options.server_config.trusted_proxy_hops);
 
-    // 3) Build API routes with status configuration
-    let api = api_routes(db.clone(), root_pass.clone(), status_config.clone(), save_images)
+    // 3) Start the live event bus, the status transition monitor feeding it, the
+    //    alert evaluator, webhook dispatcher, email notifier and command hooks
+    //    following it, the retention task pruning expired data and the rollup
+    //    of records stored before rollups existed
+    let events = new_event_bus();
+    spawn_status_monitor(db.clone(), events.clone());
+    spawn_alert_evaluator(db.clone(), events.clone());
+    spawn_webhook_dispatcher(db.clone(), events.clone(), Arc::new(options.server_config.webhooks.clone()));
+    spawn_email_notifier(db.clone(), events.clone(), options.server_config.smtp.clone().map(Arc::new));
+    spawn_command_hooks(db.clone(), events.clone(), &options.server_config);
+    spawn_retention_task(db.clone(), options.server_config.audit);
+    spawn_rollup_backfill(db.clone());
+    let limiter = RateLimiter::new(options.server_config.rate_limits);
+    let ingestor = Ingestor::new(db.clone(), events, options.ingest, limiter.clone());
+
+    // 4) Start optional ingestion listeners
+    if let Some(addr) = options.mqtt_bind {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        println!("pulson MQTT listener running on {}", addr);
+        tokio::spawn(mqtt::serve(listener, ingestor.clone()));
+    }
+    let udp_stats = Arc::new(UdpStats::default());
+    if let Some(addr) = options.udp_bind {
+        let socket = tokio::net::UdpSocket::bind(addr).await?;
+        println!("pulson UDP listener running on {}", addr);
+        tokio::spawn(udp::serve(socket, ingestor.clone(), udp_stats.clone()));
+    }
+
+    // 5) Build API routes with status configuration
+    let api = api_routes(db.clone(), root_pass.clone(), status_config.clone(), ingestor, udp_stats, &options.server_config, limiter)
         .recover(|err: Rejection| async move {
             if err.find::<Unauthorized>().is_some() {
                 Ok(warp::reply::with_status(
                     warp::reply::json(&serde_json::json!({ "error": "Unauthorized" })),
                     warp::http::StatusCode::UNAUTHORIZED,
-                ))
+                ).into_response())
+            } else if err.find::<Forbidden>().is_some() {
+                Ok(warp::reply::with_status(
+                    warp::reply::json(&serde_json::json!({ "error": "Forbidden: your role or API key does not allow this" })),
+                    warp::http::StatusCode::FORBIDDEN,
+                ).into_response())
+            } else if let Some(limited) = err.find::<TooManyRequests>() {
+                Ok(too_many_requests(limited.retry_after))
             } else {
                 Err(err)
             }
-        })
-        .boxed();
+        });
+    // Record every mutating call, including refused ones, in the audit log
+    let api = audited(db.clone(), api, options.server_config.audit).boxed();
 
-    // 4) Build UI routes (static + SPA)
+    // 6) Build UI routes (static + SPA)
     let ui = ui_routes().boxed();
 
-    // 5) Combine and serve
+    // 7) Combine and serve
     let routes = api.or(ui);
     println!("pulson server running on {}", host_config.server_url());
     
@@ -0,0 +1,342 @@
+pub mod packet;
+
+use crate::logic::serve::api::password_utils::secrets_match;
+use crate::logic::serve::api::token_service::validate_token;
+use crate::logic::serve::api_keys::Scope;
+use crate::logic::serve::database::{get_device_secret, get_org_role, Database};
+use crate::logic::serve::ingest::{decode_payload, IncomingPulse, IngestError, Ingestor, MAX_PULSE_BYTES};
+use crate::logic::serve::roles::user_grants;
+use packet::{
+    connack, pingresp, puback, pubcomp, pubrec, read_packet, suback_refused, unsuback, Connect, Packet, Publish,
+    CONNACK_ACCEPTED, CONNACK_BAD_CREDENTIALS, CONNACK_BAD_PROTOCOL, CONNACK_NOT_AUTHORIZED, MAX_CONTROL_PACKET_BYTES,
+};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+/// Every accepted topic has the form `pulson/<device_id>/<topic>`
+const TOPIC_PREFIX: &str = "pulson/";
+
+
+/// How long a new connection may take to send CONNECT
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Who a session publishes as
+#[derive(Debug, PartialEq)]
+enum Principal {
+    /// Logged in with a pulson token: may publish for any device of the user, or
+    /// of the organization named as the username
+    User(String),
+    /// Logged in with a device credential: may only publish for that device
+    Device { username: String, device_id: String },
+}
+
+impl Principal {
+    fn username(&self) -> &str {
+        match self {
+            Principal::User(username) | Principal::Device { username, .. } => username,
+        }
+    }
+}
+
+/// Accept MQTT connections until the listener fails. Clients publish to
+/// `pulson/<device_id>/<topic>`; subscriptions are refused.
+pub async fn serve(listener: TcpListener, ingestor: Ingestor) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let ingestor = ingestor.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, ingestor).await {
+                        eprintln!("MQTT connection from {} closed: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("MQTT accept failed: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, ingestor: Ingestor) -> Result<(), String> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let connect = match timeout(CONNECT_TIMEOUT, read_packet(&mut reader, MAX_CONTROL_PACKET_BYTES)).await {
+        Ok(Ok(Some(Packet::Connect(connect)))) => connect,
+        Ok(Ok(None)) => return Ok(()),
+        Ok(Ok(Some(_))) => return Err("expected CONNECT".to_string()),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("no CONNECT received".to_string()),
+    };
+
+    if connect.protocol_name != "MQTT" || connect.protocol_level != 4 {
+        let _ = writer.write_all(&connack(CONNACK_BAD_PROTOCOL)).await;
+        return Err(format!("unsupported protocol {} level {}", connect.protocol_name, connect.protocol_level));
+    }
+    let principal = match authenticate(&ingestor.db, &connect) {
+        Ok(principal) => principal,
+        Err(code) => {
+            let _ = writer.write_all(&connack(code)).await;
+            return Err(format!("client '{}' failed to authenticate", connect.client_id));
+        }
+    };
+    write(&mut writer, &connack(CONNACK_ACCEPTED)).await?;
+
+    let username = principal.username().to_string();
+    println!("MQTT client '{}' connected (user: {})", connect.client_id, username);
+
+    // Devices whose liveness this connection vouches for
+    let mut devices: HashSet<String> = match &principal {
+        Principal::Device { device_id, .. } => HashSet::from([device_id.clone()]),
+        Principal::User(_) => HashSet::new(),
+    };
+    ingestor.keep_alive(&username, &devices);
+
+    // Clients must send something within one and a half keep alive periods
+    let idle_limit = (connect.keep_alive > 0).then(|| Duration::from_millis(connect.keep_alive as u64 * 1500));
+
+    loop {
+        let next = read_packet(&mut reader, MAX_PULSE_BYTES);
+        let packet = match idle_limit {
+            Some(limit) => timeout(limit, next).await.map_err(|_| "keep alive expired".to_string())?,
+            None => next.await,
+        }
+        .map_err(|e| e.to_string())?;
+
+        match packet {
+            None | Some(Packet::Disconnect) => break,
+            Some(Packet::Publish(publish)) => {
+                // MQTT 3.1.1 has no negative acknowledgement: a rejected publish closes
+                // the connection, and a QoS 1/2 message is redelivered on reconnect
+                let device_id = publish_pulse(&ingestor, &principal, &publish)?;
+                devices.insert(device_id);
+                match (publish.qos, publish.packet_id) {
+                    (1, Some(id)) => write(&mut writer, &puback(id)).await?,
+                    (2, Some(id)) => write(&mut writer, &pubrec(id)).await?,
+                    _ => {}
+                }
+            }
+            Some(Packet::PubRel(id)) => write(&mut writer, &pubcomp(id)).await?,
+            Some(Packet::Subscribe { packet_id, filters }) => {
+                write(&mut writer, &suback_refused(packet_id, filters)).await?
+            }
+            Some(Packet::Unsubscribe(id)) => write(&mut writer, &unsuback(id)).await?,
+            Some(Packet::PingReq) => {
+                ingestor.keep_alive(&username, &devices);
+                write(&mut writer, &pingresp()).await?;
+            }
+            Some(Packet::Connect(_)) | Some(Packet::Unsupported(_)) => {
+                return Err("unexpected packet".to_string());
+            }
+        }
+    }
+
+    println!("MQTT client '{}' disconnected (user: {})", connect.client_id, username);
+    Ok(())
+}
+
+async fn write(writer: &mut tokio::net::tcp::OwnedWriteHalf, bytes: &[u8]) -> Result<(), String> {
+    writer.write_all(bytes).await.map_err(|e| e.to_string())
+}
+
+/// Check CONNECT credentials. A username of the form `<user>:<device_id>` logs in
+/// with that device's credential; otherwise the password must be a pulson token
+/// and the username, if given, its owner or an organization they publish for.
+/// Errors are CONNACK codes.
+fn authenticate(db: &Database, connect: &Connect) -> Result<Principal, u8> {
+    let password = connect
+        .password
+        .as_ref()
+        .and_then(|p| String::from_utf8(p.clone()).ok())
+        .ok_or(CONNACK_BAD_CREDENTIALS)?;
+    let login = connect.username.clone().unwrap_or_default();
+
+    if let Some((username, device_id)) = login.split_once(':') {
+        let secret = get_device_secret(db, &login)
+            .map_err(|_| CONNACK_NOT_AUTHORIZED)?
+            .ok_or(CONNACK_BAD_CREDENTIALS)?;
+        if !secrets_match(password.as_bytes(), secret.as_bytes()) {
+            return Err(CONNACK_BAD_CREDENTIALS);
+        }
+        return Ok(Principal::Device {
+            username: username.to_string(),
+            device_id: device_id.to_string(),
+        });
+    }
+
+    let username = validate_token(db, &password).map_err(|_| CONNACK_BAD_CREDENTIALS)?;
+    if !login.is_empty() && login != username {
+        return match get_org_role(db, &login, &username) {
+            Ok(Some(role)) if role.grants(Scope::PulseWrite) => Ok(Principal::User(login)),
+            Ok(Some(_)) => Err(CONNACK_NOT_AUTHORIZED),
+            _ => Err(CONNACK_BAD_CREDENTIALS),
+        };
+    }
+    if !user_grants(db, &username, Scope::PulseWrite) {
+        return Err(CONNACK_NOT_AUTHORIZED);
+    }
+    Ok(Principal::User(username))
+}
+
+/// Store one PUBLISH and return the device it was for
+fn publish_pulse(ingestor: &Ingestor, principal: &Principal, publish: &Publish) -> Result<String, String> {
+    let (device_id, topic) =
+        parse_topic(&publish.topic).ok_or_else(|| format!("topic '{}' is not pulson/<device_id>/<topic>", publish.topic))?;
+    if let Principal::Device { device_id: allowed, .. } = principal {
+        if device_id != allowed {
+            return Err(format!("credential for device '{}' cannot publish for '{}'", allowed, device_id));
+        }
+    }
+
+    let pulse = IncomingPulse {
+        device_id: device_id.to_string(),
+        topic: topic.to_string(),
+        data: decode_payload(&publish.payload)?,
+        timestamp: None,
+    };
+    match ingestor.ingest(principal.username(), pulse) {
+        Ok(()) => Ok(device_id.to_string()),
+        Err(IngestError::Invalid(e)) => Err(e),
+        Err(e) => Err(e.message()),
+    }
+}
+
+/// Split `pulson/<device_id>/<topic>` into device and topic; the topic may contain `/`
+fn parse_topic(topic: &str) -> Option<(&str, &str)> {
+    let (device_id, topic) = topic.strip_prefix(TOPIC_PREFIX)?.split_once('/')?;
+    if device_id.is_empty() || topic.is_empty() {
+        return None;
+    }
+    Some((device_id, topic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::packet::frame;
+    use super::*;
+    use crate::logic::serve::api::token_service::hash_token;
+    use crate::logic::serve::database::{
+        create_organization, create_session, create_user, get_device_latest_data, init_database, set_device_credential,
+    };
+    use crate::logic::serve::rate_limit::{RateLimitConfig, RateLimiter};
+    use crate::logic::serve::roles::Role;
+    use crate::logic::serve::db_types::DataPage;
+    use crate::logic::serve::events::new_event_bus;
+    use crate::logic::serve::ingest::IngestOptions;
+    use tokio::io::AsyncReadExt;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = (s.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    fn connect_packet(username: &str, password: &str) -> Vec<u8> {
+        let mut body = string("MQTT");
+        body.extend([4, 0xc2, 0, 30]);
+        body.extend(string("test-client"));
+        body.extend(string(username));
+        body.extend(string(password));
+        frame(0x10, &body)
+    }
+
+    fn publish_packet(topic: &str, packet_id: u16, payload: &[u8]) -> Vec<u8> {
+        let mut body = string(topic);
+        body.extend(packet_id.to_be_bytes());
+        body.extend_from_slice(payload);
+        frame(0x32, &body)
+    }
+
+    /// Start a listener on an ephemeral port backed by an in-memory database
+    async fn start_listener() -> (Database, std::net::SocketAddr) {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "unused", Role::Operator).unwrap();
+        create_session(&db, &hash_token("alice-token"), &hash_token("alice-refresh"), "alice", None).unwrap();
+
+        let options = IngestOptions { save_images: false, max_clock_skew_seconds: 60 };
+        let ingestor = Ingestor::new(db.clone(), new_event_bus(), options, RateLimiter::new(RateLimitConfig::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, ingestor));
+        (db, addr)
+    }
+
+    async fn read_bytes(stream: &mut TcpStream, n: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; n];
+        stream.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_parse_topic() {
+        assert_eq!(parse_topic("pulson/robot1/temp"), Some(("robot1", "temp")));
+        assert_eq!(parse_topic("pulson/robot1/arm/joint2"), Some(("robot1", "arm/joint2")));
+        assert_eq!(parse_topic("pulson/robot1/"), None);
+        assert_eq!(parse_topic("other/robot1/temp"), None);
+    }
+
+    #[tokio::test]
+    async fn test_publish_with_token() {
+        let (db, addr) = start_listener().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        client.write_all(&connect_packet("alice", "alice-token")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, connack(CONNACK_ACCEPTED));
+
+        client.write_all(&publish_packet("pulson/robot1/temp", 7, b"21.5")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, puback(7));
+
+        let stored = get_device_latest_data(&db, "alice:robot1", Some("temp"), None, &DataPage::default()).unwrap();
+        assert_eq!(stored["data"].as_array().map(|a| a.len()), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_publish_for_organization() {
+        let (db, addr) = start_listener().await;
+        create_organization(&db, "acme", "alice").unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&connect_packet("acme", "alice-token")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, connack(CONNACK_ACCEPTED));
+        client.write_all(&publish_packet("pulson/robot1/temp", 3, b"19.0")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, puback(3));
+        let stored = get_device_latest_data(&db, "acme:robot1", Some("temp"), None, &DataPage::default()).unwrap();
+        assert_eq!(stored["data"].as_array().map(|a| a.len()), Some(1));
+
+        // Only members may publish for an organization
+        let mut other = TcpStream::connect(addr).await.unwrap();
+        other.write_all(&connect_packet("globex", "alice-token")).await.unwrap();
+        assert_eq!(read_bytes(&mut other, 4).await, connack(CONNACK_BAD_CREDENTIALS));
+    }
+
+    #[tokio::test]
+    async fn test_bad_token_is_refused() {
+        let (_db, addr) = start_listener().await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        client.write_all(&connect_packet("alice", "wrong-token")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, connack(CONNACK_BAD_CREDENTIALS));
+    }
+
+    #[tokio::test]
+    async fn test_device_credential_is_limited_to_its_device() {
+        let (db, addr) = start_listener().await;
+        set_device_credential(&db, "alice:robot1", "s3cret").unwrap();
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&connect_packet("alice:robot1", "s3cret")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, connack(CONNACK_ACCEPTED));
+
+        client.write_all(&publish_packet("pulson/robot1/door", 1, b"true")).await.unwrap();
+        assert_eq!(read_bytes(&mut client, 4).await, puback(1));
+
+        // Publishing for another device closes the connection without an ack
+        client.write_all(&publish_packet("pulson/robot2/door", 2, b"true")).await.unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+        let other = get_device_latest_data(&db, "alice:robot2", None, None, &DataPage::default()).unwrap();
+        assert_eq!(other["data"].as_array().map(|a| a.len()), Some(0));
+    }
+}
@@ -0,0 +1,355 @@
+use std::fmt;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const CONNECT: u8 = 1;
+const PUBLISH: u8 = 3;
+const PUBREL: u8 = 6;
+const SUBSCRIBE: u8 = 8;
+const UNSUBSCRIBE: u8 = 10;
+const PINGREQ: u8 = 12;
+const DISCONNECT: u8 = 14;
+
+/// CONNACK return codes
+pub const CONNACK_ACCEPTED: u8 = 0x00;
+pub const CONNACK_BAD_PROTOCOL: u8 = 0x01;
+pub const CONNACK_BAD_CREDENTIALS: u8 = 0x04;
+pub const CONNACK_NOT_AUTHORIZED: u8 = 0x05;
+
+/// Largest accepted packet other than PUBLISH, and of any packet before the
+/// client is authenticated
+pub const MAX_CONTROL_PACKET_BYTES: usize = 4 * 1024;
+
+/// SUBACK return code for a refused subscription
+const SUBACK_FAILURE: u8 = 0x80;
+
+#[derive(Debug, PartialEq)]
+pub struct Connect {
+    pub protocol_name: String,
+    pub protocol_level: u8,
+    pub client_id: String,
+    pub keep_alive: u16,
+    pub username: Option<String>,
+    pub password: Option<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Publish {
+    pub topic: String,
+    pub qos: u8,
+    pub packet_id: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+/// MQTT 3.1.1 packets a client may send to a publish-only listener
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    Connect(Connect),
+    Publish(Publish),
+    PubRel(u16),
+    Subscribe { packet_id: u16, filters: usize },
+    Unsubscribe(u16),
+    PingReq,
+    Disconnect,
+    /// Any other (server-to-client or reserved) packet type
+    Unsupported(u8),
+}
+
+#[derive(Debug)]
+pub enum PacketError {
+    Io(std::io::Error),
+    Malformed(&'static str),
+    TooLarge(usize),
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Io(e) => write!(f, "{}", e),
+            PacketError::Malformed(what) => write!(f, "malformed packet: {}", what),
+            PacketError::TooLarge(size) => write!(f, "packet of {} bytes is too large", size),
+        }
+    }
+}
+
+impl From<std::io::Error> for PacketError {
+    fn from(e: std::io::Error) -> Self {
+        PacketError::Io(e)
+    }
+}
+
+/// Read one packet. `max_size` bounds PUBLISH packets; every other packet is
+/// bounded by [`MAX_CONTROL_PACKET_BYTES`] as well. Returns `Ok(None)` when the
+/// peer closed the connection cleanly between packets.
+pub async fn read_packet<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Option<Packet>, PacketError> {
+    let mut header = [0u8; 1];
+    if reader.read(&mut header).await? == 0 {
+        return Ok(None);
+    }
+
+    // Remaining length: up to four 7-bit groups, least significant first
+    let mut length = 0usize;
+    for i in 0..4 {
+        let byte = reader.read_u8().await?;
+        length |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if i == 3 {
+            return Err(PacketError::Malformed("remaining length"));
+        }
+    }
+    let max_size = if header[0] >> 4 == PUBLISH { max_size } else { max_size.min(MAX_CONTROL_PACKET_BYTES) };
+    if length > max_size {
+        return Err(PacketError::TooLarge(length));
+    }
+
+    // The body grows as it arrives rather than being allocated up front
+    let mut body = Vec::with_capacity(length.min(64 * 1024));
+    reader.take(length as u64).read_to_end(&mut body).await?;
+    if body.len() < length {
+        return Err(PacketError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    decode(header[0], &body).map(Some)
+}
+
+/// Decode a packet from its fixed header byte and its body
+pub fn decode(header: u8, body: &[u8]) -> Result<Packet, PacketError> {
+    let flags = header & 0x0f;
+    let mut body = Reader(body);
+    match header >> 4 {
+        CONNECT => {
+            let protocol_name = body.string()?;
+            let protocol_level = body.u8()?;
+            let connect_flags = body.u8()?;
+            let keep_alive = body.u16()?;
+            let client_id = body.string()?;
+            if connect_flags & 0x04 != 0 {
+                // Will topic and message are accepted but never published
+                body.string()?;
+                body.bytes()?;
+            }
+            let username = if connect_flags & 0x80 != 0 { Some(body.string()?) } else { None };
+            let password = if connect_flags & 0x40 != 0 { Some(body.bytes()?.to_vec()) } else { None };
+            Ok(Packet::Connect(Connect {
+                protocol_name,
+                protocol_level,
+                client_id,
+                keep_alive,
+                username,
+                password,
+            }))
+        }
+        PUBLISH => {
+            let qos = (flags >> 1) & 0x03;
+            if qos == 3 {
+                return Err(PacketError::Malformed("QoS 3"));
+            }
+            let topic = body.string()?;
+            let packet_id = if qos > 0 { Some(body.u16()?) } else { None };
+            Ok(Packet::Publish(Publish {
+                topic,
+                qos,
+                packet_id,
+                payload: body.rest().to_vec(),
+            }))
+        }
+        PUBREL => Ok(Packet::PubRel(body.u16()?)),
+        SUBSCRIBE => {
+            let packet_id = body.u16()?;
+            let mut filters = 0;
+            while !body.is_empty() {
+                body.string()?;
+                body.u8()?;
+                filters += 1;
+            }
+            Ok(Packet::Subscribe { packet_id, filters })
+        }
+        UNSUBSCRIBE => Ok(Packet::Unsubscribe(body.u16()?)),
+        PINGREQ => Ok(Packet::PingReq),
+        DISCONNECT => Ok(Packet::Disconnect),
+        other => Ok(Packet::Unsupported(other)),
+    }
+}
+
+pub fn connack(return_code: u8) -> Vec<u8> {
+    vec![0x20, 0x02, 0x00, return_code]
+}
+
+pub fn puback(packet_id: u16) -> Vec<u8> {
+    with_packet_id(0x40, packet_id)
+}
+
+pub fn pubrec(packet_id: u16) -> Vec<u8> {
+    with_packet_id(0x50, packet_id)
+}
+
+pub fn pubcomp(packet_id: u16) -> Vec<u8> {
+    with_packet_id(0x70, packet_id)
+}
+
+pub fn unsuback(packet_id: u16) -> Vec<u8> {
+    with_packet_id(0xb0, packet_id)
+}
+
+/// Refuse every requested subscription; the listener only accepts publishes
+pub fn suback_refused(packet_id: u16, filters: usize) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    body.extend(std::iter::repeat_n(SUBACK_FAILURE, filters));
+    frame(0x90, &body)
+}
+
+pub fn pingresp() -> Vec<u8> {
+    vec![0xd0, 0x00]
+}
+
+fn with_packet_id(header: u8, packet_id: u16) -> Vec<u8> {
+    frame(header, &packet_id.to_be_bytes())
+}
+
+/// Prefix a body with its fixed header and remaining length
+pub fn frame(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    out
+}
+
+/// Cursor over a packet body
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PacketError> {
+        if self.0.len() < n {
+            return Err(PacketError::Malformed("truncated body"));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, PacketError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PacketError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], PacketError> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, PacketError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| PacketError::Malformed("invalid UTF-8 string"))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = (s.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    #[test]
+    fn test_decode_connect_with_credentials() {
+        let mut body = string("MQTT");
+        body.extend([4, 0xc2, 0, 60]);
+        body.extend(string("robot1"));
+        body.extend(string("alice"));
+        body.extend(string("secret"));
+
+        let packet = decode(0x10, &body).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Connect(Connect {
+                protocol_name: "MQTT".to_string(),
+                protocol_level: 4,
+                client_id: "robot1".to_string(),
+                keep_alive: 60,
+                username: Some("alice".to_string()),
+                password: Some(b"secret".to_vec()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_publish() {
+        let mut body = string("pulson/robot1/temp");
+        body.extend([0x00, 0x07]);
+        body.extend(b"21.5");
+
+        let packet = decode(0x32, &body).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Publish(Publish {
+                topic: "pulson/robot1/temp".to_string(),
+                qos: 1,
+                packet_id: Some(7),
+                payload: b"21.5".to_vec(),
+            })
+        );
+
+        assert!(decode(0x36, &body).is_err());
+        assert!(decode(0x30, &[0x00]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_packet_with_long_remaining_length() {
+        let mut body = string("t");
+        body.extend(vec![b'x'; 300]);
+        let bytes = frame(0x30, &body);
+        assert_eq!(&bytes[1..3], &[0xaf, 0x02]);
+
+        let packet = read_packet(&mut bytes.as_slice(), 1024).await.unwrap().unwrap();
+        match packet {
+            Packet::Publish(publish) => assert_eq!(publish.payload.len(), 300),
+            other => panic!("unexpected packet {:?}", other),
+        }
+
+        assert!(matches!(read_packet(&mut bytes.as_slice(), 100).await, Err(PacketError::TooLarge(303))));
+        assert!(read_packet(&mut [].as_slice(), 100).await.unwrap().is_none());
+        assert!(matches!(read_packet(&mut &bytes[..200], 1024).await, Err(PacketError::Io(_))));
+
+        // Only PUBLISH may use the large limit
+        let mut connect = string("MQTT");
+        connect.extend([4, 0x02, 0, 60]);
+        connect.extend(string(&"c".repeat(5000)));
+        let bytes = frame(0x10, &connect);
+        assert!(matches!(read_packet(&mut bytes.as_slice(), 1 << 20).await, Err(PacketError::TooLarge(5012))));
+    }
+
+    #[test]
+    fn test_encode_acks() {
+        assert_eq!(connack(CONNACK_ACCEPTED), vec![0x20, 2, 0, 0]);
+        assert_eq!(puback(0x0102), vec![0x40, 2, 1, 2]);
+        assert_eq!(suback_refused(5, 2), vec![0x90, 4, 0, 5, 0x80, 0x80]);
+    }
+}
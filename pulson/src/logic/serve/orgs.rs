@@ -0,0 +1,108 @@
+use crate::logic::serve::roles::Role;
+
+/// Longest accepted organization name
+const MAX_NAME_LEN: usize = 64;
+
+/// An organization as listed to one of its members, with the member's role.
+/// Organizations own devices like users do: their devices are stored as
+/// `org:device`, and alert rules, webhooks, thresholds and the other settings
+/// of the fleet belong to the organization.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Organization {
+    pub name: String,
+    pub role: Role,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct OrgMember {
+    pub username: String,
+    pub role: Role,
+    pub joined_at: String,
+}
+
+/// Organization names share one namespace with usernames, since both prefix
+/// device ids: letters, digits, `.`, `_` and `-` only
+pub fn validate_org_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(format!("name must be 1 to {} characters", MAX_NAME_LEN));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        return Err("name may only contain letters, digits, '.', '_' and '-'".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::alerts::{AlertCondition, AlertRuleSpec};
+    use crate::logic::serve::database::{
+        create_alert_rule, create_maintenance_window, create_organization, create_user, init_database, list_alert_rules,
+        list_maintenance_windows, list_retention_policies, list_status_overrides, move_devices, set_retention_policy,
+        set_status_override, store_device_data, RetentionPolicy,
+    };
+    use crate::logic::serve::maintenance::{MaintenanceWindow, WindowKind};
+    use serde_json::json;
+
+    #[test]
+    fn test_org_names() {
+        assert!(validate_org_name("acme-robotics").is_ok());
+        assert!(validate_org_name("lab_2.eu").is_ok());
+        assert!(validate_org_name("").is_err());
+        assert!(validate_org_name("acme:robots").is_err());
+        assert!(validate_org_name("a b").is_err());
+        assert!(validate_org_name(&"x".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_moved_devices_keep_their_settings() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        create_organization(&db, "acme", "alice").unwrap();
+        let now = chrono::Utc::now();
+        for device in ["robot-1", "robot-2"] {
+            store_device_data(&db, &format!("alice:{}", device), Some(device), "temp", &json!(20.0), &now.to_rfc3339(), false).unwrap();
+        }
+        set_retention_policy(&db, "alice", Some("robot-1"), None, None, 86_400).unwrap();
+        set_retention_policy(&db, "alice", None, None, Some("sensor"), 86_400).unwrap();
+        set_status_override(&db, "alice", Some("robot-1"), None, Some([10, 20, 30])).unwrap();
+        let rule = AlertRuleSpec {
+            name: "robot down".to_string(),
+            device_id: Some("robot-1".to_string()),
+            topic: None,
+            condition: AlertCondition::DeviceOffline,
+            enabled: true,
+        };
+        create_alert_rule(&db, "alice", &rule).unwrap();
+        let window = |device_id: &str| MaintenanceWindow {
+            id: 0,
+            device_id: Some(device_id.to_string()),
+            topic: None,
+            kind: WindowKind::Maintenance,
+            starts_at: now,
+            ends_at: now + chrono::Duration::hours(1),
+            reason: "upgrade".to_string(),
+        };
+        create_maintenance_window(&db, "alice", &window("robot-1")).unwrap();
+        create_maintenance_window(&db, "alice", &window("robot-*")).unwrap();
+
+        assert_eq!(move_devices(&db, "alice", "acme", &["robot-1".to_string()]), Ok(1));
+
+        let moved = |policies: Vec<RetentionPolicy>| policies.iter().filter(|p| p.device_id.as_deref() == Some("robot-1")).count();
+        assert_eq!(moved(list_retention_policies(&db, "acme").unwrap()), 1);
+        assert_eq!(moved(list_retention_policies(&db, "alice").unwrap()), 0);
+        assert_eq!(list_retention_policies(&db, "alice").unwrap().len(), 1, "rules for all devices stay");
+        assert_eq!(list_status_overrides(&db, "acme").unwrap().len(), 1);
+        assert!(list_status_overrides(&db, "alice").unwrap().is_empty());
+        assert_eq!(list_alert_rules(&db, "acme").unwrap().len(), 1);
+        assert!(list_alert_rules(&db, "alice").unwrap().is_empty());
+
+        // The pattern still covers robot-2, so its window stays and is copied
+        let devices = |owner: &str| -> Vec<Option<String>> {
+            list_maintenance_windows(&db, owner, false).unwrap().into_iter().map(|w| w.device_id).collect()
+        };
+        assert_eq!(devices("acme"), vec![Some("robot-1".to_string()), Some("robot-1".to_string())]);
+        assert_eq!(devices("alice"), vec![Some("robot-*".to_string())]);
+    }
+}
@@ -0,0 +1,463 @@
+use crate::logic::serve::audit::client_ip;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::HeaderMap;
+use warp::reject::Reject;
+use warp::{Filter, Rejection, Reply};
+
+/// Most buckets of each kind, and most failure counts, kept at once. Once
+/// full, idle ones are dropped at most every `SWEEP_INTERVAL`, and failing that
+/// the least recently used tenth.
+const MAX_TRACKED: usize = 10_000;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A token bucket: `burst` requests at once, refilled at `per_minute`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Limit {
+    pub burst: u32,
+    /// Requests allowed per minute once the burst is used up; 0 disables the limit
+    pub per_minute: u32,
+}
+
+impl Limit {
+    const fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+
+    fn enabled(&self) -> bool {
+        self.per_minute > 0
+    }
+
+    fn tokens_per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// Request limits and the login lockout, the `[rate_limits]` table of the server config
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Logins and registrations from one IP address (default: 10, then 10 a minute)
+    pub account_per_ip: Limit,
+    /// Pulses from one IP address (default: 1000, then 6000 a minute)
+    pub pulse_per_ip: Limit,
+    /// Pulses for one user or organization (default: 1000, then 6000 a minute)
+    pub pulse_per_user: Limit,
+    /// Pulses for one device (default: 120, then 600 a minute)
+    pub pulse_per_device: Limit,
+    /// Failed logins in a row that lock the account; 0 disables the lockout (default: 5)
+    pub login_max_failures: u32,
+    /// How long a locked account refuses logins (default: 15 minutes)
+    pub login_lockout_seconds: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            account_per_ip: Limit::new(10, 10),
+            pulse_per_ip: Limit::new(1000, 6000),
+            pulse_per_user: Limit::new(1000, 6000),
+            pulse_per_device: Limit::new(120, 600),
+            login_max_failures: 5,
+            login_lockout_seconds: 15 * 60,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let limits = [
+            ("account_per_ip", self.account_per_ip),
+            ("pulse_per_ip", self.pulse_per_ip),
+            ("pulse_per_user", self.pulse_per_user),
+            ("pulse_per_device", self.pulse_per_device),
+        ];
+        if let Some((name, _)) = limits.iter().find(|(_, limit)| limit.enabled() && limit.burst == 0) {
+            return Err(format!("rate_limits {} burst must be at least 1", name));
+        }
+        if self.login_max_failures > 0 && self.login_lockout_seconds == 0 {
+            return Err("rate_limits login_lockout_seconds must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What a bucket counts requests of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    AccountPerIp,
+    PulsePerIp,
+    PulsePerUser,
+    PulsePerDevice,
+}
+
+/// Refused because a rate limit is used up or the account is locked
+#[derive(Debug)]
+pub struct TooManyRequests {
+    /// Seconds until the request would be accepted, for `Retry-After`
+    pub retry_after: u64,
+}
+impl Reject for TooManyRequests {}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket is full again if left alone
+    full_at: Instant,
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Entries of one kind, at most `MAX_TRACKED` of them
+#[derive(Debug)]
+struct Tracked<V> {
+    entries: HashMap<String, V>,
+    swept: Option<Instant>,
+}
+
+impl<V> Default for Tracked<V> {
+    fn default() -> Self {
+        Self { entries: HashMap::new(), swept: None }
+    }
+}
+
+impl<V> Tracked<V> {
+    /// Make room for `key` if it is new and the map is full: drop the entries
+    /// that are `idle`, at most every `SWEEP_INTERVAL`, and failing that the
+    /// tenth `used` longest ago
+    fn make_room(&mut self, key: &str, now: Instant, idle: impl Fn(&V) -> bool, used: impl Fn(&V) -> Instant) {
+        if self.entries.len() < MAX_TRACKED || self.entries.contains_key(key) {
+            return;
+        }
+        if sweep_due(&mut self.swept, now) {
+            self.entries.retain(|_, entry| !idle(entry));
+        }
+        if self.entries.len() < MAX_TRACKED {
+            return;
+        }
+        let mut used_at: Vec<Instant> = self.entries.values().map(&used).collect();
+        let (_, cutoff, _) = used_at.select_nth_unstable(MAX_TRACKED / 10 - 1);
+        let cutoff = *cutoff;
+        self.entries.retain(|_, entry| used(entry) > cutoff);
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    buckets: HashMap<LimitKind, Tracked<Bucket>>,
+    failures: Tracked<Failures>,
+}
+
+/// Whether a full map may be swept again
+fn sweep_due(swept: &mut Option<Instant>, now: Instant) -> bool {
+    if swept.is_some_and(|swept| now.saturating_duration_since(swept) < SWEEP_INTERVAL) {
+        return false;
+    }
+    *swept = Some(now);
+    true
+}
+
+/// Token buckets and failed login counts, shared by all routes
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self { config, state: Arc::new(Mutex::new(State::default())) }
+    }
+
+    fn limit(&self, kind: LimitKind) -> Limit {
+        match kind {
+            LimitKind::AccountPerIp => self.config.account_per_ip,
+            LimitKind::PulsePerIp => self.config.pulse_per_ip,
+            LimitKind::PulsePerUser => self.config.pulse_per_user,
+            LimitKind::PulsePerDevice => self.config.pulse_per_device,
+        }
+    }
+
+    /// Take a token from the bucket of `key`, or reject with the time until one is back
+    pub fn check(&self, kind: LimitKind, key: &str) -> Result<(), Rejection> {
+        self.take(kind, key, Instant::now())
+            .map_err(|retry_after| warp::reject::custom(TooManyRequests { retry_after }))
+    }
+
+    fn take(&self, kind: LimitKind, key: &str, now: Instant) -> Result<(), u64> {
+        self.take_all(&[(kind, key.to_string(), 1)], now)
+    }
+
+    /// Take `n` tokens from each bucket named, or none at all if one of them
+    /// has too few, returning the seconds until all of them have enough
+    fn take_all(&self, takes: &[(LimitKind, String, u32)], now: Instant) -> Result<(), u64> {
+        let mut state = self.state.lock().unwrap();
+        let mut retry_after = 0;
+        for (kind, key, n) in takes {
+            let limit = self.limit(*kind);
+            if !limit.enabled() {
+                continue;
+            }
+            let rate = limit.tokens_per_second();
+            let burst = f64::from(limit.burst);
+            let tracked = state.buckets.entry(*kind).or_default();
+            tracked.make_room(key, now, |bucket| bucket.full_at <= now, |bucket| bucket.updated);
+            let bucket = tracked.entries.entry(key.clone()).or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+                full_at: now,
+            });
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+            bucket.updated = now;
+            let wanted = f64::from(*n);
+            if bucket.tokens < wanted {
+                retry_after = retry_after.max(((wanted - bucket.tokens) / rate).ceil().max(1.0) as u64);
+            }
+        }
+        if retry_after > 0 {
+            return Err(retry_after);
+        }
+        for (kind, key, n) in takes {
+            let limit = self.limit(*kind);
+            let Some(bucket) = state.buckets.get_mut(kind).and_then(|tracked| tracked.entries.get_mut(key)) else {
+                continue;
+            };
+            bucket.tokens -= f64::from(*n);
+            bucket.full_at = now + Duration::from_secs_f64((f64::from(limit.burst) - bucket.tokens) / limit.tokens_per_second());
+        }
+        Ok(())
+    }
+
+    /// Take a token for each pulse from the bucket of `owner` and from the
+    /// bucket of its device, or none at all; returns the seconds until they are back
+    pub fn check_pulses<'a>(&self, owner: &str, device_ids: impl IntoIterator<Item = &'a str>) -> Result<(), u64> {
+        let mut per_device: BTreeMap<&str, u32> = BTreeMap::new();
+        for device_id in device_ids {
+            *per_device.entry(device_id).or_default() += 1;
+        }
+        let total = per_device.values().sum();
+        let mut takes = vec![(LimitKind::PulsePerUser, owner.to_string(), total)];
+        takes.extend(
+            per_device
+                .into_iter()
+                .map(|(device_id, n)| (LimitKind::PulsePerDevice, format!("{}/{}", owner, device_id), n)),
+        );
+        self.take_all(&takes, Instant::now())
+    }
+
+    /// Reject a login to a locked account with the time until it unlocks
+    pub fn check_login(&self, username: &str) -> Result<(), Rejection> {
+        match self.locked_for(username, Instant::now()) {
+            Some(retry_after) => Err(warp::reject::custom(TooManyRequests { retry_after })),
+            None => Ok(()),
+        }
+    }
+
+    fn locked_for(&self, username: &str, now: Instant) -> Option<u64> {
+        let state = self.state.lock().unwrap();
+        let locked_until = state.failures.entries.get(username)?.locked_until?;
+        (locked_until > now).then(|| locked_until.duration_since(now).as_secs_f64().ceil().max(1.0) as u64)
+    }
+
+    /// Count a failed login; the last one allowed locks the account
+    pub fn login_failed(&self, username: &str) {
+        self.record_failure(username, Instant::now());
+    }
+
+    fn record_failure(&self, username: &str, now: Instant) {
+        if self.config.login_max_failures == 0 {
+            return;
+        }
+        let window = Duration::from_secs(self.config.login_lockout_seconds);
+        let mut state = self.state.lock().unwrap();
+        state.failures.make_room(
+            username,
+            now,
+            |failures| failures.last + window <= now && failures.locked_until.is_none_or(|until| until <= now),
+            |failures| failures.last,
+        );
+        let failures = state.failures.entries.entry(username.to_string()).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        // Failures further apart than the lockout are not counted together
+        if now.saturating_duration_since(failures.last) >= window {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+        if failures.count >= self.config.login_max_failures {
+            failures.count = 0;
+            failures.locked_until = Some(now + window);
+        }
+    }
+
+    /// Forget the failed logins of an account after a successful one
+    pub fn login_succeeded(&self, username: &str) {
+        self.state.lock().unwrap().failures.entries.remove(username);
+    }
+}
+
+/// The `429 Too Many Requests` answer, with the seconds to wait in `Retry-After`
+pub fn too_many_requests(retry_after: u64) -> warp::reply::Response {
+    warp::reply::with_header(
+        warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": format!("too many requests, try again in {}s", retry_after)
+            })),
+            warp::http::StatusCode::TOO_MANY_REQUESTS,
+        ),
+        warp::http::header::RETRY_AFTER,
+        retry_after.to_string(),
+    )
+    .into_response()
+}
+
+/// Take a token from the bucket of the client's IP address. Requests
+/// without one, such as over a Unix socket, are not limited.
+pub fn limit_ip(limiter: RateLimiter, kind: LimitKind) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .and_then(move |remote: Option<SocketAddr>, headers: HeaderMap| {
+            let limiter = limiter.clone();
+            async move {
+                match client_ip(remote, &headers) {
+                    Some(ip) => limiter.check(kind, &ip.to_string()),
+                    None => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            pulse_per_device: Limit::new(3, 60),
+            pulse_per_user: Limit::new(1, 0),
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice:robot1", start), Ok(()));
+        }
+        assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice:robot1", start), Err(1));
+        assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice:robot2", start), Ok(()));
+
+        // One token a second comes back, never more than the burst
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice:robot1", later), Ok(()));
+        assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice:robot1", later), Err(1));
+        let much_later = start + Duration::from_secs(3600);
+        for _ in 0..3 {
+            assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice:robot1", much_later), Ok(()));
+        }
+        assert!(limiter.take(LimitKind::PulsePerDevice, "alice:robot1", much_later).is_err());
+
+        // per_minute = 0 turns the limit off
+        for _ in 0..10 {
+            assert_eq!(limiter.take(LimitKind::PulsePerUser, "alice", start), Ok(()));
+        }
+
+        let slow = RateLimiter::new(RateLimitConfig { account_per_ip: Limit::new(1, 2), ..RateLimitConfig::default() });
+        assert_eq!(slow.take(LimitKind::AccountPerIp, "10.0.0.1", start), Ok(()));
+        assert_eq!(slow.take(LimitKind::AccountPerIp, "10.0.0.1", start), Err(30));
+    }
+
+    #[test]
+    fn test_batches_take_a_token_per_pulse() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            pulse_per_user: Limit::new(5, 60),
+            pulse_per_device: Limit::new(3, 60),
+            ..RateLimitConfig::default()
+        });
+
+        // More pulses for one device than its burst are refused, and take nothing
+        assert!(limiter.check_pulses("alice", ["robot1"; 4]).is_err());
+        assert_eq!(limiter.check_pulses("alice", ["robot1"; 3]), Ok(()));
+        assert!(limiter.check_pulses("alice", ["robot1"]).is_err());
+        // The owner's bucket counts every pulse too
+        assert_eq!(limiter.check_pulses("alice", ["robot2", "robot3"]), Ok(()));
+        assert!(limiter.check_pulses("alice", ["robot4"]).is_err());
+        assert_eq!(limiter.check_pulses("bob", ["robot1"]), Ok(()));
+    }
+
+    #[test]
+    fn test_tracked_keys_are_capped() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            pulse_per_device: Limit::new(2, 1),
+            account_per_ip: Limit::new(1, 1),
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        let at = |millis: usize| start + Duration::from_millis(millis as u64);
+        for device in 0..MAX_TRACKED {
+            assert_eq!(limiter.take(LimitKind::PulsePerDevice, &format!("alice/{}", device), at(device)), Ok(()));
+        }
+        let now = at(MAX_TRACKED);
+        let buckets = |kind| limiter.state.lock().unwrap().buckets[&kind].entries.len();
+
+        // A full map of device buckets does not hold up logins from a new address
+        assert_eq!(limiter.take(LimitKind::AccountPerIp, "203.0.113.7", now), Ok(()));
+        assert_eq!(buckets(LimitKind::AccountPerIp), 1);
+
+        // New keys make room by dropping the least recently used buckets
+        assert_eq!(limiter.take(LimitKind::PulsePerDevice, "alice/new", now), Ok(()));
+        assert_eq!(buckets(LimitKind::PulsePerDevice), MAX_TRACKED - MAX_TRACKED / 10 + 1);
+        let state = limiter.state.lock().unwrap();
+        let devices = &state.buckets[&LimitKind::PulsePerDevice].entries;
+        assert!(!devices.contains_key("alice/0"));
+        assert!(devices.contains_key(&format!("alice/{}", MAX_TRACKED - 1)));
+    }
+
+    #[test]
+    fn test_login_lockout() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            login_max_failures: 3,
+            login_lockout_seconds: 60,
+            ..RateLimitConfig::default()
+        });
+        let start = Instant::now();
+        limiter.record_failure("alice", start);
+        limiter.record_failure("alice", start);
+        assert_eq!(limiter.locked_for("alice", start), None);
+        limiter.record_failure("alice", start);
+        assert_eq!(limiter.locked_for("alice", start), Some(60));
+        assert_eq!(limiter.locked_for("alice", start + Duration::from_secs(45)), Some(15));
+        assert_eq!(limiter.locked_for("alice", start + Duration::from_secs(60)), None);
+        assert_eq!(limiter.locked_for("bob", start), None);
+
+        // Failures spread wider than the lockout do not add up
+        let later = start + Duration::from_secs(120);
+        limiter.record_failure("alice", later);
+        limiter.record_failure("alice", later + Duration::from_secs(61));
+        limiter.record_failure("alice", later + Duration::from_secs(62));
+        assert_eq!(limiter.locked_for("alice", later + Duration::from_secs(62)), None);
+
+        limiter.login_succeeded("alice");
+        limiter.record_failure("alice", later + Duration::from_secs(63));
+        limiter.record_failure("alice", later + Duration::from_secs(63));
+        assert_eq!(limiter.locked_for("alice", later + Duration::from_secs(63)), None);
+
+        assert!(RateLimitConfig::default().validate().is_ok());
+        assert!(RateLimitConfig { pulse_per_ip: Limit::new(0, 60), ..RateLimitConfig::default() }.validate().is_err());
+        assert!(RateLimitConfig { pulse_per_ip: Limit::new(0, 0), ..RateLimitConfig::default() }.validate().is_ok());
+        assert!(RateLimitConfig { login_lockout_seconds: 0, ..RateLimitConfig::default() }.validate().is_err());
+    }
+}
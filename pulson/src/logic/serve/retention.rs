@@ -0,0 +1,385 @@
+use crate::logic::serve::database::{
+    backfill_rollups, list_all_retention_policies, list_all_topics, prune_audit_log, prune_device_data, prune_rollups,
+    prune_status_transitions, Database, RetentionPolicy,
+};
+use crate::logic::serve::audit::AuditConfig;
+use crate::logic::serve::db_types::{DataType, Rollup};
+use crate::logic::serve::events::split_device_id;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use warp::http::StatusCode;
+
+/// How often retention rules are enforced
+const PRUNE_INTERVAL_SECS: u64 = 600;
+
+/// Records deleted per database lock; the lock is released between batches so
+/// ingestion and queries are never blocked for long
+const PRUNE_BATCH_SIZE: usize = 500;
+
+/// Longest accepted keep time (100 years)
+pub const MAX_KEEP_SECONDS: u64 = 100 * 365 * 86_400;
+
+/// Data type of rules for the minute, hour and day rollups. Only rules naming
+/// it prune rollups, so by default they outlive the raw data.
+pub const ROLLUP_TYPE: &str = "rollup";
+
+/// Start the background task that deletes data past its retention time
+pub fn spawn_retention_task(db: Database, audit: AuditConfig) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(PRUNE_INTERVAL_SECS));
+        loop {
+            ticker.tick().await;
+            match prune_expired(&db, Utc::now()).await {
+                Ok(0) => {}
+                Ok(pruned) => println!("Retention: pruned {} records", pruned),
+                Err(_) => eprintln!("Retention: failed to prune expired data"),
+            }
+            if audit.keep_seconds == 0 {
+                continue;
+            }
+            let cutoff = Utc::now() - chrono::Duration::seconds(audit.keep_seconds.min(MAX_KEEP_SECONDS) as i64);
+            match prune_audit(&db, &cutoff).await {
+                Ok(0) => {}
+                Ok(pruned) => println!("Retention: pruned {} audit entries", pruned),
+                Err(_) => eprintln!("Retention: failed to prune the audit log"),
+            }
+        }
+    });
+}
+
+/// Delete the audit entries made before `cutoff`, returning how many
+pub async fn prune_audit(db: &Database, cutoff: &DateTime<Utc>) -> Result<usize, StatusCode> {
+    let mut pruned = 0;
+    loop {
+        let deleted = prune_audit_log(db, cutoff, PRUNE_BATCH_SIZE)?;
+        pruned += deleted;
+        if deleted < PRUNE_BATCH_SIZE {
+            return Ok(pruned);
+        }
+        tokio::task::yield_now().await;
+    }
+}
+
+/// Start the background task that rolls up records stored before the rollup
+/// tables existed, a batch at a time
+pub fn spawn_rollup_backfill(db: Database) {
+    tokio::spawn(async move {
+        let mut batches = 0;
+        loop {
+            match backfill_rollups(&db, PRUNE_BATCH_SIZE) {
+                Ok(true) => batches += 1,
+                Ok(false) if batches > 0 => {
+                    println!("Rollups: rolled up existing records");
+                    break;
+                }
+                Ok(false) => break,
+                Err(_) => {
+                    eprintln!("Rollups: failed to roll up existing records");
+                    break;
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+    });
+}
+
+/// Delete every record older than the retention rule that applies to it and
+/// return how many were deleted. Data without a matching rule is kept forever.
+/// Status transitions follow the rules that name no data type, rollups only
+/// the rules for [`ROLLUP_TYPE`].
+pub async fn prune_expired(db: &Database, now: DateTime<Utc>) -> Result<usize, StatusCode> {
+    let mut policies: HashMap<String, Vec<RetentionPolicy>> = HashMap::new();
+    for (username, policy) in list_all_retention_policies(db)? {
+        policies.entry(username).or_default().push(policy);
+    }
+    if policies.is_empty() {
+        return Ok(0);
+    }
+
+    let topics = list_all_topics(db)?;
+    let mut pruned = 0;
+    for (device_id, topic) in &topics {
+        let Some((owner, display_id)) = split_device_id(device_id) else {
+            continue;
+        };
+        let Some(rules) = policies.get(owner) else {
+            continue;
+        };
+
+        for data_type in DataType::TYPE_NAMES {
+            let Some(policy) = resolve_policy(rules, display_id, topic, data_type) else {
+                continue;
+            };
+            let cutoff = (now - chrono::Duration::seconds(policy.keep_seconds as i64)).to_rfc3339();
+            loop {
+                let deleted = prune_device_data(db, device_id, topic, data_type, &cutoff, PRUNE_BATCH_SIZE)?;
+                pruned += deleted;
+                if deleted < PRUNE_BATCH_SIZE {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        }
+
+        let Some(policy) = resolve_rollup_policy(rules, display_id, topic) else {
+            continue;
+        };
+        let cutoff = now - chrono::Duration::seconds(policy.keep_seconds as i64);
+        for rollup in Rollup::ALL {
+            loop {
+                let deleted = prune_rollups(db, device_id, topic, rollup, &cutoff, PRUNE_BATCH_SIZE)?;
+                pruned += deleted;
+                if deleted < PRUNE_BATCH_SIZE {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+
+    // Transitions of every topic, and of each device itself under an empty topic
+    let devices: BTreeSet<&String> = topics.iter().map(|(device_id, _)| device_id).collect();
+    let scopes = topics.iter().map(|(device_id, topic)| (device_id, topic.as_str()))
+        .chain(devices.into_iter().map(|device_id| (device_id, "")));
+    for (device_id, topic) in scopes {
+        let Some((owner, display_id)) = split_device_id(device_id) else {
+            continue;
+        };
+        let Some(rules) = policies.get(owner) else {
+            continue;
+        };
+        let Some(policy) = resolve_policy(rules, display_id, topic, "") else {
+            continue;
+        };
+        let cutoff = (now - chrono::Duration::seconds(policy.keep_seconds as i64))
+            .to_rfc3339_opts(chrono::SecondsFormat::Micros, true);
+        loop {
+            let deleted = prune_status_transitions(db, device_id, topic, &cutoff, PRUNE_BATCH_SIZE)?;
+            pruned += deleted;
+            if deleted < PRUNE_BATCH_SIZE {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Pick the rule for one device, topic and data type. The rule that names the
+/// most of the three wins; on a tie device beats topic and topic beats data type.
+pub fn resolve_policy<'a>(
+    policies: impl IntoIterator<Item = &'a RetentionPolicy>,
+    device_id: &str,
+    topic: &str,
+    data_type: &str,
+) -> Option<&'a RetentionPolicy> {
+    policies
+        .into_iter()
+        .filter(|p| {
+            p.device_id.as_deref().is_none_or(|d| d == device_id)
+                && p.topic.as_deref().is_none_or(|t| t == topic)
+                && p.data_type.as_deref().is_none_or(|t| t == data_type)
+        })
+        .max_by_key(|p| {
+            let named = [p.device_id.is_some(), p.topic.is_some(), p.data_type.is_some()];
+            (named.iter().filter(|n| **n).count(), named)
+        })
+}
+
+/// Pick the rollup rule for one device and topic, among the rules for [`ROLLUP_TYPE`]
+fn resolve_rollup_policy<'a>(
+    policies: &'a [RetentionPolicy],
+    device_id: &str,
+    topic: &str,
+) -> Option<&'a RetentionPolicy> {
+    let rollup_rules = policies.iter().filter(|p| p.data_type.as_deref() == Some(ROLLUP_TYPE));
+    resolve_policy(rollup_rules, device_id, topic, ROLLUP_TYPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::database::{
+        create_user, get_device_latest_data, get_pulse_stats, init_database, list_audit_entries, list_status_transitions,
+        record_audit_entry, record_status_transition, set_retention_policy, store_device_data,
+    };
+    use crate::logic::serve::audit::{AuditQuery, NewAuditEntry};
+    use crate::logic::serve::roles::Role;
+    use crate::logic::serve::db_types::{DataPage, TimeWindow};
+    use serde_json::json;
+
+    fn policy(id: i64, device_id: Option<&str>, topic: Option<&str>, data_type: Option<&str>, keep_seconds: u64) -> RetentionPolicy {
+        RetentionPolicy {
+            id,
+            device_id: device_id.map(str::to_string),
+            topic: topic.map(str::to_string),
+            data_type: data_type.map(str::to_string),
+            keep_seconds,
+        }
+    }
+
+    #[test]
+    fn test_most_specific_policy_wins() {
+        let policies = vec![
+            policy(1, None, None, None, 1000),
+            policy(2, None, None, Some("image"), 10),
+            policy(3, Some("robot1"), None, None, 500),
+            policy(4, Some("robot1"), Some("camera"), Some("image"), 50),
+        ];
+
+        assert_eq!(resolve_policy(&policies, "robot2", "temp", "sensor").unwrap().id, 1);
+        assert_eq!(resolve_policy(&policies, "robot2", "camera", "image").unwrap().id, 2);
+        assert_eq!(resolve_policy(&policies, "robot1", "front", "image").unwrap().id, 3);
+        assert_eq!(resolve_policy(&policies, "robot1", "camera", "image").unwrap().id, 4);
+        assert!(resolve_policy(&policies[1..2], "robot1", "temp", "sensor").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_prune_expired() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let now = Utc::now();
+        let days_ago = |days: i64| (now - chrono::Duration::days(days)).to_rfc3339();
+
+        for days in [0, 2, 40] {
+            store_device_data(&db, "alice:robot1", Some("robot1"), "temp", &json!(21.5), &days_ago(days), false).unwrap();
+            store_device_data(&db, "alice:robot1", Some("robot1"), "log", &json!("boot"), &days_ago(days), false).unwrap();
+        }
+        set_retention_policy(&db, "alice", None, None, Some("sensor"), 30 * 86_400).unwrap();
+        set_retention_policy(&db, "alice", Some("robot1"), Some("temp"), None, 86_400).unwrap();
+
+        assert_eq!(prune_expired(&db, now).await.unwrap(), 2);
+
+        let count = |topic: &str| {
+            get_device_latest_data(&db, "alice:robot1", Some(topic), None, &DataPage::default()).unwrap()["data"]
+                .as_array()
+                .unwrap()
+                .len()
+        };
+        assert_eq!(count("temp"), 1);
+        assert_eq!(count("log"), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rollups_outlive_pruned_data() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let now = Utc::now();
+        for (days, value) in [(2, 21.5), (3, 23.5)] {
+            let timestamp = (now - chrono::Duration::days(days)).to_rfc3339();
+            store_device_data(&db, "alice:robot1", Some("robot1"), "temp", &json!(value), &timestamp, false).unwrap();
+        }
+        set_retention_policy(&db, "alice", None, None, None, 86_400).unwrap();
+
+        assert_eq!(prune_expired(&db, now).await.unwrap(), 2);
+
+        let window = TimeWindow::parse(Some("-30d"), None, None, None, now).unwrap();
+        let stats = get_pulse_stats(&db, "alice:robot1", &window).unwrap();
+        assert_eq!(stats["source"], "day");
+        assert_eq!(stats["total_pulses"], 2);
+        let temp = &stats["stats"][0];
+        assert_eq!(temp["min"], 21.5);
+        assert_eq!(temp["max"], 23.5);
+        assert_eq!(temp["avg"], 22.5);
+        assert_eq!(temp["last_value"], 21.5);
+
+        // A rollup rule prunes whole buckets older than its keep time, here all
+        // buckets up to the end of the day of the older record
+        let older_bucket = Rollup::Day.bucket(&(now - chrono::Duration::days(3)));
+        let older_day = DateTime::parse_from_rfc3339(&older_bucket).unwrap().with_timezone(&Utc);
+        let keep_seconds = (now - (older_day + chrono::Duration::days(1))).num_seconds() as u64;
+        set_retention_policy(&db, "alice", None, None, Some(ROLLUP_TYPE), keep_seconds).unwrap();
+        let rolled_up = prune_expired(&db, now).await.unwrap();
+        assert!(rolled_up >= 3, "the minute, hour and day buckets of the older record");
+        let stats = get_pulse_stats(&db, "alice:robot1", &window).unwrap();
+        assert_eq!(stats["total_pulses"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_rollup_backfill() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let now = Utc::now();
+        for days in 1..=5 {
+            let timestamp = (now - chrono::Duration::days(days)).to_rfc3339();
+            store_device_data(&db, "alice:robot1", Some("robot1"), "temp", &json!(20.0), &timestamp, false).unwrap();
+        }
+        // As if the records were stored before the rollup tables existed
+        db.lock().unwrap().execute_batch(
+            "DELETE FROM rollup_minute; DELETE FROM rollup_hour; DELETE FROM rollup_day;
+             INSERT INTO rollup_backfill (next_id, last_id) SELECT MIN(id), MAX(id) FROM device_data;",
+        ).unwrap();
+
+        assert!(backfill_rollups(&db, 2).unwrap());
+        assert!(backfill_rollups(&db, 2).unwrap());
+        assert!(!backfill_rollups(&db, 2).unwrap());
+        assert!(!backfill_rollups(&db, 2).unwrap());
+
+        let window = TimeWindow::parse(Some("-30d"), None, None, None, now).unwrap();
+        let stats = get_pulse_stats(&db, "alice:robot1", &window).unwrap();
+        assert_eq!(stats["source"], "day");
+        assert_eq!(stats["total_pulses"], 5);
+    }
+
+    #[tokio::test]
+    async fn test_prune_status_transitions() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let now = Utc::now();
+        store_device_data(&db, "alice:robot1", Some("robot1"), "temp", &json!(21.5), &now.to_rfc3339(), false).unwrap();
+        for (status, previous) in [("Online", None), ("Offline", Some("Online")), ("Online", Some("Offline"))] {
+            record_status_transition(&db, "alice:robot1", None, status, previous).unwrap();
+            record_status_transition(&db, "alice:robot1", Some("temp"), status, previous).unwrap();
+        }
+        set_retention_policy(&db, "alice", None, None, None, 30 * 86_400).unwrap();
+        set_retention_policy(&db, "alice", Some("robot1"), Some("temp"), Some("sensor"), 90 * 86_400).unwrap();
+
+        // The sensor rule keeps the data, the rule without a data type prunes the
+        // transitions but the newest one of the device and of the topic
+        let later = now + chrono::Duration::days(60);
+        assert_eq!(prune_expired(&db, later).await.unwrap(), 4);
+
+        let window = TimeWindow::parse(Some("-1d"), None, None, None, later).unwrap();
+        for topic in [None, Some("temp")] {
+            let (initial, transitions) = list_status_transitions(&db, "alice:robot1", topic, &window).unwrap();
+            assert_eq!(initial.as_deref(), Some("Online"));
+            assert!(transitions.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prune_audit() {
+        let db = init_database(":memory:").unwrap();
+        for target in ["robot1", "robot2", "robot3"] {
+            let entry = NewAuditEntry {
+                actor: Some("alice".to_string()),
+                api_key_id: None,
+                org: None,
+                action: "device.delete".to_string(),
+                target: Some(target.to_string()),
+                method: "POST".to_string(),
+                path: "/api/device/delete".to_string(),
+                source_ip: None,
+                status: 200,
+                repeats: 0,
+            };
+            record_audit_entry(&db, &entry).unwrap();
+        }
+        let now = Utc::now();
+        let count = |db: &Database| {
+            let filter = AuditQuery::default().resolve(now).unwrap();
+            list_audit_entries(db, &filter).unwrap().len()
+        };
+
+        // Entries cannot be deleted other than by pruning, which spares newer ones
+        assert!(db.lock().unwrap().execute("DELETE FROM audit_log", []).is_err());
+        assert_eq!(prune_audit(&db, &(now - chrono::Duration::hours(1))).await.unwrap(), 0);
+        assert_eq!(count(&db), 3);
+
+        assert_eq!(prune_audit(&db, &(now + chrono::Duration::hours(1))).await.unwrap(), 3);
+        assert_eq!(count(&db), 0);
+        assert!(db.lock().unwrap().execute("DELETE FROM audit_log", []).is_ok());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::logic::serve::api_keys::Scope;
+use crate::logic::serve::database::{get_user_role, Database};
+use serde::{Deserialize, Serialize};
+
+/// What a user may do with their own devices and data. Each role grants the
+/// API key scopes of the one before it; admins also manage users and roles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Read devices, data, configuration and alerts
+    Viewer,
+    /// Also send pulses and manage devices: credentials, thresholds, retention and maintenance
+    DeviceWriter,
+    /// Also change alert rules, webhooks, email settings and status thresholds
+    Operator,
+    /// Everything, plus users, roles and server-wide statistics
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::DeviceWriter, Role::Operator, Role::Admin];
+
+    /// Role of users registering without the root password
+    pub const DEFAULT: Role = Role::Operator;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::DeviceWriter => "device-writer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Role> {
+        Role::ALL.into_iter().find(|role| role.as_str() == text)
+    }
+
+    /// The API key scopes the role grants on its user's own devices
+    pub fn scopes(&self) -> &'static [Scope] {
+        match self {
+            Role::Viewer => &[Scope::Read],
+            Role::DeviceWriter => &[Scope::Read, Scope::PulseWrite, Scope::DevicesWrite],
+            Role::Operator | Role::Admin => &Scope::ALL,
+        }
+    }
+
+    pub fn grants(&self, scope: Scope) -> bool {
+        self.scopes().contains(&scope)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        *self == Role::Admin
+    }
+}
+
+/// Whether a user's role grants a scope; for listeners outside the HTTP API
+pub fn user_grants(db: &Database, username: &str, scope: Scope) -> bool {
+    matches!(get_user_role(db, username), Ok(Some(role)) if role.grants(scope))
+}
+
+/// Whether a request is for admins only, from its method and path: the
+/// `/api/admin` routes, the user list, deleting users, the UDP counters and
+/// the server-wide status thresholds
+pub fn admin_only(method: &str, path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["api", "admin", ..] | ["api", "udp", "stats"] | ["api", "account", "users"] | ["api", "config", "update"] => true,
+        ["api", "account", username] => method == "DELETE" && *username != "sessions",
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Viewer.grants(Scope::Read) && !Role::Viewer.grants(Scope::PulseWrite));
+        assert!(Role::DeviceWriter.grants(Scope::DevicesWrite) && !Role::DeviceWriter.grants(Scope::ConfigWrite));
+        assert!(Role::Operator.grants(Scope::ConfigWrite) && !Role::Operator.is_admin());
+        assert_eq!(Role::parse("device-writer"), Some(Role::DeviceWriter));
+        assert_eq!(Role::parse("root"), None);
+        assert_eq!(serde_json::to_string(&Role::DeviceWriter).unwrap(), "\"device-writer\"");
+
+        assert!(admin_only("PUT", "/api/admin/users/alice/role"));
+        assert!(admin_only("GET", "/api/account/users"));
+        assert!(admin_only("DELETE", "/api/account/alice"));
+        assert!(!admin_only("DELETE", "/api/account/sessions"));
+        assert!(!admin_only("POST", "/api/account/logout"));
+        assert!(!admin_only("GET", "/api/devices"));
+        assert!(admin_only("POST", "/api/config/update"));
+        assert!(!admin_only("GET", "/api/config"));
+        assert!(!admin_only("POST", "/api/user/config"));
+    }
+}
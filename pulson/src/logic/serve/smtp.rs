@@ -0,0 +1,318 @@
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{self, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+/// Longest time one message may take to send, from connecting to QUIT
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The SMTP server notifications are sent through, from the `[smtp]` table
+/// of the server config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// Port of the server (default: 587)
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Upgrade the connection with STARTTLS before sending anything else (default: true)
+    #[serde(default = "default_starttls")]
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, either `pulson@example.com` or `Pulson <pulson@example.com>`
+    pub from: String,
+    /// Seconds status changes are collected before they are mailed together (default: 60)
+    #[serde(default = "default_digest_seconds")]
+    pub digest_seconds: u64,
+}
+
+fn default_port() -> u16 {
+    587
+}
+
+fn default_starttls() -> bool {
+    true
+}
+
+fn default_digest_seconds() -> u64 {
+    60
+}
+
+/// A plain text email
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub to: Vec<String>,
+    pub subject: String,
+    pub body: String,
+}
+
+impl SmtpConfig {
+    /// Check the settings when the server starts
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.trim().is_empty() {
+            return Err("smtp host is empty".to_string());
+        }
+        if !is_valid_address(self.sender_address()) {
+            return Err(format!("smtp from '{}' is not an email address", self.from));
+        }
+        if self.username.is_some() != self.password.is_some() {
+            return Err("smtp username and password must be set together".to_string());
+        }
+        if self.username.is_some() && !self.starttls {
+            return Err("smtp credentials are only sent over STARTTLS".to_string());
+        }
+        if self.digest_seconds == 0 {
+            return Err("smtp digest_seconds must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// The bare address of `from`, without a display name
+    fn sender_address(&self) -> &str {
+        match (self.from.find('<'), self.from.rfind('>')) {
+            (Some(start), Some(end)) if start < end => self.from[start + 1..end].trim(),
+            _ => self.from.trim(),
+        }
+    }
+
+    /// Deliver a message to the server
+    pub async fn send(&self, message: &Message) -> Result<(), String> {
+        tokio::time::timeout(SEND_TIMEOUT, self.session(message))
+            .await
+            .unwrap_or_else(|_| Err(format!("timed out after {}s", SEND_TIMEOUT.as_secs())))
+    }
+
+    async fn session(&self, message: &Message) -> Result<(), String> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| format!("cannot connect to {}:{}: {}", self.host, self.port, e))?;
+        let mut conn = BufReader::new(stream);
+        expect_reply(&mut conn, &[220]).await?;
+        command(&mut conn, "EHLO localhost", &[250]).await?;
+
+        if !self.starttls {
+            return self.transaction(&mut conn, message).await;
+        }
+        command(&mut conn, "STARTTLS", &[220]).await?;
+        let server_name = ServerName::try_from(self.host.as_str())
+            .map_err(|_| format!("invalid TLS server name '{}'", self.host))?;
+        let stream = tls_connector()
+            .connect(server_name, conn.into_inner())
+            .await
+            .map_err(|e| format!("STARTTLS failed: {}", e))?;
+        let mut conn = BufReader::new(stream);
+        command(&mut conn, "EHLO localhost", &[250]).await?;
+        self.transaction(&mut conn, message).await
+    }
+
+    /// Authenticate if configured, then send the message and quit
+    async fn transaction<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        conn: &mut BufReader<S>,
+        message: &Message,
+    ) -> Result<(), String> {
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            use base64::Engine;
+            let credentials = base64::engine::general_purpose::STANDARD.encode(format!("\0{}\0{}", username, password));
+            command(conn, &format!("AUTH PLAIN {}", credentials), &[235]).await?;
+        }
+
+        command(conn, &format!("MAIL FROM:<{}>", self.sender_address()), &[250]).await?;
+        for recipient in &message.to {
+            command(conn, &format!("RCPT TO:<{}>", recipient), &[250, 251]).await?;
+        }
+        command(conn, "DATA", &[354]).await?;
+        let data = format_message(&self.from, message, chrono::Utc::now());
+        conn.write_all(data.as_bytes()).await.map_err(|e| e.to_string())?;
+        command(conn, ".", &[250]).await?;
+        // The message is accepted; a failing QUIT changes nothing
+        let _ = command(conn, "QUIT", &[221]).await;
+        Ok(())
+    }
+}
+
+/// Whether `address` looks like a mailbox we can put in an SMTP command or header
+pub fn is_valid_address(address: &str) -> bool {
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.is_empty()
+        && address.len() <= 254
+        && !address.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | ',' | ';'))
+}
+
+fn tls_connector() -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Send one command and check the reply code
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut BufReader<S>,
+    line: &str,
+    expected: &[u16],
+) -> Result<String, String> {
+    conn.write_all(format!("{}\r\n", line).as_bytes()).await.map_err(|e| e.to_string())?;
+    conn.flush().await.map_err(|e| e.to_string())?;
+    expect_reply(conn, expected).await.map_err(|e| {
+        // Keep credentials out of the error
+        let verb = line.split(' ').take(2).collect::<Vec<_>>().join(" ");
+        format!("{}: {}", if verb.starts_with("AUTH") { "AUTH" } else { &verb }, e)
+    })
+}
+
+/// Read a (possibly multi-line) reply and check its code
+async fn expect_reply<S: AsyncRead + Unpin>(conn: &mut BufReader<S>, expected: &[u16]) -> Result<String, String> {
+    let mut text = Vec::new();
+    loop {
+        let mut line = String::new();
+        if conn.read_line(&mut line).await.map_err(|e| e.to_string())? == 0 {
+            return Err("connection closed by server".to_string());
+        }
+        let line = line.trim_end();
+        let code: u16 = line.get(..3).and_then(|code| code.parse().ok())
+            .ok_or_else(|| format!("malformed reply '{}'", line))?;
+        text.push(line.get(4..).unwrap_or_default().to_string());
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        let text = text.join(" ");
+        return if expected.contains(&code) {
+            Ok(text)
+        } else {
+            Err(format!("server answered {} {}", code, text))
+        };
+    }
+}
+
+/// Headers and dot-stuffed body of a message, ending in the CRLF that precedes
+/// the terminating `.`
+fn format_message(from: &str, message: &Message, date: chrono::DateTime<chrono::Utc>) -> String {
+    let header = |value: &str| value.replace(['\r', '\n'], " ");
+    let mut data = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@pulson>\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        header(from),
+        header(&message.to.join(", ")),
+        encode_header(&header(&message.subject)),
+        date.to_rfc2822(),
+        uuid::Uuid::new_v4(),
+    );
+    for line in message.body.lines() {
+        if line.starts_with('.') {
+            data.push('.');
+        }
+        data.push_str(line);
+        data.push_str("\r\n");
+    }
+    data
+}
+
+/// RFC 2047 encode a header value that is not plain ASCII
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    use base64::Engine;
+    format!("=?UTF-8?B?{}?=", base64::engine::general_purpose::STANDARD.encode(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Accept one SMTP session, answering everything positively, and return what the client sent
+    async fn sink(listener: TcpListener) -> Vec<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = BufReader::new(stream);
+        let mut transcript = Vec::new();
+        conn.write_all(b"220 sink ready\r\n").await.unwrap();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if conn.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end_matches("\r\n").to_string();
+            let reply: &[u8] = if in_data {
+                if line == "." {
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else {
+                    b""
+                }
+            } else if line.starts_with("EHLO") {
+                b"250-sink\r\n250 8BITMIME\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                b"221 bye\r\n"
+            } else {
+                b"250 ok\r\n"
+            };
+            transcript.push(line);
+            conn.write_all(reply).await.unwrap();
+            if transcript.last().map(String::as_str) == Some("QUIT") {
+                break;
+            }
+        }
+        transcript
+    }
+
+    #[tokio::test]
+    async fn test_send_to_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(sink(listener));
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            starttls: false,
+            username: None,
+            password: None,
+            from: "Pulson <pulson@example.com>".to_string(),
+            digest_seconds: 60,
+        };
+        config.validate().unwrap();
+        let message = Message {
+            to: vec!["ops@example.com".to_string(), "dev@example.com".to_string()],
+            subject: "robot1 is Offline".to_string(),
+            body: "robot1: Online -> Offline\n.hidden line".to_string(),
+        };
+        config.send(&message).await.unwrap();
+
+        let transcript = sink.await.unwrap();
+        assert_eq!(transcript[1], "MAIL FROM:<pulson@example.com>");
+        assert_eq!(transcript[2], "RCPT TO:<ops@example.com>");
+        assert_eq!(transcript[3], "RCPT TO:<dev@example.com>");
+        assert!(transcript.contains(&"Subject: robot1 is Offline".to_string()));
+        assert!(transcript.contains(&"..hidden line".to_string()));
+        assert_eq!(transcript.last().unwrap(), "QUIT");
+    }
+
+    #[test]
+    fn test_config_and_addresses() {
+        assert!(is_valid_address("ops@example.com"));
+        assert!(!is_valid_address("ops@example.com>\r\nRCPT TO:<x@y"));
+        assert!(!is_valid_address("ops"));
+        assert_eq!(encode_header("Offline"), "Offline");
+        assert_eq!(encode_header("Röbot"), "=?UTF-8?B?UsO2Ym90?=");
+
+        let config: SmtpConfig = toml::from_str("host = \"smtp.example.com\"\nfrom = \"pulson@example.com\"\nusername = \"u\"").unwrap();
+        assert_eq!((config.port, config.starttls, config.digest_seconds), (587, true, 60));
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::logic::config::{learned_thresholds, StatusConfig, ThresholdOverride};
+use crate::logic::serve::database::{
+    get_user_config_or_default, latest_status_transitions, list_device_activity, list_learned_status_overrides,
+    observed_publish_interval, record_learned_interval, record_status_transition, Database,
+};
+use crate::logic::serve::events::{publish, split_device_id, EventBus, LiveEvent};
+use crate::logic::types::{DeviceStatus, TopicStatus};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use warp::http::StatusCode;
+
+/// How often device and topic statuses are recomputed to catch devices going quiet
+const STATUS_CHECK_INTERVAL_SECS: u64 = 5;
+
+/// How often learned thresholds are re-derived from recent publish intervals
+const LEARN_INTERVAL_SECS: u64 = 60;
+
+/// Intervals between records a learned threshold is based on
+const LEARN_SAMPLES: usize = 100;
+
+/// Start the background task that publishes and records device and topic status transitions.
+///
+/// Statuses degrade with time alone, so a periodic sweep recomputes them from the
+/// database. Incoming data is also watched so that a device coming back online is
+/// reported immediately rather than on the next sweep.
+pub fn spawn_status_monitor(db: Database, events: EventBus) {
+    tokio::spawn(async move {
+        StatusMonitor::new(db, events).run().await;
+    });
+}
+
+struct StatusMonitor {
+    db: Database,
+    events: EventBus,
+    /// Last known status per namespaced device id
+    devices: HashMap<String, DeviceStatus>,
+    /// Last known status per (namespaced device id, topic)
+    topics: HashMap<(String, String), TopicStatus>,
+}
+
+impl StatusMonitor {
+    /// Start from the last recorded statuses, so changes while the server was
+    /// down are recorded by the first sweep
+    fn new(db: Database, events: EventBus) -> Self {
+        let mut devices = HashMap::new();
+        let mut topics = HashMap::new();
+        for ((device_id, topic), status) in latest_status_transitions(&db).unwrap_or_default() {
+            let status = serde_json::Value::String(status);
+            if topic.is_empty() {
+                if let Ok(status) = serde_json::from_value(status) {
+                    devices.insert(device_id, status);
+                }
+            } else if let Ok(status) = serde_json::from_value(status) {
+                topics.insert((device_id, topic), status);
+            }
+        }
+        Self { db, events, devices, topics }
+    }
+
+    /// Persist a transition for uptime reports
+    fn record<S: std::fmt::Debug>(&self, device_id: &str, topic: Option<&str>, status: &S, previous_status: Option<&S>) {
+        let previous_status = previous_status.map(|s| format!("{:?}", s));
+        if record_status_transition(&self.db, device_id, topic, &format!("{:?}", status), previous_status.as_deref()).is_err() {
+            eprintln!("Status monitor: failed to record the status of {}", device_id);
+        }
+    }
+
+    async fn run(mut self) {
+        let mut rx = self.events.subscribe();
+        let mut ticker = tokio::time::interval(Duration::from_secs(STATUS_CHECK_INTERVAL_SECS));
+        let mut learn_ticker = tokio::time::interval(Duration::from_secs(LEARN_INTERVAL_SECS));
+        // The first sweep only learns the current state; there is nothing to compare with yet
+        let mut initialized = false;
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    self.sweep(initialized);
+                    initialized = true;
+                }
+                _ = learn_ticker.tick() => {
+                    if refresh_learned_thresholds(&self.db).is_err() {
+                        eprintln!("Status monitor: failed to refresh learned thresholds");
+                    }
+                }
+                event = rx.recv() => match event {
+                    Ok(LiveEvent::Data { owner, device_id, topic, timestamp, .. }) => {
+                        self.on_data(&owner, &device_id, &topic, &timestamp);
+                    }
+                    Ok(_) => {}
+                    // Missed data events are picked up by the next sweep
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
+    }
+
+    /// Recompute every status from the database and publish the ones that changed
+    fn sweep(&mut self, emit: bool) {
+        let activity = match list_device_activity(&self.db) {
+            Ok(activity) => activity,
+            Err(_) => {
+                eprintln!("Status monitor: failed to load device activity");
+                return;
+            }
+        };
+
+        let mut configs: HashMap<String, StatusConfig> = HashMap::new();
+        let mut seen_devices = HashSet::new();
+        let mut seen_topics = HashSet::new();
+
+        for device in activity {
+            let Some((owner, display_id)) = split_device_id(&device.device_id) else {
+                continue;
+            };
+            let config = configs
+                .entry(owner.to_string())
+                .or_insert_with(|| get_user_config_or_default(&self.db, owner))
+                .clone();
+
+            if let Some(last_seen) = parse_timestamp(&device.last_seen) {
+                let status = config.calculate_device_status(display_id, &last_seen);
+                let previous = self.devices.insert(device.device_id.clone(), status.clone());
+                let changed = previous.as_ref() != Some(&status);
+                if changed {
+                    self.record(&device.device_id, None, &status, previous.as_ref());
+                }
+                if emit && changed {
+                    publish(&self.events, LiveEvent::DeviceStatus {
+                        owner: owner.to_string(),
+                        device_id: display_id.to_string(),
+                        status,
+                        previous_status: previous,
+                        last_seen: device.last_seen.clone(),
+                    });
+                }
+            }
+
+            for (topic, topic_last_seen) in &device.topics {
+                let Some(last_seen) = parse_timestamp(topic_last_seen) else {
+                    continue;
+                };
+                let status = config.calculate_topic_status(display_id, topic, &last_seen);
+                let key = (device.device_id.clone(), topic.clone());
+                let previous = self.topics.insert(key.clone(), status.clone());
+                let changed = previous.as_ref() != Some(&status);
+                if changed {
+                    self.record(&device.device_id, Some(topic), &status, previous.as_ref());
+                }
+                if emit && changed {
+                    publish(&self.events, LiveEvent::TopicStatus {
+                        owner: owner.to_string(),
+                        device_id: display_id.to_string(),
+                        topic: topic.clone(),
+                        status,
+                        previous_status: previous,
+                        last_seen: topic_last_seen.clone(),
+                    });
+                }
+                seen_topics.insert(key);
+            }
+
+            seen_devices.insert(device.device_id);
+        }
+
+        // Forget deleted devices and topics
+        self.devices.retain(|id, _| seen_devices.contains(id));
+        self.topics.retain(|key, _| seen_topics.contains(key));
+    }
+
+    /// Report a device or topic that became fresher because of a newly stored record
+    fn on_data(&mut self, owner: &str, device_id: &str, topic: &str, timestamp: &str) {
+        let full_device_id = format!("{}:{}", owner, device_id);
+        let topic_key = (full_device_id.clone(), topic.to_string());
+        let device_previous = self.devices.get(&full_device_id).cloned();
+        let topic_previous = self.topics.get(&topic_key).cloned();
+
+        // Fast path for the common case of a healthy device sending more data
+        if device_previous == Some(DeviceStatus::Online) && topic_previous == Some(TopicStatus::Active) {
+            return;
+        }
+        let Some(last_seen) = parse_timestamp(timestamp) else {
+            return;
+        };
+        let config = get_user_config_or_default(&self.db, owner);
+
+        // Backfilled data never makes a known device look older than it is
+        let status = config.calculate_device_status(device_id, &last_seen);
+        if device_previous.as_ref().is_none_or(|prev| device_rank(&status) < device_rank(prev)) {
+            self.record(&full_device_id, None, &status, device_previous.as_ref());
+            self.devices.insert(full_device_id.clone(), status.clone());
+            publish(&self.events, LiveEvent::DeviceStatus {
+                owner: owner.to_string(),
+                device_id: device_id.to_string(),
+                status,
+                previous_status: device_previous,
+                last_seen: timestamp.to_string(),
+            });
+        }
+
+        let status = config.calculate_topic_status(device_id, topic, &last_seen);
+        if topic_previous.as_ref().is_none_or(|prev| topic_rank(&status) < topic_rank(prev)) {
+            self.record(&full_device_id, Some(topic), &status, topic_previous.as_ref());
+            self.topics.insert(topic_key, status.clone());
+            publish(&self.events, LiveEvent::TopicStatus {
+                owner: owner.to_string(),
+                device_id: device_id.to_string(),
+                topic: topic.to_string(),
+                status,
+                previous_status: topic_previous,
+                last_seen: timestamp.to_string(),
+            });
+        }
+    }
+}
+
+/// Device statuses by namespaced device id
+type DeviceStatuses = HashMap<String, DeviceStatus>;
+/// Topic statuses by (namespaced device id, topic)
+type TopicStatuses = HashMap<(String, String), TopicStatus>;
+
+/// The status of every device and topic right now, for consumers that need a
+/// starting point before following the published transitions
+pub fn current_statuses(db: &Database) -> Result<(DeviceStatuses, TopicStatuses), StatusCode> {
+    let mut configs: HashMap<String, StatusConfig> = HashMap::new();
+    let mut devices = HashMap::new();
+    let mut topics = HashMap::new();
+
+    for device in list_device_activity(db)? {
+        let Some((owner, display_id)) = split_device_id(&device.device_id) else {
+            continue;
+        };
+        let config = configs
+            .entry(owner.to_string())
+            .or_insert_with(|| get_user_config_or_default(db, owner));
+
+        if let Some(last_seen) = parse_timestamp(&device.last_seen) {
+            devices.insert(device.device_id.clone(), config.calculate_device_status(display_id, &last_seen));
+        }
+        for (topic, topic_last_seen) in &device.topics {
+            if let Some(last_seen) = parse_timestamp(topic_last_seen) {
+                let status = config.calculate_topic_status(display_id, topic, &last_seen);
+                topics.insert((device.device_id.clone(), topic.clone()), status);
+            }
+        }
+    }
+
+    Ok((devices, topics))
+}
+
+/// Re-derive the thresholds of every learned override from how often its device
+/// or topic published recently, returning how many changed
+pub fn refresh_learned_thresholds(db: &Database) -> Result<usize, StatusCode> {
+    let mut changed = 0;
+    for (username, learned) in list_learned_status_overrides(db)? {
+        if refresh_learned_override(db, &username, &learned)? {
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Re-derive the thresholds of one learned override of `username`, returning
+/// whether they changed
+pub fn refresh_learned_override(db: &Database, username: &str, learned: &ThresholdOverride) -> Result<bool, StatusCode> {
+    // Learned overrides always name a device
+    let Some(device_id) = learned.device_id.as_deref() else {
+        return Ok(false);
+    };
+    let full_device_id = format!("{}:{}", username, device_id);
+    let Some(interval) = observed_publish_interval(db, &full_device_id, learned.topic.as_deref(), LEARN_SAMPLES)? else {
+        return Ok(false);
+    };
+    if learned.observed_interval_seconds == Some(interval) {
+        return Ok(false);
+    }
+    record_learned_interval(db, learned.id, interval, learned_thresholds(interval))?;
+    Ok(true)
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+/// Lower is fresher
+fn device_rank(status: &DeviceStatus) -> u8 {
+    match status {
+        DeviceStatus::Online => 0,
+        DeviceStatus::Warning => 1,
+        DeviceStatus::Offline => 2,
+        // Only the sweep moves a device into maintenance
+        DeviceStatus::Maintenance => 3,
+    }
+}
+
+/// Lower is fresher
+fn topic_rank(status: &TopicStatus) -> u8 {
+    match status {
+        TopicStatus::Active => 0,
+        TopicStatus::Recent => 1,
+        TopicStatus::Stale => 2,
+        TopicStatus::Inactive => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::database::{create_user, init_database, set_status_override, store_device_data};
+    use crate::logic::serve::roles::Role;
+    use serde_json::json;
+
+    #[test]
+    fn test_learned_thresholds_follow_publish_interval() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let now = Utc::now();
+        for i in 0..10 {
+            let timestamp = (now - chrono::Duration::seconds(60 * i)).to_rfc3339();
+            store_device_data(&db, "alice:robot1", None, "battery", &json!(null), &timestamp, false).unwrap();
+        }
+
+        let learned = set_status_override(&db, "alice", Some("robot1"), Some("battery"), None).unwrap();
+        assert!(learned.learned && learned.online_threshold_seconds.is_none());
+        assert_eq!(refresh_learned_thresholds(&db).unwrap(), 1);
+        assert_eq!(refresh_learned_thresholds(&db).unwrap(), 0);
+
+        let config = get_user_config_or_default(&db, "alice");
+        assert_eq!(config.overrides[0].observed_interval_seconds, Some(60.0));
+        assert_eq!(config.overrides[0].online_threshold_seconds, Some(180));
+        let two_minutes_ago = now - chrono::Duration::seconds(120);
+        assert_eq!(config.calculate_topic_status("robot1", "battery", &two_minutes_ago), TopicStatus::Active);
+        assert_eq!(config.calculate_topic_status("robot1", "odom", &two_minutes_ago), TopicStatus::Recent);
+    }
+}
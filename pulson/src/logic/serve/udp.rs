@@ -0,0 +1,383 @@
+use crate::logic::serve::api::token_service::validate_token;
+use crate::logic::serve::api_keys::Scope;
+use crate::logic::serve::database::{get_device_secret, Database};
+use crate::logic::serve::ingest::{decode_payload, IncomingPulse, IngestError, Ingestor};
+use crate::logic::serve::roles::user_grants;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+
+/// Largest payload a UDP datagram can carry
+const MAX_DATAGRAM_BYTES: usize = 65_507;
+
+/// Datagrams waiting to be stored. When the database falls behind, further
+/// datagrams are dropped instead of piling up in memory.
+const QUEUE_CAPACITY: usize = 4096;
+
+/// How far the time in a signed datagram may be from the server clock
+const SIGNED_FRESHNESS_SECONDS: i64 = 60;
+
+/// Counters of the UDP listener, served by `GET /api/udp/stats`
+#[derive(Default)]
+pub struct UdpStats {
+    enabled: AtomicBool,
+    received: AtomicU64,
+    stored: AtomicU64,
+    malformed: AtomicU64,
+    unauthorized: AtomicU64,
+    limited: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UdpStatsSnapshot {
+    pub enabled: bool,
+    pub received: u64,
+    pub stored: u64,
+    pub malformed: u64,
+    pub unauthorized: u64,
+    pub limited: u64,
+    pub dropped: u64,
+}
+
+impl UdpStats {
+    pub fn snapshot(&self) -> UdpStatsSnapshot {
+        UdpStatsSnapshot {
+            enabled: self.enabled.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            stored: self.stored.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+            unauthorized: self.unauthorized.load(Ordering::Relaxed),
+            limited: self.limited.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, PartialEq)]
+enum Auth {
+    /// `t:<token>` - a pulson token sent in the clear, for trusted networks only
+    Token(String),
+    /// `h:<username>:<unix time>:<hex>` - HMAC-SHA256 of the time, a space and the
+    /// rest of the datagram, keyed with the device secret from `pulson device
+    /// credentials`
+    Hmac { username: String, sent_at: i64, mac: Vec<u8> },
+}
+
+#[derive(Debug, PartialEq)]
+struct Datagram {
+    auth: Auth,
+    device_id: String,
+    topic: String,
+    data: Option<Value>,
+}
+
+/// Receive datagrams until the socket fails. Each datagram is
+///
+/// ```text
+/// <auth> <device_id> <topic> [f32]\n<payload>
+/// ```
+///
+/// Nothing is sent back: outcomes are only visible through the counters.
+pub async fn serve(socket: UdpSocket, ingestor: Ingestor, stats: Arc<UdpStats>) {
+    stats.enabled.store(true, Ordering::Relaxed);
+    let (tx, rx) = mpsc::channel::<Vec<u8>>(QUEUE_CAPACITY);
+    tokio::spawn(store_datagrams(rx, ingestor, stats.clone()));
+
+    let mut buf = vec![0u8; MAX_DATAGRAM_BYTES];
+    loop {
+        match socket.recv_from(&mut buf).await {
+            Ok((len, _peer)) => {
+                bump(&stats.received);
+                if tx.try_send(buf[..len].to_vec()).is_err() {
+                    bump(&stats.dropped);
+                }
+            }
+            Err(e) => eprintln!("UDP receive failed: {}", e),
+        }
+    }
+}
+
+/// Signatures of recently accepted datagrams, so none is stored twice while
+/// its time is still fresh
+#[derive(Default)]
+struct ReplayGuard {
+    seen: HashSet<Vec<u8>>,
+    /// Accepted signatures with the server time they arrived, oldest first
+    arrivals: VecDeque<(i64, Vec<u8>)>,
+}
+
+impl ReplayGuard {
+    /// Remember a signature; false if it was seen before
+    fn first_use(&mut self, mac: &[u8], now: i64) -> bool {
+        // A signature stays fresh until at most twice the window after arrival
+        while self.arrivals.front().is_some_and(|(at, _)| *at < now - 2 * SIGNED_FRESHNESS_SECONDS) {
+            if let Some((_, old)) = self.arrivals.pop_front() {
+                self.seen.remove(&old);
+            }
+        }
+        if !self.seen.insert(mac.to_vec()) {
+            return false;
+        }
+        self.arrivals.push_back((now, mac.to_vec()));
+        true
+    }
+}
+
+async fn store_datagrams(mut rx: mpsc::Receiver<Vec<u8>>, ingestor: Ingestor, stats: Arc<UdpStats>) {
+    let mut replays = ReplayGuard::default();
+    while let Some(bytes) = rx.recv().await {
+        let Ok(datagram) = parse_datagram(&bytes) else {
+            bump(&stats.malformed);
+            continue;
+        };
+        let now = chrono::Utc::now().timestamp();
+        let Some(username) = authenticate(&ingestor.db, &datagram, &bytes, now) else {
+            bump(&stats.unauthorized);
+            continue;
+        };
+        if let Auth::Hmac { mac, .. } = &datagram.auth {
+            if !replays.first_use(mac, now) {
+                bump(&stats.unauthorized);
+                continue;
+            }
+        }
+
+        let pulse = IncomingPulse {
+            device_id: datagram.device_id,
+            topic: datagram.topic,
+            data: datagram.data,
+            timestamp: None,
+        };
+        match ingestor.ingest(&username, pulse) {
+            Ok(()) => bump(&stats.stored),
+            Err(IngestError::Invalid(_)) => bump(&stats.malformed),
+            Err(IngestError::Storage(_)) => bump(&stats.dropped),
+            Err(IngestError::Limited(_)) => bump(&stats.limited),
+        }
+    }
+}
+
+/// Return the user a datagram is stored for, if its credentials hold and a
+/// signed one was sent within the freshness window of `now`
+fn authenticate(db: &Database, datagram: &Datagram, bytes: &[u8], now: i64) -> Option<String> {
+    match &datagram.auth {
+        Auth::Token(token) => validate_token(db, token)
+            .ok()
+            .filter(|username| user_grants(db, username, Scope::PulseWrite)),
+        Auth::Hmac { username, sent_at, mac } => {
+            if (now - sent_at).abs() > SIGNED_FRESHNESS_SECONDS {
+                return None;
+            }
+            let full_device_id = format!("{}:{}", username, datagram.device_id);
+            let secret = get_device_secret(db, &full_device_id).ok()??;
+            let mut expected = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+            expected.update(format!("{} ", sent_at).as_bytes());
+            expected.update(signed_part(bytes));
+            expected.verify_slice(mac).ok()?;
+            Some(username.clone())
+        }
+    }
+}
+
+/// Everything after the auth field; an HMAC signs this after the time
+fn signed_part(bytes: &[u8]) -> &[u8] {
+    match bytes.iter().position(|&b| b == b' ') {
+        Some(space) => &bytes[space + 1..],
+        None => &[],
+    }
+}
+
+fn parse_datagram(bytes: &[u8]) -> Result<Datagram, String> {
+    let header_end = bytes.iter().position(|&b| b == b'\n').unwrap_or(bytes.len());
+    let header = std::str::from_utf8(&bytes[..header_end]).map_err(|_| "header is not UTF-8".to_string())?;
+    let payload = bytes.get(header_end + 1..).unwrap_or_default();
+
+    let mut fields = header.trim_end_matches('\r').split(' ');
+    let auth = parse_auth(fields.next().unwrap_or_default())?;
+    let device_id = fields.next().filter(|f| !f.is_empty()).ok_or("missing device id")?;
+    let topic = fields.next().filter(|f| !f.is_empty()).ok_or("missing topic")?;
+    let data = match fields.next() {
+        None => decode_payload(payload)?,
+        Some("f32") => decode_f32(payload)?,
+        Some(other) => return Err(format!("unknown payload encoding '{}'", other)),
+    };
+    if fields.next().is_some() {
+        return Err("too many header fields".to_string());
+    }
+
+    Ok(Datagram {
+        auth,
+        device_id: device_id.to_string(),
+        topic: topic.to_string(),
+        data,
+    })
+}
+
+fn parse_auth(field: &str) -> Result<Auth, String> {
+    if let Some(token) = field.strip_prefix("t:") {
+        if !token.is_empty() {
+            return Ok(Auth::Token(token.to_string()));
+        }
+    }
+    let signed = field.strip_prefix("h:").and_then(|rest| {
+        let (rest, mac) = rest.rsplit_once(':')?;
+        let (username, sent_at) = rest.rsplit_once(':')?;
+        Some((username, sent_at, mac))
+    });
+    if let Some((username, sent_at, mac)) = signed {
+        let sent_at = sent_at.parse().map_err(|_| "signed time is not a number".to_string())?;
+        let mac = hex::decode(mac).map_err(|_| "HMAC is not hex".to_string())?;
+        if !username.is_empty() {
+            return Ok(Auth::Hmac { username: username.to_string(), sent_at, mac });
+        }
+    }
+    Err("auth must be t:<token> or h:<username>:<time>:<hmac>".to_string())
+}
+
+/// Little-endian `f32` values: one becomes a number, several an array
+fn decode_f32(payload: &[u8]) -> Result<Option<Value>, String> {
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    if !payload.len().is_multiple_of(4) {
+        return Err("f32 payload length must be a multiple of 4".to_string());
+    }
+    let values = payload
+        .chunks_exact(4)
+        .map(|chunk| {
+            let value = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            serde_json::Number::from_f64(value as f64)
+                .map(Value::Number)
+                .ok_or_else(|| "f32 payload contains a non-finite value".to_string())
+        })
+        .collect::<Result<Vec<Value>, String>>()?;
+
+    Ok(Some(match <[Value; 1]>::try_from(values) {
+        Ok([value]) => value,
+        Err(values) => Value::Array(values),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::api::token_service::hash_token;
+    use crate::logic::serve::database::{create_session, create_user, get_device_latest_data, init_database, set_device_credential};
+    use crate::logic::serve::roles::Role;
+    use crate::logic::serve::db_types::DataPage;
+    use crate::logic::serve::events::new_event_bus;
+    use crate::logic::serve::ingest::IngestOptions;
+    use crate::logic::serve::rate_limit::{RateLimitConfig, RateLimiter};
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// A datagram signed with `secret` as sent at `sent_at`
+    fn signed_datagram(secret: &str, sent_at: i64, signed: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{} ", sent_at).as_bytes());
+        mac.update(signed);
+        let mut datagram = format!("h:alice:{}:{} ", sent_at, hex::encode(mac.finalize().into_bytes())).into_bytes();
+        datagram.extend_from_slice(signed);
+        datagram
+    }
+
+    #[test]
+    fn test_parse_datagram() {
+        let datagram = parse_datagram(b"t:abc robot1 temp\n{\"value\": 21.5}").unwrap();
+        assert_eq!(
+            datagram,
+            Datagram {
+                auth: Auth::Token("abc".to_string()),
+                device_id: "robot1".to_string(),
+                topic: "temp".to_string(),
+                data: Some(json!({ "value": 21.5 })),
+            }
+        );
+
+        let ping = parse_datagram(b"h:alice:1700000000:00ff robot1 heartbeat").unwrap();
+        assert_eq!(ping.auth, Auth::Hmac { username: "alice".to_string(), sent_at: 1_700_000_000, mac: vec![0x00, 0xff] });
+        assert_eq!(ping.data, None);
+
+        assert!(parse_datagram(b"t:abc robot1").is_err());
+        assert!(parse_datagram(b"x:abc robot1 temp\n1").is_err());
+        assert!(parse_datagram(b"h:alice:1700000000:zz robot1 temp\n1").is_err());
+        assert!(parse_datagram(b"h:alice:00ff robot1 temp\n1").is_err());
+        assert!(parse_datagram(b"t:abc robot1 temp i8\n1").is_err());
+    }
+
+    #[test]
+    fn test_decode_f32() {
+        let mut payload = 21.5f32.to_le_bytes().to_vec();
+        assert_eq!(decode_f32(&payload), Ok(Some(json!(21.5))));
+
+        payload.extend(1.0f32.to_le_bytes());
+        assert_eq!(decode_f32(&payload), Ok(Some(json!([21.5, 1.0]))));
+
+        assert!(decode_f32(&payload[..5]).is_err());
+        assert!(decode_f32(&f32::NAN.to_le_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_datagrams_are_stored_and_counted() {
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        create_session(&db, &hash_token("alice-token"), &hash_token("alice-refresh"), "alice", None).unwrap();
+        set_device_credential(&db, "alice:robot2", "s3cret").unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let stats = Arc::new(UdpStats::default());
+        let options = IngestOptions { save_images: false, max_clock_skew_seconds: 60 };
+        let ingestor = Ingestor::new(db.clone(), new_event_bus(), options, RateLimiter::new(RateLimitConfig::default()));
+        tokio::spawn(serve(socket, ingestor, stats.clone()));
+
+        let signed = b"robot2 temp f32\n\x00\x00\xac\x41";
+        let now = chrono::Utc::now().timestamp();
+        let hmac_datagram = signed_datagram("s3cret", now, signed);
+        let forged = signed_datagram("guess", now, signed);
+        let stale = signed_datagram("s3cret", now - 600, signed);
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for datagram in [
+            b"t:alice-token robot1 temp\n21.5".to_vec(),
+            hmac_datagram.clone(),
+            hmac_datagram,
+            forged,
+            stale,
+            b"t:wrong-token robot1 temp\n1".to_vec(),
+            b"garbage".to_vec(),
+        ] {
+            client.send_to(&datagram, addr).await.unwrap();
+        }
+
+        for _ in 0..100 {
+            let snapshot = stats.snapshot();
+            if snapshot.stored + snapshot.unauthorized + snapshot.malformed == 7 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let snapshot = stats.snapshot();
+        assert!(snapshot.enabled);
+        assert_eq!(snapshot.received, 7);
+        assert_eq!(snapshot.stored, 2);
+        assert_eq!(snapshot.unauthorized, 4);
+        assert_eq!(snapshot.malformed, 1);
+        assert_eq!(snapshot.dropped, 0);
+
+        let robot2 = get_device_latest_data(&db, "alice:robot2", Some("temp"), None, &DataPage::default()).unwrap();
+        assert_eq!(robot2["data"].as_array().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::logic::serve::database::StatusTransition;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Availability of a device or topic over a window, worked out from its
+/// recorded status transitions. Time before the first recorded status and time
+/// in maintenance are not observed and count neither as up nor as down.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UptimeReport {
+    pub from: String,
+    pub to: String,
+    /// Status at the end of the window
+    pub status: Option<String>,
+    pub observed_seconds: f64,
+    pub uptime_seconds: f64,
+    pub downtime_seconds: f64,
+    /// Share of the observed time that was up, in percent
+    pub availability_percent: Option<f64>,
+    /// Outages overlapping the window, including one already going on at its start
+    pub outage_count: usize,
+    pub longest_outage_seconds: Option<f64>,
+    /// Longest stretch of uptime between outages
+    pub longest_time_between_failures_seconds: Option<f64>,
+    /// Uptime divided by the number of failures that started inside the window
+    pub mean_time_between_failures_seconds: Option<f64>,
+}
+
+/// Whether a status counts as down: an Offline device, or a topic that went
+/// Stale or Inactive (both past the warning threshold, like Offline)
+pub fn is_down(status: &str) -> bool {
+    matches!(status, "Offline" | "Stale" | "Inactive")
+}
+
+const MAINTENANCE: &str = "Maintenance";
+
+/// Build the report for `[from, to)`; `initial` is the status at `from` and
+/// `transitions` are the changes after it, oldest first
+pub fn uptime_report(
+    initial: Option<&str>,
+    transitions: &[StatusTransition],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> UptimeReport {
+    let mut report = UptimeReport {
+        from: from.to_rfc3339(),
+        to: to.to_rfc3339(),
+        status: initial.map(str::to_string),
+        observed_seconds: 0.0,
+        uptime_seconds: 0.0,
+        downtime_seconds: 0.0,
+        availability_percent: None,
+        outage_count: 0,
+        longest_outage_seconds: None,
+        longest_time_between_failures_seconds: None,
+        mean_time_between_failures_seconds: None,
+    };
+    let mut failures = 0;
+    // (down, seconds) of the run of equal up/down state in progress
+    let mut run: Option<(bool, f64)> = initial.filter(|s| *s != MAINTENANCE).map(|status| (is_down(status), 0.0));
+    // A device still down when its maintenance ends failed, too
+    let mut after_maintenance = initial == Some(MAINTENANCE);
+    if run.is_some_and(|(down, _)| down) {
+        report.outage_count = 1;
+    }
+    let mut cursor = from;
+
+    let close = |report: &mut UptimeReport, run: Option<(bool, f64)>| {
+        let (longest, seconds) = match run {
+            Some((true, seconds)) => (&mut report.longest_outage_seconds, seconds),
+            Some((false, seconds)) => (&mut report.longest_time_between_failures_seconds, seconds),
+            None => return,
+        };
+        *longest = Some(longest.map_or(seconds, |longest| longest.max(seconds)));
+    };
+
+    let changes = transitions.iter().filter_map(|transition| {
+        let at = DateTime::parse_from_rfc3339(&transition.at).ok()?.with_timezone(&Utc);
+        (at > from && at < to).then_some((at, transition.status.as_str()))
+    });
+    for (at, status) in changes.map(|(at, status)| (Some(at), Some(status))).chain([(None, None)]) {
+        let until = at.unwrap_or(to).max(cursor);
+        let seconds = (until - cursor).num_milliseconds() as f64 / 1000.0;
+        cursor = until;
+        if let Some((down, elapsed)) = run.as_mut() {
+            *elapsed += seconds;
+            report.observed_seconds += seconds;
+            if *down {
+                report.downtime_seconds += seconds;
+            } else {
+                report.uptime_seconds += seconds;
+            }
+        }
+
+        let Some(status) = status else {
+            continue;
+        };
+        report.status = Some(status.to_string());
+        if status == MAINTENANCE {
+            close(&mut report, run);
+            run = None;
+            after_maintenance = true;
+            continue;
+        }
+        let down = is_down(status);
+        if run.is_some_and(|(was_down, _)| was_down == down) {
+            continue;
+        }
+        if down {
+            report.outage_count += 1;
+            if run.is_some() || after_maintenance {
+                failures += 1;
+            }
+        }
+        after_maintenance = false;
+        close(&mut report, run);
+        run = Some((down, 0.0));
+    }
+    close(&mut report, run);
+
+    if report.observed_seconds > 0.0 {
+        report.availability_percent = Some(100.0 * report.uptime_seconds / report.observed_seconds);
+    }
+    if failures > 0 {
+        report.mean_time_between_failures_seconds = Some(report.uptime_seconds / failures as f64);
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-06-01T12:00:00Z").unwrap().with_timezone(&Utc)
+            + chrono::Duration::minutes(minutes)
+    }
+
+    fn transition(minutes: i64, status: &str) -> StatusTransition {
+        StatusTransition { status: status.to_string(), previous_status: None, at: at(minutes).to_rfc3339() }
+    }
+
+    #[test]
+    fn test_uptime_report() {
+        // Up 0-10, Offline 10-15, Warning (still up) 15-20, Online 20-50, Offline 50-60
+        let transitions = [
+            transition(10, "Offline"),
+            transition(15, "Warning"),
+            transition(20, "Online"),
+            transition(50, "Offline"),
+        ];
+        let report = uptime_report(Some("Online"), &transitions, at(0), at(60));
+        assert_eq!(report.status.as_deref(), Some("Offline"));
+        assert_eq!((report.observed_seconds, report.uptime_seconds, report.downtime_seconds), (3600.0, 2700.0, 900.0));
+        assert_eq!(report.availability_percent, Some(75.0));
+        assert_eq!(report.outage_count, 2);
+        assert_eq!(report.longest_outage_seconds, Some(600.0));
+        assert_eq!(report.longest_time_between_failures_seconds, Some(2100.0));
+        assert_eq!(report.mean_time_between_failures_seconds, Some(1350.0));
+    }
+
+    #[test]
+    fn test_uptime_report_edges() {
+        // Nothing recorded before minute 30, and an outage going on from the start of a later window
+        let report = uptime_report(None, &[transition(30, "Active")], at(0), at(60));
+        assert_eq!((report.observed_seconds, report.availability_percent), (1800.0, Some(100.0)));
+        assert_eq!((report.outage_count, report.mean_time_between_failures_seconds), (0, None));
+
+        let report = uptime_report(Some("Inactive"), &[transition(45, "Active")], at(30), at(60));
+        assert_eq!(report.availability_percent, Some(50.0));
+        assert_eq!((report.outage_count, report.mean_time_between_failures_seconds), (1, None));
+
+        // Maintenance is left out, but still being down after it is a failure
+        let maintenance = [transition(10, "Maintenance"), transition(40, "Offline"), transition(50, "Online")];
+        let report = uptime_report(Some("Online"), &maintenance, at(0), at(60));
+        assert_eq!((report.observed_seconds, report.uptime_seconds), (1800.0, 1200.0));
+        assert_eq!((report.outage_count, report.mean_time_between_failures_seconds), (1, Some(1200.0)));
+
+        let report = uptime_report(None, &[], at(0), at(60));
+        assert_eq!((report.observed_seconds, report.availability_percent, report.status), (0.0, None, None));
+    }
+}
@@ -0,0 +1,572 @@
+use crate::logic::serve::database::{
+    create_webhook_delivery, get_webhook, list_enabled_webhooks, list_pending_webhook_deliveries,
+    record_webhook_attempt, Database, Webhook, WebhookDelivery,
+};
+use crate::logic::serve::events::{EventBus, LiveEvent};
+use crate::logic::serve::maintenance::event_silenced;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use warp::hyper::client::connect::dns::Name;
+
+/// Live events a webhook can subscribe to; stored data is never pushed
+pub const WEBHOOK_EVENTS: [&str; 3] = ["device_status", "topic_status", "alert"];
+
+/// Longest accepted webhook name
+const MAX_WEBHOOK_NAME_LEN: usize = 100;
+
+/// Most custom headers per webhook
+const MAX_WEBHOOK_HEADERS: usize = 20;
+
+/// Headers set by pulson itself, which custom headers may not replace
+const RESERVED_HEADERS: [&str; 5] = ["content-type", "content-length", "host", "x-pulson-event", "x-pulson-signature"];
+
+/// Webhook settings, the `[webhooks]` table of the server config
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Hosts (names or IP addresses) webhooks may reach although they are, or
+    /// resolve to, loopback, private or link-local addresses
+    pub allowed_private_hosts: Vec<String>,
+}
+
+impl WebhookConfig {
+    /// Whether a webhook may connect to `addr`, which `host` is or resolved to
+    fn allows(&self, host: &str, addr: IpAddr) -> bool {
+        is_public(addr)
+            || self
+                .allowed_private_hosts
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(host) || allowed.parse::<IpAddr>() == Ok(addr))
+    }
+
+    /// Resolve the host of a webhook url and refuse it when any of its
+    /// addresses is one webhooks may not reach
+    pub async fn check_url(&self, url: &str) -> Result<(), String> {
+        let url = url::Url::parse(url).map_err(|e| format!("invalid url: {}", e))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        let (host, addrs): (String, Vec<IpAddr>) = match url.host() {
+            Some(url::Host::Ipv4(ip)) => (ip.to_string(), vec![ip.into()]),
+            Some(url::Host::Ipv6(ip)) => (ip.to_string(), vec![ip.into()]),
+            Some(url::Host::Domain(domain)) => {
+                let addrs = tokio::net::lookup_host((domain, port))
+                    .await
+                    .map_err(|_| format!("cannot resolve host '{}'", domain))?;
+                (domain.to_string(), addrs.map(|addr| addr.ip()).collect())
+            }
+            None => return Err("url has no host".to_string()),
+        };
+        match addrs.into_iter().find(|addr| !self.allows(&host, *addr)) {
+            Some(addr) => Err(format!(
+                "url points to the private address {}; the server admin can allow '{}' under [webhooks] allowed_private_hosts",
+                addr, host
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether an address is reachable on the internet, rather than loopback,
+/// private, link-local, shared (carrier-grade NAT), multicast or unspecified
+fn is_public(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [first, second, ..] = v4.octets();
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || first == 0
+                || (first == 100 && second & 0xc0 == 64))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public(v4.into()),
+            None => !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || v6.is_unique_local()
+                || v6.is_unicast_link_local()),
+        },
+    }
+}
+
+/// DNS resolver for webhook requests that drops the addresses webhooks may not
+/// reach, so a host cannot be pointed at one after its url was checked
+struct WebhookResolver(Arc<WebhookConfig>);
+
+impl reqwest::dns::Resolve for WebhookResolver {
+    fn resolve(&self, name: Name) -> reqwest::dns::Resolving {
+        let config = self.0.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| config.allows(host, addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no address webhooks may reach", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// How often and how patiently a delivery is attempted
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// Wait before the first retry; doubled for every further one
+    pub base_delay: Duration,
+    /// Time allowed for one request
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Six attempts over about a minute (waits of 2, 4, 8, 16 and 32 seconds)
+    fn default() -> Self {
+        Self {
+            max_attempts: 6,
+            base_delay: Duration::from_secs(2),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait after the given failed attempt (1-based)
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.base_delay * 2u32.saturating_pow(attempt.saturating_sub(1))
+    }
+}
+
+/// A webhook as written by its owner
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookSpec {
+    pub name: String,
+    pub url: String,
+    /// Signing key for `X-Pulson-Signature`. On update, leaving it out keeps the
+    /// current one and an empty string removes it.
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Body template; without one the event itself is sent
+    pub template: Option<Value>,
+    /// Event kinds to send; empty means all of `WEBHOOK_EVENTS`
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl WebhookSpec {
+    pub fn normalize(mut self) -> Result<Self, String> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() || self.name.len() > MAX_WEBHOOK_NAME_LEN {
+            return Err(format!("name must be 1 to {} characters", MAX_WEBHOOK_NAME_LEN));
+        }
+
+        let url = url::Url::parse(self.url.trim()).map_err(|e| format!("invalid url: {}", e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err("url must start with http:// or https://".to_string());
+        }
+        self.url = url.to_string();
+
+        if self.headers.len() > MAX_WEBHOOK_HEADERS {
+            return Err(format!("at most {} headers are allowed", MAX_WEBHOOK_HEADERS));
+        }
+        for (name, value) in &self.headers {
+            let header = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{}'", name))?;
+            if RESERVED_HEADERS.contains(&header.as_str()) {
+                return Err(format!("header '{}' is set by pulson", name));
+            }
+            reqwest::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header '{}'", name))?;
+        }
+
+        self.events.sort();
+        self.events.dedup();
+        if let Some(unknown) = self.events.iter().find(|e| !WEBHOOK_EVENTS.contains(&e.as_str())) {
+            return Err(format!("unknown event '{}', expected one of {}", unknown, WEBHOOK_EVENTS.join(", ")));
+        }
+        Ok(self)
+    }
+}
+
+impl Webhook {
+    fn wants(&self, kind: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == kind)
+    }
+}
+
+/// Fill a body template from an event. A string that is exactly `{{field}}`
+/// becomes the field's JSON value; other strings get fields substituted as text.
+/// Unknown fields render as null or as an empty string.
+pub fn render_template(template: &Value, event: &Value) -> Value {
+    match template {
+        Value::String(text) => {
+            if let Some(field) = whole_placeholder(text) {
+                return event.get(field).cloned().unwrap_or(Value::Null);
+            }
+            let mut rendered = String::new();
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find("{{") {
+                let Some(end) = rest[start..].find("}}") else {
+                    break;
+                };
+                rendered.push_str(&rest[..start]);
+                match event.get(rest[start + 2..start + end].trim()) {
+                    Some(Value::String(value)) => rendered.push_str(value),
+                    Some(Value::Null) | None => {}
+                    Some(value) => rendered.push_str(&value.to_string()),
+                }
+                rest = &rest[start + end + 2..];
+            }
+            rendered.push_str(rest);
+            Value::String(rendered)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_template(item, event)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), render_template(value, event)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn whole_placeholder(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix("{{")?.strip_suffix("}}")?;
+    if inner.contains("{{") || inner.contains("}}") {
+        return None;
+    }
+    Some(inner.trim())
+}
+
+/// `sha256=<hex>` HMAC of the request body
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Start the background task that sends matching live events to webhooks.
+/// Deliveries left pending by a restart are resumed.
+pub fn spawn_webhook_dispatcher(db: Database, events: EventBus, config: Arc<WebhookConfig>) {
+    let rx = events.subscribe();
+    let dispatcher = Arc::new(WebhookDispatcher::new(db, RetryPolicy::default(), config));
+    tokio::spawn(async move {
+        dispatcher.resume_pending();
+        dispatcher.run(rx).await;
+    });
+}
+
+struct WebhookDispatcher {
+    db: Database,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+    config: Arc<WebhookConfig>,
+}
+
+impl WebhookDispatcher {
+    fn new(db: Database, policy: RetryPolicy, config: Arc<WebhookConfig>) -> Self {
+        // Redirects are not followed, they could lead anywhere
+        let client = reqwest::Client::builder()
+            .timeout(policy.timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(WebhookResolver(config.clone())))
+            .build()
+            .unwrap_or_default();
+        Self { db, client, policy, config }
+    }
+
+    async fn run(self: Arc<Self>, mut rx: tokio::sync::broadcast::Receiver<LiveEvent>) {
+        loop {
+            match rx.recv().await {
+                Ok(event) => self.dispatch(&event),
+                Err(RecvError::Lagged(missed)) => eprintln!("Webhooks: missed {} events", missed),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    fn resume_pending(self: &Arc<Self>) {
+        let Ok(pending) = list_pending_webhook_deliveries(&self.db) else {
+            eprintln!("Webhooks: failed to load pending deliveries");
+            return;
+        };
+        for delivery in pending {
+            if let Ok(Some(webhook)) = get_webhook(&self.db, delivery.webhook_id) {
+                tokio::spawn(self.clone().deliver(webhook, delivery));
+            }
+        }
+    }
+
+    /// Log a delivery for every webhook of the owner that wants the event and
+    /// send them in the background
+    fn dispatch(self: &Arc<Self>, event: &LiveEvent) {
+        let kind = event.kind();
+        if !WEBHOOK_EVENTS.contains(&kind) || event_silenced(&self.db, event) {
+            return;
+        }
+        let webhooks = match list_enabled_webhooks(&self.db, event.owner()) {
+            Ok(webhooks) => webhooks,
+            Err(_) => {
+                eprintln!("Webhooks: failed to load webhooks of {}", event.owner());
+                return;
+            }
+        };
+
+        let Ok(mut body) = serde_json::to_value(event) else {
+            return;
+        };
+        body["username"] = Value::String(event.owner().to_string());
+
+        for webhook in webhooks.into_iter().filter(|w| w.wants(kind)) {
+            let payload = match &webhook.template {
+                Some(template) => render_template(template, &body),
+                None => body.clone(),
+            };
+            match create_webhook_delivery(&self.db, webhook.id, kind, &payload.to_string()) {
+                Ok(delivery) => {
+                    tokio::spawn(self.clone().deliver(webhook, delivery));
+                }
+                Err(_) => eprintln!("Webhooks: failed to log delivery for webhook {}", webhook.id),
+            }
+        }
+    }
+
+    /// Send one delivery until it succeeds, fails permanently or runs out of attempts
+    async fn deliver(self: Arc<Self>, webhook: Webhook, delivery: WebhookDelivery) {
+        let mut attempt = delivery.attempts;
+        loop {
+            attempt += 1;
+            let (status, error) = self.send(&webhook, &delivery).await;
+            let delivered = status.is_some_and(|s| (200..300).contains(&s));
+            // Client errors other than timeouts and rate limits won't go away by retrying
+            let permanent = status.is_some_and(|s| (400..500).contains(&s) && s != 408 && s != 429);
+            let state = if delivered {
+                "delivered"
+            } else if permanent || attempt >= self.policy.max_attempts {
+                "failed"
+            } else {
+                "pending"
+            };
+
+            if record_webhook_attempt(&self.db, delivery.id, attempt, state, status, error.as_deref()).is_err() {
+                eprintln!("Webhooks: failed to record attempt of delivery {}", delivery.id);
+            }
+            if state != "pending" {
+                if state == "failed" {
+                    eprintln!(
+                        "Webhooks: delivery {} to '{}' failed after {} attempts",
+                        delivery.id, webhook.name, attempt
+                    );
+                }
+                return;
+            }
+            tokio::time::sleep(self.policy.backoff(attempt)).await;
+        }
+    }
+
+    /// One request; returns the response status or why there was none
+    async fn send(&self, webhook: &Webhook, delivery: &WebhookDelivery) -> (Option<u16>, Option<String>) {
+        if let Err(e) = self.config.check_url(&webhook.url).await {
+            return (None, Some(e));
+        }
+        if webhook.signed && webhook.secret.is_none() {
+            return (None, Some("the signing secret cannot be read; set it again".to_string()));
+        }
+        let mut request = self
+            .client
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Pulson-Event", &delivery.event)
+            .header("X-Pulson-Delivery", delivery.id.to_string());
+        if let Some(secret) = &webhook.secret {
+            request = request.header("X-Pulson-Signature", sign_payload(secret, delivery.payload.as_bytes()));
+        }
+        for (name, value) in &webhook.headers {
+            request = request.header(name, value);
+        }
+
+        match request.body(delivery.payload.clone()).send().await {
+            Ok(response) if response.status().is_success() => (Some(response.status().as_u16()), None),
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("server answered {}", response.status())),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logic::serve::database::{create_user, create_webhook, init_database, list_webhook_deliveries};
+    use crate::logic::serve::roles::Role;
+    use crate::logic::types::DeviceStatus;
+    use serde_json::json;
+    use std::sync::Mutex;
+    use warp::Filter;
+
+    #[test]
+    fn test_render_template() {
+        let event = json!({ "kind": "device_status", "device_id": "robot1", "status": "Offline", "count": 3 });
+        let template = json!({
+            "text": "{{device_id}} is {{ status }} ({{count}}, {{missing}})",
+            "status": "{{status}}",
+            "count": "{{count}}",
+            "fields": ["{{kind}}", 1, null],
+            "missing": "{{missing}}"
+        });
+
+        assert_eq!(
+            render_template(&template, &event),
+            json!({
+                "text": "robot1 is Offline (3, )",
+                "status": "Offline",
+                "count": 3,
+                "fields": ["device_status", 1, null],
+                "missing": null
+            })
+        );
+    }
+
+    #[test]
+    fn test_webhook_spec_validation() {
+        let spec = |url: &str, headers: &[(&str, &str)], events: &[&str]| WebhookSpec {
+            name: "ops".to_string(),
+            url: url.to_string(),
+            secret: None,
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            template: None,
+            events: events.iter().map(|e| e.to_string()).collect(),
+            enabled: true,
+        };
+
+        assert!(spec("https://example.com/hook", &[("X-Team", "ops")], &["alert", "alert"]).normalize().is_ok());
+        assert!(spec("ftp://example.com", &[], &[]).normalize().is_err());
+        assert!(spec("not a url", &[], &[]).normalize().is_err());
+        assert!(spec("http://example.com", &[("Content-Type", "text/plain")], &[]).normalize().is_err());
+        assert!(spec("http://example.com", &[("bad header", "x")], &[]).normalize().is_err());
+        assert!(spec("http://example.com", &[], &["data"]).normalize().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_private_targets_are_refused() {
+        let config = WebhookConfig::default();
+        assert!(config.check_url("https://93.184.215.14/hook").await.is_ok());
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://10.1.2.3/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/hook",
+            "http://[::ffff:192.168.1.1]/hook",
+            "http://[fd00::1]/hook",
+            "http://0.0.0.0/hook",
+            "http://localhost/hook",
+        ] {
+            assert!(config.check_url(url).await.is_err(), "{} should be refused", url);
+        }
+
+        let config = WebhookConfig { allowed_private_hosts: vec!["127.0.0.1".to_string(), "LocalHost".to_string()] };
+        assert!(config.check_url("http://127.0.0.1:8080/hook").await.is_ok());
+        assert!(config.check_url("http://localhost/hook").await.is_ok());
+        assert!(config.check_url("http://10.1.2.3/hook").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_signed_and_retried() {
+        // Stand-in receiver that fails the first request and accepts the rest
+        let received: Arc<Mutex<Vec<(warp::http::HeaderMap, String)>>> = Arc::default();
+        let log = received.clone();
+        let receiver = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers, body: warp::hyper::body::Bytes| {
+                let mut log = log.lock().unwrap();
+                log.push((headers, String::from_utf8_lossy(&body).to_string()));
+                let status = if log.len() == 1 { 500 } else { 200 };
+                warp::reply::with_status("", warp::http::StatusCode::from_u16(status).unwrap())
+            });
+        let (addr, server) = warp::serve(receiver).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let db = init_database(":memory:").unwrap();
+        create_user(&db, "alice", "hash", Role::Operator).unwrap();
+        let spec = WebhookSpec {
+            name: "ops".to_string(),
+            url: format!("http://{}/hook", addr),
+            secret: Some("s3cret".to_string()),
+            headers: BTreeMap::from([("X-Team".to_string(), "ops".to_string())]),
+            template: Some(json!({ "text": "{{device_id}} is {{status}}" })),
+            events: vec!["device_status".to_string()],
+            enabled: true,
+        };
+        let webhook = create_webhook(&db, "alice", &spec.normalize().unwrap()).unwrap();
+        // The secret is stored sealed, not as given
+        let stored: String = db
+            .lock()
+            .unwrap()
+            .query_row("SELECT encrypted_secret FROM webhooks WHERE id = ?1", [webhook.id], |row| row.get(0))
+            .unwrap();
+        assert!(!stored.contains("s3cret") && !stored.contains(&hex::encode("s3cret")));
+        assert_eq!(webhook.secret.as_deref(), Some("s3cret"));
+
+        let policy = RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(10), timeout: Duration::from_secs(5) };
+        let config = WebhookConfig { allowed_private_hosts: vec!["127.0.0.1".to_string()] };
+        let dispatcher = Arc::new(WebhookDispatcher::new(db.clone(), policy, Arc::new(config)));
+        dispatcher.dispatch(&LiveEvent::DeviceStatus {
+            owner: "alice".to_string(),
+            device_id: "robot1".to_string(),
+            status: DeviceStatus::Offline,
+            previous_status: Some(DeviceStatus::Warning),
+            last_seen: "2025-06-01T12:00:00Z".to_string(),
+        });
+        // Not subscribed to topic changes
+        dispatcher.dispatch(&LiveEvent::TopicStatus {
+            owner: "alice".to_string(),
+            device_id: "robot1".to_string(),
+            topic: "temp".to_string(),
+            status: crate::logic::types::TopicStatus::Stale,
+            previous_status: None,
+            last_seen: "2025-06-01T12:00:00Z".to_string(),
+        });
+
+        let mut deliveries = Vec::new();
+        for _ in 0..100 {
+            deliveries = list_webhook_deliveries(&db, "alice", webhook.id, 10).unwrap().unwrap();
+            if deliveries.iter().all(|d| d.state != "pending") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(deliveries.len(), 1);
+        assert_eq!(deliveries[0].state, "delivered");
+        assert_eq!(deliveries[0].attempts, 2);
+        assert_eq!(deliveries[0].response_status, Some(200));
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        let (headers, body) = &received[1];
+        assert_eq!(body, r#"{"text":"robot1 is Offline"}"#);
+        assert_eq!(headers["x-team"], "ops");
+        assert_eq!(headers["x-pulson-event"], "device_status");
+        assert_eq!(headers["x-pulson-signature"], sign_payload("s3cret", body.as_bytes()).as_str());
+    }
+}
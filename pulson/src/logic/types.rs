@@ -7,6 +7,7 @@ pub enum DeviceStatus {
     Online,   // Active within online threshold
     Warning,  // Active within warning threshold  
     Offline,  // No activity beyond warning threshold
+    Maintenance, // Covered by a maintenance window
 }
 
 /// Topic status enum  
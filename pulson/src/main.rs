@@ -4,10 +4,12 @@ mod cli;
 mod logic;
 
 use clap::Parser;
-use cli::{AccountAction, Cli, Commands, DeviceAction, ConfigAction};
-use crate::logic::client::{account, list, pulse, device};
-use crate::logic::client::config::{show, set}; // Import show and set directly using crate path
-use logic::config::StatusConfig;
+use cli::{AccountAction, AdminAction, AlertAction, Cli, Commands, DeviceAction, ConfigAction, KeyAction, MaintenanceAction, OrgAction, RetentionAction, ThresholdsAction};
+use crate::logic::client::{account, admin, alert, keys, list, maintenance, orgs, pulse, device};
+use crate::logic::client::config::{show, set, set_retention, remove_retention, set_thresholds, remove_thresholds}; // Import show and set directly using crate path
+use logic::config::{ServerConfig, StatusConfig};
+use logic::serve::ingest::IngestOptions;
+use logic::serve::ServeOptions;
 use std::sync::{Arc, Mutex};
 
 #[tokio::main]
@@ -17,13 +19,16 @@ async fn main() -> anyhow::Result<()> {
 
     // Parse the host configuration
     let host_config = args.parse_host();
+    if let Some(org) = args.org.clone() {
+        logic::client::url_utils::set_org(org);
+    }
 
     // Pre‐load token for client commands (List & Ping)
     let token = match &args.command {
         Commands::Serve { .. } => None,
         Commands::Account { .. } => None,
         Commands::Config { .. } => None, // Config commands work with local files, no auth needed
-        Commands::Device { .. } | Commands::Pulse { .. } => match account::read_token() {
+        Commands::Device { .. } | Commands::Pulse { .. } | Commands::Alert { .. } | Commands::Maintenance { .. } | Commands::Key { .. } | Commands::Admin { .. } | Commands::Org { .. } => match account::fresh_token(host_config.base_url(), &host_config.host, host_config.port).await {
             Ok(t) => Some(t),
             Err(_) => {
                 eprintln!("✗ Not logged in: please run `pulson account login` first`");
@@ -39,11 +44,14 @@ async fn main() -> anyhow::Result<()> {
             daemon,
             root_pass,
             webui,
-            config: _,  // Config file option ignored - using purely server-based configuration
+            config,
             online_threshold,
             warning_threshold,
             stale_threshold,
             save_images,
+            max_clock_skew,
+            mqtt_bind,
+            udp_bind,
         } => {
             // Create configuration from CLI arguments and environment variables only
             let status_config = StatusConfig::from_args_and_env(online_threshold, warning_threshold, stale_threshold);
@@ -51,8 +59,21 @@ async fn main() -> anyhow::Result<()> {
             // Wrap configuration in Arc<Mutex<>> for thread-safe sharing
             let status_config = Arc::new(Mutex::new(status_config));
 
+            let options = ServeOptions {
+                ingest: IngestOptions {
+                    save_images,
+                    max_clock_skew_seconds: max_clock_skew,
+                },
+                mqtt_bind,
+                udp_bind,
+                server_config: match config {
+                    Some(path) => ServerConfig::load(&path)?,
+                    None => ServerConfig::default(),
+                },
+            };
+
             // Run the HTTP server - use host_config for server
-            logic::serve::run(host_config, db_path, daemon, root_pass, webui, status_config, save_images).await?
+            logic::serve::run(host_config, db_path, daemon, root_pass, webui, status_config, options).await?
         }
 
         Commands::Device { action } => match action {
@@ -95,6 +116,17 @@ async fn main() -> anyhow::Result<()> {
                 // TODO: Implement actual device deletion logic e.g.:
                 device::delete(host_config.base_url(), host_config.host, host_config.port, device_id, token.unwrap()).await?
             }
+            DeviceAction::History { device_id, topic, from, to, bucket, stats } => {
+                let query = device::HistoryQuery { topic, from, to, bucket, stats };
+                device::history(host_config.base_url(), host_config.host, host_config.port, device_id, query, token.unwrap()).await?
+            }
+            DeviceAction::Uptime { device_id, topic, from, to, transitions } => {
+                let query = device::UptimeQuery { topic, from, to, transitions };
+                device::uptime(host_config.base_url(), host_config.host, host_config.port, device_id, query, token.unwrap()).await?
+            }
+            DeviceAction::Credentials { device_id, revoke } => {
+                device::credentials(host_config.base_url(), host_config.host, host_config.port, device_id, revoke, token.unwrap()).await?
+            }
         },
 
         Commands::Pulse { 
@@ -115,14 +147,22 @@ async fn main() -> anyhow::Result<()> {
             image_file,
             image_data,
             channels,
+            timestamp,
+            batch,
         } => {
+            if let Some(file) = batch {
+                // Client: send many records in a single request
+                pulse::run_batch(host_config.base_url(), host_config.host, host_config.port, file, token.unwrap()).await?;
+                return Ok(());
+            }
+
             // Client: send a unified pulse (ping or data)
             pulse::run(
                 host_config.base_url(),
                 host_config.host, 
                 host_config.port, 
-                device_id, 
-                topic, 
+                device_id.unwrap(), 
+                topic.unwrap(), 
                 data_type,
                 data,
                 latitude,
@@ -138,6 +178,7 @@ async fn main() -> anyhow::Result<()> {
                 image_file,
                 image_data,
                 channels,
+                timestamp,
                 token.unwrap()
             ).await?
         }
@@ -156,6 +197,10 @@ async fn main() -> anyhow::Result<()> {
                 AccountAction::Logout => account::logout(host_config.base_url(), host_config.host, host_config.port).await?,
                 AccountAction::Delete { username } => account::delete(host_config.base_url(), host_config.host, host_config.port, username).await?,
                 AccountAction::List => account::list_users(host_config.base_url(), host_config.host, host_config.port).await?,
+                AccountAction::Sessions { revoke, revoke_all } => {
+                    account::sessions(host_config.base_url(), host_config.host, host_config.port, revoke, revoke_all).await?
+                }
+                AccountAction::Refresh => account::refresh(host_config.base_url(), host_config.host, host_config.port).await?,
             }
         }
 
@@ -172,6 +217,113 @@ async fn main() -> anyhow::Result<()> {
                 } => {
                     set(online_threshold, warning_threshold, stale_threshold).await? // Call imported set function
                 }
+                ConfigAction::Retention { action } => match action {
+                    RetentionAction::Set { keep, device, topic, data_type, rollups } => {
+                        set_retention(keep, device, topic, data_type, rollups).await?
+                    }
+                    RetentionAction::Remove { id } => remove_retention(id).await?,
+                },
+                ConfigAction::Thresholds { action } => match action {
+                    ThresholdsAction::Set {
+                        device,
+                        topic,
+                        online_threshold,
+                        warning_threshold,
+                        stale_threshold,
+                        learn,
+                    } => {
+                        let thresholds = [online_threshold, warning_threshold, stale_threshold];
+                        set_thresholds(device, topic, thresholds, learn).await?
+                    }
+                    ThresholdsAction::Remove { id } => remove_thresholds(id).await?,
+                },
+            }
+        }
+
+        Commands::Alert { action } => {
+            let token = token.unwrap();
+            match action {
+                AlertAction::List => alert::list(host_config.base_url(), host_config.host, host_config.port, token).await?,
+                AlertAction::Add { name, when, device, topic, min, max, state, pattern } => {
+                    let rule = alert::NewRule { name, when, device, topic, min, max, state, pattern };
+                    alert::add(host_config.base_url(), host_config.host, host_config.port, rule, token).await?
+                }
+                AlertAction::Remove { id } => {
+                    alert::remove(host_config.base_url(), host_config.host, host_config.port, id, token).await?
+                }
+                AlertAction::Enable { id } => {
+                    alert::set_enabled(host_config.base_url(), host_config.host, host_config.port, id, true, token).await?
+                }
+                AlertAction::Disable { id } => {
+                    alert::set_enabled(host_config.base_url(), host_config.host, host_config.port, id, false, token).await?
+                }
+                AlertAction::History { firing, limit } => {
+                    alert::history(host_config.base_url(), host_config.host, host_config.port, firing, limit, token).await?
+                }
+            }
+        }
+
+        Commands::Maintenance { action } => {
+            let token = token.unwrap();
+            match action {
+                MaintenanceAction::List { all } => {
+                    maintenance::list(host_config.base_url(), host_config.host, host_config.port, all, token).await?
+                }
+                MaintenanceAction::Add { device, topic, reason, from, until, duration, silence } => {
+                    let window = maintenance::NewWindow { device, topic, reason, from, until, duration, silence };
+                    maintenance::add(host_config.base_url(), host_config.host, host_config.port, window, token).await?
+                }
+                MaintenanceAction::Remove { id } => {
+                    maintenance::remove(host_config.base_url(), host_config.host, host_config.port, id, token).await?
+                }
+            }
+        }
+
+        Commands::Key { action } => {
+            let token = token.unwrap();
+            match action {
+                KeyAction::List => keys::list(host_config.base_url(), host_config.host, host_config.port, token).await?,
+                KeyAction::Create { name, scopes, device, expires } => {
+                    let key = keys::NewKey { name, scopes, device, expires };
+                    keys::create(host_config.base_url(), host_config.host, host_config.port, key, token).await?
+                }
+                KeyAction::Revoke { id } => {
+                    keys::revoke(host_config.base_url(), host_config.host, host_config.port, id, token).await?
+                }
+            }
+        }
+
+        Commands::Admin { action } => {
+            let token = token.unwrap();
+            match action {
+                AdminAction::Roles => admin::roles(host_config.base_url(), host_config.host, host_config.port, token).await?,
+                AdminAction::Role { username, role } => {
+                    admin::set_role(host_config.base_url(), host_config.host, host_config.port, username, role, token).await?
+                }
+                AdminAction::Audit { actor, action, target, for_org, result, ip, from, to, limit } => {
+                    let query = admin::AuditQuery { actor, action, target, org: for_org, result, ip, from, to, limit };
+                    admin::audit(host_config.base_url(), host_config.host, host_config.port, query, token).await?
+                }
+            }
+        }
+
+        Commands::Org { action } => {
+            let token = token.unwrap();
+            let (base_url, host, port) = (host_config.base_url(), host_config.host, host_config.port);
+            match action {
+                OrgAction::List => orgs::list(base_url, host, port, token).await?,
+                OrgAction::Create { name } => orgs::create(base_url, host, port, name, token).await?,
+                OrgAction::Delete { name } => orgs::delete(base_url, host, port, name, token).await?,
+                OrgAction::Members { org } => orgs::members(base_url, host, port, org, token).await?,
+                OrgAction::Add { org, username, role } => {
+                    orgs::set_member(base_url, host, port, org, username, role, token).await?
+                }
+                OrgAction::Remove { org, username } => {
+                    orgs::remove_member(base_url, host, port, org, username, token).await?
+                }
+                OrgAction::Move { org, devices, all } => {
+                    orgs::move_devices(base_url, host, port, org, devices, all, token).await?
+                }
             }
         }
     }